@@ -0,0 +1,38 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_redis::pub_sub::PubSubState;
+
+//
+// ──────────────────────────────────────────────────────────────
+//   In-process fan-out: one publish delivered to N subscribers
+// ──────────────────────────────────────────────────────────────
+//
+
+fn bench_fanout(c: &mut Criterion) {
+    let payload = "x".repeat(1024);
+    let mut group = c.benchmark_group("PUBLISH_fanout_1KB");
+
+    for subscribers in [100usize, 1_000, 10_000] {
+        let mut state = PubSubState::new();
+        let mut receivers: Vec<_> = (0..subscribers)
+            .map(|_| {
+                let (subscriber_id, rx) = state.create_subscriber();
+                state.subscribe(subscriber_id, "hot".to_string());
+                rx
+            })
+            .collect();
+
+        group.bench_with_input(BenchmarkId::from_parameter(subscribers), &subscribers, |b, _| {
+            b.iter(|| {
+                state.publish("hot", payload.clone());
+                for rx in receivers.iter_mut() {
+                    let _ = rx.try_recv();
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_fanout);
+criterion_main!(benches);
@@ -0,0 +1,124 @@
+// AGGREGATE: folds the values of the keys matching a pattern into one number on the server, so
+// a client wanting a total or an extreme does not have to fetch every key. Only string values
+// holding a 64-bit integer take part in SUM, MIN, MAX and AVG; COUNT counts every matching key.
+use crate::data_types::RedisValue;
+use crate::protocol::Reply;
+
+// Keys read under one hold of the database lock; other clients run between batches
+pub const AGGREGATE_BATCH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reducer {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl Reducer {
+    pub fn from_string(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "COUNT" => Some(Reducer::Count),
+            "SUM" => Some(Reducer::Sum),
+            "MIN" => Some(Reducer::Min),
+            "MAX" => Some(Reducer::Max),
+            "AVG" => Some(Reducer::Avg),
+            _ => None,
+        }
+    }
+}
+
+/// The running state of one AGGREGATE call.
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    reducer: Reducer,
+    keys: u64,
+    numbers: u64,
+    // Wide enough that no i64 inputs overflow it before the reply checks the range
+    sum: i128,
+    min: Option<i64>,
+    max: Option<i64>,
+}
+
+fn integer_value(value: &RedisValue) -> Option<i64> {
+    match value {
+        RedisValue::Integer(number) => Some(*number),
+        RedisValue::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+impl Aggregator {
+    pub fn new(reducer: Reducer) -> Self {
+        Self { reducer, keys: 0, numbers: 0, sum: 0, min: None, max: None }
+    }
+
+    /// Folds in the value of one matching key.
+    pub fn add(&mut self, value: &RedisValue) {
+        self.keys += 1;
+        if let Some(number) = integer_value(value) {
+            self.numbers += 1;
+            self.sum += number as i128;
+            self.min = Some(self.min.map_or(number, |min| min.min(number)));
+            self.max = Some(self.max.map_or(number, |max| max.max(number)));
+        }
+    }
+
+    /// The reply to the AGGREGATE call. MIN, MAX and AVG are nil without any integers.
+    pub fn reply(&self) -> Reply {
+        let extreme = |value: Option<i64>| value.map_or(Reply::Nil, Reply::Integer);
+        match self.reducer {
+            Reducer::Count => Reply::integer(self.keys),
+            Reducer::Sum => match i64::try_from(self.sum) {
+                Ok(sum) => Reply::Integer(sum),
+                Err(_) => Reply::error("ERR SUM is out of the 64-bit integer range"),
+            },
+            Reducer::Min => extreme(self.min),
+            Reducer::Max => extreme(self.max),
+            Reducer::Avg if self.numbers == 0 => Reply::Nil,
+            Reducer::Avg => Reply::bulk((self.sum as f64 / self.numbers as f64 + 0.0).to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregate(reducer: Reducer, values: &[RedisValue]) -> String {
+        let mut aggregator = Aggregator::new(reducer);
+        values.iter().for_each(|value| aggregator.add(value));
+        aggregator.reply().to_text()
+    }
+
+    #[test]
+    fn test_reducers_skip_values_that_are_not_integers() {
+        let values = [
+            RedisValue::Integer(4),
+            RedisValue::String("-1".into()),
+            RedisValue::String("abc".into()),
+            RedisValue::List(vec!["7".into()].into()),
+            RedisValue::Integer(3),
+        ];
+        assert_eq!(aggregate(Reducer::Count, &values), "(integer) 5");
+        assert_eq!(aggregate(Reducer::Sum, &values), "(integer) 6");
+        assert_eq!(aggregate(Reducer::Min, &values), "(integer) -1");
+        assert_eq!(aggregate(Reducer::Max, &values), "(integer) 4");
+        assert_eq!(aggregate(Reducer::Avg, &values), "\"2\"");
+        assert_eq!(aggregate(Reducer::Avg, &values[..2]), "\"1.5\"");
+        assert_eq!(Reducer::from_string("avg"), Some(Reducer::Avg));
+        assert_eq!(Reducer::from_string("median"), None);
+    }
+
+    #[test]
+    fn test_empty_and_overflowing_aggregates() {
+        assert_eq!(aggregate(Reducer::Count, &[]), "(integer) 0");
+        assert_eq!(aggregate(Reducer::Sum, &[]), "(integer) 0");
+        assert_eq!(aggregate(Reducer::Max, &[RedisValue::String("x".into())]), "(nil)");
+        assert_eq!(aggregate(Reducer::Avg, &[]), "(nil)");
+        let huge = [RedisValue::Integer(i64::MAX), RedisValue::Integer(1)];
+        assert_eq!(aggregate(Reducer::Sum, &huge), "(error) ERR SUM is out of the 64-bit integer range");
+        assert_eq!(aggregate(Reducer::Max, &huge), format!("(integer) {}", i64::MAX));
+    }
+}
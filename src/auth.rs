@@ -1,24 +1,113 @@
-use std::sync::Arc;
+use rand::Rng;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Name AUTH uses for the server-wide password, as in Redis ACLs
+pub const DEFAULT_USER: &str = "default";
+// How long a SESSION CREATE token stays valid when no lifetime is given, and at most
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(300);
+pub const MAX_SESSION_TTL: Duration = Duration::from_secs(86400);
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Checks the credentials of users that are not tenants. Verification returns a future so
+/// backends can ask another service; see auth_backends for those beyond the static password.
+pub trait Authenticator: std::fmt::Debug + Send + Sync {
+    fn verify<'a>(&'a self, username: &'a str, password: &'a str) -> BoxFuture<'a, bool>;
+}
+
+/// The --password backend: one password, for the `default` user only.
+#[derive(Debug)]
+pub struct StaticPassword(pub String);
+
+impl Authenticator for StaticPassword {
+    fn verify<'a>(&'a self, username: &'a str, password: &'a str) -> BoxFuture<'a, bool> {
+        let verified = username == DEFAULT_USER && password == self.0;
+        Box::pin(async move { verified })
+    }
+}
+
+/// A user confined to the keys under `prefix`, which is applied to and stripped from every key
+/// its connections name.
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub password: String,
+    pub prefix: String,
+}
+
+#[derive(Debug)]
+struct Session {
+    // The tenant the token logs in as, None for the unprefixed keyspace
+    key_prefix: Option<String>,
+    expires_at: Instant,
+}
+
+/// Tokens handed out by SESSION CREATE, so pooled connections can log in without sending the
+/// password again. Shared by every connection; not persisted.
+#[derive(Debug, Default)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl SessionStore {
+    pub fn create(&self, key_prefix: Option<String>, ttl: Duration) -> String {
+        let token: String = rand::thread_rng().gen::<[u8; 16]>().iter().map(|b| format!("{:02x}", b)).collect();
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(token.clone(), Session { key_prefix, expires_at: now + ttl });
+        token
+    }
+
+    /// The keyspace a live token logs in to, or None if it is unknown or expired.
+    pub fn redeem(&self, token: &str) -> Option<Option<String>> {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.get(token)
+            .filter(|session| session.expires_at > Instant::now())
+            .map(|session| session.key_prefix.clone())
+    }
+
+    pub fn revoke(&self, token: &str) -> bool {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner()).remove(token).is_some()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct AuthConfig {
+    // Also what this server sends when it authenticates to invalidation peers
     pub password: Option<String>,
+    // Checks AUTH for everyone but tenants: the static password unless another backend is set
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    pub tenants: HashMap<String, Tenant>,
+    pub sessions: Arc<SessionStore>,
 }
 
 impl AuthConfig {
     pub fn new(password: Option<String>) -> Self {
-        Self { password }
+        let authenticator = password.clone().map(|password| Arc::new(StaticPassword(password)) as Arc<dyn Authenticator>);
+        Self { password, authenticator, tenants: HashMap::new(), sessions: Arc::default() }
     }
 
-    pub fn is_auth_required(&self) -> bool {
-        self.password.is_some()
+    /// Adds a tenant whose keys live under `name:`.
+    pub fn add_tenant(&mut self, name: &str, password: &str) -> Result<(), String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("invalid tenant name '{}'", name));
+        }
+        if name == DEFAULT_USER || self.tenants.contains_key(name) {
+            return Err(format!("tenant '{}' is already defined", name));
+        }
+        if password.is_empty() {
+            return Err(format!("tenant '{}' needs a password", name));
+        }
+        self.tenants.insert(name.to_string(), Tenant { password: password.to_string(), prefix: format!("{}:", name) });
+        Ok(())
     }
 
-    pub fn verify_password(&self, provided_password: &str) -> bool {
-        match &self.password {
-            Some(password) => password == provided_password,
-            None => true, // No password required
-        }
+    pub fn is_auth_required(&self) -> bool {
+        self.authenticator.is_some() || !self.tenants.is_empty()
     }
 }
 
@@ -26,6 +115,14 @@ impl AuthConfig {
 pub struct ClientAuth {
     pub is_authenticated: bool,
     pub auth_config: Arc<AuthConfig>,
+    // Protocol negotiated with HELLO; on 3 the connection can receive pub/sub pushes
+    // between ordinary replies
+    pub protocol: u8,
+    // Set once the connection authenticates as a tenant
+    pub key_prefix: Option<String>,
+    // READONLY: the client may be sent to replicas for reads. This server is always a
+    // master, so the flag changes nothing yet
+    pub readonly: bool,
 }
 
 impl ClientAuth {
@@ -33,16 +130,54 @@ impl ClientAuth {
         Self {
             is_authenticated: !auth_config.is_auth_required(),
             auth_config,
+            protocol: 2,
+            key_prefix: None,
+            readonly: false,
         }
     }
 
-    pub fn authenticate(&mut self, password: &str) -> bool {
-        if self.auth_config.verify_password(password) {
+    pub async fn authenticate(&mut self, password: &str) -> bool {
+        self.authenticate_user(DEFAULT_USER, password).await
+    }
+
+    /// AUTH with a user name: a tenant by its own password, anyone else through the
+    /// authenticator, where `default` is the server password.
+    pub async fn authenticate_user(&mut self, username: &str, password: &str) -> bool {
+        if let Some(tenant) = self.auth_config.tenants.get(username) {
+            if tenant.password != password {
+                return false;
+            }
+            self.key_prefix = Some(tenant.prefix.clone());
+            self.is_authenticated = true;
+            return true;
+        }
+        // With tenants configured and no authenticator, nobody may use the unprefixed keyspace
+        let verified = match self.auth_config.authenticator.clone() {
+            Some(authenticator) => authenticator.verify(username, password).await,
+            None => self.auth_config.tenants.is_empty(),
+        };
+        if verified {
             self.is_authenticated = true;
-            true
-        } else {
-            false
+            self.key_prefix = None;
         }
+        verified
+    }
+
+    /// SESSION AUTH: logs in as whoever created the token.
+    pub fn authenticate_session(&mut self, token: &str) -> bool {
+        match self.auth_config.sessions.redeem(token) {
+            Some(key_prefix) => {
+                self.key_prefix = key_prefix;
+                self.is_authenticated = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// SESSION CREATE: a token that logs in as this connection is logged in now.
+    pub fn create_session(&self, ttl: Duration) -> String {
+        self.auth_config.sessions.create(self.key_prefix.clone(), ttl)
     }
 
     pub fn is_authenticated(&self) -> bool {
@@ -52,4 +187,4 @@ impl ClientAuth {
     pub fn requires_auth(&self) -> bool {
         self.auth_config.is_auth_required() && !self.is_authenticated
     }
-}
\ No newline at end of file
+}
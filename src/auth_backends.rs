@@ -0,0 +1,209 @@
+// Authenticators other than the static --password, picked at startup with --auth-backend:
+//   file:PATH    users and salted SHA-256 password hashes, one `user:salt:hash` per line
+//   env:VAR      `user=token` pairs (or bare tokens for `default`), comma-separated, from VAR
+//   webhook:URL  POSTs {"username", "password"} to an http:// URL; any 2xx reply allows the
+//                login, so an LDAP or SSO bridge can sit behind it
+// Tenants keep their own passwords and are never sent to a backend.
+use crate::auth::{Authenticator, BoxFuture, DEFAULT_USER};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+// A webhook that has not answered by then is treated as a refusal
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Builds the backend named by an --auth-backend value.
+pub fn from_spec(spec: &str) -> Result<Arc<dyn Authenticator>, String> {
+    match spec.split_once(':') {
+        Some(("file", path)) => {
+            let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            Ok(Arc::new(UserFile::parse(&contents).map_err(|e| format!("{}: {}", path, e))?))
+        },
+        Some(("env", var)) => {
+            let value = std::env::var(var).map_err(|_| format!("environment variable {} is not set", var))?;
+            Ok(Arc::new(EnvTokens::parse(&value)))
+        },
+        Some(("webhook", url)) => Ok(Arc::new(Webhook::new(url)?)),
+        _ => Err(format!("unknown auth backend '{}', expected file:PATH, env:VAR or webhook:URL", spec)),
+    }
+}
+
+fn sha256_hex(data: &str) -> String {
+    Sha256::digest(data.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hashes `password` the way a user file stores it, for tools that write one.
+pub fn hash_password(salt: &str, password: &str) -> String {
+    sha256_hex(&format!("{}{}", salt, password))
+}
+
+#[derive(Debug)]
+pub struct UserFile {
+    // User name to (salt, hex SHA-256 of salt followed by password)
+    users: HashMap<String, (String, String)>,
+}
+
+impl UserFile {
+    /// Reads `user:salt:hash` lines; `user:hash` means an empty salt. Blank lines and lines
+    /// starting with # are skipped.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(':').collect();
+            let (user, salt, hash) = match fields[..] {
+                [user, hash] => (user, "", hash),
+                [user, salt, hash] => (user, salt, hash),
+                _ => return Err(format!("line {}: expected user:salt:hash", number + 1)),
+            };
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("line {}: the hash is not a hex SHA-256 digest", number + 1));
+            }
+            users.insert(user.to_string(), (salt.to_string(), hash.to_ascii_lowercase()));
+        }
+        Ok(Self { users })
+    }
+}
+
+impl Authenticator for UserFile {
+    fn verify<'a>(&'a self, username: &'a str, password: &'a str) -> BoxFuture<'a, bool> {
+        let verified = self.users.get(username).is_some_and(|(salt, hash)| hash_password(salt, password) == *hash);
+        Box::pin(async move { verified })
+    }
+}
+
+#[derive(Debug)]
+pub struct EnvTokens {
+    tokens: HashSet<(String, String)>,
+}
+
+impl EnvTokens {
+    pub fn parse(value: &str) -> Self {
+        let tokens = value.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once('=') {
+                Some((user, token)) => (user.to_string(), token.to_string()),
+                None => (DEFAULT_USER.to_string(), entry.to_string()),
+            })
+            .collect();
+        Self { tokens }
+    }
+}
+
+impl Authenticator for EnvTokens {
+    fn verify<'a>(&'a self, username: &'a str, password: &'a str) -> BoxFuture<'a, bool> {
+        let verified = self.tokens.contains(&(username.to_string(), password.to_string()));
+        Box::pin(async move { verified })
+    }
+}
+
+#[derive(Debug)]
+pub struct Webhook {
+    // host:port to connect to, and the request path
+    authority: String,
+    path: String,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| format!("webhook URL {} must start with http://", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("webhook URL {} has no host", url));
+        }
+        let authority = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+        Ok(Self { authority, path: path.to_string() })
+    }
+
+    async fn post(&self, username: &str, password: &str) -> std::io::Result<bool> {
+        let body = serde_json::json!({ "username": username, "password": password }).to_string();
+        let request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path, self.authority, body.len(), body
+        );
+        let mut stream = TcpStream::connect(&self.authority).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await?;
+        // Only the status line matters: "HTTP/1.1 204 No Content"
+        let status = String::from_utf8_lossy(&reply).split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+        Ok(status.is_some_and(|code| (200..300).contains(&code)))
+    }
+}
+
+impl Authenticator for Webhook {
+    fn verify<'a>(&'a self, username: &'a str, password: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            match timeout(WEBHOOK_TIMEOUT, self.post(username, password)).await {
+                Ok(Ok(verified)) => verified,
+                Ok(Err(e)) => {
+                    eprintln!("Auth webhook {} failed: {}", self.authority, e);
+                    false
+                },
+                Err(_) => {
+                    eprintln!("Auth webhook {} timed out", self.authority);
+                    false
+                },
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_user_file_and_env_tokens() {
+        let contents = format!("# ops team\nalice:pepper:{}\n\nbob:{}\n", hash_password("pepper", "s3cret"), hash_password("", "hunter2"));
+        let users = UserFile::parse(&contents).unwrap();
+        assert!(users.verify("alice", "s3cret").await);
+        assert!(!users.verify("alice", "hunter2").await);
+        assert!(users.verify("bob", "hunter2").await);
+        assert!(!users.verify("carol", "s3cret").await);
+        assert!(UserFile::parse("alice:not-a-hash").is_err());
+
+        let tokens = EnvTokens::parse("svc=tok-1, tok-2");
+        assert!(tokens.verify("svc", "tok-1").await);
+        assert!(tokens.verify(DEFAULT_USER, "tok-2").await);
+        assert!(!tokens.verify(DEFAULT_USER, "tok-1").await);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_follows_the_status_code() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                // The body is the last thing sent, and it ends the JSON object
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"}") {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let request = String::from_utf8_lossy(&request).into_owned();
+                let status = if request.contains(r#""password":"open-sesame""#) { "204 No Content" } else { "403 Forbidden" };
+                socket.write_all(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes()).await.unwrap();
+            }
+        });
+
+        let webhook = Webhook::new(&format!("http://{}/auth", addr)).unwrap();
+        assert!(webhook.verify("alice", "open-sesame").await);
+        assert!(!webhook.verify("alice", "guess").await);
+        assert!(Webhook::new("https://example.com").is_err());
+    }
+}
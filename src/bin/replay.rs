@@ -0,0 +1,36 @@
+use clap::Parser;
+use rust_redis::capture::{read_frames, replay};
+
+/// Re-sends a file recorded with `rust_redis --capture` to a running server.
+#[derive(Parser)]
+#[command(name = "replay")]
+struct Args {
+    /// Capture file to replay
+    file: String,
+
+    #[arg(long, default_value = "127.0.0.1:6380")]
+    target: String,
+
+    #[arg(long, default_value = "1.0", help = "Replay this many times faster than captured; 0 sends everything at once")]
+    speed: f64,
+
+    #[arg(long, help = "Password to send in place of the redacted AUTH passwords")]
+    password: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let frames = read_frames(&args.file)?;
+    println!("Replaying {} frames from {} to {}", frames.len(), args.file, args.target);
+
+    let stats = replay(frames, &args.target, args.speed, args.password.as_deref()).await?;
+    println!(
+        "Sent {} frames over {} connections in {:.3}s",
+        stats.frames,
+        stats.connections,
+        stats.elapsed.as_secs_f64()
+    );
+    Ok(())
+}
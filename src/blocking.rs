@@ -0,0 +1,140 @@
+// Clients parked by BLPOP, BRPOP and BLMOVE, by the keys they wait on. Whenever a list is
+// written at a key, everyone parked on it is woken to retry its pop; whoever takes the
+// database lock first gets the element and the rest park again. A wake-up is kept until the
+// client next waits, so one sent between its failed pop and its wait is not lost. Built on std
+// alone, like the rest of the storage core.
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+#[derive(Debug, Default)]
+struct Signal {
+    woken: bool,
+    waker: Option<Waker>,
+}
+
+type SharedSignal = Arc<Mutex<Signal>>;
+
+#[derive(Debug, Default)]
+struct Registry {
+    next_id: u64,
+    by_key: HashMap<String, Vec<(u64, SharedSignal)>>,
+}
+
+/// Shared outside the database lock, so a parked client that goes away unregisters itself
+/// without taking it.
+#[derive(Debug, Default)]
+pub struct KeyWaiters {
+    registry: Mutex<Registry>,
+}
+
+impl KeyWaiters {
+    /// Parks a client on `keys` until the returned handle is dropped.
+    pub fn park(self: &Arc<Self>, keys: &[String]) -> Parked {
+        let signal = SharedSignal::default();
+        let mut registry = self.registry.lock().unwrap_or_else(|e| e.into_inner());
+        registry.next_id += 1;
+        let id = registry.next_id;
+        for key in keys {
+            registry.by_key.entry(key.clone()).or_default().push((id, Arc::clone(&signal)));
+        }
+        Parked { waiters: Arc::clone(self), id, keys: keys.to_vec(), signal }
+    }
+
+    /// Wakes the clients parked on `key`.
+    pub fn wake(&self, key: &str) {
+        let registry = self.registry.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, signal) in registry.by_key.get(key).into_iter().flatten() {
+            let mut signal = signal.lock().unwrap_or_else(|e| e.into_inner());
+            signal.woken = true;
+            if let Some(waker) = signal.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Clients parked on at least one key.
+    pub fn blocked_clients(&self) -> usize {
+        let registry = self.registry.lock().unwrap_or_else(|e| e.into_inner());
+        let mut ids: Vec<u64> = registry.by_key.values().flatten().map(|(id, _)| *id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.len()
+    }
+}
+
+/// A client's place in the registry.
+#[derive(Debug)]
+pub struct Parked {
+    waiters: Arc<KeyWaiters>,
+    id: u64,
+    keys: Vec<String>,
+    signal: SharedSignal,
+}
+
+impl Parked {
+    /// Resolves once one of the keys was written since the last call.
+    pub async fn woken(&self) {
+        poll_fn(|cx| {
+            let mut signal = self.signal.lock().unwrap_or_else(|e| e.into_inner());
+            if std::mem::take(&mut signal.woken) {
+                return Poll::Ready(());
+            }
+            signal.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }).await
+    }
+}
+
+impl Drop for Parked {
+    fn drop(&mut self) {
+        let mut registry = self.waiters.registry.lock().unwrap_or_else(|e| e.into_inner());
+        for key in &self.keys {
+            if let Some(parked) = registry.by_key.get_mut(key) {
+                parked.retain(|(id, _)| *id != self.id);
+                if parked.is_empty() {
+                    registry.by_key.remove(key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn keys(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_wakes_are_kept_until_waited_for() {
+        let waiters = Arc::new(KeyWaiters::default());
+        let parked = waiters.park(&keys(&["a", "b"]));
+        let other = waiters.park(&keys(&["b"]));
+        assert_eq!(waiters.blocked_clients(), 2);
+
+        // Sent before either waits
+        waiters.wake("b");
+        tokio::time::timeout(Duration::from_secs(1), parked.woken()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), other.woken()).await.unwrap();
+
+        waiters.wake("c");
+        assert!(tokio::time::timeout(Duration::from_millis(10), parked.woken()).await.is_err());
+    }
+
+    #[test]
+    fn test_dropping_a_handle_unparks_the_client() {
+        let waiters = Arc::new(KeyWaiters::default());
+        let parked = waiters.park(&keys(&["a", "b"]));
+        let other = waiters.park(&keys(&["a"]));
+        drop(parked);
+        assert_eq!(waiters.blocked_clients(), 1);
+        drop(other);
+        assert_eq!(waiters.blocked_clients(), 0);
+        assert!(waiters.registry.lock().unwrap().by_key.is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const DEFAULT_ERROR_RATE: f64 = 0.01;
+pub const DEFAULT_CAPACITY: u64 = 100;
+// Largest bit array one filter may allocate
+pub const MAX_FILTER_BYTES: u64 = 512 * 1024 * 1024;
+
+// Fixed-size (non-scaling) bloom filter. Bit positions come from SHA-256 rather than the std
+// hasher so a filter loaded from a snapshot hashes identically in every process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    pub capacity: u64,
+    pub error_rate: f64,
+    pub items_inserted: u64,
+}
+
+// Bits needed to hold `capacity` items at `error_rate`; saturates rather than overflowing
+fn num_bits(error_rate: f64, capacity: u64) -> u64 {
+    let ln2 = std::f64::consts::LN_2;
+    ((-(capacity.max(1) as f64) * error_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64
+}
+
+/// The bytes a filter reserved with these parameters allocates, checked before creating one.
+pub fn filter_bytes(error_rate: f64, capacity: u64) -> u64 {
+    num_bits(error_rate, capacity).div_ceil(64).saturating_mul(8)
+}
+
+impl BloomFilter {
+    pub fn new(error_rate: f64, capacity: u64) -> Self {
+        let capacity = capacity.max(1);
+        let num_bits = num_bits(error_rate, capacity);
+        let num_hashes = ((num_bits as f64 / capacity as f64) * std::f64::consts::LN_2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            capacity,
+            error_rate,
+            items_inserted: 0,
+        }
+    }
+
+    fn positions(&self, item: &str) -> impl Iterator<Item = u64> + '_ {
+        let digest = Sha256::digest(item.as_bytes());
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().unwrap_or_default());
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().unwrap_or_default()) | 1;
+
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    /// Returns true if the item was not already (probably) present.
+    pub fn add(&mut self, item: &str) -> bool {
+        let positions: Vec<u64> = self.positions(item).collect();
+        let mut newly_set = false;
+
+        for position in positions {
+            let (word, bit) = ((position / 64) as usize, position % 64);
+            if self.bits[word] & (1 << bit) == 0 {
+                self.bits[word] |= 1 << bit;
+                newly_set = true;
+            }
+        }
+
+        if newly_set {
+            self.items_inserted += 1;
+        }
+        newly_set
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.positions(item).all(|position| self.bits[(position / 64) as usize] & (1 << (position % 64)) != 0)
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<u64>()
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_added_items_are_found() {
+        let mut filter = BloomFilter::new(0.01, 1000);
+        // An add can collide with earlier items, so only most of them report as new
+        let newly_added = (0..1000).filter(|i| filter.add(&format!("item{}", i))).count();
+        assert!(newly_added > 980, "newly added: {}", newly_added);
+        for i in 0..1000 {
+            assert!(filter.contains(&format!("item{}", i)));
+        }
+        assert!(!filter.add("item1"));
+        assert_eq!(filter.items_inserted, newly_added as u64);
+    }
+
+    #[test]
+    fn test_false_positive_rate_is_bounded() {
+        let mut filter = BloomFilter::new(0.01, 1000);
+        for i in 0..1000 {
+            filter.add(&format!("item{}", i));
+        }
+        let false_positives = (0..10000).filter(|i| filter.contains(&format!("other{}", i))).count();
+        assert!(false_positives < 300, "false positives: {}", false_positives);
+    }
+
+    #[test]
+    fn test_filter_size_is_known_before_allocating() {
+        assert_eq!(filter_bytes(0.01, 1000) as usize, BloomFilter::new(0.01, 1000).size_in_bytes());
+        assert!(filter_bytes(0.01, 100_000_000_000_000) > MAX_FILTER_BYTES);
+        assert!(filter_bytes(1e-300, u64::MAX) > MAX_FILTER_BYTES);
+    }
+}
@@ -0,0 +1,217 @@
+// Records every command line clients send, so traffic can be replayed against another server
+// to reproduce an incident or benchmark with a realistic mix. The file holds one JSON record per
+// line: milliseconds since capture started, the connection it came from, and the line itself.
+use crate::protocol::{quote_arg, split_inline};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::sleep_until;
+
+// Stands in for passwords and session tokens, which are never written to the capture
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub at_ms: u64,
+    pub client: u64,
+    pub line: String,
+}
+
+#[derive(Debug)]
+pub struct Capture {
+    file: Mutex<LineWriter<File>>,
+    started: Instant,
+    next_client: AtomicU64,
+}
+
+/// One connection's view of the capture.
+#[derive(Debug, Clone)]
+pub struct ClientCapture {
+    capture: Arc<Capture>,
+    client: u64,
+}
+
+impl Capture {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+        Ok(Self { file: Mutex::new(LineWriter::new(file)), started: Instant::now(), next_client: AtomicU64::new(1) })
+    }
+
+    pub fn client(self: &Arc<Self>) -> ClientCapture {
+        ClientCapture { capture: Arc::clone(self), client: self.next_client.fetch_add(1, Ordering::Relaxed) }
+    }
+}
+
+// Where the credentials are among the arguments of a command line: AUTH's password, the token
+// of SESSION AUTH and SESSION REVOKE, the password in HELLO's and MIGRATE's AUTH options, and
+// the value of CONFIG SET requirepass or masterauth
+fn secret_positions(parts: &[String]) -> Vec<usize> {
+    let is = |index: usize, word: &str| parts.get(index).is_some_and(|part| part.eq_ignore_ascii_case(word));
+    let name = parts.first().map(|name| name.to_uppercase()).unwrap_or_default();
+    let after = |option: &str, offset: usize| -> Vec<usize> {
+        (1..parts.len()).filter(|&index| is(index, option)).map(|index| index + offset).collect()
+    };
+    let positions = match name.as_str() {
+        "AUTH" if parts.len() > 1 => vec![parts.len() - 1],
+        "SESSION" if is(1, "AUTH") || is(1, "REVOKE") => vec![2],
+        "HELLO" => after("AUTH", 2),
+        "MIGRATE" => after("AUTH", 1).into_iter().chain(after("AUTH2", 2)).collect(),
+        "CONFIG" if is(1, "SET") && (is(2, "requirepass") || is(2, "masterauth")) => vec![3],
+        _ => Vec::new(),
+    };
+    positions.into_iter().filter(|&index| index < parts.len()).collect()
+}
+
+fn redact(line: &str) -> String {
+    // Split as the server does, so a quoted password with spaces is hidden whole
+    let parts = split_inline(line).unwrap_or_else(|_| line.split_whitespace().map(str::to_string).collect());
+    let secrets = secret_positions(&parts);
+    if secrets.is_empty() {
+        return line.to_string();
+    }
+    parts.iter().enumerate()
+        .map(|(index, part)| if secrets.contains(&index) { REDACTED.into() } else { quote_arg(part) })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl ClientCapture {
+    pub fn record(&self, line: &str) {
+        let frame = Frame {
+            at_ms: self.capture.started.elapsed().as_millis() as u64,
+            client: self.client,
+            line: redact(line),
+        };
+        let Ok(mut json) = serde_json::to_string(&frame) else { return };
+        json.push('\n');
+        // Losing a frame is better than failing the command it belongs to
+        if let Ok(mut file) = self.capture.file.lock() {
+            if let Err(e) = file.write_all(json.as_bytes()) {
+                eprintln!("Failed to write capture: {}", e);
+            }
+        }
+    }
+}
+
+pub fn read_frames(path: &str) -> Result<Vec<Frame>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let mut frames = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame = serde_json::from_str(&line).map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// When a frame captured `at_ms` into the capture is due, replayed `speed` times faster.
+/// A speed of 0 sends everything at once.
+pub fn due_after(at_ms: u64, speed: f64) -> Duration {
+    if speed <= 0.0 {
+        return Duration::ZERO;
+    }
+    Duration::from_secs_f64(at_ms as f64 / 1000.0 / speed)
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayStats {
+    pub connections: usize,
+    pub frames: usize,
+    pub elapsed: Duration,
+}
+
+/// Re-sends `frames` to `target`, one connection per captured client, each frame at its
+/// original offset divided by `speed`. Redacted AUTH passwords are replaced by `password`.
+pub async fn replay(frames: Vec<Frame>, target: &str, speed: f64, password: Option<&str>) -> Result<ReplayStats, String> {
+    // Offsets count from the first frame, not from when the capture started
+    let first_ms = frames.iter().map(|frame| frame.at_ms).min().unwrap_or(0);
+    let mut by_client: Vec<(u64, Vec<Frame>)> = Vec::new();
+    for frame in frames {
+        match by_client.iter_mut().find(|(client, _)| *client == frame.client) {
+            Some((_, client_frames)) => client_frames.push(frame),
+            None => by_client.push((frame.client, vec![frame])),
+        }
+    }
+
+    let started = tokio::time::Instant::now();
+    let mut stats = ReplayStats { connections: by_client.len(), ..Default::default() };
+    let mut tasks = Vec::new();
+    for (_, client_frames) in by_client {
+        let stream = TcpStream::connect(target).await.map_err(|e| format!("Failed to connect to {}: {}", target, e))?;
+        let password = password.map(str::to_string);
+        stats.frames += client_frames.len();
+        tasks.push(tokio::spawn(async move {
+            let (mut reader, mut writer) = stream.into_split();
+            // Replies are not checked, only drained so the server never blocks on a full socket
+            let drain = tokio::spawn(async move {
+                let mut buffer = [0u8; 8192];
+                while matches!(reader.read(&mut buffer).await, Ok(read) if read > 0) {}
+            });
+            for frame in client_frames {
+                sleep_until(started + due_after(frame.at_ms - first_ms, speed)).await;
+                let line = match &password {
+                    Some(password) => frame.line.replace(REDACTED, password),
+                    None => frame.line,
+                };
+                writer.write_all(format!("{}\r\n", line).as_bytes()).await?;
+            }
+            writer.shutdown().await?;
+            let _ = drain.await;
+            Ok::<(), std::io::Error>(())
+        }));
+    }
+    for task in tasks {
+        task.await.map_err(|e| e.to_string())?.map_err(|e| format!("Replay to {} failed: {}", target, e))?;
+    }
+    stats.elapsed = started.elapsed();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_round_trips_and_redacts_auth() {
+        let path = std::env::temp_dir().join(format!("rust_redis_capture_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let capture = Arc::new(Capture::create(path).unwrap());
+        let (first, second) = (capture.client(), capture.client());
+        first.record("SET a 1");
+        second.record("AUTH app1 secret");
+        second.record("AUTH \"pass word\"");
+        first.record("GET a");
+
+        let frames = read_frames(path).unwrap();
+        let lines: Vec<(u64, &str)> = frames.iter().map(|frame| (frame.client, frame.line.as_str())).collect();
+        assert_eq!(lines, vec![(1, "SET a 1"), (2, "AUTH app1 <redacted>"), (2, "AUTH <redacted>"), (1, "GET a")]);
+        assert!(frames.windows(2).all(|pair| pair[0].at_ms <= pair[1].at_ms));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_session_tokens_and_auth_options_are_redacted() {
+        assert_eq!(redact("SESSION AUTH 3f9c2a"), "SESSION AUTH <redacted>");
+        assert_eq!(redact("session revoke 3f9c2a"), "session revoke <redacted>");
+        assert_eq!(redact("SESSION CREATE 60"), "SESSION CREATE 60");
+        assert_eq!(redact("HELLO 3 AUTH app1 secret SETNAME worker"), "HELLO 3 AUTH app1 <redacted> SETNAME worker");
+        assert_eq!(redact("MIGRATE host 6379 k 0 5000 AUTH2 app1 secret"), "MIGRATE host 6379 k 0 5000 AUTH2 app1 <redacted>");
+        assert_eq!(redact("CONFIG SET requirepass \"new pass\""), "CONFIG SET requirepass <redacted>");
+        assert_eq!(redact("GET auth"), "GET auth");
+    }
+
+    #[test]
+    fn test_replay_speed_scales_offsets() {
+        assert_eq!(due_after(1000, 1.0), Duration::from_secs(1));
+        assert_eq!(due_after(1000, 4.0), Duration::from_millis(250));
+        assert_eq!(due_after(1000, 0.0), Duration::ZERO);
+    }
+}
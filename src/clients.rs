@@ -0,0 +1,214 @@
+// Connections on the TCP port as CLIENT LIST shows them. Each connection registers itself when
+// it is accepted and is removed when its handle is dropped, and says which kind of client it is
+// as it changes modes, so tooling can pick out e.g. the subscribers without guessing from commands.
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientType {
+    #[default]
+    Normal,
+    // Subscribed to at least one channel or pattern
+    PubSub,
+    Monitor,
+    Replica,
+}
+
+impl ClientType {
+    pub fn from_string(kind: &str) -> Option<Self> {
+        match kind.to_lowercase().as_str() {
+            "normal" => Some(ClientType::Normal),
+            "pubsub" => Some(ClientType::PubSub),
+            "monitor" => Some(ClientType::Monitor),
+            "replica" | "slave" => Some(ClientType::Replica),
+            _ => None,
+        }
+    }
+
+    // The flag Redis shows for it in CLIENT LIST
+    fn flag(self) -> char {
+        match self {
+            ClientType::Normal => 'N',
+            ClientType::PubSub => 'P',
+            ClientType::Monitor => 'O',
+            ClientType::Replica => 'S',
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub kind: ClientType,
+    pub connected_at: Instant,
+    pub last_active: Instant,
+    // Lowercased name of the last command, empty before the first
+    pub last_command: String,
+    // Its pub/sub subscriber, for the sub and psub counts
+    pub subscriber: Option<usize>,
+    // Key prefix of the tenant it authenticated as; a tenant only sees its own connections
+    pub tenant: Option<String>,
+}
+
+impl ClientInfo {
+    /// One line of CLIENT LIST, given its channel and pattern subscription counts.
+    pub fn render(&self, subscriptions: (usize, usize), now: Instant) -> String {
+        format!(
+            "id={} addr={} age={} idle={} flags={} sub={} psub={} cmd={}",
+            self.id,
+            self.addr,
+            now.saturating_duration_since(self.connected_at).as_secs(),
+            now.saturating_duration_since(self.last_active).as_secs(),
+            self.kind.flag(),
+            subscriptions.0,
+            subscriptions.1,
+            if self.last_command.is_empty() { "NULL" } else { &self.last_command }
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ClientRegistry {
+    clients: Mutex<BTreeMap<u64, ClientInfo>>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(self: &Arc<Self>, id: u64, addr: SocketAddr) -> ClientHandle {
+        let now = Instant::now();
+        let info = ClientInfo {
+            id,
+            addr,
+            kind: ClientType::Normal,
+            connected_at: now,
+            last_active: now,
+            last_command: String::new(),
+            subscriber: None,
+            tenant: None,
+        };
+        self.lock().insert(id, info);
+        ClientHandle { registry: Arc::clone(self), id }
+    }
+
+    /// Connected clients in connection order, only those of `kind` if given.
+    pub fn list(&self, kind: Option<ClientType>) -> Vec<ClientInfo> {
+        self.lock().values()
+            .filter(|client| kind.is_none_or(|kind| client.kind == kind))
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, ClientInfo>> {
+        // Nothing panics while holding it, so a poisoned lock still guards whole entries
+        self.clients.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn update(&self, id: u64, change: impl FnOnce(&mut ClientInfo)) {
+        if let Some(client) = self.lock().get_mut(&id) {
+            change(client);
+        }
+    }
+}
+
+/// A connection's entry in the registry, removed when this is dropped.
+#[derive(Debug)]
+pub struct ClientHandle {
+    registry: Arc<ClientRegistry>,
+    id: u64,
+}
+
+impl ClientHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn registry(&self) -> &ClientRegistry {
+        &self.registry
+    }
+
+    /// Records a command as the connection's latest activity.
+    pub fn command(&self, name: &str) {
+        self.registry.update(self.id, |client| {
+            client.last_active = Instant::now();
+            client.last_command = name.to_lowercase();
+        });
+    }
+
+    pub fn set_type(&self, kind: ClientType) {
+        self.registry.update(self.id, |client| client.kind = kind);
+    }
+
+    pub fn set_subscriber(&self, subscriber: Option<usize>) {
+        self.registry.update(self.id, |client| client.subscriber = subscriber);
+    }
+
+    pub fn set_tenant(&self, tenant: Option<String>) {
+        self.registry.update(self.id, |client| client.tenant = tenant);
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_clients_are_listed_by_type_until_dropped() {
+        let registry = Arc::new(ClientRegistry::new());
+        let first = registry.register(1, addr(5001));
+        let second = registry.register(2, addr(5002));
+        second.set_type(ClientType::PubSub);
+
+        let ids = |kind| registry.list(kind).iter().map(|client| client.id).collect::<Vec<_>>();
+        assert_eq!(ids(None), vec![1, 2]);
+        assert_eq!(ids(Some(ClientType::PubSub)), vec![2]);
+        assert_eq!(ids(Some(ClientType::Normal)), vec![1]);
+        assert!(ids(Some(ClientType::Replica)).is_empty());
+
+        drop(first);
+        assert_eq!(ids(None), vec![2]);
+        second.set_type(ClientType::Normal);
+        assert_eq!(ids(Some(ClientType::Normal)), vec![2]);
+        drop(second);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_render_and_type_names() {
+        let registry = Arc::new(ClientRegistry::new());
+        let client = registry.register(7, addr(6000));
+        let listed = &registry.list(None)[0];
+        assert_eq!(listed.render((0, 0), listed.connected_at), "id=7 addr=127.0.0.1:6000 age=0 idle=0 flags=N sub=0 psub=0 cmd=NULL");
+
+        client.command("SUBSCRIBE");
+        client.set_type(ClientType::PubSub);
+        let listed = &registry.list(None)[0];
+        assert!(listed.render((2, 1), listed.last_active).ends_with("flags=P sub=2 psub=1 cmd=subscribe"));
+
+        assert_eq!(ClientType::from_string("SLAVE"), Some(ClientType::Replica));
+        assert_eq!(ClientType::from_string("master"), None);
+    }
+}
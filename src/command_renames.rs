@@ -0,0 +1,148 @@
+// Startup-time renaming and aliasing of commands, applied to each request line before it is
+// parsed. Renaming hides the original name (an empty new name disables the command outright);
+// an alias adds a second name and keeps the original.
+use crate::commands::Command;
+use crate::protocol::{parse_args, parse_command, split_inline};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandRenames {
+    // Upper-case name a client sends -> upper-case command it runs
+    names: HashMap<String, String>,
+    // Upper-case original names clients can no longer use
+    hidden: HashSet<String>,
+}
+
+// A bare command name parses unless the parser does not know it at all
+fn is_known_command(name: &str) -> bool {
+    match parse_command(name) {
+        Ok(_) => true,
+        Err(error) => !error.starts_with("ERR unknown command"),
+    }
+}
+
+impl CommandRenames {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.hidden.is_empty()
+    }
+
+    fn check_new_name(&self, new_name: &str) -> Result<(), String> {
+        if new_name.split_whitespace().count() != 1 {
+            return Err(format!("invalid command name '{}'", new_name));
+        }
+        if self.names.contains_key(new_name) || (is_known_command(new_name) && !self.hidden.contains(new_name)) {
+            return Err(format!("command name '{}' is already in use", new_name));
+        }
+        Ok(())
+    }
+
+    fn check_command(&self, command: &str) -> Result<(), String> {
+        if !is_known_command(command) {
+            return Err(format!("unknown command '{}'", command));
+        }
+        if self.hidden.contains(command) {
+            return Err(format!("command '{}' is already renamed", command));
+        }
+        Ok(())
+    }
+
+    /// Makes `command` available only as `new_name`, or not at all if `new_name` is empty.
+    pub fn rename(&mut self, command: &str, new_name: &str) -> Result<(), String> {
+        let command = command.to_uppercase();
+        let new_name = new_name.to_uppercase();
+        self.check_command(&command)?;
+        if !new_name.is_empty() {
+            self.check_new_name(&new_name)?;
+            self.names.insert(new_name, command.clone());
+        }
+        self.hidden.insert(command);
+        Ok(())
+    }
+
+    /// Makes `command` also available as `alias`.
+    pub fn alias(&mut self, alias: &str, command: &str) -> Result<(), String> {
+        let alias = alias.to_uppercase();
+        let command = command.to_uppercase();
+        self.check_command(&command)?;
+        self.check_new_name(&alias)?;
+        self.names.insert(alias, command);
+        Ok(())
+    }
+
+    // The command a name runs: None when it is not renamed, an error when it is hidden
+    fn resolve_name(&self, name: &str) -> Result<Option<&String>, String> {
+        let upper = name.to_uppercase();
+        if let Some(command) = self.names.get(&upper) {
+            return Ok(Some(command));
+        }
+        if self.hidden.contains(&upper) {
+            return Err(format!("ERR unknown command '{}'", upper));
+        }
+        Ok(None)
+    }
+
+    /// Rewrites the command name at the start of `line` to the command it runs.
+    pub fn resolve<'a>(&self, line: &'a str) -> Result<Cow<'a, str>, String> {
+        let name = line.split_whitespace().next().unwrap_or_default();
+        match self.resolve_name(name)? {
+            Some(command) => {
+                let rest = &line.trim_start()[name.len()..];
+                Ok(Cow::Owned(format!("{}{}", command, rest)))
+            },
+            None => Ok(Cow::Borrowed(line)),
+        }
+    }
+
+    /// Parses `line` as the command its name resolves to. The line is split first, so a
+    /// quoted name cannot get past a rename.
+    pub fn parse(&self, line: &str) -> Result<Command, String> {
+        self.parse_args(&split_inline(line)?)
+    }
+
+    /// Parses arguments received as a RESP array, resolving the command name in the first.
+    pub fn parse_args(&self, args: &[String]) -> Result<Command, String> {
+        match args.split_first() {
+            Some((name, rest)) => match self.resolve_name(name)? {
+                Some(command) => parse_args(&[std::slice::from_ref(command), rest].concat()),
+                None => parse_args(args),
+            },
+            None => parse_args(args),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_hides_original() {
+        let mut renames = CommandRenames::default();
+        renames.rename("flushall", "").unwrap();
+        renames.rename("KEYS", "listkeys").unwrap();
+        renames.alias("LEN", "DBSIZE").unwrap();
+
+        assert!(renames.resolve("FLUSHALL").is_err());
+        assert_eq!(renames.resolve("listkeys user:*").unwrap(), "KEYS user:*");
+        assert!(renames.resolve("KEYS *").is_err());
+        assert_eq!(renames.resolve("len").unwrap(), "DBSIZE");
+        assert_eq!(renames.resolve("DBSIZE").unwrap(), "DBSIZE");
+        assert_eq!(renames.resolve("GET a").unwrap(), "GET a");
+        assert!(renames.parse("\"flushall\"").is_err());
+    }
+
+    #[test]
+    fn test_invalid_renames_are_rejected() {
+        let mut renames = CommandRenames::default();
+        assert!(renames.rename("NOSUCHCOMMAND", "X").is_err());
+        assert!(renames.rename("GET", "SET").is_err());
+        assert!(renames.alias("two words", "GET").is_err());
+        renames.rename("GET", "FETCH").unwrap();
+        assert!(renames.rename("GET", "READ").is_err());
+        assert!(renames.alias("FETCH", "SET").is_err());
+        // A hidden name is free to be reused
+        renames.rename("SET", "GET").unwrap();
+        assert_eq!(renames.resolve("get k v").unwrap(), "SET k v");
+    }
+}
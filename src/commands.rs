@@ -1,11 +1,35 @@
-use crate::data_types::RedisValue;
-use crate::database::{Database, RedisDatabase};
-use crate::auth::ClientAuth;
-use crate::persistence_clean::MmapPersistence;
-use crate::pub_sub::PubSubManager;
+use crate::data_types::{DelayQueue, RedisValue};
+use crate::aggregate::{Aggregator, Reducer, AGGREGATE_BATCH};
+use crate::delpattern;
+use crate::jobs::JobKind;
+use crate::bloom::{filter_bytes, BloomFilter};
+use crate::timeseries::{Aggregation, TimeSeries};
+use crate::json_path::{self, PathSegment};
+#[cfg(feature = "search")]
+use crate::search::{FieldKind, Predicate, SearchIndex};
+use crate::vector::{DistanceMetric, VectorSet};
+use crate::database::RedisDatabase;
+use crate::shared::Database;
+use crate::auth::{ClientAuth, DEFAULT_SESSION_TTL};
+use crate::tenancy;
+use crate::invalidation::glob_regex;
+use crate::persistence_bench;
+use crate::persistence_clean::{MmapPersistence, Snapshot};
+use crate::pub_sub::{PubSubManager, PubSubState, RetentionPolicy};
+use crate::clients::ClientType;
+use crate::metrics::Metrics;
+use crate::protocol::Reply;
+use crate::migration::{MergeOutcome, MigrationProgress, Throttle, MIGRATION_BATCH};
+use crate::rng::CommandRng;
+use crate::ttl_index::TTL_BUCKETS;
+use crate::string_ops::{self, escape_bytes, BitOp};
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::time::Duration;
-use clap::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DELAYQ_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Redis's reply when INCR, DECR or HINCRBY would leave the 64-bit range
+const OVERFLOW_ERROR: &str = "ERR increment or decrement would overflow";
 
 #[derive(Debug, Clone)]
 pub enum MergeStrategy {
@@ -14,30 +38,118 @@ pub enum MergeStrategy {
     Merge,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    Always,
+    IfNotExists,
+    IfExists,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCondition {
+    Always,
+    IfNoTtl,
+    IfHasTtl,
+    IfGreater,
+    IfLess,
+}
+
+// Which end of a list LMOVE pops from or pushes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
 #[derive(Debug, Clone)]
 pub enum Command {
     // String commands
     Get { key: String },
-    Set { key: String, value: String },
-    SetEx { key: String, value: String, seconds: u64 },
+    GetWithMeta { key: String },
+    // Without an expiry the key's TTL is dropped unless keep_ttl; with get the reply is the old value
+    Set { key: String, value: String, expiry: Option<Duration>, condition: SetCondition, keep_ttl: bool, get: bool },
+    SetEx { key: String, value: String, ttl: Duration },
+    SetNx { key: String, value: String },
+    DelIfEq { key: String, value: String },
+    Cas { key: String, expected: String, value: String, expiry: Option<Duration> },
     Del { keys: Vec<String> },
     Exists { keys: Vec<String> },
+    Invalidate { keys: Vec<String> },
     Incr { key: String },
     Decr { key: String },
+    IncrBy { key: String, increment: i64 },
+    DecrBy { key: String, decrement: i64 },
+    IncrByFloat { key: String, increment: f64 },
+    RateLimit { key: String, max: u64, window_secs: u64 },
     Append { key: String, value: String },
     Strlen { key: String },
     GetRange { key: String, start: i32, end: i32 },
+    Lcs { key1: String, key2: String, len: bool, idx: bool, min_match_len: usize, with_match_len: bool },
+
+    // Bitmap commands, on the bytes of string values
+    SetBit { key: String, offset: u64, on: bool },
+    GetBit { key: String, offset: u64 },
+    // A range counts bytes, or bits with `bits`
+    BitCount { key: String, range: Option<(i64, i64)>, bits: bool },
+    BitOp { op: BitOp, destkey: String, keys: Vec<String> },
 
     // List commands
     LPush { key: String, values: Vec<String> },
     RPush { key: String, values: Vec<String> },
-    LPop { key: String },
-    RPop { key: String },
+    // With a count, replies with an array of up to count elements
+    LPop { key: String, count: Option<usize> },
+    RPop { key: String, count: Option<usize> },
+    // LMOVE, and RPOPLPUSH as RIGHT LEFT
+    LMove { source: String, destination: String, from: ListEnd, to: ListEnd },
+    // BLPOP and BRPOP: pops from the first non-empty list, waiting up to timeout (zero waits for good)
+    BPop { keys: Vec<String>, end: ListEnd, timeout: Duration },
+    // BLMOVE, and BRPOPLPUSH as RIGHT LEFT
+    BLMove { source: String, destination: String, from: ListEnd, to: ListEnd, timeout: Duration },
     LLen { key: String },
     LRange { key: String, start: i32, stop: i32 },
     LIndex { key: String, index: i32 },
     LSet { key: String, index: i32, value: String },
 
+    // Delay queue commands
+    DelayQPush { key: String, delay_ms: u64, members: Vec<String> },
+    DelayQPop { key: String, count: Option<usize> },
+    DelayQBPop { key: String, timeout: Duration },
+    DelayQLen { key: String },
+
+    // Bloom filter commands
+    BfReserve { key: String, error_rate: f64, capacity: u64 },
+    BfAdd { key: String, items: Vec<String>, multi: bool },
+    BfExists { key: String, items: Vec<String>, multi: bool },
+    BfInfo { key: String },
+
+    // Time series commands
+    TsCreate { key: String, retention_ms: u64 },
+    TsAdd { key: String, timestamp: Option<i64>, value: f64, retention_ms: Option<u64> },
+    TsGet { key: String },
+    TsRange { key: String, from: i64, to: i64, aggregation: Option<(Aggregation, i64)> },
+    TsCreateRule { source: String, dest: String, aggregation: Aggregation, bucket_ms: i64 },
+
+    // JSON document commands
+    JsonSet { key: String, path: Vec<PathSegment>, value: serde_json::Value, condition: SetCondition },
+    JsonGet { key: String, path: Vec<PathSegment> },
+    JsonDel { key: String, path: Vec<PathSegment> },
+    JsonNumIncrBy { key: String, path: Vec<PathSegment>, increment: serde_json::Number },
+
+    // Secondary index commands
+    #[cfg(feature = "search")]
+    FtCreate { index: String, prefixes: Vec<String>, fields: Vec<(String, FieldKind)> },
+    #[cfg(feature = "search")]
+    FtSearch { index: String, predicates: Vec<Predicate> },
+    #[cfg(feature = "search")]
+    FtDropIndex { index: String },
+    #[cfg(feature = "search")]
+    FtInfo { index: String },
+
+    // Vector commands
+    VectorAdd { key: String, element: String, vector: Vec<f32> },
+    VectorSearch { key: String, k: usize, metric: DistanceMetric, vector: Vec<f32> },
+    VectorRem { key: String, element: String },
+
     // Set commands
     SAdd { key: String, members: Vec<String> },
     SRem { key: String, members: Vec<String> },
@@ -58,177 +170,421 @@ pub enum Command {
     HLen { key: String },
     HExists { key: String, field: String },
     HIncrBy { key: String, field: String, increment: i64 },
+    HRandField { key: String, count: Option<i64>, with_values: bool },
+    HExpire { key: String, ttl: Duration, condition: ExpireCondition, fields: Vec<String> },
+    HTtl { key: String, fields: Vec<String>, millis: bool },
+    HPersist { key: String, fields: Vec<String> },
 
     // Generic commands
     Keys { pattern: String },
+    // Folds the integer values of the keys matching pattern into one number
+    Aggregate { reducer: Reducer, pattern: String },
+    TagSet { key: String, tags: Vec<String> },
+    TagGet { key: String },
+    TagDel { key: String, tags: Vec<String> },
+    KeysByTag { tag: String },
+    // Starts a background job deleting the keys matching pattern
+    DelPattern { pattern: String, throttle: Throttle },
+    JobList,
+    JobStatus { id: u64 },
+    JobCancel { id: u64 },
     Type { key: String },
-    Expire { key: String, seconds: u64 },
-    Ttl { key: String },
+    Expire { key: String, ttl: Duration },
+    // PEXPIREAT: expires at a unix time in milliseconds
+    ExpireAt { key: String, unix_ms: u64 },
+    Ttl { key: String, millis: bool },
+    TtlMany { keys: Vec<String>, millis: bool },
+    TtlStats,
+    // The keys accessed most over the last `minutes` minutes
+    HotKeys { count: usize, minutes: usize },
     FlushAll,
+    UndoFlush,
+    // Brings back a key DEL moved to the trash
+    RestoreKey { key: String, replace: bool },
     DbSize,
     Persist { key: String },
+    Pin { keys: Vec<String> },
+    Unpin { keys: Vec<String> },
     Rename { key: String, newkey: String },
+    Copy { source: String, destination: String, replace: bool },
     RandomKey,
 
+    // Advisory lock commands
+    Lock { key: String, token: String, ttl: Duration },
+    Unlock { key: String, token: String },
+
     // Pub/Sub commands
     Publish { channel: String, message: String },
-    Subscribe { channels: Vec<String> },
+    Subscribe { channels: Vec<String>, replay: Option<usize>, consumer: Option<String> },
     Unsubscribe { channels: Vec<String> },
     PSubscribe { patterns: Vec<String> },
     PUnsubscribe { patterns: Vec<String> },
     PubSubChannels { pattern: Option<String> },
     PubSubNumSub { channels: Vec<String> },
     PubSubNumPat,
+    PubSubRetention { channel: String, policy: Option<RetentionPolicy> },
+    PubSubReliable { channel: String, ack_timeout: Option<Duration> },
+    PubSubPending { channel: Option<String> },
+    PubSubConsumers,
+    PubSubStats { count: usize },
+    Ack { ids: Vec<u64> },
 
     // Connection commands
     Ping { message: Option<String> },
     Echo { message: String },
-    Auth { password: String },
+    Auth { username: Option<String>, password: String },
+    SessionCreate { ttl: Option<u64> },
+    SessionAuth { token: String },
+    SessionRevoke { token: String },
+    Hello { protover: Option<u8> },
+    ReadOnly,
+    ReadWrite,
     Info,
+    StatHistory { count: usize },
+    StatSizes { command: Option<String> },
     Memory,
+    MemoryStats,
+    DbStatsByPrefix,
+    ExpiredRead { after: u64, count: usize },
+    ExpiredInfo,
+    ObjectEncoding { key: String },
+    SnapshotBegin,
+    SnapshotEnd,
+    // Runs the next `count` commands on the connection under one write lock
+    Atomic { count: usize },
+    ClientList { kind: Option<ClientType> },
+    ClientId,
     ShowAll,
-    Merge { file_path: String, strategy: MergeStrategy },
+    // BACKGROUND replies with a job id instead of waiting for the merge
+    Merge { file_path: String, strategy: MergeStrategy, throttle: Throttle, background: bool },
+    MigrationStatus,
     VerifyIntegrity,
     RecoverFromBackup,
+    DebugSetRngSeed { seed: Option<u64> },
+    DebugPersistenceBench { ops: usize },
+    DebugPanic,
     Quit,
 }
 
+impl Command {
+    /// Whether the command can change the dataset, so it counts towards the unsaved backlog.
+    /// Locks and pins count too: a snapshot session's frozen copy has neither, so taking one
+    /// there would go unseen by every other client.
+    pub fn is_write(&self) -> bool {
+        matches!(self,
+            Command::Set { .. } | Command::SetEx { .. } | Command::SetNx { .. } | Command::DelIfEq { .. } | Command::Cas { .. } |
+            Command::Del { .. } | Command::Invalidate { .. } | Command::Incr { .. } | Command::Decr { .. } | Command::RateLimit { .. } |
+            Command::IncrBy { .. } | Command::DecrBy { .. } | Command::IncrByFloat { .. } |
+            Command::SetBit { .. } | Command::BitOp { .. } |
+            Command::Append { .. } | Command::LPush { .. } | Command::RPush { .. } | Command::LPop { .. } | Command::LMove { .. } |
+            Command::BPop { .. } | Command::BLMove { .. } |
+            Command::RPop { .. } | Command::LSet { .. } | Command::DelayQPush { .. } | Command::DelayQPop { .. } |
+            Command::DelayQBPop { .. } | Command::BfReserve { .. } | Command::BfAdd { .. } |
+            Command::TsCreate { .. } | Command::TsAdd { .. } | Command::TsCreateRule { .. } |
+            Command::JsonSet { .. } | Command::JsonDel { .. } | Command::JsonNumIncrBy { .. } |
+            Command::VectorAdd { .. } | Command::VectorRem { .. } | Command::SAdd { .. } | Command::SRem { .. } |
+            Command::HSet { .. } | Command::HDel { .. } | Command::HIncrBy { .. } | Command::HExpire { .. } |
+            Command::HPersist { .. } | Command::Expire { .. } | Command::ExpireAt { .. } | Command::FlushAll | Command::UndoFlush | Command::RestoreKey { .. } | Command::Persist { .. } |
+            Command::Rename { .. } | Command::Copy { .. } | Command::Merge { .. } | Command::RecoverFromBackup |
+            Command::TagSet { .. } | Command::TagDel { .. } | Command::DelPattern { .. } |
+            Command::Lock { .. } | Command::Unlock { .. } | Command::Pin { .. } | Command::Unpin { .. })
+    }
+
+    /// Writes refused once used memory is over maxmemory under noeviction. Writes that only
+    /// remove data or change TTLs are still allowed, so clients can free memory.
+    pub fn is_deny_oom(&self) -> bool {
+        self.is_write() && !matches!(self,
+            Command::Del { .. } | Command::Invalidate { .. } | Command::DelIfEq { .. } | Command::LPop { .. } |
+            Command::RPop { .. } | Command::BPop { .. } | Command::DelayQPop { .. } | Command::DelayQBPop { .. } | Command::JsonDel { .. } |
+            Command::VectorRem { .. } | Command::SRem { .. } | Command::HDel { .. } | Command::HExpire { .. } |
+            Command::HPersist { .. } | Command::Expire { .. } | Command::ExpireAt { .. } | Command::Persist { .. } | Command::FlushAll |
+            Command::Rename { .. } | Command::TagDel { .. } | Command::DelPattern { .. } | Command::Unlock { .. } | Command::Unpin { .. })
+    }
+
+    /// Commands that can wait for another client's write before replying.
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, Command::BPop { .. } | Command::BLMove { .. } | Command::DelayQBPop { .. })
+    }
+}
+
 pub async fn execute_command(
     db: Database,
     command: Command,
     client_auth: &mut ClientAuth,
-    pubsub_manager: Option<&PubSubManager>
-) -> String {
+    pubsub_manager: Option<&PubSubManager>,
+    metrics: Option<&Metrics>,
+) -> Reply {
     // Check authentication for all commands except AUTH
-    if let Command::Auth { password } = &command {
-        if client_auth.authenticate(password) {
-            return "OK".to_string();
+    if let Command::Auth { username, password } = &command {
+        return match username {
+            Some(username) if client_auth.authenticate_user(username, password).await => Reply::ok(),
+            Some(_) => Reply::error("WRONGPASS invalid username-password pair"),
+            None if client_auth.authenticate(password).await => Reply::ok(),
+            None => Reply::error("ERR invalid password"),
+        };
+    }
+
+    if let Command::SessionAuth { token } = &command {
+        return if client_auth.authenticate_session(token) {
+            Reply::ok()
         } else {
-            return "(error) ERR invalid password".to_string();
-        }
+            Reply::error("WRONGPASS invalid or expired session token")
+        };
     }
 
     // Check if client is authenticated for other commands
     if client_auth.requires_auth() {
-        return "(error) NOAUTH Authentication required.".to_string();
+        return Reply::error("NOAUTH Authentication required.");
+    }
+
+    // Tenants only ever see their own keys
+    let command = match &client_auth.key_prefix {
+        Some(prefix) => match tenancy::scope_command(command, prefix) {
+            Ok(command) => command,
+            Err(e) => return Reply::error(e),
+        },
+        None => command,
+    };
+
+    if command.is_deny_oom() && db.read().await.memory_manager.is_over_limit() {
+        return Reply::error("OOM command not allowed when used memory > 'maxmemory'.");
     }
 
     match command {
         Command::Get { key } => {
             let mut db_write = db.write().await;
-            match db_write.get(&key) {
-                Some(RedisValue::String(s)) => format!("\"{}\"", s),
-                Some(RedisValue::Integer(i)) => i.to_string(),
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(nil)".to_string(),
+            match db_write.get(&key).map(RedisValue::into_string) {
+                Some(Some(s)) => Reply::bulk(s),
+                Some(None) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Nil,
+            }
+        },
+
+        Command::GetWithMeta { key } => {
+            let mut db_write = db.write().await;
+
+            // Read the previous access before this lookup refreshes it
+            let last_access = db_write.memory_manager.access_times.get(&key).copied();
+            let value = match db_write.get(&key) {
+                Some(value) => value,
+                None => return Reply::Nil,
+            };
+
+            let type_name = value.type_name();
+            let rendered = match value.into_string() {
+                Some(s) => Reply::bulk(s),
+                None => Reply::Nil,
+            };
+            let now = std::time::Instant::now();
+            let pttl = match db_write.expires.get(&key) {
+                Some(expire_time) => expire_time.saturating_duration_since(now).as_millis() as i64,
+                None => -1,
+            };
+            let last_access_ms = match last_access {
+                Some(at) => unix_millis().saturating_sub(now.duration_since(at).as_millis() as u64),
+                None => unix_millis(),
+            };
+
+            Reply::Array(vec![
+                Reply::bulk("value"),
+                rendered,
+                Reply::bulk("type"),
+                Reply::bulk(type_name),
+                Reply::bulk("pttl"),
+                Reply::Integer(pttl),
+                Reply::bulk("last_access_ms"),
+                Reply::integer(last_access_ms),
+            ])
+        },
+
+        Command::Set { key, value, expiry, condition, keep_ttl, get } => {
+            let mut db_write = db.write().await;
+
+            // GET reads the old value first, and refuses anything but a string before writing
+            let reply = if get {
+                match db_write.get(&key).map(RedisValue::into_string) {
+                    Some(Some(s)) => Reply::bulk(s),
+                    Some(None) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => Reply::Nil,
+                }
+            } else {
+                Reply::ok()
+            };
+
+            let exists = db_write.exists(&key);
+            if (condition == SetCondition::IfNotExists && exists) || (condition == SetCondition::IfExists && !exists) {
+                return if get { reply } else { Reply::Nil };
+            }
+
+            let _ = match expiry {
+                Some(ttl) => db_write.set_with_expiry(key, RedisValue::String(value), ttl),
+                None => {
+                    if !keep_ttl {
+                        db_write.expires.remove(&key);
+                    }
+                    db_write.set(key, RedisValue::String(value))
+                },
+            };
+            reply
+        },
+
+        Command::SetNx { key, value } => {
+            let mut db_write = db.write().await;
 
+            if db_write.exists(&key) {
+                Reply::Integer(0)
+            } else {
+                let _ = db_write.set(key, RedisValue::String(value));
+                Reply::Integer(1)
             }
         },
 
-        Command::Set { key, value } => {
+        Command::DelIfEq { key, value } => {
+            let mut db_write = db.write().await;
+
+            let matches = match db_write.get(&key).map(RedisValue::into_string) {
+                Some(Some(s)) => s == value,
+                Some(None) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => false,
+            };
+
+            if matches && db_write.delete(&key) {
+                Reply::Integer(1)
+            } else {
+                Reply::Integer(0)
+            }
+        },
+        Command::Cas { key, expected, value, expiry } => {
             let mut db_write = db.write().await;
-            db_write.set(key, RedisValue::String(value));
-            "OK".to_string()
+
+            let current = match db_write.get(&key).map(RedisValue::into_string) {
+                Some(Some(s)) => Some(s),
+                Some(None) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => None,
+            };
+
+            let swapped = current.as_deref() == Some(expected.as_str());
+            if swapped {
+                let _ = match expiry {
+                    Some(ttl) => db_write.set_with_expiry(key, RedisValue::String(value), ttl),
+                    None => db_write.set(key, RedisValue::String(value)),
+                };
+            }
+            let old = current.map_or(Reply::Nil, Reply::bulk);
+            Reply::Array(vec![Reply::integer(swapped), old])
         },
-        Command::Ping { message} =>{"OK".to_string()}
 
-        Command::SetEx { key, value, seconds } => {
+        Command::Ping { message: _ } => Reply::ok(),
+
+        Command::SetEx { key, value, ttl } => {
             let mut db_write = db.write().await;
-            db_write.set_with_expiry(key, RedisValue::String(value), Duration::from_secs(seconds));
-            "OK".to_string()
+            let _ = db_write.set_with_expiry(key, RedisValue::String(value), ttl);
+            Reply::ok()
         },
 
         Command::Del { keys } => {
             let mut db_write = db.write().await;
-            let mut count = 0;
-            for key in keys {
-                if db_write.delete(&key) {
-                    count += 1;
-                }
+            if let Some(error) = too_many_keys(&db_write, keys.len()) {
+                return error;
             }
-            format!("(integer) {}", count)
+            Reply::integer(db_write.trash_many(&keys))
+        },
+
+        // Sent by peers; deleting here must not be announced again
+        Command::Invalidate { keys } => {
+            let mut db_write = db.write().await;
+            Reply::integer(db_write.drop_invalidated(&keys))
         },
 
         Command::Exists { keys } => {
             let mut db_write = db.write().await;
-            let mut count = 0;
-            for key in keys {
-                if db_write.exists(&key) {
-                    count += 1;
-                }
+            if let Some(error) = too_many_keys(&db_write, keys.len()) {
+                return error;
             }
-            format!("(integer) {}", count)
+            Reply::integer(db_write.exists_many(&keys))
         },
 
 
 
         Command::Incr { key } => {
             let mut db_write = db.write().await;
+            increment_key(&mut db_write, key, 1)
+        },
 
-            match db_write.get(&key) {
-                Some(RedisValue::Integer(i)) => {
-                    let new_val = i + 1;
-                    db_write.set(key, RedisValue::Integer(new_val));
-                    format!("(integer) {}", new_val)
-                },
-                Some(RedisValue::String(s)) => {
-                    if let Ok(i) = s.parse::<i64>() {
-                        let new_val = i + 1;
-                        db_write.set(key, RedisValue::Integer(new_val));
-                        format!("(integer) {}", new_val)
-                    } else {
-                        "(error) ERR value is not an integer or out of range".to_string()
-                    }
-                },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => {
-                    db_write.set(key, RedisValue::Integer(1));
-                    "(integer) 1".to_string()
-                }
+        Command::Decr { key } => {
+            let mut db_write = db.write().await;
+            increment_key(&mut db_write, key, -1)
+        },
+
+        Command::IncrBy { key, increment } => {
+            let mut db_write = db.write().await;
+            increment_key(&mut db_write, key, increment)
+        },
+
+        Command::DecrBy { key, decrement } => {
+            let mut db_write = db.write().await;
+            match decrement.checked_neg() {
+                Some(delta) => increment_key(&mut db_write, key, delta),
+                None => Reply::error(OVERFLOW_ERROR),
             }
         },
 
-        Command::Decr { key } => {
+        Command::IncrByFloat { key, increment } => {
             let mut db_write = db.write().await;
+            increment_key_by_float(&mut db_write, key, increment)
+        },
 
-            match db_write.get(&key) {
-                Some(RedisValue::Integer(i)) => {
-                    let new_val = i - 1;
-                    db_write.set(key, RedisValue::Integer(new_val));
-                    format!("(integer) {}", new_val)
-                },
-                Some(RedisValue::String(s)) => {
-                    if let Ok(i) = s.parse::<i64>() {
-                        let new_val = i - 1;
-                        db_write.set(key, RedisValue::Integer(new_val));
-                        format!("(integer) {}", new_val)
-                    } else {
-                        "(error) ERR value is not an integer or out of range".to_string()
-                    }
+        Command::RateLimit { key, max, window_secs } => {
+            let mut db_write = db.write().await;
+
+            let current = match db_write.get(&key) {
+                Some(RedisValue::Integer(i)) => i.max(0) as u64,
+                Some(RedisValue::String(s)) => match s.parse::<u64>() {
+                    Ok(i) => i,
+                    Err(_) => return Reply::error("ERR value is not an integer or out of range"),
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => {
-                    db_write.set(key, RedisValue::Integer(-1));
-                    "(integer) -1".to_string()
-                }
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => 0,
+            };
+
+            let window = Duration::from_secs(window_secs);
+            let allowed = current < max;
+            let count = if allowed { current + 1 } else { current };
+
+            if current == 0 {
+                let _ = db_write.set_with_expiry(key.clone(), RedisValue::Integer(count as i64), window);
+            } else if allowed {
+                // Plain set keeps the window's existing expiry
+                let _ = db_write.set(key.clone(), RedisValue::Integer(count as i64));
+            }
+            if !db_write.expires.contains_key(&key) {
+                db_write.expire(&key, window);
             }
+
+            let reset_secs = match db_write.ttl(&key) {
+                Some(remaining) if remaining != Duration::MAX => remaining.as_millis().div_ceil(1000) as u64,
+                _ => window_secs,
+            };
+
+            Reply::Array(vec![Reply::integer(allowed), Reply::integer(max.saturating_sub(count)), Reply::integer(reset_secs)])
         },
 
         Command::Append { key, value } => {
             let mut db_write = db.write().await;
 
-            match db_write.get(&key) {
-                Some(RedisValue::String(s)) => {
-                    let new_val = format!("{}{}", s, value);
-                    let new_len = new_val.len();
-                    db_write.set(key, RedisValue::String(new_val));
-                    format!("(integer) {}", new_len)
+            match db_write.get(&key).map(RedisValue::into_bytes) {
+                Some(Some(mut bytes)) => {
+                    // An integer becomes a plain string once digits are no longer all it holds
+                    bytes.extend_from_slice(value.as_bytes());
+                    let new_len = bytes.len();
+                    let _ = db_write.set(key, RedisValue::Bytes(bytes));
+                    Reply::integer(new_len)
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                Some(None) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
                 None => {
                     let len = value.len();
-                    db_write.set(key, RedisValue::String(value));
-                    format!("(integer) {}", len)
+                    let _ = db_write.set(key, RedisValue::String(value));
+                    Reply::integer(len)
                 }
             }
         },
@@ -236,31 +592,139 @@ pub async fn execute_command(
         Command::Strlen { key } => {
             let mut db_write = db.write().await;
 
-            match db_write.get(&key) {
-                Some(RedisValue::String(s)) => format!("(integer) {}", s.len()),
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(integer) 0".to_string(),
+            match db_write.get(&key).map(RedisValue::into_bytes) {
+                Some(Some(bytes)) => Reply::integer(bytes.len()),
+                Some(None) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
             }
         },
 
         Command::GetRange { key, start, end } => {
             let mut db_write = db.write().await;
 
-            match db_write.get(&key) {
-                Some(RedisValue::String(s)) => {
-                    let len = s.len() as i32;
+            match db_write.get(&key).map(RedisValue::into_bytes) {
+                Some(Some(bytes)) => {
+                    let len = bytes.len() as i32;
                     let start_idx = if start < 0 { (len + start).max(0) } else { start.min(len) } as usize;
                     let end_idx = if end < 0 { (len + end + 1).max(0) } else { (end + 1).min(len) } as usize;
 
-                    if start_idx >= end_idx || start_idx >= s.len() {
-                        "\"\"".to_string()
+                    if start_idx >= end_idx || start_idx >= bytes.len() {
+                        Reply::bulk("")
                     } else {
-                        format!("\"{}\"", &s[start_idx..end_idx.min(s.len())])
+                        // A range may split a multi-byte character, which is then shown escaped
+                        let range = &bytes[start_idx..end_idx.min(bytes.len())];
+                        match std::str::from_utf8(range) {
+                            Ok(s) => Reply::bulk(s),
+                            Err(_) => Reply::bulk(escape_bytes(range)),
+                        }
                     }
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "\"\"".to_string(),
+                Some(None) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::bulk(""),
+            }
+        },
+
+        Command::Lcs { key1, key2, len, idx, min_match_len, with_match_len } => {
+            let mut db_write = db.write().await;
+
+            let mut values = Vec::with_capacity(2);
+            for key in [&key1, &key2] {
+                match db_write.get(key).map(RedisValue::into_bytes) {
+                    Some(Some(bytes)) => values.push(bytes),
+                    Some(None) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => values.push(Vec::new()),
+                }
+            }
+
+            let result = match crate::string_ops::lcs(&values[0], &values[1], min_match_len) {
+                Ok(result) => result,
+                Err(e) => return Reply::error(e),
+            };
+
+            if len {
+                return Reply::integer(result.subsequence.len());
+            }
+            if !idx {
+                return Reply::bulk(String::from_utf8_lossy(&result.subsequence));
+            }
+
+            // Each match is [[a_start, a_end], [b_start, b_end]], plus its length with WITHMATCHLEN
+            let matches = result.matches.iter().map(|m| {
+                let mut entry = vec![
+                    Reply::Array(vec![Reply::integer(m.a_range.0), Reply::integer(m.a_range.1)]),
+                    Reply::Array(vec![Reply::integer(m.b_range.0), Reply::integer(m.b_range.1)]),
+                ];
+                if with_match_len {
+                    entry.push(Reply::integer(m.len));
+                }
+                Reply::Array(entry)
+            }).collect();
+            Reply::Array(vec![Reply::bulk("matches"), Reply::Array(matches), Reply::bulk("len"), Reply::integer(result.subsequence.len())])
+        },
+
+        Command::SetBit { key, offset, on } => {
+            let mut db_write = db.write().await;
+
+            let old = match db_write.get_mut(&key).map(RedisValue::as_bytes_mut) {
+                Some(Some(bytes)) => string_ops::set_bit(bytes, offset, on),
+                Some(None) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => {
+                    let mut bytes = Vec::new();
+                    let old = string_ops::set_bit(&mut bytes, offset, on);
+                    let _ = db_write.set(key.clone(), RedisValue::Bytes(bytes));
+                    old
+                },
+            };
+            Reply::integer(old)
+        },
+
+        Command::GetBit { key, offset } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get_ref(&key) {
+                Some(RedisValue::Bytes(bytes)) => Reply::integer(string_ops::get_bit(bytes, offset)),
+                Some(RedisValue::String(s)) => Reply::integer(string_ops::get_bit(s.as_bytes(), offset)),
+                Some(RedisValue::Integer(i)) => Reply::integer(string_ops::get_bit(i.to_string().as_bytes(), offset)),
+                Some(RedisValue::CompressedString(s)) => Reply::integer(string_ops::get_bit(s.decompress().as_bytes(), offset)),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
+            }
+        },
+
+        Command::BitCount { key, range, bits } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get(&key).map(RedisValue::into_bytes) {
+                Some(Some(bytes)) => Reply::integer(string_ops::bit_count(&bytes, range, bits)),
+                Some(None) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
+            }
+        },
+
+        Command::BitOp { op, destkey, keys } => {
+            let mut db_write = db.write().await;
+            if let Some(error) = too_many_keys(&db_write, keys.len() + 1) {
+                return error;
+            }
+
+            // Missing keys count as empty strings
+            let mut sources = Vec::with_capacity(keys.len());
+            for key in &keys {
+                match db_write.get(key).map(RedisValue::into_bytes) {
+                    Some(Some(bytes)) => sources.push(bytes),
+                    Some(None) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => sources.push(Vec::new()),
+                }
             }
+            let result = string_ops::bit_op(op, &sources);
+            let len = result.len();
+            if result.is_empty() {
+                db_write.delete(&destkey);
+            } else {
+                db_write.expires.remove(&destkey);
+                let _ = db_write.set(destkey, RedisValue::Bytes(result));
+            }
+            Reply::integer(len)
         },
 
         Command::LPush { key, values } => {
@@ -268,7 +732,7 @@ pub async fn execute_command(
 
             let mut list = match db_write.get(&key) {
                 Some(RedisValue::List(existing_list)) => existing_list.clone(),
-                Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
                 None => VecDeque::new(),
             };
 
@@ -277,8 +741,8 @@ pub async fn execute_command(
             }
 
             let list_len = list.len();
-            db_write.set(key, RedisValue::List(list));
-            format!("(integer) {}", list_len)
+            let _ = db_write.set(key, RedisValue::List(list));
+            Reply::integer(list_len)
         },
 
         Command::RPush { key, values } => {
@@ -286,7 +750,7 @@ pub async fn execute_command(
 
             let mut list = match db_write.get(&key) {
                 Some(RedisValue::List(existing_list)) => existing_list.clone(),
-                Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
                 None => VecDeque::new(),
             };
 
@@ -295,59 +759,66 @@ pub async fn execute_command(
             }
 
             let list_len = list.len();
-            db_write.set(key, RedisValue::List(list));
-            format!("(integer) {}", list_len)
+            let _ = db_write.set(key, RedisValue::List(list));
+            Reply::integer(list_len)
         },
 
-        Command::LPop { key } => {
+        Command::LPop { key, count } => {
             let mut db_write = db.write().await;
+            pop_reply(&mut db_write, &key, ListEnd::Left, count)
+        },
 
-            match db_write.get(&key) {
-                Some(RedisValue::List(mut list)) => {
-                    if let Some(value) = list.pop_front() {
-                        if list.is_empty() {
-                            db_write.delete(&key);
-                        } else {
-                            db_write.set(key, RedisValue::List(list));
-                        }
-                        format!("\"{}\"", value)
-                    } else {
-                        "(nil)".to_string()
-                    }
-                },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(nil)".to_string(),
+        Command::LMove { source, destination, from, to } => {
+            let mut db_write = db.write().await;
+
+            match move_list_element(&mut db_write, source, destination, from, to) {
+                Ok(Some(value)) => Reply::bulk(value),
+                Ok(None) => Reply::Nil,
+                Err(e) => e,
             }
         },
 
-        Command::RPop { key } => {
+        Command::RPop { key, count } => {
             let mut db_write = db.write().await;
+            pop_reply(&mut db_write, &key, ListEnd::Right, count)
+        },
 
-            match db_write.get(&key) {
-                Some(RedisValue::List(mut list)) => {
-                    if let Some(value) = list.pop_back() {
-                        if list.is_empty() {
-                            db_write.delete(&key);
-                        } else {
-                            db_write.set(key, RedisValue::List(list));
-                        }
-                        format!("\"{}\"", value)
-                    } else {
-                        "(nil)".to_string()
+        Command::BPop { keys, end, timeout } => {
+            let waiting = keys.clone();
+            let prefix = client_auth.key_prefix.as_deref();
+            block_on_lists(&db, &waiting, timeout, |db_write| {
+                for key in &keys {
+                    match pop_list(db_write, key, end, 1).map(|popped| popped.and_then(|mut values| values.pop())) {
+                        Ok(Some(value)) => {
+                            // The key as a tenant named it
+                            let key = prefix.and_then(|prefix| tenancy::unscope(prefix, key)).unwrap_or(key);
+                            return Some(Reply::bulks([key.to_string(), value]));
+                        },
+                        Ok(None) => {},
+                        Err(e) => return Some(e),
                     }
-                },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(nil)".to_string(),
-            }
+                }
+                None
+            }).await
+        },
+
+        Command::BLMove { source, destination, from, to, timeout } => {
+            block_on_lists(&db, std::slice::from_ref(&source), timeout, |db_write| {
+                match move_list_element(db_write, source.clone(), destination.clone(), from, to) {
+                    Ok(Some(value)) => Some(Reply::bulk(value)),
+                    Ok(None) => None,
+                    Err(e) => Some(e),
+                }
+            }).await
         },
 
         Command::LLen { key } => {
             let mut db_write = db.write().await;
 
             match db_write.get(&key) {
-                Some(RedisValue::List(list)) => format!("(integer) {}", list.len()),
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(integer) 0".to_string(),
+                Some(RedisValue::List(list)) => Reply::integer(list.len()),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
             }
         },
 
@@ -361,24 +832,17 @@ pub async fn execute_command(
                     let stop_idx = if stop < 0 { (len + stop).max(-1) } else { stop.min(len - 1) } as usize;
 
                     if start_idx > stop_idx || start_idx >= list.len() {
-                        return "(empty array)".to_string();
+                        return Reply::empty();
                     }
-
-                    let result: Vec<String> = list.iter()
-                        .skip(start_idx)
-                        .take(stop_idx - start_idx + 1)
-                        .enumerate()
-                        .map(|(i, item)| format!("{}) \"{}\"", i + 1, item))
-                        .collect();
-
-                    if result.is_empty() {
-                        "(empty array)".to_string()
-                    } else {
-                        result.join("\n")
+                    let selected = list.iter().skip(start_idx).take(stop_idx - start_idx + 1);
+                    if let Some(error) = reply_too_large(&db_write, elements_size(selected)) {
+                        return error;
                     }
+
+                    Reply::bulks(list.iter().skip(start_idx).take(stop_idx - start_idx + 1).cloned())
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(empty array)".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::empty(),
             }
         },
 
@@ -388,16 +852,16 @@ pub async fn execute_command(
             match db_write.get(&key) {
                 Some(RedisValue::List(list)) => {
                     let len = list.len() as i32;
-                    let idx = if index < 0 { (len + index) } else { index };
+                    let idx = if index < 0 { len + index } else { index };
 
                     if idx < 0 || idx >= len {
-                        "(nil)".to_string()
+                        Reply::Nil
                     } else {
-                        format!("\"{}\"", list[idx as usize])
+                        Reply::bulk(&list[idx as usize])
                     }
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(nil)".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Nil,
             }
         },
 
@@ -407,179 +871,604 @@ pub async fn execute_command(
             match db_write.get(&key) {
                 Some(RedisValue::List(mut list)) => {
                     let len = list.len() as i32;
-                    let idx = if index < 0 { (len + index) } else { index };
+                    let idx = if index < 0 { len + index } else { index };
 
                     if idx < 0 || idx >= len {
-                        "(error) ERR index out of range".to_string()
+                        Reply::error("ERR index out of range")
                     } else {
                         list[idx as usize] = value;
-                        db_write.set(key, RedisValue::List(list));
-                        "OK".to_string()
+                        let _ = db_write.set(key, RedisValue::List(list));
+                        Reply::ok()
                     }
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(error) ERR no such key".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::error("ERR no such key"),
             }
         },
 
-        Command::SAdd { key, members } => {
+        Command::DelayQPush { key, delay_ms, members } => {
+            let due_ms = match unix_millis().checked_add(delay_ms) {
+                Some(due_ms) => due_ms,
+                None => return Reply::error("ERR delay is out of range"),
+            };
             let mut db_write = db.write().await;
 
-            let mut set = match db_write.get(&key) {
-                Some(RedisValue::Set(existing_set)) => existing_set.clone(),
-                Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => HashSet::new(),
+            let mut queue = match db_write.get(&key) {
+                Some(RedisValue::DelayQueue(existing_queue)) => existing_queue,
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => DelayQueue::default(),
             };
 
-            let mut added = 0;
             for member in members {
-                if set.insert(member) {
-                    added += 1;
-                }
+                queue.push(due_ms, member);
             }
 
-            db_write.set(key, RedisValue::Set(set));
-            format!("(integer) {}", added)
+            let queue_len = queue.len();
+            let _ = db_write.set(key, RedisValue::DelayQueue(queue));
+            Reply::integer(queue_len)
         },
 
-        Command::SRem { key, members } => {
+        Command::DelayQPop { key, count } => {
             let mut db_write = db.write().await;
 
-            match db_write.get(&key) {
-                Some(RedisValue::Set(mut set)) => {
-                    let mut removed = 0;
-                    for member in members {
-                        if set.remove(&member) {
-                            removed += 1;
-                        }
-                    }
+            let popped = match pop_ready_delayed(&mut db_write, &key, count.unwrap_or(1)) {
+                Ok(popped) => popped,
+                Err(e) => return e,
+            };
 
-                    if set.is_empty() {
-                        db_write.delete(&key);
-                    } else {
-                        db_write.set(key, RedisValue::Set(set));
-                    }
-                    format!("(integer) {}", removed)
-                },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(integer) 0".to_string(),
+            match count {
+                None => popped.into_iter().next().map_or(Reply::Nil, Reply::Bulk),
+                Some(_) => Reply::bulks(popped),
             }
         },
 
-        Command::SMembers { key } => {
-            let mut db_write = db.write().await;
-
-            match db_write.get(&key) {
-                Some(RedisValue::Set(set)) => {
-                    if set.is_empty() {
-                        return "(empty set)".to_string();
+        Command::DelayQBPop { key, timeout } => {
+            // A zero timeout blocks until an item is ready, like BLPOP
+            let deadline = if timeout.is_zero() { None } else { Some(tokio::time::Instant::now() + timeout) };
+
+            loop {
+                let next_due = {
+                    let mut db_write = db.write().await;
+                    match pop_ready_delayed(&mut db_write, &key, 1) {
+                        Ok(popped) => {
+                            if let Some(member) = popped.first() {
+                                return Reply::bulks([&key, member]);
+                            }
+                        },
+                        Err(e) => return e,
                     }
+                    match db_write.get_ref(&key) {
+                        Some(RedisValue::DelayQueue(queue)) => queue.next_due(),
+                        _ => None,
+                    }
+                };
 
-                    let mut members: Vec<_> = set.iter().collect();
-                    members.sort();
-                    members.iter()
-                        .enumerate()
-                        .map(|(i, member)| format!("{}) \"{}\"", i + 1, member))
-                        .collect::<Vec<_>>()
-                        .join("\n")
-                },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(empty set)".to_string(),
-            }
-        },
-
-        Command::SCard { key } => {
-            let mut db_write = db.write().await;
+                let now = tokio::time::Instant::now();
+                if deadline.is_some_and(|deadline| now >= deadline) {
+                    return Reply::Nil;
+                }
 
-            match db_write.get(&key) {
-                Some(RedisValue::Set(set)) => format!("(integer) {}", set.len()),
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(integer) 0".to_string(),
+                // Earlier items may be pushed while waiting, so never sleep longer than the poll interval
+                let mut wait = DELAYQ_POLL_INTERVAL;
+                if let Some(due_ms) = next_due {
+                    wait = wait.min(Duration::from_millis(due_ms.saturating_sub(unix_millis())));
+                }
+                if let Some(deadline) = deadline {
+                    wait = wait.min(deadline - now);
+                }
+                tokio::time::sleep(wait).await;
             }
         },
 
-        Command::SIsMember { key, member } => {
+        Command::DelayQLen { key } => {
             let mut db_write = db.write().await;
 
-            match db_write.get(&key) {
-                Some(RedisValue::Set(set)) => {
-                    if set.contains(&member) {
-                        "(integer) 1".to_string()
-                    } else {
-                        "(integer) 0".to_string()
-                    }
+            match db_write.get_ref(&key) {
+                Some(RedisValue::DelayQueue(queue)) => {
+                    let ready = queue.ready_count(unix_millis());
+                    Reply::Array(vec![Reply::integer(queue.len()), Reply::integer(ready)])
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(integer) 0".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Array(vec![Reply::Integer(0), Reply::Integer(0)]),
             }
         },
 
-        Command::SInter { keys } => {
+        Command::BfReserve { key, error_rate, capacity } => {
             let mut db_write = db.write().await;
 
-            if keys.is_empty() {
-                return "(error) ERR wrong number of arguments".to_string();
+            if db_write.exists(&key) {
+                return Reply::error("ERR item exists");
+            }
+            let bytes = filter_bytes(error_rate, capacity);
+            if db_write.memory_manager.max_memory.is_some_and(|max_memory| bytes > max_memory as u64) {
+                return Reply::error(format!("ERR filter of {} bytes would exceed maxmemory", bytes));
             }
+            let _ = db_write.set(key, RedisValue::BloomFilter(BloomFilter::new(error_rate, capacity)));
+            Reply::ok()
+        },
 
-            let mut result: Option<HashSet<String>> = None;
+        Command::BfAdd { key, items, multi } => {
+            let mut db_write = db.write().await;
 
-            for key in keys {
-                match db_write.get(&key) {
-                    Some(RedisValue::Set(set)) => {
-                        if let Some(ref mut res) = result {
-                            *res = res.intersection(&set).cloned().collect();
-                        } else {
-                            result = Some(set.clone());
-                        }
-                    },
-                    Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                    None => return "(empty set)".to_string(),
-                }
+            if !db_write.exists(&key) {
+                let filter = BloomFilter::new(crate::bloom::DEFAULT_ERROR_RATE, crate::bloom::DEFAULT_CAPACITY);
+                let _ = db_write.set(key.clone(), RedisValue::BloomFilter(filter));
             }
 
-            match result {
-                Some(set) if !set.is_empty() => {
-                    let mut members: Vec<_> = set.iter().collect();
-                    members.sort();
-                    members.iter()
-                        .enumerate()
-                        .map(|(i, member)| format!("{}) \"{}\"", i + 1, member))
-                        .collect::<Vec<_>>()
-                        .join("\n")
+            // Filters can be large, so mutate in place instead of the usual get + set copy
+            match db_write.get_mut(&key) {
+                Some(RedisValue::BloomFilter(filter)) => {
+                    let added: Vec<bool> = items.iter().map(|item| filter.add(item)).collect();
+                    format_flags(&added, multi)
                 },
-                _ => "(empty set)".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::error("ERR not found"),
             }
         },
 
-        Command::SUnion { keys } => {
+        Command::BfExists { key, items, multi } => {
             let mut db_write = db.write().await;
 
-            if keys.is_empty() {
-                return "(error) ERR wrong number of arguments".to_string();
-            }
+            let found: Vec<bool> = match db_write.get_ref(&key) {
+                Some(RedisValue::BloomFilter(filter)) => items.iter().map(|item| filter.contains(item)).collect(),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => vec![false; items.len()],
+            };
+            format_flags(&found, multi)
+        },
 
-            let mut result = HashSet::new();
+        Command::BfInfo { key } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get_ref(&key) {
+                Some(RedisValue::BloomFilter(filter)) => Reply::Array(vec![
+                    Reply::bulk("Capacity"), Reply::integer(filter.capacity),
+                    Reply::bulk("Size"), Reply::integer(filter.size_in_bytes()),
+                    Reply::bulk("Number of filters"), Reply::Integer(1),
+                    Reply::bulk("Number of items inserted"), Reply::integer(filter.items_inserted),
+                    Reply::bulk("Number of hash functions"), Reply::integer(filter.num_hashes()),
+                ]),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::error("ERR not found"),
+            }
+        },
+
+        Command::TsCreate { key, retention_ms } => {
+            let mut db_write = db.write().await;
+
+            if db_write.exists(&key) {
+                return Reply::error("ERR TSDB: key already exists");
+            }
+            let _ = db_write.set(key, RedisValue::TimeSeries(TimeSeries::new(retention_ms)));
+            Reply::ok()
+        },
+
+        Command::TsAdd { key, timestamp, value, retention_ms } => {
+            let mut db_write = db.write().await;
+
+            if !db_write.exists(&key) {
+                let _ = db_write.set(key.clone(), RedisValue::TimeSeries(TimeSeries::new(retention_ms.unwrap_or(0))));
+            }
+
+            let timestamp = timestamp.unwrap_or_else(|| unix_millis() as i64);
+            let compacted = match db_write.get_mut(&key) {
+                Some(RedisValue::TimeSeries(series)) => match series.add(timestamp, value) {
+                    Ok(compacted) => compacted,
+                    Err(e) => return Reply::error(e),
+                },
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return Reply::error("ERR TSDB: the key does not exist"),
+            };
+
+            for (dest, bucket_start, aggregate) in compacted {
+                if let Some(RedisValue::TimeSeries(dest_series)) = db_write.get_mut(&dest) {
+                    let _ = dest_series.add(bucket_start, aggregate);
+                }
+            }
+            Reply::integer(timestamp)
+        },
+
+        Command::TsGet { key } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get_ref(&key) {
+                Some(RedisValue::TimeSeries(series)) => match series.last() {
+                    Some((timestamp, value)) => Reply::Array(vec![Reply::integer(timestamp), Reply::bulk(value.to_string())]),
+                    None => Reply::empty(),
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::error("ERR TSDB: the key does not exist"),
+            }
+        },
+
+        Command::TsRange { key, from, to, aggregation } => {
+            let mut db_write = db.write().await;
+
+            let samples = match db_write.get_ref(&key) {
+                Some(RedisValue::TimeSeries(series)) => series.range(from, to, aggregation),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return Reply::error("ERR TSDB: the key does not exist"),
+            };
+
+            if samples.is_empty() {
+                return Reply::empty();
+            }
+            // A sample renders as two nested lines of about 40 bytes
+            if let Some(error) = reply_too_large(&db_write, samples.len() * 40) {
+                return error;
+            }
+            Reply::Array(samples.iter()
+                .map(|(timestamp, value)| Reply::Array(vec![Reply::integer(*timestamp), Reply::bulk(value.to_string())]))
+                .collect())
+        },
+
+        Command::TsCreateRule { source, dest, aggregation, bucket_ms } => {
+            let mut db_write = db.write().await;
+
+            if source == dest {
+                return Reply::error("ERR TSDB: the source key and destination key should be different");
+            }
+            match db_write.get_ref(&dest) {
+                Some(RedisValue::TimeSeries(_)) => {},
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return Reply::error("ERR TSDB: the key does not exist"),
+            }
+            match db_write.get_mut(&source) {
+                Some(RedisValue::TimeSeries(series)) => {
+                    if series.rules.iter().any(|rule| rule.dest == dest) {
+                        return Reply::error("ERR TSDB: the destination key already has a rule");
+                    }
+                    series.add_rule(dest, aggregation, bucket_ms);
+                    Reply::ok()
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::error("ERR TSDB: the key does not exist"),
+            }
+        },
+
+        Command::JsonSet { key, path, value, condition } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get_mut(&key) {
+                Some(RedisValue::Json(doc)) => {
+                    let exists = json_path::get(doc, &path).is_some();
+                    if (condition == SetCondition::IfNotExists && exists) || (condition == SetCondition::IfExists && !exists) {
+                        return Reply::Nil;
+                    }
+                    if json_path::set(doc, &path, value) {
+                        Reply::ok()
+                    } else {
+                        Reply::Nil
+                    }
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => {
+                    if !path.is_empty() {
+                        return Reply::error("ERR new objects must be created at the root");
+                    }
+                    if condition == SetCondition::IfExists {
+                        return Reply::Nil;
+                    }
+                    let _ = db_write.set(key, RedisValue::Json(value));
+                    Reply::ok()
+                },
+            }
+        },
+
+        Command::JsonGet { key, path } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get_ref(&key) {
+                Some(RedisValue::Json(doc)) => match json_path::get(doc, &path) {
+                    Some(value) => Reply::bulk(value.to_string()),
+                    None => Reply::Nil,
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Nil,
+            }
+        },
+
+        Command::JsonDel { key, path } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get_mut(&key) {
+                Some(RedisValue::Json(_)) if path.is_empty() => {
+                    db_write.delete(&key);
+                    Reply::Integer(1)
+                },
+                Some(RedisValue::Json(doc)) => Reply::integer(json_path::delete(doc, &path) as i64),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
+            }
+        },
+
+        Command::JsonNumIncrBy { key, path, increment } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get_mut(&key) {
+                Some(RedisValue::Json(doc)) => match json_path::num_incr_by(doc, &path, &increment) {
+                    Ok(value) => Reply::bulk(value.to_string()),
+                    Err(e) => Reply::error(e),
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::error("ERR could not perform this operation on a key that doesn't exist"),
+            }
+        },
+
+        #[cfg(feature = "search")]
+        Command::FtCreate { index, prefixes, fields } => {
+            let mut db_write = db.write().await;
+
+            if db_write.indexes.indexes.contains_key(&index) {
+                return Reply::error("ERR Index already exists");
+            }
+            let RedisDatabase { data, indexes, .. } = &mut *db_write;
+            indexes.create(index, SearchIndex::new(prefixes, fields), data.iter().map(|(key, value)| (key, &**value)));
+            Reply::ok()
+        },
+
+        #[cfg(feature = "search")]
+        Command::FtSearch { index, predicates } => {
+            let db_read = db.read().await;
+
+            let search_index = match db_read.indexes.indexes.get(&index) {
+                Some(search_index) => search_index,
+                None => {
+                    let prefix = client_auth.key_prefix.as_deref().unwrap_or_default();
+                    return Reply::error(format!("ERR {}: no such index", tenancy::unscope(prefix, &index).unwrap_or(&index)));
+                },
+            };
+            match search_index.search(&predicates) {
+                Ok(keys) => {
+                    let mut items = vec![Reply::integer(keys.len())];
+                    let prefix = client_auth.key_prefix.as_deref().unwrap_or_default();
+                    items.extend(keys.iter().map(|key| Reply::bulk(tenancy::unscope(prefix, key).unwrap_or(key))));
+                    Reply::Array(items)
+                },
+                Err(e) => Reply::error(e),
+            }
+        },
+
+        #[cfg(feature = "search")]
+        Command::FtDropIndex { index } => {
+            let mut db_write = db.write().await;
+
+            match db_write.indexes.indexes.remove(&index) {
+                Some(_) => Reply::ok(),
+                None => Reply::error("ERR Unknown Index name"),
+            }
+        },
+
+        #[cfg(feature = "search")]
+        Command::FtInfo { index } => {
+            let db_read = db.read().await;
+
+            match db_read.indexes.indexes.get(&index) {
+                Some(search_index) => {
+                    let fields: Vec<String> = search_index.fields.iter()
+                        .map(|(name, kind)| format!("{} {}", name, kind.as_str()))
+                        .collect();
+                    Reply::Array(vec![
+                        Reply::bulk("index_name"), Reply::bulk(index),
+                        Reply::bulk("prefixes"), Reply::bulk(search_index.prefixes.join(", ")),
+                        Reply::bulk("fields"), Reply::bulk(fields.join(", ")),
+                        Reply::bulk("num_docs"), Reply::integer(search_index.num_docs()),
+                    ])
+                },
+                None => Reply::error("ERR Unknown Index name"),
+            }
+        },
+
+        Command::VectorAdd { key, element, vector } => {
+            let mut db_write = db.write().await;
+
+            if !db_write.exists(&key) {
+                let _ = db_write.set(key.clone(), RedisValue::VectorSet(VectorSet::new(vector.len())));
+            }
+            match db_write.get_mut(&key) {
+                Some(RedisValue::VectorSet(set)) => match set.add(element, vector) {
+                    Ok(is_new) => Reply::integer(is_new as i64),
+                    Err(e) => Reply::error(e),
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
+            }
+        },
+
+        Command::VectorSearch { key, k, metric, vector } => {
+            let mut db_write = db.write().await;
+
+            let results = match db_write.get_ref(&key) {
+                Some(RedisValue::VectorSet(set)) => match set.search(&vector, k, metric) {
+                    Ok(results) => results,
+                    Err(e) => return Reply::error(e),
+                },
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return Reply::empty(),
+            };
+
+            if results.is_empty() {
+                return Reply::empty();
+            }
+            Reply::Array(results.iter()
+                .map(|(element, distance)| Reply::bulks([element.clone(), format!("{:.6}", distance)]))
+                .collect())
+        },
+
+        Command::VectorRem { key, element } => {
+            let mut db_write = db.write().await;
+
+            let (removed, now_empty) = match db_write.get_mut(&key) {
+                Some(RedisValue::VectorSet(set)) => (set.remove(&element), set.is_empty()),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return Reply::Integer(0),
+            };
+            if now_empty {
+                db_write.delete(&key);
+            }
+            Reply::integer(removed as i64)
+        },
+
+        Command::SAdd { key, members } => {
+            let mut db_write = db.write().await;
+
+            let mut set = match db_write.get(&key) {
+                Some(RedisValue::Set(existing_set)) => existing_set.clone(),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => HashSet::new(),
+            };
+
+            let mut added = 0;
+            for member in members {
+                if set.insert(member) {
+                    added += 1;
+                }
+            }
+
+            let _ = db_write.set(key, RedisValue::Set(set));
+            Reply::integer(added)
+        },
+
+        Command::SRem { key, members } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get(&key) {
+                Some(RedisValue::Set(mut set)) => {
+                    let mut removed = 0;
+                    for member in members {
+                        if set.remove(&member) {
+                            removed += 1;
+                        }
+                    }
+
+                    if set.is_empty() {
+                        db_write.delete(&key);
+                    } else {
+                        let _ = db_write.set(key, RedisValue::Set(set));
+                    }
+                    Reply::integer(removed)
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
+            }
+        },
+
+        Command::SMembers { key } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get(&key) {
+                Some(RedisValue::Set(set)) => {
+                    if set.is_empty() {
+                        return Reply::empty();
+                    }
+                    if let Some(error) = reply_too_large(&db_write, elements_size(set.iter())) {
+                        return error;
+                    }
+
+                    let mut members: Vec<_> = set.iter().collect();
+                    members.sort();
+                    Reply::bulks(members)
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::empty(),
+            }
+        },
+
+        Command::SCard { key } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get(&key) {
+                Some(RedisValue::Set(set)) => Reply::integer(set.len()),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
+            }
+        },
+
+        Command::SIsMember { key, member } => {
+            let mut db_write = db.write().await;
+
+            match db_write.get(&key) {
+                Some(RedisValue::Set(set)) => {
+                    if set.contains(&member) {
+                        Reply::Integer(1)
+                    } else {
+                        Reply::Integer(0)
+                    }
+                },
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
+            }
+        },
+
+        Command::SInter { keys } => {
+            let mut db_write = db.write().await;
+
+            if keys.is_empty() {
+                return Reply::error("ERR wrong number of arguments");
+            }
+            if let Some(error) = too_many_keys(&db_write, keys.len()) {
+                return error;
+            }
+
+            let mut result: Option<HashSet<String>> = None;
+
+            for key in keys {
+                match db_write.get(&key) {
+                    Some(RedisValue::Set(set)) => {
+                        if let Some(ref mut res) = result {
+                            *res = res.intersection(&set).cloned().collect();
+                        } else {
+                            result = Some(set.clone());
+                        }
+                    },
+                    Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    None => return Reply::empty(),
+                }
+            }
+
+            match result {
+                Some(set) if !set.is_empty() => {
+                    if let Some(error) = reply_too_large(&db_write, elements_size(set.iter())) {
+                        return error;
+                    }
+                    let mut members: Vec<_> = set.iter().collect();
+                    members.sort();
+                    Reply::bulks(members)
+                },
+                _ => Reply::empty(),
+            }
+        },
+
+        Command::SUnion { keys } => {
+            let mut db_write = db.write().await;
+
+            if keys.is_empty() {
+                return Reply::error("ERR wrong number of arguments");
+            }
+            if let Some(error) = too_many_keys(&db_write, keys.len()) {
+                return error;
+            }
+
+            let mut result = HashSet::new();
 
             for key in keys {
                 match db_write.get(&key) {
                     Some(RedisValue::Set(set)) => {
                         result = result.union(&set).cloned().collect();
                     },
-                    Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                    Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
                     None => continue,
                 }
             }
 
             if result.is_empty() {
-                "(empty set)".to_string()
+                Reply::empty()
+            } else if let Some(error) = reply_too_large(&db_write, elements_size(result.iter())) {
+                error
             } else {
                 let mut members: Vec<_> = result.iter().collect();
                 members.sort();
-                members.iter()
-                    .enumerate()
-                    .map(|(i, member)| format!("{}) \"{}\"", i + 1, member))
-                    .collect::<Vec<_>>()
-                    .join("\n")
+                Reply::bulks(members)
             }
         },
 
@@ -587,14 +1476,17 @@ pub async fn execute_command(
             let mut db_write = db.write().await;
 
             if keys.is_empty() {
-                return "(error) ERR wrong number of arguments".to_string();
+                return Reply::error("ERR wrong number of arguments");
+            }
+            if let Some(error) = too_many_keys(&db_write, keys.len()) {
+                return error;
             }
 
             let first_key = &keys[0];
             let mut result = match db_write.get(first_key) {
                 Some(RedisValue::Set(set)) => set.clone(),
-                Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => return "(empty set)".to_string(),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return Reply::empty(),
             };
 
             for key in keys.iter().skip(1) {
@@ -602,21 +1494,19 @@ pub async fn execute_command(
                     Some(RedisValue::Set(set)) => {
                         result = result.difference(&set).cloned().collect();
                     },
-                    Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                    Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
                     None => continue,
                 }
             }
 
             if result.is_empty() {
-                "(empty set)".to_string()
+                Reply::empty()
+            } else if let Some(error) = reply_too_large(&db_write, elements_size(result.iter())) {
+                error
             } else {
                 let mut members: Vec<_> = result.iter().collect();
                 members.sort();
-                members.iter()
-                    .enumerate()
-                    .map(|(i, member)| format!("{}) \"{}\"", i + 1, member))
-                    .collect::<Vec<_>>()
-                    .join("\n")
+                Reply::bulks(members)
             }
         },
 
@@ -625,13 +1515,15 @@ pub async fn execute_command(
 
             let mut hash = match db_write.get(&key) {
                 Some(RedisValue::Hash(existing_hash)) => existing_hash.clone(),
-                Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
                 None => HashMap::new(),
             };
 
+            // Overwriting a field discards its TTL, as in Redis
+            db_write.persist_field(&key, &field);
             let is_new = hash.insert(field, value).is_none();
-            db_write.set(key, RedisValue::Hash(hash));
-            format!("(integer) {}", if is_new { 1 } else { 0 })
+            let _ = db_write.set(key, RedisValue::Hash(hash));
+            Reply::integer(if is_new { 1 } else { 0 })
         },
 
         Command::HGet { key, field } => {
@@ -640,12 +1532,12 @@ pub async fn execute_command(
             match db_write.get(&key) {
                 Some(RedisValue::Hash(hash)) => {
                     match hash.get(&field) {
-                        Some(value) => format!("\"{}\"", value),
-                        None => "(nil)".to_string(),
+                        Some(value) => Reply::bulk(value),
+                        None => Reply::Nil,
                     }
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(nil)".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Nil,
             }
         },
 
@@ -664,12 +1556,12 @@ pub async fn execute_command(
                     if hash.is_empty() {
                         db_write.delete(&key);
                     } else {
-                        db_write.set(key, RedisValue::Hash(hash));
+                        let _ = db_write.set(key, RedisValue::Hash(hash));
                     }
-                    format!("(integer) {}", deleted)
+                    Reply::integer(deleted)
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(integer) 0".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
             }
         },
 
@@ -679,23 +1571,19 @@ pub async fn execute_command(
             match db_write.get(&key) {
                 Some(RedisValue::Hash(hash)) => {
                     if hash.is_empty() {
-                        return "(empty hash)".to_string();
+                        return Reply::empty();
+                    }
+                    if let Some(error) = reply_too_large(&db_write, elements_size(hash.keys()) + elements_size(hash.values())) {
+                        return error;
                     }
 
                     let mut fields: Vec<_> = hash.iter().collect();
                     fields.sort_by_key(|(k, _)| *k);
 
-                    let mut result = Vec::new();
-                    let mut idx = 1;
-                    for (field, value) in fields {
-                        result.push(format!("{}) \"{}\"", idx, field));
-                        result.push(format!("{}) \"{}\"", idx + 1, value));
-                        idx += 2;
-                    }
-                    result.join("\n")
+                    Reply::bulks(fields.into_iter().flat_map(|(field, value)| [field, value]))
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(empty hash)".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::empty(),
             }
         },
 
@@ -705,19 +1593,18 @@ pub async fn execute_command(
             match db_write.get(&key) {
                 Some(RedisValue::Hash(hash)) => {
                     if hash.is_empty() {
-                        return "(empty array)".to_string();
+                        return Reply::empty();
                     }
 
+                    if let Some(error) = reply_too_large(&db_write, elements_size(hash.keys())) {
+                        return error;
+                    }
                     let mut keys: Vec<_> = hash.keys().collect();
                     keys.sort();
-                    keys.iter()
-                        .enumerate()
-                        .map(|(i, k)| format!("{}) \"{}\"", i + 1, k))
-                        .collect::<Vec<_>>()
-                        .join("\n")
+                    Reply::bulks(keys)
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(empty array)".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::empty(),
             }
         },
 
@@ -727,20 +1614,19 @@ pub async fn execute_command(
             match db_write.get(&key) {
                 Some(RedisValue::Hash(hash)) => {
                     if hash.is_empty() {
-                        return "(empty array)".to_string();
+                        return Reply::empty();
                     }
 
+                    if let Some(error) = reply_too_large(&db_write, elements_size(hash.values())) {
+                        return error;
+                    }
                     let mut entries: Vec<_> = hash.iter().collect();
                     entries.sort_by_key(|(k, _)| *k);
 
-                    entries.iter()
-                        .enumerate()
-                        .map(|(i, (_, v))| format!("{}) \"{}\"", i + 1, v))
-                        .collect::<Vec<_>>()
-                        .join("\n")
+                    Reply::bulks(entries.into_iter().map(|(_, v)| v))
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(empty array)".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::empty(),
             }
         },
 
@@ -748,9 +1634,9 @@ pub async fn execute_command(
             let mut db_write = db.write().await;
 
             match db_write.get(&key) {
-                Some(RedisValue::Hash(hash)) => format!("(integer) {}", hash.len()),
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(integer) 0".to_string(),
+                Some(RedisValue::Hash(hash)) => Reply::integer(hash.len()),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
             }
         },
 
@@ -760,13 +1646,13 @@ pub async fn execute_command(
             match db_write.get(&key) {
                 Some(RedisValue::Hash(hash)) => {
                     if hash.contains_key(&field) {
-                        "(integer) 1".to_string()
+                        Reply::Integer(1)
                     } else {
-                        "(integer) 0".to_string()
+                        Reply::Integer(0)
                     }
                 },
-                Some(_) => "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
-                None => "(integer) 0".to_string(),
+                Some(_) => Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => Reply::Integer(0),
             }
         },
 
@@ -775,178 +1661,723 @@ pub async fn execute_command(
 
             let mut hash = match db_write.get(&key) {
                 Some(RedisValue::Hash(existing_hash)) => existing_hash.clone(),
-                Some(_) => return "(error) WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
                 None => HashMap::new(),
             };
 
             let new_value = match hash.get(&field) {
                 Some(val) => {
                     match val.parse::<i64>() {
-                        Ok(current) => current + increment,
-                        Err(_) => return "(error) ERR hash value is not an integer".to_string(),
+                        Ok(current) => match current.checked_add(increment) {
+                            Some(new_value) => new_value,
+                            None => return Reply::error(OVERFLOW_ERROR),
+                        },
+                        Err(_) => return Reply::error("ERR hash value is not an integer"),
                     }
                 },
                 None => increment,
             };
 
             hash.insert(field, new_value.to_string());
-            db_write.set(key, RedisValue::Hash(hash));
-            format!("(integer) {}", new_value)
+            let _ = db_write.set(key, RedisValue::Hash(hash));
+            Reply::integer(new_value)
         },
 
-        Command::Keys { pattern: _ } => {
+        Command::HExpire { key, ttl, condition, fields } => {
+            let mut db_write = db.write().await;
+
+            let mut hash = match db_write.get(&key) {
+                Some(RedisValue::Hash(hash)) => hash,
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return format_integers(&vec![-2; fields.len()]),
+            };
+
+            let mut results = Vec::with_capacity(fields.len());
+            let mut deleted_any = false;
+            for field in &fields {
+                if !hash.contains_key(field) {
+                    results.push(-2);
+                    continue;
+                }
+
+                // A field without a TTL counts as never expiring for GT/LT
+                let current = db_write.field_ttl(&key, field);
+                let allowed = match condition {
+                    ExpireCondition::Always => true,
+                    ExpireCondition::IfNoTtl => current.is_none(),
+                    ExpireCondition::IfHasTtl => current.is_some(),
+                    ExpireCondition::IfGreater => current.is_some_and(|current| ttl > current),
+                    ExpireCondition::IfLess => current.is_none_or(|current| ttl < current),
+                };
+                if !allowed {
+                    results.push(0);
+                } else if ttl.is_zero() {
+                    hash.remove(field);
+                    db_write.persist_field(&key, field);
+                    deleted_any = true;
+                    results.push(2);
+                } else {
+                    db_write.expire_field(&key, field, ttl);
+                    results.push(1);
+                }
+            }
+
+            if deleted_any {
+                if hash.is_empty() {
+                    db_write.delete(&key);
+                } else {
+                    let _ = db_write.set(key, RedisValue::Hash(hash));
+                }
+            }
+            format_integers(&results)
+        },
+
+        Command::HTtl { key, fields, millis } => {
+            let mut db_write = db.write().await;
+
+            let hash = match db_write.get_ref(&key) {
+                Some(RedisValue::Hash(hash)) => hash,
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return format_integers(&vec![-2; fields.len()]),
+            };
+            let present: Vec<bool> = fields.iter().map(|field| hash.contains_key(field)).collect();
+
+            let results: Vec<i64> = fields.iter().zip(present)
+                .map(|(field, present)| match (present, db_write.field_ttl(&key, field)) {
+                    (false, _) => -2,
+                    (true, None) => -1,
+                    (true, Some(remaining)) if millis => remaining.as_millis() as i64,
+                    (true, Some(remaining)) => remaining.as_secs() as i64,
+                })
+                .collect();
+            format_integers(&results)
+        },
+
+        Command::HPersist { key, fields } => {
+            let mut db_write = db.write().await;
+
+            let hash = match db_write.get_mut(&key) {
+                Some(RedisValue::Hash(hash)) => hash,
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => return format_integers(&vec![-2; fields.len()]),
+            };
+            let present: Vec<bool> = fields.iter().map(|field| hash.contains_key(field)).collect();
+
+            let results: Vec<i64> = fields.iter().zip(present)
+                .map(|(field, present)| match present {
+                    false => -2,
+                    true if db_write.persist_field(&key, field) => 1,
+                    true => -1,
+                })
+                .collect();
+            format_integers(&results)
+        },
+
+        Command::HRandField { key, count, with_values } => {
             let mut db_write = db.write().await;
-            let keys = db_write.keys();
+
+            match db_write.get_ref(&key) {
+                Some(RedisValue::Hash(_)) => {},
+                Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                None => {
+                    return if count.is_some() { Reply::empty() } else { Reply::Nil };
+                }
+            };
+            // Borrow the hash and the RNG separately
+            let db_fields = &mut *db_write;
+            let hash = match db_fields.data.get(&key).map(|value| &**value) {
+                Some(RedisValue::Hash(hash)) => hash,
+                _ => return Reply::Nil,
+            };
+            // Repeats are not bounded by the hash, so the reply is sized before it is built
+            if let Some(repeats) = count.filter(|count| *count < 0).map(i64::unsigned_abs) {
+                let values = if with_values { elements_size(hash.values()) } else { 0 };
+                let average = (elements_size(hash.keys()) + values) / hash.len().max(1);
+                if let Some(error) = reply_too_large(db_fields, average.saturating_mul(repeats as usize)) {
+                    return error;
+                }
+            }
+            let rng = &mut db_fields.rng;
+
+            let count = match count {
+                Some(count) => count,
+                None => {
+                    return match sample_hash_fields(hash, 1, rng).first() {
+                        Some((field, _)) => Reply::bulk(field),
+                        None => Reply::Nil,
+                    };
+                }
+            };
+
+            let sampled = sample_hash_fields(hash, count, rng);
+            if sampled.is_empty() {
+                return Reply::empty();
+            }
+
+            let mut result = Vec::new();
+            for (field, value) in sampled {
+                result.push(Reply::bulk(field));
+                if with_values {
+                    result.push(Reply::bulk(value));
+                }
+            }
+            Reply::Array(result)
+        },
+
+        Command::Keys { pattern: _ } => {
+            let db_write = db.write().await;
+            let keys = match &client_auth.key_prefix {
+                Some(prefix) => db_write.keys_with_prefix(prefix).iter()
+                    .filter_map(|key| tenancy::unscope(prefix, key).map(str::to_string))
+                    .collect(),
+                None => db_write.keys(),
+            };
+            if let Some(error) = reply_too_large(&db_write, elements_size(keys.iter())) {
+                return error;
+            }
             if keys.is_empty() {
-                "(empty array)".to_string()
+                Reply::empty()
+            } else {
+                Reply::bulks(keys)
+            }
+        },
+
+        Command::Aggregate { reducer, pattern } => {
+            let pattern = glob_regex(&pattern);
+            let keys: Vec<String> = db.read().await.keys().into_iter().filter(|key| pattern.is_match(key)).collect();
+            // Keys changed by other clients between batches are read as they are by then
+            let mut aggregator = Aggregator::new(reducer);
+            for batch in keys.chunks(AGGREGATE_BATCH) {
+                {
+                    let mut db_write = db.write().await;
+                    for key in batch {
+                        if let Some(value) = db_write.get(key) {
+                            aggregator.add(&value);
+                        }
+                    }
+                }
+                tokio::task::yield_now().await;
+            }
+            aggregator.reply()
+        },
+
+        Command::DelPattern { pattern, throttle } => {
+            let matcher = glob_regex(&pattern);
+            let mut db_write = db.write().await;
+            let keys: Vec<String> = db_write.keys().into_iter().filter(|key| matcher.is_match(key)).collect();
+            match db_write.jobs.start(JobKind::DelPattern, &pattern, client_auth.key_prefix.as_deref(), throttle, keys.len() as u64) {
+                Ok(id) => {
+                    tokio::spawn(delpattern::run(Arc::clone(&db), id, keys));
+                    Reply::integer(id)
+                },
+                Err(e) => Reply::error(e),
+            }
+        },
+
+        Command::JobList => {
+            let prefix = client_auth.key_prefix.as_deref();
+            let db_read = db.read().await;
+            let jobs = db_read.jobs.list(prefix);
+            if jobs.is_empty() {
+                return Reply::empty();
+            }
+            Reply::bulks(jobs.iter().map(|(id, job)| job.summary(*id, prefix)))
+        },
+
+        Command::JobStatus { id } => {
+            // Tenants only see their own jobs
+            let prefix = client_auth.key_prefix.as_deref();
+            match db.read().await.jobs.get(id, prefix) {
+                Some(job) => Reply::bulk(job.render(id, prefix)),
+                None => Reply::error(format!("ERR no such job {}", id)),
+            }
+        },
+
+        Command::JobCancel { id } => {
+            if db.write().await.jobs.cancel(id, client_auth.key_prefix.as_deref()) {
+                Reply::ok()
             } else {
-                keys.iter()
-                    .enumerate()
-                    .map(|(i, key)| format!("{}) \"{}\"", i + 1, key))
-                    .collect::<Vec<_>>()
-                    .join("\n")
+                Reply::error(format!("ERR no running job {}", id))
             }
         },
 
+        Command::TagSet { key, tags } => {
+            let mut db_write = db.write().await;
+            if !db_write.exists(&key) {
+                return Reply::Integer(0);
+            }
+            match db_write.tags.add(&key, &tags) {
+                Ok(added) => {
+                    db_write.dirty += added as u64;
+                    Reply::integer(added)
+                },
+                Err(error) => Reply::error(error),
+            }
+        },
+
+        Command::TagGet { key } => {
+            let mut db_write = db.write().await;
+            if !db_write.exists(&key) {
+                return Reply::empty();
+            }
+            let tags = db_write.tags.tags_of(&key);
+            if tags.is_empty() {
+                return Reply::empty();
+            }
+            Reply::bulks(tags)
+        },
+
+        Command::TagDel { key, tags } => {
+            let mut db_write = db.write().await;
+            let removed = db_write.tags.remove(&key, &tags);
+            db_write.dirty += removed as u64;
+            Reply::integer(removed)
+        },
+
+        Command::KeysByTag { tag } => {
+            let mut db_write = db.write().await;
+            let tagged = db_write.tags.keys_with(&tag);
+            // Checking each key drops the tags of any that expired since
+            let live: Vec<String> = tagged.into_iter().filter(|key| db_write.exists(key)).collect();
+            let keys: Vec<String> = match &client_auth.key_prefix {
+                Some(prefix) => live.iter().filter_map(|key| tenancy::unscope(prefix, key).map(str::to_string)).collect(),
+                None => live,
+            };
+            if let Some(error) = reply_too_large(&db_write, elements_size(keys.iter())) {
+                return error;
+            }
+            if keys.is_empty() {
+                return Reply::empty();
+            }
+            Reply::bulks(keys)
+        },
+
         Command::Type { key } => {
             let mut db_write = db.write().await;
 
-            match db_write.get(&key) {
-                Some(RedisValue::String(_)) => "string".to_string(),
-                Some(RedisValue::Integer(_)) => "string".to_string(),
-                Some(RedisValue::List(_)) => "list".to_string(),
-                Some(RedisValue::Set(_)) => "set".to_string(),
-                Some(RedisValue::Hash(_)) => "hash".to_string(),
-                None => "none".to_string(),
+            match db_write.get(&key) {
+                Some(RedisValue::String(_)) | Some(RedisValue::CompressedString(_)) => Reply::Simple("string".into()),
+                Some(RedisValue::Integer(_)) | Some(RedisValue::Bytes(_)) => Reply::Simple("string".into()),
+                Some(RedisValue::List(_)) => Reply::Simple("list".into()),
+                Some(RedisValue::Set(_)) => Reply::Simple("set".into()),
+                Some(RedisValue::Hash(_)) => Reply::Simple("hash".into()),
+                Some(RedisValue::DelayQueue(_)) => Reply::Simple("delayqueue".into()),
+                Some(RedisValue::BloomFilter(_)) => Reply::Simple("MBbloom--".into()),
+                Some(RedisValue::TimeSeries(_)) => Reply::Simple("TSDB-TYPE".into()),
+                Some(RedisValue::Json(_)) => Reply::Simple("ReJSON-RL".into()),
+                Some(RedisValue::VectorSet(_)) => Reply::Simple("vectorset".into()),
+                None => Reply::Simple("none".into()),
+            }
+        },
+
+        Command::Expire { key, ttl } => {
+            let mut db_write = db.write().await;
+
+            if db_write.exists(&key) && db_write.expire(&key, ttl) {
+                Reply::Integer(1)
+            } else {
+                Reply::Integer(0)
+            }
+        },
+
+        Command::ExpireAt { key, unix_ms } => {
+            let mut db_write = db.write().await;
+
+            if db_write.exists(&key) && db_write.expire_at(&key, unix_ms) {
+                Reply::Integer(1)
+            } else {
+                Reply::Integer(0)
+            }
+        },
+
+        Command::Ttl { key, millis } => {
+            let mut db_write = db.write().await;
+
+            let ttl = match db_write.ttl(&key) {
+                None => -2,
+                Some(Duration::MAX) => -1,
+                Some(remaining) if millis => remaining.as_millis() as i64,
+                Some(remaining) => remaining.as_secs() as i64,
+            };
+            Reply::integer(ttl)
+        },
+
+        Command::TtlMany { keys, millis } => {
+            let mut db_write = db.write().await;
+            if let Some(error) = too_many_keys(&db_write, keys.len()) {
+                return error;
             }
+
+            Reply::Array(db_write.ttl_many(&keys)
+                .into_iter()
+                .map(|ttl| match ttl {
+                    None => Reply::Integer(-2),
+                    Some(Duration::MAX) => Reply::Integer(-1),
+                    Some(remaining) if millis => Reply::integer(remaining.as_millis()),
+                    Some(remaining) => Reply::integer(remaining.as_secs()),
+                })
+                .collect())
         },
 
-        Command::Expire { key, seconds } => {
+        Command::Persist { key } => {
             let mut db_write = db.write().await;
 
-            if !db_write.exists(&key) {
-                return "(integer) 0".to_string();
-            }
-
-            if let Some(value) = db_write.get(&key) {
-                db_write.set_with_expiry(key, value.clone(), Duration::from_secs(seconds));
-                "(integer) 1".to_string()
+            if db_write.expires.remove(&key).is_some() {
+                Reply::Integer(1)
             } else {
-                "(integer) 0".to_string()
+                Reply::Integer(0)
             }
         },
 
-        Command::Ttl { key } => {
+        Command::Lock { key, token, ttl } => {
             let mut db_write = db.write().await;
-
-            if !db_write.exists(&key) {
-                return "(integer) -2".to_string();
+            match db_write.locks.acquire(&key, &token, ttl, std::time::Instant::now()) {
+                Some(fence) => Reply::integer(fence),
+                None => Reply::Nil,
             }
+        },
 
-            if let Some(expire_time) = db_write.expires.get(&key) {
-                let now = std::time::Instant::now();
-                if *expire_time > now {
-                    let remaining = (*expire_time - now).as_secs();
-                    format!("(integer) {}", remaining)
-                } else {
-                    "(integer) -2".to_string()
+        Command::Unlock { key, token } => {
+            let mut db_write = db.write().await;
+            let released = db_write.locks.release(&key, &token, std::time::Instant::now());
+            Reply::integer(released as u8)
+        },
+
+        Command::Pin { keys } => {
+            let mut db_write = db.write().await;
+            let mut pinned = 0;
+            for key in &keys {
+                if db_write.exists(key) && db_write.memory_manager.pin(key) {
+                    pinned += 1;
                 }
-            } else {
-                "(integer) -1".to_string()
             }
+            Reply::integer(pinned)
         },
 
-        Command::Persist { key } => {
+        Command::Unpin { keys } => {
             let mut db_write = db.write().await;
-
-            if db_write.expires.remove(&key).is_some() {
-                "(integer) 1".to_string()
-            } else {
-                "(integer) 0".to_string()
-            }
+            let unpinned = keys.iter().filter(|key| db_write.memory_manager.unpin(key)).count();
+            Reply::integer(unpinned)
         },
 
         Command::Rename { key, newkey } => {
             let mut db_write = db.write().await;
 
             if !db_write.exists(&key) {
-                return "(error) ERR no such key".to_string();
+                return Reply::error("ERR no such key");
             }
 
             if let Some(value) = db_write.get(&key) {
                 let value_clone = value.clone();
                 let expiry = db_write.expires.get(&key).copied();
+                let field_expiry = db_write.field_expires.get(&key).cloned();
+                let pinned = db_write.memory_manager.is_pinned(&key);
+                let tags = db_write.tags.tags_of(&key);
 
                 db_write.delete(&key);
+                db_write.field_expires.remove(&newkey);
+                if let Some(fields) = field_expiry {
+                    db_write.field_expires.insert(newkey.clone(), fields);
+                }
 
                 if let Some(expire_time) = expiry {
                     let now = std::time::Instant::now();
                     if expire_time > now {
                         let remaining = expire_time - now;
-                        db_write.set_with_expiry(newkey, value_clone, remaining);
+                        let _ = db_write.set_with_expiry(newkey.clone(), value_clone, remaining);
                     } else {
-                        db_write.set(newkey, value_clone);
+                        let _ = db_write.set(newkey.clone(), value_clone);
                     }
                 } else {
-                    db_write.set(newkey, value_clone);
+                    let _ = db_write.set(newkey.clone(), value_clone);
                 }
+                if pinned {
+                    db_write.memory_manager.pin(&newkey);
+                } else {
+                    db_write.memory_manager.unpin(&newkey);
+                }
+                db_write.tags.remove_key(&newkey);
+                let _ = db_write.tags.add(&newkey, &tags);
 
-                "OK".to_string()
+                Reply::ok()
             } else {
-                "(error) ERR no such key".to_string()
+                Reply::error("ERR no such key")
             }
         },
 
-        Command::RandomKey => {
-            let db_write = db.write().await;
-            let keys = db_write.keys();
-
-            if keys.is_empty() {
-                "(nil)".to_string()
+        Command::Copy { source, destination, replace } => {
+            let mut db_write = db.write().await;
+            if db_write.copy(&source, &destination, replace) {
+                Reply::Integer(1)
             } else {
-                use std::collections::hash_map::RandomState;
-                use std::hash::{BuildHasher, Hash, Hasher};
+                Reply::Integer(0)
+            }
+        },
 
-                let random_state = RandomState::new();
-                let mut hasher = random_state.build_hasher();
-                std::time::SystemTime::now().hash(&mut hasher);
-                let random_idx = (hasher.finish() as usize) % keys.len();
+        Command::RandomKey => {
+            let mut db_write = db.write().await;
+            let keys = match &client_auth.key_prefix {
+                Some(prefix) => db_write.keys_with_prefix(prefix).iter()
+                    .filter_map(|key| tenancy::unscope(prefix, key).map(str::to_string))
+                    .collect(),
+                None => db_write.keys(),
+            };
 
-                format!("\"{}\"", keys[random_idx])
+            match db_write.rng.choose(keys) {
+                Some(key) => Reply::bulk(key),
+                None => Reply::Nil,
             }
         },
 
         Command::DbSize => {
             let db_write = db.write().await;
-            format!("(integer) {}", db_write.size())
+            match &client_auth.key_prefix {
+                Some(prefix) => Reply::integer(db_write.keys_with_prefix(prefix).len()),
+                None => Reply::integer(db_write.size()),
+            }
+        },
+
+        Command::TtlStats => {
+            let db_read = db.read().await;
+            let stats = db_read.ttl_stats(client_auth.key_prefix.as_deref().unwrap_or_default());
+            let mut fields = vec![("volatile_keys".to_string(), stats.volatile_keys as u64)];
+            for ((window, _), count) in TTL_BUCKETS.iter().zip(stats.expiring) {
+                fields.push((format!("expiring_{}", window), count as u64));
+            }
+            fields.push(("avg_ttl_ms".to_string(), stats.avg_ttl_ms));
+            Reply::Array(fields.iter()
+                .flat_map(|(name, value)| [Reply::bulk(name), Reply::integer(*value)])
+                .collect())
+        },
+
+        Command::HotKeys { count, minutes } => {
+            let db_read = db.read().await;
+            let prefix = client_auth.key_prefix.as_deref().unwrap_or_default();
+            // Tenants see only their own keys, ranked among themselves
+            let hottest: Vec<(String, u64)> = db_read.memory_manager.heatmap.hottest(usize::MAX, minutes)
+                .into_iter()
+                .filter_map(|(key, accesses)| tenancy::unscope(prefix, &key).map(|key| (key.to_string(), accesses)))
+                .take(count)
+                .collect();
+            if hottest.is_empty() {
+                return Reply::empty();
+            }
+            Reply::Array(hottest.iter()
+                .flat_map(|(key, accesses)| [Reply::bulk(key), Reply::integer(*accesses)])
+                .collect())
         },
 
         Command::Echo { message } => {
-            format!("\"{}\"", message)
+            Reply::bulk(message)
+        },
+
+        Command::SessionCreate { ttl } => {
+            let ttl = ttl.map_or(DEFAULT_SESSION_TTL, Duration::from_secs);
+            Reply::bulk(client_auth.create_session(ttl))
+        },
+
+        Command::SessionRevoke { token } => {
+            Reply::integer(client_auth.auth_config.sessions.revoke(&token) as i64)
+        },
+
+        Command::SessionAuth { .. } => unreachable!("handled before authentication"),
+
+        Command::ReadOnly => {
+            client_auth.readonly = true;
+            Reply::ok()
+        },
+
+        Command::ReadWrite => {
+            client_auth.readonly = false;
+            Reply::ok()
+        },
+
+        Command::Hello { protover } => {
+            match protover {
+                Some(2) | Some(3) | None => {},
+                Some(_) => return Reply::error("NOPROTO unsupported protocol version"),
+            }
+            if let Some(protover) = protover {
+                client_auth.protocol = protover;
+            }
+            Reply::Array(vec![
+                Reply::bulk("server"), Reply::bulk("redis-clone"),
+                Reply::bulk("version"), Reply::bulk(env!("CARGO_PKG_VERSION")),
+                Reply::bulk("proto"), Reply::integer(client_auth.protocol),
+                Reply::bulk("mode"), Reply::bulk("standalone"),
+            ])
         },
 
         Command::Info => {
-            let mut db_write = db.write().await;
+            let stats = match metrics {
+                Some(metrics) => {
+                    let ring = metrics.read().await;
+                    format!(
+                        "total_commands_processed:{}\ninstantaneous_ops_per_sec:{}\ntotal_net_input_bytes:{}\ntotal_net_output_bytes:{}\ninstantaneous_input_kbps:{:.2}\ninstantaneous_output_kbps:{:.2}\nstalled_writes:{}\nrejected_writes:{}\nthrottled_writes:{}\nclient_yields:{}\ninternal_errors:{}",
+                        ring.total_commands,
+                        ring.instantaneous_ops_per_sec(),
+                        ring.total_input_bytes,
+                        ring.total_output_bytes,
+                        ring.instantaneous_input_kbps(),
+                        ring.instantaneous_output_kbps(),
+                        ring.stalled_writes,
+                        ring.rejected_writes,
+                        ring.throttled_writes,
+                        ring.client_yields,
+                        ring.internal_errors
+                    )
+                },
+                None => "total_commands_processed:0\ninstantaneous_ops_per_sec:0".to_string(),
+            };
+            let pubsub_info = match pubsub_manager {
+                Some(pubsub) => pubsub.read().await.render_info(),
+                None => PubSubState::new().render_info(),
+            };
+
+            let db_write = db.write().await;
+            let ttl_stats = db_write.ttl_stats("");
+            let expiring: Vec<String> = TTL_BUCKETS.iter()
+                .zip(ttl_stats.expiring)
+                .map(|((window, _), count)| format!("expiring_{}:{}", window, count))
+                .collect();
             let info = format!(
-                "# Server\nredis_version:7.0.0-clone\nredis_mode:standalone\n# Memory\nused_memory:{}\n# Keyspace\ndb0:keys={}",
+                "# Server\nredis_version:7.0.0-clone\nredis_mode:standalone\n# Replication\nrole:master\nconnected_slaves:0\n# Clients\nblocked_clients:{}\n# Memory\nused_memory:{}\n# Persistence\nrdb_changes_since_last_save:{}\n{}\n# Stats\n{}\n# Pubsub\n{}\n# Warmup\n{}\n# Expiry\n{}\navg_ttl_ms:{}\n# Keyspace\ndb0:keys={},expires={},avg_ttl={}",
+                db_write.waiters.blocked_clients(),
                 db_write.size() * 100,
-                db_write.size()
+                db_write.dirty,
+                db_write.save_stats.render(),
+                stats,
+                pubsub_info,
+                db_write.warmup.render(),
+                expiring.join("\n"),
+                ttl_stats.avg_ttl_ms,
+                db_write.size(),
+                ttl_stats.volatile_keys,
+                ttl_stats.avg_ttl_ms
             );
-            format!("\"{}\"", info)
+            Reply::bulk(info)
+        },
+
+        Command::StatSizes { command } => {
+            let metrics = match metrics {
+                Some(metrics) => metrics.read().await,
+                None => return Reply::error("ERR metrics not available"),
+            };
+            Reply::bulks(metrics.command_sizes.iter()
+                .filter(|(name, _)| command.as_ref().is_none_or(|command| command.eq_ignore_ascii_case(name)))
+                .map(|(name, sizes)| format!(
+                    "{} calls:{} request_bytes:{} request_max:{} reply_bytes:{} reply_max:{}",
+                    name, sizes.requests.count(),
+                    sizes.requests.render(), sizes.requests.max,
+                    sizes.replies.render(), sizes.replies.max
+                )))
+        },
+
+        Command::StatHistory { count } => {
+            if let Some(metrics) = metrics {
+                let ring = metrics.read().await;
+                Reply::bulks(ring.history(count)
+                    .iter()
+                    .map(|sample| format!(
+                        "{} ops:{} input_bytes:{} output_bytes:{}",
+                        sample.second, sample.commands, sample.input_bytes, sample.output_bytes
+                    )))
+            } else {
+                Reply::error("ERR metrics not available")
+            }
         },
 
         Command::Memory => {
             let db_write = db.write().await;
+            if let Some(prefix) = &client_auth.key_prefix {
+                let used = db_write.get_prefix_memory_usage(prefix);
+                return Reply::Verbatim(format!("used_memory:{}\nused_memory_human:{}", used, crate::memory::format_bytes(used)));
+            }
             let memory_info = db_write.get_memory_info();
-            format!("used_memory:{}\nused_memory_human:{}",
+            Reply::Verbatim(format!("used_memory:{}\nused_memory_human:{}",
                     memory_info.get("used_memory").unwrap_or(&"0".to_string()),
-                    memory_info.get("used_memory_human").unwrap_or(&"0B".to_string()))
+                    memory_info.get("used_memory_human").unwrap_or(&"0B".to_string())))
         },
 
-        Command::ShowAll => {
+        Command::MemoryStats if client_auth.key_prefix.is_some() => {
+            let db_write = db.write().await;
+            let prefix = client_auth.key_prefix.as_deref().unwrap_or_default();
+            let (pinned_keys, pinned_bytes) = db_write.get_pinned_usage(prefix);
+            Reply::Array(vec![
+                Reply::bulk("used_memory"), Reply::integer(db_write.get_prefix_memory_usage(prefix)),
+                Reply::bulk("keys.count"), Reply::integer(db_write.keys_with_prefix(prefix).len()),
+                Reply::bulk("pinned.keys"), Reply::integer(pinned_keys),
+                Reply::bulk("pinned.bytes"), Reply::integer(pinned_bytes),
+            ])
+        },
+
+        Command::MemoryStats => {
+            let db_write = db.write().await;
+            let (keys, original, stored) = db_write.compression_stats();
+            let (pinned_keys, pinned_bytes) = db_write.get_pinned_usage("");
+            Reply::Array(vec![
+                Reply::bulk("used_memory"), Reply::integer(db_write.get_memory_usage()),
+                Reply::bulk("keys.count"), Reply::integer(db_write.size()),
+                Reply::bulk("compression.keys"), Reply::integer(keys),
+                Reply::bulk("compression.original_bytes"), Reply::integer(original),
+                Reply::bulk("compression.stored_bytes"), Reply::integer(stored),
+                Reply::bulk("compression.saved_bytes"), Reply::integer(original - stored),
+                Reply::bulk("pinned.keys"), Reply::integer(pinned_keys),
+                Reply::bulk("pinned.bytes"), Reply::integer(pinned_bytes),
+                Reply::bulk("tracking.bytes"), Reply::integer(db_write.memory_manager.tracking_overhead()),
+            ])
+        },
+
+        Command::DbStatsByPrefix => {
+            let db_read = db.read().await;
+            Reply::bulks(db_read.prefix_summaries().iter()
+                .map(|summary| format!(
+                    "{} keys:{} bytes:{} hits:{} misses:{} hit_rate:{}",
+                    summary.prefix, summary.keys, summary.bytes,
+                    summary.lookups.hits, summary.lookups.misses,
+                    summary.hit_rate().map_or("-".to_string(), |rate| format!("{:.2}%", rate))
+                )))
+        },
+
+        Command::ExpiredRead { after, count } => {
+            let db_read = db.read().await;
+            Reply::bulks(db_read.expiry_log.read_after(after, count).map(|entry| entry.to_string()))
+        },
+
+        Command::ExpiredInfo => {
+            let db_read = db.read().await;
+            let log = &db_read.expiry_log;
+            let fields = [
+                ("length", log.len() as u64),
+                ("max_len", log.max_len() as u64),
+                ("first_id", log.first_id().unwrap_or(0)),
+                ("last_id", log.last_id()),
+            ];
+            Reply::Array(fields.iter()
+                .flat_map(|(name, value)| [Reply::bulk(*name), Reply::integer(*value)])
+                .collect())
+        },
+
+        Command::ObjectEncoding { key } => {
             let mut db_write = db.write().await;
+            if !db_write.exists(&key) {
+                return Reply::Nil;
+            }
+            let encoding = match db_write.data.get(&key).map(|value| &**value) {
+                Some(RedisValue::String(s)) if s.len() <= 44 => "embstr",
+                Some(RedisValue::CompressedString(_)) => "lz4",
+                Some(RedisValue::Integer(_)) => "int",
+                Some(RedisValue::List(_)) => "quicklist",
+                Some(RedisValue::Set(_)) | Some(RedisValue::Hash(_)) => "hashtable",
+                _ => "raw",
+            };
+            Reply::bulk(encoding)
+        },
+
+        Command::ShowAll => {
+            let db_write = db.write().await;
             if db_write.data.is_empty() {
-                return "(empty database)".to_string();
+                return Reply::empty();
             }
 
             let mut result = String::new();
@@ -965,13 +2396,20 @@ pub async fn execute_command(
                     "".to_string()
                 };
 
-                match value {
+                match &**value {
                     RedisValue::String(s) => {
                         result.push_str(&format!("\"{}\" -> STRING: \"{}\"{}\n", key, s, ttl_info));
                     },
+                    RedisValue::CompressedString(s) => {
+                        result.push_str(&format!("\"{}\" -> STRING (lz4, {} -> {} bytes): \"{}\"{}\n",
+                                                 key, s.original_len(), s.stored_len(), s.decompress(), ttl_info));
+                    },
                     RedisValue::Integer(i) => {
                         result.push_str(&format!("\"{}\" -> INTEGER: {}{}\n", key, i, ttl_info));
                     },
+                    RedisValue::Bytes(bytes) => {
+                        result.push_str(&format!("\"{}\" -> STRING (binary, {} bytes): \"{}\"{}\n", key, bytes.len(), escape_bytes(bytes), ttl_info));
+                    },
                     RedisValue::List(list) => {
                         result.push_str(&format!("\"{}\" -> LIST ({} items): [{}]{}\n",
                                                  key,
@@ -1004,119 +2442,144 @@ pub async fn execute_command(
                                                  ttl_info
                         ));
                     },
+                    RedisValue::DelayQueue(queue) => {
+                        result.push_str(&format!("\"{}\" -> DELAYQUEUE ({} items, next due: {}){}\n",
+                                                 key,
+                                                 queue.len(),
+                                                 queue.next_due().map(|due| due.to_string()).unwrap_or_default(),
+                                                 ttl_info
+                        ));
+                    },
+                    RedisValue::BloomFilter(filter) => {
+                        result.push_str(&format!("\"{}\" -> BLOOM ({} items, capacity {}, error rate {}){}\n",
+                                                 key,
+                                                 filter.items_inserted,
+                                                 filter.capacity,
+                                                 filter.error_rate,
+                                                 ttl_info
+                        ));
+                    },
+                    RedisValue::TimeSeries(series) => {
+                        result.push_str(&format!("\"{}\" -> TIMESERIES ({} samples, retention {}ms, {} rules){}\n",
+                                                 key,
+                                                 series.len(),
+                                                 series.retention_ms,
+                                                 series.rules.len(),
+                                                 ttl_info
+                        ));
+                    },
+                    RedisValue::Json(doc) => {
+                        result.push_str(&format!("\"{}\" -> JSON {}{}\n", key, doc, ttl_info));
+                    },
+                    RedisValue::VectorSet(set) => {
+                        result.push_str(&format!("\"{}\" -> VECTORSET ({} elements, dim {}){}\n", key, set.len(), set.dim, ttl_info));
+                    },
                 }
             }
 
             result.push_str("=== END OF DATABASE ===");
-            result
+            Reply::Verbatim(result)
         },
 
-        Command::Merge { file_path, strategy } => {
-            let mut db_write = db.write().await;
+        Command::Merge { file_path, strategy, throttle, background } => {
+            if db.read().await.migration.is_running() {
+                return Reply::error("ERR a migration is already running, see MIGRATION STATUS");
+            }
 
+            // Loaded without holding the lock, so clients are served while the file is parsed
             let persistence = MmapPersistence::new(file_path.clone());
-            let merge_db = match persistence.load_database() {
-                Ok(db) => db,
-                Err(e) => return format!("(error) ERR failed to load merge file: {}", e),
+            let mut merge_db = match tokio::task::spawn_blocking(move || persistence.load_database().map_err(|e| e.to_string())).await {
+                Ok(Ok(merge_db)) => merge_db,
+                Ok(Err(e)) => return Reply::error(format!("ERR failed to load merge file: {}", e)),
+                Err(e) => return Reply::error(format!("ERR failed to load merge file: {}", e)),
             };
-
-            let mut merged_count = 0;
-            let mut skipped_count = 0;
-            let mut overwritten_count = 0;
-
-            for (key, value) in merge_db.data {
-                let key_exists = db_write.exists(&key);
-
-                match strategy {
-                    MergeStrategy::Overwrite => {
-                        if key_exists {
-                            overwritten_count += 1;
-                        } else {
-                            merged_count += 1;
-                        }
-                        db_write.set(key, value);
-                    },
-
-                    MergeStrategy::Skip => {
-                        if key_exists {
-                            skipped_count += 1;
-                        } else {
-                            db_write.set(key, value);
-                            merged_count += 1;
-                        }
+            let entries: Vec<(String, Arc<RedisValue>, u64)> = std::mem::take(&mut merge_db.data)
+                .into_iter()
+                .map(|(key, value)| {
+                    let bytes = (key.len() + merge_db.memory_manager.calculate_value_size(&value)) as u64;
+                    (key, value, bytes)
+                })
+                .collect();
+            let total_bytes = entries.iter().map(|(_, _, bytes)| bytes).sum();
+
+            let job = {
+                let mut db_write = db.write().await;
+                if db_write.migration.is_running() {
+                    return Reply::error("ERR a migration is already running, see MIGRATION STATUS");
+                }
+                let job = match background {
+                    true => match db_write.jobs.start(JobKind::Merge, &file_path, None, throttle, entries.len() as u64) {
+                        Ok(id) => Some(id),
+                        Err(e) => return Reply::error(e),
                     },
+                    false => None,
+                };
+                db_write.migration = MigrationProgress::start(&file_path, throttle, entries.len() as u64, total_bytes);
+                job
+            };
 
-                    MergeStrategy::Merge => {
-                        if key_exists {
-                            match (db_write.get(&key), &value) {
-                                (Some(RedisValue::List(existing_list)), RedisValue::List(new_list)) => {
-                                    let mut combined_list = existing_list.clone();
-                                    for item in new_list {
-                                        if !combined_list.contains(item) {
-                                            combined_list.push_back(item.clone());
-                                        }
-                                    }
-                                    db_write.set(key, RedisValue::List(combined_list));
-                                    merged_count += 1;
-                                },
-
-                                (Some(RedisValue::Set(existing_set)), RedisValue::Set(new_set)) => {
-                                    let mut combined_set = existing_set.clone();
-                                    for item in new_set {
-                                        combined_set.insert(item.clone());
-                                    }
-                                    db_write.set(key, RedisValue::Set(combined_set));
-                                    merged_count += 1;
-                                },
-
-                                (Some(RedisValue::Hash(existing_hash)), RedisValue::Hash(new_hash)) => {
-                                    let mut combined_hash = existing_hash.clone();
-                                    for (field, val) in new_hash {
-                                        combined_hash.insert(field.clone(), val.clone());
-                                    }
-                                    db_write.set(key, RedisValue::Hash(combined_hash));
-                                    merged_count += 1;
-                                },
-
-                                _ => {
-                                    db_write.set(key, value);
-                                    overwritten_count += 1;
-                                }
-                            }
-                        } else {
-                            db_write.set(key, value);
-                            merged_count += 1;
-                        }
-                    }
-                }
+            if let Some(id) = job {
+                tokio::spawn(merge_entries(Arc::clone(&db), entries, strategy, throttle, job));
+                return Reply::integer(id);
             }
+            merge_entries(Arc::clone(&db), entries, strategy.clone(), throttle, None).await;
+
+            let db_read = db.read().await;
+            let progress = &db_read.migration;
+            Reply::Verbatim(format!(
+                "OK - Merged from '{}' using {:?} strategy\nNew keys: {}\nOverwritten: {}\nSkipped: {}\nErrors: {}",
+                file_path, strategy, progress.new_keys, progress.overwritten, progress.skipped, progress.errors
+            ))
+        },
 
-            format!(
-                "OK - Merged from '{}' using {:?} strategy\nNew keys: {}\nOverwritten: {}\nSkipped: {}",
-                file_path, strategy, merged_count, overwritten_count, skipped_count
-            )
+        Command::MigrationStatus => {
+            Reply::bulk(db.read().await.migration.render())
         },
 
         Command::FlushAll => {
             let mut db_write = db.write().await;
-            db_write.clear();
-            "OK".to_string()
+            db_write.flush_all();
+            Reply::ok()
+        },
+
+        Command::UndoFlush => {
+            let mut db_write = db.write().await;
+            match db_write.undo_flush() {
+                Some(restored) => Reply::integer(restored),
+                None => Reply::error("ERR no FLUSHALL to undo"),
+            }
+        },
+
+        Command::RestoreKey { key, replace } => {
+            let mut db_write = db.write().await;
+            if db_write.trash.retention.is_none() {
+                return Reply::error("ERR the trash is disabled, see --trash-retention-seconds");
+            }
+            match db_write.restore_from_trash(&key, replace) {
+                Ok(()) => Reply::ok(),
+                Err(e) => Reply::error(e),
+            }
         },
 
         Command::Publish { channel, message } => {
             if let Some(pubsub) = pubsub_manager {
-                let pubsub_state = pubsub.read().await;
+                let mut pubsub_state = pubsub.write().await;
                 let count = pubsub_state.publish(&channel, message);
-                format!("(integer) {}", count)
+                Reply::integer(count)
             } else {
-                "(error) ERR Pub/Sub not available".to_string()
+                Reply::error("ERR Pub/Sub not available")
             }
         },
 
         Command::PubSubChannels { pattern } => {
             if let Some(pubsub) = pubsub_manager {
                 let pubsub_state = pubsub.read().await;
-                let channels = pubsub_state.get_channels();
+                let channels = match &client_auth.key_prefix {
+                    Some(prefix) => pubsub_state.get_channels().iter()
+                        .filter_map(|channel| tenancy::unscope(prefix, channel).map(str::to_string))
+                        .collect(),
+                    None => pubsub_state.get_channels(),
+                };
 
                 let filtered: Vec<String> = if let Some(pat) = pattern {
                     channels.into_iter()
@@ -1127,16 +2590,12 @@ pub async fn execute_command(
                 };
 
                 if filtered.is_empty() {
-                    "(empty array)".to_string()
+                    Reply::empty()
                 } else {
-                    filtered.iter()
-                        .enumerate()
-                        .map(|(i, ch)| format!("{}) \"{}\"", i + 1, ch))
-                        .collect::<Vec<_>>()
-                        .join("\n")
+                    Reply::bulks(filtered)
                 }
             } else {
-                "(error) ERR Pub/Sub not available".to_string()
+                Reply::error("ERR Pub/Sub not available")
             }
         },
 
@@ -1145,39 +2604,460 @@ pub async fn execute_command(
                 let pubsub_state = pubsub.read().await;
                 let mut result = Vec::new();
 
+                let prefix = client_auth.key_prefix.as_deref();
                 for channel in channels {
                     let count = pubsub_state.get_channel_subscribers(&channel);
-                    result.push(format!("\"{}\"", channel));
-                    result.push(format!("(integer) {}", count));
+                    // The channel as a tenant named it
+                    result.push(Reply::bulk(prefix.and_then(|prefix| tenancy::unscope(prefix, &channel)).unwrap_or(&channel)));
+                    result.push(Reply::integer(count));
                 }
 
-                if result.is_empty() {
-                    "(empty array)".to_string()
-                } else {
-                    result.iter()
-                        .enumerate()
-                        .map(|(i, item)| format!("{}) {}", i + 1, item))
-                        .collect::<Vec<_>>()
-                        .join("\n")
+                Reply::Array(result)
+            } else {
+                Reply::error("ERR Pub/Sub not available")
+            }
+        },
+
+        Command::PubSubRetention { channel, policy } => {
+            if let Some(pubsub) = pubsub_manager {
+                pubsub.write().await.set_retention(&channel, policy);
+                Reply::ok()
+            } else {
+                Reply::error("ERR Pub/Sub not available")
+            }
+        },
+
+        Command::PubSubReliable { channel, ack_timeout } => {
+            if let Some(pubsub) = pubsub_manager {
+                pubsub.write().await.set_reliable(&channel, ack_timeout);
+                Reply::ok()
+            } else {
+                Reply::error("ERR Pub/Sub not available")
+            }
+        },
+
+        Command::PubSubPending { channel } => {
+            if let Some(pubsub) = pubsub_manager {
+                let counts = pubsub.read().await.pending_counts(channel.as_deref());
+                if counts.is_empty() {
+                    return Reply::empty();
+                }
+                Reply::Array(counts.iter()
+                    .flat_map(|(subscriber_id, count)| [Reply::bulk(format!("subscriber:{}", subscriber_id)), Reply::integer(*count)])
+                    .collect())
+            } else {
+                Reply::error("ERR Pub/Sub not available")
+            }
+        },
+
+        Command::PubSubConsumers => {
+            if let Some(pubsub) = pubsub_manager {
+                let consumers = pubsub.read().await.consumers();
+                if consumers.is_empty() {
+                    return Reply::empty();
                 }
+                Reply::bulks(consumers.iter()
+                    .map(|consumer| format!(
+                        "{} {} subscriptions:{} pending:{}",
+                        consumer.name,
+                        if consumer.attached { "attached" } else { "detached" },
+                        consumer.subscriptions,
+                        consumer.pending
+                    )))
+            } else {
+                Reply::error("ERR Pub/Sub not available")
+            }
+        },
+
+        Command::PubSubStats { count } => {
+            if let Some(pubsub) = pubsub_manager {
+                let state = pubsub.read().await;
+                let channels = state.channel_stats().into_iter().take(count).map(|channel| format!(
+                    "channel:{} subscribers:{} published:{} delivered:{} msg_per_sec:{:.2}",
+                    channel.channel, channel.subscribers, channel.published, channel.delivered, channel.per_sec
+                ));
+                let subscribers = state.subscriber_stats().into_iter().take(count).map(|subscriber| format!(
+                    "subscriber:{} subscriptions:{} queued:{} peak_queued:{} sent:{} pending:{}",
+                    subscriber.id, subscriber.subscriptions, subscriber.queued, subscriber.peak_queued, subscriber.sent, subscriber.pending
+                ));
+                Reply::bulks(channels.chain(subscribers))
             } else {
-                "(error) ERR Pub/Sub not available".to_string()
+                Reply::error("ERR Pub/Sub not available")
             }
         },
 
         Command::PubSubNumPat => {
             if let Some(pubsub) = pubsub_manager {
                 let pubsub_state = pubsub.read().await;
-                format!("(integer) {}", pubsub_state.patterns.len())  // just access fields
+                Reply::integer(pubsub_state.patterns.len())  // just access fields
             } else {
-                "(error) ERR Pub/Sub not available".to_string()
+                Reply::error("ERR Pub/Sub not available")
             }
         },
         Command::Subscribe { .. } | Command::Unsubscribe { .. } |
-        Command::PSubscribe { .. } | Command::PUnsubscribe { .. } => {
-            "(error) ERR only allowed in subscriber mode".to_string()
+        Command::PSubscribe { .. } | Command::PUnsubscribe { .. } | Command::Ack { .. } => {
+            Reply::error("ERR only allowed in subscriber mode")
+        },
+
+        // The connection owns the frozen copy, so the server handles these itself
+        Command::SnapshotBegin | Command::SnapshotEnd => {
+            Reply::error("ERR SNAPSHOT is only available on client connections")
+        },
+
+        // The commands it covers are the connection's next requests, so the server runs it
+        Command::Atomic { .. } => {
+            Reply::error("ERR ATOMIC is only available on client connections")
+        },
+
+        // Only the server knows the connections, so it answers these itself
+        Command::ClientList { .. } | Command::ClientId => {
+            Reply::error("ERR CLIENT is only available on client connections")
+        },
+
+        Command::DebugSetRngSeed { seed } => {
+            db.write().await.rng.reseed(seed);
+            Reply::ok()
+        },
+
+        Command::DebugPersistenceBench { ops } => {
+            let (snapshot, scratch_prefix) = {
+                let db_read = db.read().await;
+                let scratch_prefix = db_read.snapshot_path.clone().unwrap_or_else(|| std::env::temp_dir().join("mini_redis"));
+                (Snapshot::capture(&db_read), scratch_prefix)
+            };
+            // Disk I/O and serialization must not stall the async workers
+            match tokio::task::spawn_blocking(move || persistence_bench::run(snapshot, &scratch_prefix, ops)).await {
+                Ok(Ok(report)) => Reply::bulk(report.render()),
+                Ok(Err(e)) => Reply::error(format!("ERR persistence benchmark failed: {}", e)),
+                Err(e) => Reply::error(format!("ERR persistence benchmark failed: {}", e)),
+            }
+        },
+
+        // Exercises the connection's panic guard
+        Command::DebugPanic => panic!("DEBUG PANIC"),
+
+        Command::Quit => Reply::ok(),
+        _ => Reply::Simple(String::new())    }
+}
+
+// Picks random fields without copying the whole hash: a positive count samples distinct
+// fields (reservoir sampling, never more than the hash holds), a negative count allows
+// repetitions (drawn one at a time), matching HRANDFIELD semantics
+fn sample_hash_fields(hash: &HashMap<String, String>, count: i64, rng: &mut CommandRng) -> Vec<(String, String)> {
+    use rand::seq::{IteratorRandom, SliceRandom};
+    use rand::Rng;
+
+    if hash.is_empty() || count == 0 {
+        return Vec::new();
+    }
+    // Seeded picks have to walk the fields in the same order every run
+    let mut sorted: Vec<(&String, &String)> = Vec::new();
+    let entries: Box<dyn Iterator<Item = (&String, &String)>> = match rng.seed() {
+        Some(_) => {
+            sorted.extend(hash.iter());
+            rng.canonical_order(&mut sorted);
+            Box::new(sorted.into_iter())
+        },
+        None => Box::new(hash.iter()),
+    };
+    let rng = rng.rng();
+
+    if count > 0 {
+        let count = (count as u64).min(hash.len() as u64) as usize;
+        let mut picked: Vec<(String, String)> = entries
+            .choose_multiple(rng, count)
+            .into_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        picked.shuffle(rng);
+        return picked;
+    }
+
+    let entries: Vec<(&String, &String)> = entries.collect();
+    let mut picked = Vec::new();
+    for _ in 0..count.unsigned_abs() {
+        let (field, value) = entries[rng.gen_range(0..entries.len())];
+        picked.push((field.clone(), value.clone()));
+    }
+    picked
+}
+
+// Approximate reply size of an array of these strings: each element adds `N) ""` and a newline
+fn elements_size<'a>(elements: impl Iterator<Item = &'a String>) -> usize {
+    elements.map(|element| element.len() + 8).sum()
+}
+
+// Refuses a reply estimated at `estimated` bytes before it is built, if that is over the
+// configured limit
+fn reply_too_large(db: &RedisDatabase, estimated: usize) -> Option<Reply> {
+    let limit = db.max_reply_bytes?;
+    (estimated > limit).then(|| reply_too_large_error(estimated, limit))
+}
+
+pub fn reply_too_large_error(size: usize, limit: usize) -> Reply {
+    Reply::error(format!("ERR reply of about {} bytes exceeds max-reply-bytes ({}); fetch it in parts, e.g. LRANGE in pages or HGET by field", size, limit))
+}
+
+// Refuses a variadic command naming more keys than the configured limit
+fn too_many_keys(db: &RedisDatabase, count: usize) -> Option<Reply> {
+    let limit = db.max_keys_per_command?;
+    (count > limit).then(|| Reply::error(format!("ERR command names {} keys, more than max-keys-per-command ({})", count, limit)))
+}
+
+// INCR and DECR: adds `delta` to the integer at `key`, starting from 0 if it is missing
+fn increment_key(db: &mut RedisDatabase, key: String, delta: i64) -> Reply {
+    let current = match db.get(&key) {
+        Some(RedisValue::Integer(i)) => i,
+        Some(RedisValue::String(s)) => match s.parse::<i64>() {
+            Ok(i) => i,
+            Err(_) => return Reply::error("ERR value is not an integer or out of range"),
+        },
+        Some(_) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+        None => 0,
+    };
+
+    match current.checked_add(delta) {
+        Some(new_val) => {
+            let _ = db.set(key, RedisValue::Integer(new_val));
+            Reply::integer(new_val)
+        },
+        None => Reply::error(OVERFLOW_ERROR),
+    }
+}
+
+// INCRBYFLOAT: the result is kept as a string in its shortest exact decimal form, without an
+// exponent or a trailing ".0", so "10.5" plus 0.1 reads back as "10.6" and 2.5 plus 0.5 as "3"
+fn increment_key_by_float(db: &mut RedisDatabase, key: String, delta: f64) -> Reply {
+    let current = match db.get(&key).map(RedisValue::into_string) {
+        Some(Some(s)) => match s.parse::<f64>() {
+            Ok(f) if f.is_finite() => f,
+            _ => return Reply::error("ERR value is not a valid float"),
+        },
+        Some(None) => return Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+        None => 0.0,
+    };
+
+    let new_val = current + delta;
+    if !new_val.is_finite() {
+        return Reply::error("ERR increment would produce NaN or Infinity");
+    }
+    // -0 would read back oddly; Redis has no negative zero either
+    let formatted = (new_val + 0.0).to_string();
+    let _ = db.set(key, RedisValue::String(formatted.clone()));
+    Reply::bulk(formatted)
+}
+
+// Applies entries in throttled batches, recording progress and stopping if `job` is cancelled
+async fn merge_entries(db: Database, entries: Vec<(String, Arc<RedisValue>, u64)>, strategy: MergeStrategy, throttle: Throttle, job: Option<u64>) {
+    let started = Instant::now();
+    let mut entries = entries.into_iter();
+    loop {
+        let batch: Vec<_> = entries.by_ref().take(MIGRATION_BATCH).collect();
+        if batch.is_empty() {
+            break;
+        }
+        let (moved_keys, moved_bytes) = {
+            let mut db_write = db.write().await;
+            if job.is_some_and(|id| db_write.jobs.running(id).is_none()) {
+                break;
+            }
+            for (key, value, bytes) in batch {
+                let outcome = merge_key(&mut db_write, key, Arc::unwrap_or_clone(value), &strategy);
+                db_write.migration.record(bytes, outcome);
+            }
+            let progress = &db_write.migration;
+            let moved = (progress.moved_keys, progress.moved_bytes);
+            let counters = [("new_keys", progress.new_keys), ("overwritten", progress.overwritten), ("skipped", progress.skipped), ("errors", progress.errors)];
+            if let Some(job) = job.and_then(|id| db_write.jobs.running(id)) {
+                job.done = moved.0;
+                job.counters.extend(counters);
+            }
+            moved
+        };
+        match throttle.delay(moved_keys, moved_bytes, started.elapsed()) {
+            Duration::ZERO => tokio::task::yield_now().await,
+            delay => tokio::time::sleep(delay).await,
+        }
+    }
+
+    let mut db_write = db.write().await;
+    db_write.migration.finish();
+    if let Some(id) = job {
+        db_write.jobs.finish(id);
+    }
+}
+
+// Applies one key of a MERGE: OVERWRITE replaces, SKIP keeps what is here, MERGE combines
+// lists, sets and hashes and replaces anything else
+fn merge_key(db: &mut RedisDatabase, key: String, value: RedisValue, strategy: &MergeStrategy) -> Result<MergeOutcome, String> {
+    if !db.exists(&key) {
+        db.set(key, value)?;
+        return Ok(MergeOutcome::New);
+    }
+    let (combined, outcome) = match (strategy, db.get(&key), value) {
+        (MergeStrategy::Skip, _, _) => return Ok(MergeOutcome::Skipped),
+        (MergeStrategy::Merge, Some(RedisValue::List(mut existing)), RedisValue::List(new)) => {
+            for item in new {
+                if !existing.contains(&item) {
+                    existing.push_back(item);
+                }
+            }
+            (RedisValue::List(existing), MergeOutcome::Combined)
+        },
+        (MergeStrategy::Merge, Some(RedisValue::Set(mut existing)), RedisValue::Set(new)) => {
+            existing.extend(new);
+            (RedisValue::Set(existing), MergeOutcome::Combined)
+        },
+        (MergeStrategy::Merge, Some(RedisValue::Hash(mut existing)), RedisValue::Hash(new)) => {
+            existing.extend(new);
+            (RedisValue::Hash(existing), MergeOutcome::Combined)
+        },
+        (_, _, value) => (value, MergeOutcome::Overwritten),
+    };
+    db.set(key, combined)?;
+    Ok(outcome)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Pops up to `count` elements from an end of the list at `key`, in the order they came off,
+/// deleting the key once it is empty. None when there is no list there.
+fn pop_list(db: &mut RedisDatabase, key: &str, end: ListEnd, count: usize) -> Result<Option<Vec<String>>, Reply> {
+    let mut list = match db.get(key) {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Err(Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value")),
+        None => return Ok(None),
+    };
+    let count = count.min(list.len());
+    let values: Vec<String> = match end {
+        ListEnd::Left => list.drain(..count).collect(),
+        ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+    };
+    if list.is_empty() {
+        db.delete(key);
+    } else if !values.is_empty() {
+        let _ = db.set(key.to_string(), RedisValue::List(list));
+    }
+    Ok(Some(values))
+}
+
+// LPOP and RPOP: a single element without a count, an array with one
+fn pop_reply(db: &mut RedisDatabase, key: &str, end: ListEnd, count: Option<usize>) -> Reply {
+    match pop_list(db, key, end, count.unwrap_or(1)) {
+        Err(e) => e,
+        Ok(None) => Reply::Nil,
+        Ok(Some(values)) => match count {
+            None => values.into_iter().next().map_or(Reply::Nil, Reply::Bulk),
+            Some(_) => Reply::bulks(values),
+        },
+    }
+}
+
+/// Moves one element between lists for LMOVE and BLMOVE, returning it; None when the source
+/// has no list.
+fn move_list_element(db: &mut RedisDatabase, source: String, destination: String, from: ListEnd, to: ListEnd) -> Result<Option<String>, Reply> {
+    let mut list = match db.get(&source) {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Err(Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value")),
+        None => return Ok(None),
+    };
+    // The destination is checked before anything moves; None when it is the source
+    let target = if source == destination {
+        None
+    } else {
+        match db.get(&destination) {
+            Some(RedisValue::List(target)) => Some(target),
+            Some(_) => return Err(Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value")),
+            None => Some(VecDeque::new()),
+        }
+    };
+    let value = match from {
+        ListEnd::Left => list.pop_front(),
+        ListEnd::Right => list.pop_back(),
+    };
+    let Some(value) = value else { return Ok(None) };
+    let push = |list: &mut VecDeque<String>| match to {
+        ListEnd::Left => list.push_front(value.clone()),
+        ListEnd::Right => list.push_back(value.clone()),
+    };
+
+    match target {
+        Some(mut target) => {
+            push(&mut target);
+            if list.is_empty() {
+                db.delete(&source);
+            } else {
+                let _ = db.set(source, RedisValue::List(list));
+            }
+            let _ = db.set(destination, RedisValue::List(target));
         },
+        None => {
+            push(&mut list);
+            let _ = db.set(source, RedisValue::List(list));
+        },
+    }
+    Ok(Some(value))
+}
+
+/// Runs `attempt` under the write lock until it replies, parking on `keys` in between, or
+/// replies nil once `timeout` passes. A zero timeout waits for good.
+async fn block_on_lists<F>(db: &Database, keys: &[String], timeout: Duration, mut attempt: F) -> Reply
+where
+    F: FnMut(&mut RedisDatabase) -> Option<Reply>,
+{
+    let deadline = if timeout.is_zero() { None } else { Some(tokio::time::Instant::now() + timeout) };
+    let mut parked = None;
+    loop {
+        let parked = {
+            let mut db_write = db.write().await;
+            if let Some(reply) = attempt(&mut db_write) {
+                return reply;
+            }
+            // Parked before the lock is released, so a push right after is not missed
+            &*parked.get_or_insert_with(|| db_write.waiters.park(keys))
+        };
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, parked.woken()).await.is_err() {
+                    return Reply::Nil;
+                }
+            },
+            None => parked.woken().await,
+        }
+    }
+}
 
-        Command::Quit => "OK".to_string(),
-        _ => String::new()    }
+fn pop_ready_delayed(db: &mut RedisDatabase, key: &str, count: usize) -> Result<Vec<String>, Reply> {
+    // Only a pop that removes something is a write, so a polling DELAYQ BPOP stays a read
+    let now_ms = unix_millis();
+    match db.get_ref(key) {
+        Some(RedisValue::DelayQueue(queue)) if queue.ready_count(now_ms) > 0 => {},
+        Some(RedisValue::DelayQueue(_)) | None => return Ok(Vec::new()),
+        Some(_) => return Err(Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value")),
+    }
+    let Some(RedisValue::DelayQueue(queue)) = db.get_mut(key) else {
+        return Ok(Vec::new());
+    };
+
+    let popped = queue.pop_ready(now_ms, count);
+    if queue.is_empty() {
+        db.delete(key);
+    }
+    Ok(popped)
+}
+
+fn format_integers(values: &[i64]) -> Reply {
+    Reply::Array(values.iter().map(|value| Reply::Integer(*value)).collect())
+}
+
+fn format_flags(flags: &[bool], multi: bool) -> Reply {
+    if !multi {
+        return Reply::integer(flags.first().copied().unwrap_or(false));
+    }
+    Reply::Array(flags.iter().map(|flag| Reply::integer(*flag)).collect())
 }
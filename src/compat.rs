@@ -0,0 +1,144 @@
+// Redis parity as a regression test: fixture files hold redis-cli sessions captured from a real
+// Redis, and each one is replayed against a fresh database here. Replies are compared the way a
+// RESP client would see them, so `(empty list or set)` and `(empty array)` are the same reply,
+// while a different error message or integer is a mismatch.
+use crate::auth::{AuthConfig, ClientAuth};
+use crate::commands::execute_command;
+use crate::protocol::{parse_command, Reply};
+use crate::shared::create_database;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// One command of a fixture and the reply Redis gave it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub line: usize,
+    pub command: String,
+    pub expected: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    pub name: String,
+    pub cases: Vec<Case>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub fixture: String,
+    pub line: usize,
+    pub command: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}\n  expected: {}\n  actual:   {}", self.fixture, self.line, self.command, self.expected, self.actual)
+    }
+}
+
+// The command on a prompt line: `> GET k`, or as redis-cli prints it, `127.0.0.1:6379> GET k`
+fn command_line(line: &str) -> Option<&str> {
+    let (prompt, command) = line.split_once("> ")?;
+    (!prompt.contains([' ', '"'])).then_some(command.trim())
+}
+
+/// Reads a session: prompt lines hold commands, and the lines up to the next prompt, blank line
+/// or `#` comment are the reply.
+pub fn parse_fixture(name: &str, text: &str) -> Result<Fixture, String> {
+    let mut cases: Vec<Case> = Vec::new();
+    let mut in_reply = false;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            in_reply = false;
+            continue;
+        }
+        if let Some(command) = command_line(line) {
+            if let Some(case) = cases.last().filter(|case| case.expected.is_empty()) {
+                return Err(format!("{}:{}: no reply for {}", name, case.line, case.command));
+            }
+            cases.push(Case { line: number + 1, command: command.to_string(), expected: String::new() });
+            in_reply = true;
+            continue;
+        }
+        match cases.last_mut() {
+            Some(case) if in_reply => {
+                if !case.expected.is_empty() {
+                    case.expected.push('\n');
+                }
+                case.expected.push_str(line);
+            },
+            _ => return Err(format!("{}:{}: reply without a command", name, number + 1)),
+        }
+    }
+    if let Some(case) = cases.last().filter(|case| case.expected.is_empty()) {
+        return Err(format!("{}:{}: no reply for {}", name, case.line, case.command));
+    }
+    Ok(Fixture { name: name.to_string(), cases })
+}
+
+/// Every `.txt` fixture in `dir`, by file name.
+pub fn load_fixtures(dir: &Path) -> Result<Vec<Fixture>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+        .collect();
+    paths.sort();
+    paths.iter().map(|path| {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        parse_fixture(&name, &text)
+    }).collect()
+}
+
+/// Runs a fixture's commands in order on an empty database, returning the replies that differ.
+pub async fn check_fixture(fixture: &Fixture) -> Vec<Mismatch> {
+    let db = create_database();
+    let mut client_auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut mismatches = Vec::new();
+    for case in &fixture.cases {
+        let actual = match parse_command(&case.command) {
+            Ok(command) => execute_command(Arc::clone(&db), command, &mut client_auth, None, None).await,
+            Err(error) => Reply::error(error),
+        };
+        if actual.encode() != Reply::from_text(&case.expected).encode() {
+            mismatches.push(Mismatch {
+                fixture: fixture.name.clone(),
+                line: case.line,
+                command: case.command.clone(),
+                expected: case.expected.clone(),
+                actual: actual.to_text(),
+            });
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions_are_split_into_cases() {
+        let text = "# captured from Redis 7.2\n127.0.0.1:6379> RPUSH l a \"b c\"\n(integer) 2\n\n> LRANGE l 0 -1\n1) \"a\"\n2) \"b c\"\n";
+        let fixture = parse_fixture("lists.txt", text).unwrap();
+        assert_eq!(fixture.cases, vec![
+            Case { line: 2, command: "RPUSH l a \"b c\"".into(), expected: "(integer) 2".into() },
+            Case { line: 5, command: "LRANGE l 0 -1".into(), expected: "1) \"a\"\n2) \"b c\"".into() },
+        ]);
+
+        assert_eq!(parse_fixture("f.txt", "> GET k\n> GET j\n(nil)").unwrap_err(), "f.txt:1: no reply for GET k");
+        assert_eq!(parse_fixture("f.txt", "> GET k\n(nil)\n\n\"v\"").unwrap_err(), "f.txt:4: reply without a command");
+    }
+
+    #[tokio::test]
+    async fn test_replies_are_compared_as_resp() {
+        let text = "> RPUSH l a\n(integer) 1\n> LRANGE missing 0 -1\n(empty list or set)\n> LLEN l\n(integer) 2\n> GET\n(error) ERR wrong number of arguments for 'get' command";
+        let mismatches = check_fixture(&parse_fixture("f.txt", text).unwrap()).await;
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].to_string(), "f.txt:5: LLEN l\n  expected: (integer) 2\n  actual:   (integer) 1");
+    }
+}
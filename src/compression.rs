@@ -0,0 +1,217 @@
+// LZ4 block format (https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md), used to
+// keep large string values compressed in memory. Greedy single-probe matcher: it trades some
+// ratio against the reference implementation for simplicity, the output stays standard LZ4.
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const MIN_MATCH: usize = 4;
+// The format requires the last 5 bytes to be literals and the last match to start at least
+// 12 bytes before the end of the block
+const LAST_LITERALS: usize = 5;
+const MF_LIMIT: usize = 12;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]])
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_length(out: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        out.push(255);
+        length -= 255;
+    }
+    out.push(length as u8);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], offset_and_match: Option<(usize, usize)>) {
+    let literal_code = literals.len().min(15);
+    let match_code = offset_and_match.map_or(0, |(_, length)| (length - MIN_MATCH).min(15));
+    out.push(((literal_code << 4) | match_code) as u8);
+    if literal_code == 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, length)) = offset_and_match {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_code == 15 {
+            write_length(out, length - MIN_MATCH - 15);
+        }
+    }
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    let mut anchor = 0;
+
+    if input.len() > MF_LIMIT {
+        // Positions are stored plus one so that zero means empty
+        let mut table = vec![0usize; 1 << HASH_BITS];
+        let match_limit = input.len() - LAST_LITERALS;
+        let mut pos = 0;
+
+        while pos < input.len() - MF_LIMIT {
+            let sequence = read_u32(input, pos);
+            let slot = hash(sequence);
+            let candidate = table[slot];
+            table[slot] = pos + 1;
+
+            if candidate > 0 && pos - (candidate - 1) <= MAX_OFFSET && read_u32(input, candidate - 1) == sequence {
+                let start = candidate - 1;
+                let mut length = MIN_MATCH;
+                while pos + length < match_limit && input[start + length] == input[pos + length] {
+                    length += 1;
+                }
+                write_sequence(&mut out, &input[anchor..pos], Some((pos - start, length)));
+                pos += length;
+                anchor = pos;
+            } else {
+                pos += 1;
+            }
+        }
+    }
+
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+fn read_length(input: &[u8], pos: &mut usize) -> Result<usize, String> {
+    let mut length = 0usize;
+    loop {
+        let byte = *input.get(*pos).ok_or_else(corrupt)?;
+        *pos += 1;
+        length = length.checked_add(byte as usize).ok_or_else(corrupt)?;
+        if byte != 255 {
+            return Ok(length);
+        }
+    }
+}
+
+fn corrupt() -> String {
+    "ERR corrupt compressed value".to_string()
+}
+
+/// Decompresses a block that must expand to exactly `original_len` bytes.
+pub fn decompress(input: &[u8], original_len: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(original_len);
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let token = input[pos];
+        pos += 1;
+
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals += read_length(input, &mut pos)?;
+        }
+        let literals_end = pos.checked_add(literals).filter(|end| *end <= input.len()).ok_or_else(corrupt)?;
+        if out.len() + literals > original_len {
+            return Err(corrupt());
+        }
+        out.extend_from_slice(&input[pos..literals_end]);
+        pos = literals_end;
+
+        // The last sequence has no match part
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = match input.get(pos..pos + 2) {
+            Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+            None => return Err(corrupt()),
+        };
+        pos += 2;
+        if offset == 0 || offset > out.len() {
+            return Err(corrupt());
+        }
+
+        let mut length = (token & 15) as usize + MIN_MATCH;
+        if token & 15 == 15 {
+            length += read_length(input, &mut pos)?;
+        }
+        if out.len() + length > original_len {
+            return Err(corrupt());
+        }
+        // Byte by byte, since a match may overlap the bytes it is producing
+        let start = out.len() - offset;
+        for i in 0..length {
+            out.push(out[start + i]);
+        }
+    }
+
+    if out.len() != original_len {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+/// A string value held LZ4-compressed. Serialized as the plain string, so snapshots do not
+/// depend on the compression and are recompressed on load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedString {
+    original_len: usize,
+    data: Vec<u8>,
+}
+
+impl CompressedString {
+    pub fn new(text: &str) -> Self {
+        Self { original_len: text.len(), data: compress(text.as_bytes()) }
+    }
+
+    pub fn decompress(&self) -> String {
+        let bytes = decompress(&self.data, self.original_len).expect("compressed values are only built by CompressedString::new");
+        String::from_utf8(bytes).expect("compressed values are only built from strings")
+    }
+
+    pub fn original_len(&self) -> usize {
+        self.original_len
+    }
+
+    pub fn stored_len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl Serialize for CompressedString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.decompress())
+    }
+}
+
+impl<'de> Deserialize<'de> for CompressedString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|text| Self::new(&text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let repetitive = "abcdefgh".repeat(1000) + &"x".repeat(300) + "tail";
+        let mixed: String = (0..5000u32).map(|i| char::from(b'a' + (i.wrapping_mul(7919) % 26) as u8)).collect();
+        for text in [repetitive.as_str(), mixed.as_str(), "", "short", "aaaaaaaaaaaaaaaaaaaa"] {
+            let compressed = compress(text.as_bytes());
+            assert_eq!(decompress(&compressed, text.len()).unwrap(), text.as_bytes());
+        }
+
+        let value = CompressedString::new(&repetitive);
+        assert!(value.stored_len() * 20 < value.original_len());
+        assert_eq!(value.decompress(), repetitive);
+    }
+
+    #[test]
+    fn test_corrupt_input_is_rejected() {
+        let compressed = compress("hello hello hello hello hello".as_bytes());
+        assert!(decompress(&compressed, 28).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1], 29).is_err());
+        // A match pointing before the start of the output
+        assert!(decompress(&[0x10, b'a', 0x05, 0x00], 10).is_err());
+    }
+}
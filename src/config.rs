@@ -0,0 +1,329 @@
+// Startup settings as typed values, checked as a whole before the server starts. Parsing a
+// flag and checking how flags combine both report into one Diagnostics, so a bad start lists
+// every problem at once, each against the flag it concerns, instead of stopping at the first or
+// quietly falling back to a default. Errors refuse the start; warnings are printed and the
+// server starts anyway.
+use crate::memory::{format_bytes, EvictionPolicy};
+use crate::persistence_clean::SaveRule;
+use crate::resp::ProtocolLimits;
+use crate::save_scheduler::{DEFAULT_MAX_SAVE_INTERVAL, DEFAULT_MIN_SAVE_INTERVAL};
+use crate::server::MemoryPreflight;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Below this, maxmemory cannot hold even a handful of keys
+pub const MIN_MAXMEMORY: usize = 1024;
+// Below this, most writes end up evicting or refused
+const LOW_MAXMEMORY: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One problem with the configuration, against the flag it concerns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub setting: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{:<8} --{}: {}", severity, self.setting, self.message)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub findings: Vec<Finding>,
+}
+
+impl Diagnostics {
+    pub fn error(&mut self, setting: &'static str, message: impl Into<String>) {
+        self.findings.push(Finding { severity: Severity::Error, setting, message: message.into() });
+    }
+
+    pub fn warning(&mut self, setting: &'static str, message: impl Into<String>) {
+        self.findings.push(Finding { severity: Severity::Warning, setting, message: message.into() });
+    }
+
+    /// Parses the value given for `setting`, recording an error and returning None if it is
+    /// invalid.
+    pub fn parse<T>(&mut self, setting: &'static str, value: &str, parse: impl FnOnce(&str) -> Result<T, String>) -> Option<T> {
+        parse(value).map_err(|e| self.error(setting, format!("invalid value '{}': {}", value, e))).ok()
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|finding| finding.severity == severity).count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    /// The report printed at startup: a summary line, then one line per finding, errors first.
+    pub fn report(&self) -> String {
+        if self.findings.is_empty() {
+            return "Configuration check: OK".to_string();
+        }
+        let mut lines = vec![format!("Configuration check: {} error(s), {} warning(s)", self.count(Severity::Error), self.count(Severity::Warning))];
+        for severity in [Severity::Error, Severity::Warning] {
+            lines.extend(self.findings.iter().filter(|finding| finding.severity == severity).map(|finding| format!("  {}", finding)));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Where keys live: all in memory, or the least recently used beyond a limit on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageMode {
+    #[default]
+    Memory,
+    Disk,
+}
+
+impl StorageMode {
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "memory" => Ok(StorageMode::Memory),
+            "disk" => Ok(StorageMode::Disk),
+            _ => Err("expected memory or disk".to_string()),
+        }
+    }
+}
+
+/// The settings checked before startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub port: u16,
+    pub http_port: Option<u16>,
+    pub memcached_port: Option<u16>,
+    // A password, auth backend or tenant is configured
+    pub requires_auth: bool,
+    pub dbfilename: PathBuf,
+    pub maxmemory: Option<usize>,
+    // None when not given, which means allkeys-lru
+    pub eviction_policy: Option<EvictionPolicy>,
+    pub memory_preflight: MemoryPreflight,
+    pub storage_mode: StorageMode,
+    pub storage_dir: PathBuf,
+    pub storage_hot_keys: usize,
+    pub compression_threshold: Option<usize>,
+    pub save_rules: Vec<SaveRule>,
+    pub save_interval_min: Duration,
+    pub save_interval_max: Duration,
+    pub write_stall_after: Option<u64>,
+    pub write_reject_after: Option<u64>,
+    pub trash_retention: Option<Duration>,
+    pub max_reply_bytes: Option<usize>,
+    pub protocol_limits: ProtocolLimits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 6380,
+            http_port: None,
+            memcached_port: None,
+            requires_auth: false,
+            dbfilename: PathBuf::from("dump.rdb"),
+            maxmemory: None,
+            eviction_policy: None,
+            memory_preflight: MemoryPreflight::default(),
+            storage_mode: StorageMode::default(),
+            storage_dir: PathBuf::from("data"),
+            storage_hot_keys: 100_000,
+            compression_threshold: None,
+            save_rules: Vec::new(),
+            save_interval_min: DEFAULT_MIN_SAVE_INTERVAL,
+            save_interval_max: DEFAULT_MAX_SAVE_INTERVAL,
+            write_stall_after: None,
+            write_reject_after: None,
+            trash_retention: None,
+            max_reply_bytes: None,
+            protocol_limits: ProtocolLimits::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.eviction_policy.unwrap_or(EvictionPolicy::AllKeysLru)
+    }
+
+    /// Checks how the settings combine, adding what is wrong to `diagnostics`.
+    pub fn validate(&self, diagnostics: &mut Diagnostics) {
+        self.check_memory(diagnostics);
+        self.check_persistence(diagnostics);
+        self.check_network(diagnostics);
+        self.check_limits(diagnostics);
+    }
+
+    fn check_memory(&self, diagnostics: &mut Diagnostics) {
+        match self.maxmemory {
+            Some(bytes) if bytes < MIN_MAXMEMORY => {
+                diagnostics.error("maxmemory", format!("{} is below the minimum of {}", format_bytes(bytes), format_bytes(MIN_MAXMEMORY)));
+            },
+            Some(bytes) if bytes < LOW_MAXMEMORY => {
+                diagnostics.warning("maxmemory", format!("{} leaves little room; most writes will evict keys or be refused", format_bytes(bytes)));
+            },
+            Some(_) => {
+                if self.trash_retention.is_some() {
+                    diagnostics.warning("trash-retention-seconds", "deleted keys kept for RESTOREKEY are not counted against --maxmemory");
+                }
+            },
+            None => {
+                if let Some(policy) = self.eviction_policy {
+                    diagnostics.warning("maxmemory-policy", format!("{} has no effect without --maxmemory", policy.name()));
+                }
+                if self.memory_preflight == MemoryPreflight::Refuse {
+                    diagnostics.warning("maxmemory-preflight", "refuse has no effect without --maxmemory");
+                }
+            },
+        }
+    }
+
+    fn check_persistence(&self, diagnostics: &mut Diagnostics) {
+        if self.save_interval_min.is_zero() {
+            diagnostics.error("save-interval-min", "must be at least 1");
+        } else if self.save_interval_min > self.save_interval_max {
+            diagnostics.error("save-interval-min", format!("must not be above --save-interval-max ({})", self.save_interval_max.as_secs()));
+        }
+        for rule in &self.save_rules {
+            if rule.changes == 0 {
+                diagnostics.error("save", format!("rule {} {} needs at least 1 change", rule.seconds, rule.changes));
+            } else if Duration::from_secs(rule.seconds) > self.save_interval_max {
+                diagnostics.warning("save", format!("rule {} {} never fires: unsaved changes are saved every {} seconds at most", rule.seconds, rule.changes, self.save_interval_max.as_secs()));
+            }
+        }
+        if let (Some(stall_after), Some(reject_after)) = (self.write_stall_after, self.write_reject_after) {
+            if reject_after < stall_after {
+                diagnostics.error("write-reject-after", format!("{} must not be below --write-stall-after ({})", reject_after, stall_after));
+            }
+        }
+        if self.storage_mode == StorageMode::Disk {
+            if self.storage_hot_keys == 0 {
+                diagnostics.error("storage-hot-keys", "must be at least 1 with --storage-engine disk");
+            }
+            if inside(&self.dbfilename, &self.storage_dir) {
+                diagnostics.error("dbfilename", format!("{} is inside --storage-dir {}, which belongs to the disk engine", self.dbfilename.display(), self.storage_dir.display()));
+            }
+        }
+    }
+
+    fn check_network(&self, diagnostics: &mut Diagnostics) {
+        // Port 0 picks a free port, so it never clashes
+        for (setting, port) in [("http-port", self.http_port), ("memcached-port", self.memcached_port)] {
+            if port.is_some_and(|port| port != 0 && port == self.port) {
+                diagnostics.error(setting, format!("{} is already used by --port", self.port));
+            }
+        }
+        if self.http_port.is_some_and(|port| port != 0 && self.memcached_port == Some(port)) {
+            diagnostics.error("memcached-port", format!("{} is already used by --http-port", self.memcached_port.unwrap_or_default()));
+        }
+        if self.memcached_port.is_some() && self.requires_auth {
+            diagnostics.error("memcached-port", "cannot be combined with --password, --auth-backend or --tenant: the memcached text protocol has no authentication");
+        }
+    }
+
+    fn check_limits(&self, diagnostics: &mut Diagnostics) {
+        for (setting, limit) in [
+            ("max-reply-bytes", self.max_reply_bytes.unwrap_or(1)),
+            ("proto-max-inline-len", self.protocol_limits.max_inline_len),
+            ("proto-max-bulk-len", self.protocol_limits.max_bulk_len),
+            ("proto-max-multibulk-len", self.protocol_limits.max_multibulk_len),
+        ] {
+            if limit == 0 {
+                diagnostics.error(setting, "must be above 0");
+            }
+        }
+    }
+}
+
+// Whether `path` is `dir` or lies under it, compared as written
+fn inside(path: &Path, dir: &Path) -> bool {
+    let normal = |path: &Path| path.components().filter(|component| component.as_os_str() != ".").collect::<PathBuf>();
+    normal(path).starts_with(normal(dir))
+}
+
+/// Parses a size such as 512, 64KB, 100MB or 1GB into bytes.
+pub fn parse_memory_size(size: &str) -> Result<usize, String> {
+    let size = size.to_uppercase();
+    let (number, unit) = if let Some(number) = size.strip_suffix("KB") {
+        (number, 1024)
+    } else if let Some(number) = size.strip_suffix("MB") {
+        (number, 1024 * 1024)
+    } else if let Some(number) = size.strip_suffix("GB") {
+        (number, 1024 * 1024 * 1024)
+    } else if let Some(number) = size.strip_suffix("B") {
+        (number, 1)
+    } else {
+        // Assume bytes if no suffix
+        (size.as_str(), 1)
+    };
+    let number: usize = number.parse().map_err(|e: std::num::ParseIntError| e.to_string())?;
+    number.checked_mul(unit).ok_or_else(|| "too large".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(config: &Config) -> Vec<(Severity, &'static str)> {
+        let mut diagnostics = Diagnostics::default();
+        config.validate(&mut diagnostics);
+        diagnostics.findings.iter().map(|finding| (finding.severity, finding.setting)).collect()
+    }
+
+    #[test]
+    fn test_invalid_combinations_are_all_reported() {
+        assert!(check(&Config::default()).is_empty());
+
+        let config = Config {
+            maxmemory: Some(512),
+            storage_mode: StorageMode::Disk,
+            dbfilename: PathBuf::from("./data/dump.rdb"),
+            save_rules: vec![SaveRule { seconds: 60, changes: 0 }, SaveRule { seconds: 3600, changes: 1 }],
+            write_stall_after: Some(100),
+            write_reject_after: Some(10),
+            memcached_port: Some(6380),
+            requires_auth: true,
+            ..Config::default()
+        };
+        assert_eq!(check(&config), vec![
+            (Severity::Error, "maxmemory"),
+            (Severity::Error, "save"),
+            (Severity::Warning, "save"),
+            (Severity::Error, "write-reject-after"),
+            (Severity::Error, "dbfilename"),
+            (Severity::Error, "memcached-port"),
+            (Severity::Error, "memcached-port"),
+        ]);
+
+        let config = Config { eviction_policy: Some(EvictionPolicy::VolatileLru), memory_preflight: MemoryPreflight::Refuse, ..Config::default() };
+        assert_eq!(check(&config), vec![(Severity::Warning, "maxmemory-policy"), (Severity::Warning, "maxmemory-preflight")]);
+    }
+
+    #[test]
+    fn test_report_lists_errors_before_warnings() {
+        let mut diagnostics = Diagnostics::default();
+        assert_eq!(diagnostics.parse("maxmemory", "100mb", parse_memory_size), Some(100 * 1024 * 1024));
+        diagnostics.warning("maxmemory-policy", "noeviction has no effect without --maxmemory");
+        assert_eq!(diagnostics.parse("maxmemory", "lots", parse_memory_size), None);
+        assert!(diagnostics.has_errors());
+        assert_eq!(diagnostics.report(), [
+            "Configuration check: 1 error(s), 1 warning(s)",
+            "  error    --maxmemory: invalid value 'lots': invalid digit found in string",
+            "  warning  --maxmemory-policy: noeviction has no effect without --maxmemory",
+        ].join("\n"));
+        assert_eq!(Diagnostics::default().report(), "Configuration check: OK");
+    }
+}
@@ -1,5 +1,10 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 use serde::{Deserialize, Serialize};
+use crate::bloom::BloomFilter;
+use crate::compression::CompressedString;
+use crate::string_ops::escape_bytes;
+use crate::timeseries::TimeSeries;
+use crate::vector::VectorSet;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RedisValue {
@@ -8,16 +13,83 @@ pub enum RedisValue {
     Set(HashSet<String>),
     Hash(HashMap<String, String>),
     Integer(i64),
+    DelayQueue(DelayQueue),
+    BloomFilter(BloomFilter),
+    TimeSeries(TimeSeries),
+    Json(serde_json::Value),
+    VectorSet(VectorSet),
+    // String stored compressed because it exceeded the compression threshold; reads see a String
+    CompressedString(CompressedString),
+    // String that is not valid UTF-8, as SETBIT and BITOP can leave one; reads see it escaped
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DelayedItem {
+    pub due_ms: u64,
+    pub member: String,
+}
+
+// Items ordered by due time (unix milliseconds); equal due times keep push order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DelayQueue {
+    items: VecDeque<DelayedItem>,
+}
+
+impl DelayQueue {
+    pub fn push(&mut self, due_ms: u64, member: String) {
+        let position = self.items.partition_point(|item| item.due_ms <= due_ms);
+        self.items.insert(position, DelayedItem { due_ms, member });
+    }
+
+    pub fn pop_ready(&mut self, now_ms: u64, count: usize) -> Vec<String> {
+        let mut ready = Vec::new();
+        while ready.len() < count {
+            match self.items.front() {
+                Some(item) if item.due_ms <= now_ms => {
+                    if let Some(item) = self.items.pop_front() {
+                        ready.push(item.member);
+                    }
+                },
+                _ => break,
+            }
+        }
+        ready
+    }
+
+    pub fn next_due(&self) -> Option<u64> {
+        self.items.front().map(|item| item.due_ms)
+    }
+
+    pub fn ready_count(&self, now_ms: u64) -> usize {
+        self.items.partition_point(|item| item.due_ms <= now_ms)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DelayedItem> {
+        self.items.iter()
+    }
 }
 
 impl RedisValue {
     pub fn type_name(&self) -> &'static str {
         match self {
-            RedisValue::String(_) => "string",
+            RedisValue::String(_) | RedisValue::CompressedString(_) | RedisValue::Integer(_) | RedisValue::Bytes(_) => "string",
             RedisValue::List(_) => "list",
             RedisValue::Set(_) => "set",
             RedisValue::Hash(_) => "hash",
-            RedisValue::Integer(_) => "integer",
+            RedisValue::DelayQueue(_) => "delayqueue",
+            RedisValue::BloomFilter(_) => "MBbloom--",
+            RedisValue::TimeSeries(_) => "TSDB-TYPE",
+            RedisValue::Json(_) => "ReJSON-RL",
+            RedisValue::VectorSet(_) => "vectorset",
         }
     }
 
@@ -28,6 +100,56 @@ impl RedisValue {
         }
     }
 
+    /// A string as it is best kept: as an integer when it is one written the canonical way (no
+    /// "+", leading zeros or spaces), so it reads back unchanged, and as is otherwise.
+    pub fn from_string(s: String) -> Self {
+        match s.parse::<i64>() {
+            Ok(i) if i.to_string() == s => RedisValue::Integer(i),
+            _ => RedisValue::String(s),
+        }
+    }
+
+    /// Bytes as they are best kept: as a string, or an integer, when they are valid UTF-8.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(s) => RedisValue::from_string(s),
+            Err(e) => RedisValue::Bytes(e.into_bytes()),
+        }
+    }
+
+    /// The value as the string commands see it. Integers are only a more compact encoding of a
+    /// string, so they read as their decimal digits.
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            RedisValue::String(s) => Some(s),
+            RedisValue::CompressedString(s) => Some(s.decompress()),
+            RedisValue::Integer(i) => Some(i.to_string()),
+            RedisValue::Bytes(bytes) => Some(escape_bytes(&bytes)),
+            _ => None,
+        }
+    }
+
+    /// The string's bytes, for the commands that work on bytes or bits rather than text.
+    pub fn into_bytes(self) -> Option<Vec<u8>> {
+        match self {
+            RedisValue::Bytes(bytes) => Some(bytes),
+            value => value.into_string().map(String::into_bytes),
+        }
+    }
+
+    /// The string's bytes for changing in place; a string held as text or an integer is
+    /// switched to bytes first, as writing the result back would.
+    pub fn as_bytes_mut(&mut self) -> Option<&mut Vec<u8>> {
+        if matches!(self, RedisValue::String(_) | RedisValue::CompressedString(_) | RedisValue::Integer(_)) {
+            let bytes = std::mem::replace(self, RedisValue::Bytes(Vec::new())).into_bytes().unwrap_or_default();
+            *self = RedisValue::Bytes(bytes);
+        }
+        match self {
+            RedisValue::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
     pub fn as_list_mut(&mut self) -> Option<&mut VecDeque<String>> {
         match self {
             RedisValue::List(list) => Some(list),
@@ -61,7 +183,9 @@ impl std::fmt::Display for RedisValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RedisValue::String(s) => write!(f, "{}", s),
+            RedisValue::CompressedString(s) => write!(f, "{}", s.decompress()),
             RedisValue::Integer(i) => write!(f, "{}", i),
+            RedisValue::Bytes(bytes) => write!(f, "{}", escape_bytes(bytes)),
             RedisValue::List(list) => {
                 let items: Vec<String> = list.iter().enumerate()
                     .map(|(i, item)| format!("{}) {}", i + 1, item))
@@ -80,6 +204,20 @@ impl std::fmt::Display for RedisValue {
                     .collect();
                 write!(f, "{}", items.join("\n"))
             },
+            RedisValue::DelayQueue(queue) => {
+                let items: Vec<String> = queue.iter().enumerate()
+                    .map(|(i, item)| format!("{}) {} @{}", i + 1, item.member, item.due_ms))
+                    .collect();
+                write!(f, "{}", items.join("\n"))
+            },
+            RedisValue::BloomFilter(filter) => {
+                write!(f, "bloom filter ({} items, capacity {})", filter.items_inserted, filter.capacity)
+            },
+            RedisValue::TimeSeries(series) => {
+                write!(f, "time series ({} samples)", series.len())
+            },
+            RedisValue::Json(doc) => write!(f, "{}", doc),
+            RedisValue::VectorSet(set) => write!(f, "vector set ({} elements, dim {})", set.len(), set.dim),
         }
     }
 }
\ No newline at end of file
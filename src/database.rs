@@ -1,89 +1,588 @@
+use crate::blocking::KeyWaiters;
+use crate::compression::CompressedString;
+use crate::jobs::JobManager;
 use crate::data_types::RedisValue;
+use crate::expiry_log::{ExpiryLog, RemovalReason};
+use crate::locks::LockTable;
 use crate::memory::MemoryManager;
-use std::collections::HashMap;
+use crate::migration::MigrationProgress;
+use crate::persistence_clean::SaveStats;
+use crate::prefix_stats::{PrefixStats, PrefixSummary};
+use crate::rng::CommandRng;
+use crate::search::IndexRegistry;
+use crate::storage::{now_millis, ColdTier};
+use crate::tags::TagIndex;
+use crate::trash::{Trash, TrashedKey};
+use crate::ttl_index::{stats_of, TtlIndex, TtlStats};
+use crate::warmup::Warmup;
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use std::time::{Duration, Instant};
 
-pub type Database = Arc<RwLock<RedisDatabase>>;
+// Expired keys one active expire cycle removes at most, so a mass expiry is spread over
+// several cycles instead of holding the write lock for all of it at once
+pub const ACTIVE_EXPIRE_KEYS: usize = 1000;
+
+/// Told about keys written or deleted here that other instances may have cached. The server plugs
+/// in its invalidation bus; an embedder can plug in its own.
+pub trait KeyInvalidator: std::fmt::Debug + Send + Sync {
+    fn invalidate(&self, keys: &mut dyn Iterator<Item = &String>);
+}
+
+/// FLUSHALL safety net, set at startup.
+#[derive(Debug, Clone, Default)]
+pub struct FlushPolicy {
+    // How long flushed data can be brought back with UNDOFLUSH; None frees it at once
+    pub undo_window: Option<Duration>,
+    // Every FLUSHALL and UNDOFLUSH is appended here as a JSON line
+    pub audit_log: Option<PathBuf>,
+}
+
+// What the last FLUSHALL removed, until its undo window closes
+#[derive(Debug)]
+struct Tombstone {
+    data: HashMap<String, Arc<RedisValue>>,
+    expires: TtlIndex,
+    field_expires: HashMap<String, HashMap<String, Instant>>,
+    tags: TagIndex,
+    expires_at: Instant,
+}
 
 #[derive(Debug)]
 pub struct RedisDatabase {
-    pub data: HashMap<String, RedisValue>,
-    pub expires: HashMap<String, Instant>,
+    // Values are shared copy-on-write: COPY and snapshots clone the Arc, and a shared value is
+    // only deep-cloned when it is next mutated through get_mut
+    pub data: HashMap<String, Arc<RedisValue>>,
+    pub expires: TtlIndex,
+    // Per-field expiry times for hash keys (HEXPIRE), keyed by hash key then field
+    pub field_expires: HashMap<String, HashMap<String, Instant>>,
+    // TAG SET annotations, dropped with their key
+    pub tags: TagIndex,
     pub memory_manager: MemoryManager,
+    pub indexes: IndexRegistry,
+    // Optional store for keys spilled out of `data`; a key is only ever in one of the two
+    pub cold: Option<ColdTier>,
+    // Strings at least this long are kept LZ4-compressed; None disables compression
+    pub compression_threshold: Option<usize>,
+    // Changes since the last successful save; the background saver subtracts what it wrote
+    pub dirty: u64,
+    pub save_stats: SaveStats,
+    // LOCK/UNLOCK state; not persisted
+    pub locks: LockTable,
+    pub flush_policy: FlushPolicy,
+    tombstone: Option<Tombstone>,
+    // Used by commands that pick at random; seeded by DEBUG SET-RNG-SEED
+    pub rng: CommandRng,
+    // Collection replies estimated above this many bytes are refused before they are built
+    pub max_reply_bytes: Option<usize>,
+    // Variadic commands naming more keys than this are refused
+    pub max_keys_per_command: Option<usize>,
+    // Told about every key written or deleted here, so peer instances can drop their copies
+    pub invalidation: Option<Arc<dyn KeyInvalidator>>,
+    // Cold keys still to be promoted after startup, hottest first
+    pub warmup: Warmup,
+    // Reads per key prefix, for DBSTATS BY PREFIX
+    pub prefix_stats: PrefixStats,
+    // Keys removed by expiry or eviction, for EXPIRED READ
+    pub expiry_log: ExpiryLog,
+    // Where snapshots are saved, so DEBUG PERSISTENCE-BENCH times the same disk
+    pub snapshot_path: Option<PathBuf>,
+    // The running or last MERGE, for MIGRATION STATUS
+    pub migration: MigrationProgress,
+    // Background jobs (DELPATTERN, MERGE BACKGROUND), running and recently finished
+    pub jobs: JobManager,
+    // Clients blocked in BLPOP, BRPOP and BLMOVE, woken when a list is written at their keys
+    pub waiters: Arc<KeyWaiters>,
+    // Keys removed by DEL, kept for RESTOREKEY while a trash retention is set
+    pub trash: Trash,
+}
+
+impl Default for RedisDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RedisDatabase {
     pub fn new() -> Self {
         Self {
             data: HashMap::new(),
-            expires: HashMap::new(),
+            expires: TtlIndex::default(),
+            field_expires: HashMap::new(),
+            tags: TagIndex::default(),
             memory_manager: MemoryManager::new(None, "allkeys-lru".to_string()),
+            indexes: IndexRegistry::default(),
+            cold: None,
+            compression_threshold: None,
+            dirty: 0,
+            save_stats: SaveStats::default(),
+            locks: LockTable::default(),
+            flush_policy: FlushPolicy::default(),
+            tombstone: None,
+            rng: CommandRng::default(),
+            max_reply_bytes: None,
+            max_keys_per_command: None,
+            invalidation: None,
+            warmup: Warmup::default(),
+            prefix_stats: PrefixStats::default(),
+            expiry_log: ExpiryLog::default(),
+            snapshot_path: None,
+            migration: MigrationProgress::default(),
+            jobs: JobManager::default(),
+            waiters: Arc::default(),
+            trash: Trash::default(),
         }
     }
 
     pub fn new_with_memory_config(max_memory: Option<usize>, eviction_policy: String) -> Self {
         Self {
             data: HashMap::new(),
-            expires: HashMap::new(),
+            expires: TtlIndex::default(),
+            field_expires: HashMap::new(),
+            tags: TagIndex::default(),
             memory_manager: MemoryManager::new(max_memory, eviction_policy),
+            indexes: IndexRegistry::default(),
+            cold: None,
+            compression_threshold: None,
+            dirty: 0,
+            save_stats: SaveStats::default(),
+            locks: LockTable::default(),
+            flush_policy: FlushPolicy::default(),
+            tombstone: None,
+            rng: CommandRng::default(),
+            max_reply_bytes: None,
+            max_keys_per_command: None,
+            invalidation: None,
+            warmup: Warmup::default(),
+            prefix_stats: PrefixStats::default(),
+            expiry_log: ExpiryLog::default(),
+            snapshot_path: None,
+            migration: MigrationProgress::default(),
+            jobs: JobManager::default(),
+            waiters: Arc::default(),
+            trash: Trash::default(),
+        }
+    }
+
+    /// Attaches a cold tier. Keys it already holds were spilled after the snapshot that
+    /// loaded `data` was taken, so they replace the snapshot's copies.
+    pub fn attach_cold_tier(&mut self, tier: ColdTier) {
+        for key in tier.engine.scan() {
+            self.data.remove(&key);
+            self.expires.remove(&key);
+            self.field_expires.remove(&key);
+        }
+        self.cold = Some(tier);
+    }
+
+    /// Restores the access counts saved with the snapshot and, with a cold tier attached, plans
+    /// promoting its hottest keys into whatever room `max_hot_keys` leaves.
+    pub fn restore_access_counts(&mut self, mut access_counts: HashMap<String, u64>) {
+        let cold_keys = self.cold.as_ref().map(|tier| tier.engine.scan()).unwrap_or_default();
+        let cold_set: HashSet<&String> = cold_keys.iter().collect();
+        access_counts.retain(|key, _| self.data.contains_key(key) || cold_set.contains(key));
+        if let Some(tier) = &self.cold {
+            let capacity = tier.max_hot_keys.saturating_sub(self.data.len());
+            self.warmup = Warmup::plan(&access_counts, cold_keys, capacity);
+        }
+        for (key, count) in &access_counts {
+            self.memory_manager.heatmap.seed(key, *count);
+        }
+    }
+
+    // Moves `key` back from the cold tier, if it is there, before it is accessed. The record
+    // stays in the engine until a snapshot holds the key or it is written over, since until then
+    // it is the only copy on disk
+    fn promote(&mut self, key: &str) {
+        let tier = match &mut self.cold {
+            Some(tier) if !self.data.contains_key(key) => tier,
+            _ => return,
+        };
+
+        let (value, expires_at_ms) = match tier.engine.get(key) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Failed to read {} from the cold tier: {}", key, e);
+                return;
+            },
+        };
+        tier.mark_promoted(key);
+
+        if let Some(at) = expires_at_ms {
+            let remaining = Duration::from_millis(at.saturating_sub(now_millis()));
+            self.expires.insert(key.to_string(), Instant::now() + remaining);
+        }
+        self.data.insert(key.to_string(), Arc::new(value));
+    }
+
+    /// Promotes the next batch of keys planned by `warmup`, returning how many were loaded.
+    /// Warmed keys count as just used, so the spill that follows does not send them back.
+    pub fn warm_up_batch(&mut self, size: usize) -> usize {
+        let now = Instant::now();
+        let mut loaded = 0;
+        for key in self.warmup.next_batch(size) {
+            if self.data.contains_key(&key) {
+                continue;
+            }
+            self.promote(&key);
+            if self.data.contains_key(&key) {
+                self.memory_manager.access_times.insert(key, now);
+                loaded += 1;
+            }
+        }
+        self.warmup.loaded += loaded;
+        loaded
+    }
+
+    // Drops any cold copy of `key`, returning whether it was only there rather than in memory
+    fn forget_cold(&mut self, key: &str) -> bool {
+        match &mut self.cold {
+            Some(tier) => {
+                let promoted = tier.is_promoted(key);
+                tier.unmark_promoted(key);
+                let deleted = tier.engine.delete(key).unwrap_or_else(|e| {
+                    eprintln!("Failed to remove {} from the cold tier: {}", key, e);
+                    false
+                });
+                deleted && !promoted
+            },
+            None => false,
         }
     }
 
+    /// Taken with a snapshot, for `release_promoted` once it is saved.
+    pub fn promotion_mark(&self) -> u64 {
+        self.cold.as_ref().map_or(0, ColdTier::promotion_mark)
+    }
+
+    /// Deletes the cold records of keys promoted before `mark` now that a saved snapshot holds
+    /// them.
+    pub fn release_promoted(&mut self, mark: u64) {
+        if let Some(tier) = &mut self.cold {
+            if let Err(e) = tier.release_promoted(mark) {
+                eprintln!("Failed to release promoted keys from the cold tier: {}", e);
+            }
+        }
+    }
+
+    /// Moves the least recently used keys beyond the cold tier's `max_hot_keys` out of memory,
+    /// returning how many were moved. Keys with per-field TTLs and pinned keys stay in memory.
+    pub fn spill_cold_keys(&mut self) -> usize {
+        let max_hot_keys = match &self.cold {
+            Some(tier) if self.data.len() > tier.max_hot_keys => tier.max_hot_keys,
+            _ => return 0,
+        };
+
+        let access_times = &self.memory_manager.access_times;
+        let mut candidates: Vec<(Option<Instant>, String)> = self.data.keys()
+            .filter(|key| !self.field_expires.contains_key(*key) && !self.memory_manager.is_pinned(key))
+            .map(|key| (access_times.get(key).copied(), key.clone()))
+            .collect();
+        candidates.sort();
+        candidates.truncate(self.data.len() - max_hot_keys);
+
+        let now = Instant::now();
+        let now_ms = now_millis();
+        let mut spilled = 0;
+        for (_, key) in candidates {
+            let expires_at_ms = self.expires.get(&key).map(|at| now_ms + at.saturating_duration_since(now).as_millis() as u64);
+            let (value, tier) = match (self.data.get(&key), &mut self.cold) {
+                (Some(value), Some(tier)) => (value, tier),
+                _ => continue,
+            };
+            if let Err(e) = tier.engine.set(&key, value, expires_at_ms) {
+                eprintln!("Failed to spill {} to the cold tier: {}", key, e);
+                break;
+            }
+            tier.unmark_promoted(&key);
+            self.data.remove(&key);
+            self.expires.remove(&key);
+            // The access count is kept so warm-up after a restart knows how hot the key was
+            self.memory_manager.access_times.remove(&key);
+            spilled += 1;
+        }
+        spilled
+    }
+
+    // Every string is stored through here, so commands need not pick encodings themselves:
+    // integers become Integer, bytes that are valid UTF-8 a String, and large strings are compressed
+    fn encode(&self, value: RedisValue) -> RedisValue {
+        let value = match value {
+            RedisValue::String(s) => RedisValue::from_string(s),
+            RedisValue::Bytes(bytes) => RedisValue::from_bytes(bytes),
+            value => value,
+        };
+        match (self.compression_threshold, value) {
+            (Some(threshold), RedisValue::String(s)) if s.len() >= threshold => {
+                let compressed = CompressedString::new(&s);
+                // Incompressible data is kept as is rather than paying for decompression
+                if compressed.stored_len() < s.len() {
+                    RedisValue::CompressedString(compressed)
+                } else {
+                    RedisValue::String(s)
+                }
+            },
+            (_, value) => value,
+        }
+    }
+
+    /// (compressed keys, original bytes, stored bytes) over the in-memory strings.
+    pub fn compression_stats(&self) -> (usize, usize, usize) {
+        self.data.values().fold((0, 0, 0), |(keys, original, stored), value| match &**value {
+            RedisValue::CompressedString(s) => (keys + 1, original + s.original_len(), stored + s.stored_len()),
+            _ => (keys, original, stored),
+        })
+    }
+
+    fn remove_expired(&mut self, key: &str) {
+        if self.data.contains_key(key) {
+            self.expiry_log.record(key, RemovalReason::Expired, now_millis());
+        }
+        self.data.remove(key);
+        self.expires.remove(key);
+        self.field_expires.remove(key);
+        self.tags.remove_key(key);
+        self.memory_manager.remove_tracking(key);
+        self.indexes.update(key, None);
+        // A promoted key's record would bring it back after a restart
+        self.forget_cold(key);
+    }
+
+    // Drops the hash fields of `key` whose TTL has passed, deleting the key if none are left
+    fn purge_expired_fields(&mut self, key: &str, now: Instant) -> usize {
+        let expired: Vec<String> = match self.field_expires.get(key) {
+            Some(fields) => fields.iter().filter(|(_, at)| now > **at).map(|(field, _)| field.clone()).collect(),
+            None => return 0,
+        };
+        if expired.is_empty() {
+            return 0;
+        }
+
+        if let Some(fields) = self.field_expires.get_mut(key) {
+            for field in &expired {
+                fields.remove(field);
+            }
+            if fields.is_empty() {
+                self.field_expires.remove(key);
+            }
+        }
+
+        let now_empty = match self.data.get_mut(key).map(Arc::make_mut) {
+            Some(RedisValue::Hash(hash)) => {
+                for field in &expired {
+                    hash.remove(field);
+                }
+                hash.is_empty()
+            },
+            _ => false,
+        };
+        if now_empty {
+            self.delete(key);
+            self.expiry_log.record(key, RemovalReason::Expired, now_millis());
+        } else {
+            self.indexes.update(key, self.data.get(key).map(|value| &**value));
+        }
+        expired.len()
+    }
+
     pub fn get(&mut self, key: &str) -> Option<RedisValue> {
+        self.promote(key);
+        self.purge_expired_fields(key, Instant::now());
         if let Some(expire_time) = self.expires.get(key) {
             if Instant::now() > *expire_time {
-                self.data.remove(key);
-                self.expires.remove(key);
-                self.memory_manager.remove_tracking(key);
+                self.remove_expired(key);
+                self.prefix_stats.record(key, false);
                 return None;
             }
         }
 
+        self.prefix_stats.record(key, self.data.contains_key(key));
         if let Some(value) = self.data.get(key) {
             // Track access for LRU/LFU
             self.memory_manager.track_access(key);
-            Some(value.clone())
+            match &**value {
+                RedisValue::CompressedString(s) => Some(RedisValue::String(s.decompress())),
+                value => Some(value.clone()),
+            }
         } else {
             None
         }
     }
 
     pub fn set(&mut self, key: String, value: RedisValue) -> Result<(), String> {
-        // Check memory limit before setting
-        let memory_manager = &mut self.memory_manager;
-        //  memory_manager.check_memory_limit(self)?;
-
-        self.data.insert(key.clone(), value);
+        self.announce(&key);
+        self.forget_cold(&key);
+        self.retain_field_expires(&key, &value);
+        self.indexes.update(&key, Some(&value));
+        self.wake_if_list(&key, &value);
+        let value = self.encode(value);
+        self.data.insert(key.clone(), Arc::new(value));
         self.memory_manager.track_access(&key);
+        self.dirty += 1;
         Ok(())
     }
 
     pub fn set_with_expiry(&mut self, key: String, value: RedisValue, ttl: Duration) -> Result<(), String> {
-        // Check memory limit before setting
-        let memory_manager = &mut self.memory_manager;
-        //  memory_manager.check_memory_limit(self)?;
-
-        self.data.insert(key.clone(), value);
+        self.announce(&key);
+        self.forget_cold(&key);
+        self.retain_field_expires(&key, &value);
+        self.indexes.update(&key, Some(&value));
+        self.wake_if_list(&key, &value);
+        let value = self.encode(value);
+        self.data.insert(key.clone(), Arc::new(value));
         self.expires.insert(key.clone(), Instant::now() + ttl);
         self.memory_manager.track_access(&key);
+        self.dirty += 1;
         Ok(())
     }
 
+    // Passes `key` to the invalidation bus, if one is plugged in
+    fn announce(&self, key: &str) {
+        if let Some(invalidator) = &self.invalidation {
+            invalidator.invalidate(&mut std::iter::once(&key.to_string()));
+        }
+    }
+
+    fn wake_if_list(&self, key: &str, value: &RedisValue) {
+        if matches!(value, RedisValue::List(_)) {
+            self.waiters.wake(key);
+        }
+    }
+
+    fn retain_field_expires(&mut self, key: &str, value: &RedisValue) {
+        if let Some(fields) = self.field_expires.get_mut(key) {
+            match value {
+                RedisValue::Hash(hash) => fields.retain(|field, _| hash.contains_key(field)),
+                _ => fields.clear(),
+            }
+            if fields.is_empty() {
+                self.field_expires.remove(key);
+            }
+        }
+    }
+
     pub fn delete(&mut self, key: &str) -> bool {
+        self.announce(key);
         self.expires.remove(key);
+        self.field_expires.remove(key);
+        self.tags.remove_key(key);
         self.memory_manager.remove_tracking(key);
-        self.data.remove(key).is_some()
+        self.indexes.update(key, None);
+        let was_cold = self.forget_cold(key);
+        let removed = self.data.remove(key).is_some() || was_cold;
+        if removed {
+            self.dirty += 1;
+        }
+        removed
+    }
+
+    /// Deletes every key in `keys` under one clock reading, returning how many existed.
+    /// Keys that had already expired are dropped without being counted.
+    pub fn delete_many(&mut self, keys: &[String]) -> usize {
+        if let Some(invalidator) = &self.invalidation {
+            invalidator.invalidate(&mut keys.iter());
+        }
+        let now = Instant::now();
+        let mut removed = 0;
+        for key in keys {
+            let expired = self.expires.remove(key).is_some_and(|at| now > at);
+            self.field_expires.remove(key);
+            self.tags.remove_key(key);
+            self.memory_manager.remove_tracking(key);
+            self.indexes.update(key, None);
+            let was_cold = self.forget_cold(key);
+            if (self.data.remove(key).is_some() && !expired) || was_cold {
+                removed += 1;
+            }
+        }
+        self.dirty += removed as u64;
+        removed
+    }
+
+    /// INVALIDATE from a peer: deletes `keys` like `delete_many` without announcing them, so
+    /// invalidations are never passed back. Returns how many existed.
+    pub fn drop_invalidated(&mut self, keys: &[String]) -> usize {
+        let invalidation = self.invalidation.take();
+        let removed = self.delete_many(keys);
+        self.invalidation = invalidation;
+        removed
+    }
+
+    /// DEL: with a trash retention set, moves each existing key into the trash with its TTLs
+    /// and tags instead of freeing it. Returns how many existed.
+    pub fn trash_many(&mut self, keys: &[String]) -> usize {
+        let Some(retention) = self.trash.retention else { return self.delete_many(keys) };
+        let now = Instant::now();
+        let mut trashed = 0;
+        for key in keys {
+            if !self.exists_at(key, now) {
+                continue;
+            }
+            let entry = TrashedKey::new(
+                Arc::clone(&self.data[key]),
+                self.expires.get(key).copied(),
+                self.field_expires.get(key).cloned().unwrap_or_default(),
+                self.tags.tags_of(key),
+                now + retention,
+            );
+            self.trash.put(key.clone(), entry);
+            self.delete(key);
+            trashed += 1;
+        }
+        trashed
+    }
+
+    /// RESTOREKEY: moves `key` back out of the trash as it was when deleted. A key written
+    /// under the same name since is only replaced if `replace` is set.
+    pub fn restore_from_trash(&mut self, key: &str, replace: bool) -> Result<(), String> {
+        let now = Instant::now();
+        if !self.trash.contains(key, now) {
+            return Err("ERR no such key in the trash".to_string());
+        }
+        if !replace && self.exists_at(key, now) {
+            return Err("BUSYKEY Target key name already exists.".to_string());
+        }
+        let Some(entry) = self.trash.take(key, now) else {
+            return Err("ERR no such key in the trash".to_string());
+        };
+        self.delete(key);
+        self.indexes.update(key, Some(&entry.value));
+        self.wake_if_list(key, &entry.value);
+        if let Some(at) = entry.expires_at {
+            self.expires.insert(key.to_string(), at);
+        }
+        if !entry.field_expires.is_empty() {
+            self.field_expires.insert(key.to_string(), entry.field_expires);
+        }
+        // Within the limits already, since the key held them before
+        let _ = self.tags.add(key, &entry.tags);
+        self.memory_manager.track_access(key);
+        self.data.insert(key.to_string(), entry.value);
+        self.dirty += 1;
+        Ok(())
     }
 
     pub fn exists(&mut self, key: &str) -> bool {
+        self.exists_at(key, Instant::now())
+    }
+
+    /// How many of `keys` exist, counting repeats, all checked against the same instant.
+    pub fn exists_many(&mut self, keys: &[String]) -> usize {
+        let now = Instant::now();
+        keys.iter().filter(|key| self.exists_at(key, now)).count()
+    }
+
+    fn exists_at(&mut self, key: &str, now: Instant) -> bool {
+        self.promote(key);
+        self.purge_expired_fields(key, now);
         // Check expiry first
         if let Some(expire_time) = self.expires.get(key) {
-            if Instant::now() > *expire_time {
-                self.data.remove(key);
-                self.expires.remove(key);
-                self.memory_manager.remove_tracking(key);
+            if now > *expire_time {
+                self.remove_expired(key);
                 return false;
             }
         }
@@ -95,44 +594,140 @@ impl RedisDatabase {
         exists
     }
 
+    /// Copies `source` to `destination`, sharing the value rather than cloning it, along with
+    /// its TTL and hash field TTLs. Returns false if `source` does not exist or `destination`
+    /// exists and `replace` is false.
+    pub fn copy(&mut self, source: &str, destination: &str, replace: bool) -> bool {
+        if !self.exists(source) || (!replace && self.exists(destination)) {
+            return false;
+        }
+        if source == destination {
+            return true;
+        }
+
+        self.delete(destination);
+        let value = Arc::clone(&self.data[source]);
+        self.indexes.update(destination, Some(&value));
+        self.wake_if_list(destination, &value);
+        self.data.insert(destination.to_string(), value);
+        if let Some(at) = self.expires.get(source).copied() {
+            self.expires.insert(destination.to_string(), at);
+        }
+        if let Some(fields) = self.field_expires.get(source).cloned() {
+            self.field_expires.insert(destination.to_string(), fields);
+        }
+        // Within the limits already, since the source holds them
+        let _ = self.tags.add(destination, &self.tags.tags_of(source));
+        self.memory_manager.track_access(destination);
+        self.dirty += 1;
+        true
+    }
+
+    /// A read-only view of the dataset as it is now, for SNAPSHOT sessions. Values are shared
+    /// with this database, so the copy costs one Arc per key; later writes here copy the value
+    /// they change instead of changing it under the view. Keys in the cold tier are not included.
+    pub fn frozen_copy(&self) -> RedisDatabase {
+        RedisDatabase {
+            data: self.data.clone(),
+            expires: self.expires.clone(),
+            field_expires: self.field_expires.clone(),
+            tags: self.tags.clone(),
+            ..RedisDatabase::new()
+        }
+    }
+
     pub fn keys(&self) -> Vec<String> {
-        self.data.keys().cloned().collect()
+        let mut keys: Vec<String> = self.data.keys().cloned().collect();
+        if let Some(tier) = &self.cold {
+            keys.extend(tier.engine.scan().into_iter().filter(|key| !tier.is_promoted(key)));
+        }
+        keys
+    }
+
+    /// Borrows the value of `key` for reading, without the copy `get` makes. Expiry is applied
+    /// as for any read, but the value is neither marked dirty nor unshared from snapshots.
+    /// Compressed strings are returned as stored.
+    pub fn get_ref(&mut self, key: &str) -> Option<&RedisValue> {
+        self.promote(key);
+        self.purge_expired_fields(key, Instant::now());
+        if self.expires.get(key).is_some_and(|expire_time| Instant::now() > *expire_time) {
+            self.remove_expired(key);
+            self.prefix_stats.record(key, false);
+            return None;
+        }
+
+        self.prefix_stats.record(key, self.data.contains_key(key));
+        if self.data.contains_key(key) {
+            self.memory_manager.track_access(key);
+        }
+        self.data.get(key).map(|value| &**value)
     }
 
     pub fn get_mut(&mut self, key: &str) -> Option<&mut RedisValue> {
+        self.promote(key);
+        self.purge_expired_fields(key, Instant::now());
         if let Some(expire_time) = self.expires.get(key) {
             if Instant::now() > *expire_time {
-                self.data.remove(key);
-                self.expires.remove(key);
-                self.memory_manager.remove_tracking(key);
+                self.remove_expired(key);
                 return None;
             }
         }
 
-        if self.data.contains_key(key) {
-            self.memory_manager.track_access(key);
-            self.data.get_mut(key)
-        } else {
-            None
+        match self.data.get_mut(key).map(Arc::make_mut) {
+            Some(value) => {
+                // Callers mutate in place, so a compressed string is expanded until its next SET
+                if let RedisValue::CompressedString(s) = value {
+                    *value = RedisValue::String(s.decompress());
+                }
+                self.memory_manager.track_access(key);
+                // Callers only take a mutable reference to change the value
+                self.dirty += 1;
+                if let Some(invalidator) = &self.invalidation {
+                    invalidator.invalidate(&mut std::iter::once(&key.to_string()));
+                }
+                Some(value)
+            },
+            None => None,
         }
     }
 
     pub fn expire(&mut self, key: &str, ttl: Duration) -> bool {
+        self.promote(key);
         if self.data.contains_key(key) {
             self.expires.insert(key.to_string(), Instant::now() + ttl);
+            self.dirty += 1;
             true
         } else {
             false
         }
     }
 
+    /// Expires `key` at a unix time in milliseconds. A time already past deletes it now, as an
+    /// expiry would have.
+    pub fn expire_at(&mut self, key: &str, unix_ms: u64) -> bool {
+        let now_ms = now_millis();
+        if unix_ms > now_ms {
+            return self.expire(key, Duration::from_millis(unix_ms - now_ms));
+        }
+        self.promote(key);
+        self.delete(key)
+    }
+
     pub fn ttl(&mut self, key: &str) -> Option<Duration> {
+        self.ttl_at(key, Instant::now())
+    }
+
+    /// TTLs of `keys` as `ttl` would report them, all measured from the same instant.
+    pub fn ttl_many(&mut self, keys: &[String]) -> Vec<Option<Duration>> {
+        let now = Instant::now();
+        keys.iter().map(|key| self.ttl_at(key, now)).collect()
+    }
+
+    fn ttl_at(&mut self, key: &str, now: Instant) -> Option<Duration> {
+        self.promote(key);
         if let Some(expire_time) = self.expires.get(key) {
-            let now = Instant::now();
             if now > *expire_time {
-                self.data.remove(key);
-                self.expires.remove(key);
-                self.memory_manager.remove_tracking(key);
+                self.remove_expired(key);
                 None
             } else {
                 Some(*expire_time - now)
@@ -144,15 +739,154 @@ impl RedisDatabase {
         }
     }
 
+    /// Remaining TTL of a hash field, `None` if the field has no expiry.
+    pub fn field_ttl(&self, key: &str, field: &str) -> Option<Duration> {
+        self.field_expires.get(key)
+            .and_then(|fields| fields.get(field))
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn expire_field(&mut self, key: &str, field: &str, ttl: Duration) {
+        self.field_expires.entry(key.to_string()).or_default().insert(field.to_string(), Instant::now() + ttl);
+        self.dirty += 1;
+    }
+
+    pub fn persist_field(&mut self, key: &str, field: &str) -> bool {
+        let removed = match self.field_expires.get_mut(key) {
+            Some(fields) => fields.remove(field).is_some(),
+            None => false,
+        };
+        if self.field_expires.get(key).is_some_and(|fields| fields.is_empty()) {
+            self.field_expires.remove(key);
+        }
+        if removed {
+            self.dirty += 1;
+        }
+        removed
+    }
+
+    /// Keys that have not expired yet but will within `lead`, with their deadlines.
+    pub fn expiring_within(&self, lead: Duration, now: Instant) -> Vec<(String, Instant)> {
+        self.expires.iter()
+            .filter(|(_, at)| **at > now && at.saturating_duration_since(now) <= lead)
+            .map(|(key, at)| (key.clone(), *at))
+            .collect()
+    }
+
+    /// Removes expired keys, at most ACTIVE_EXPIRE_KEYS of them, and expired hash fields,
+    /// returning how many were removed. Run periodically so data nobody reads again does not
+    /// linger until its next access; a backlog larger than one cycle's share waits for the next.
+    pub fn active_expire_cycle(&mut self) -> usize {
+        let now = Instant::now();
+        let expired_keys = self.expires.expired(now, ACTIVE_EXPIRE_KEYS);
+        for key in &expired_keys {
+            self.remove_expired(key);
+        }
+
+        self.locks.purge_expired(now);
+        self.trash.purge_expired(now);
+        if self.tombstone.as_ref().is_some_and(|tombstone| tombstone.expires_at <= now) {
+            self.tombstone = None;
+        }
+
+        let volatile_hashes: Vec<String> = self.field_expires.keys().cloned().collect();
+        let expired_fields: usize = volatile_hashes.iter().map(|key| self.purge_expired_fields(key, now)).sum();
+
+        expired_keys.len() + expired_fields
+    }
+
     pub fn clear(&mut self) {
+        self.dirty += self.data.len() as u64;
         self.data.clear();
         self.expires.clear();
+        self.field_expires.clear();
+        self.tags.clear();
+        self.trash.clear();
+        self.indexes.clear_documents();
         self.memory_manager.access_times.clear();
-        self.memory_manager.access_counts.clear();
+        self.memory_manager.heatmap.clear();
+        self.memory_manager.pinned.clear();
+        if let Some(tier) = &mut self.cold {
+            if let Err(e) = tier.clear() {
+                eprintln!("Failed to clear the cold tier: {}", e);
+            }
+        }
+    }
+
+    /// FLUSHALL: empties the database, keeping what it held for UNDOFLUSH while the undo window
+    /// is open. Returns how many keys were removed.
+    pub fn flush_all(&mut self) -> usize {
+        if let Some(invalidator) = &self.invalidation {
+            invalidator.invalidate(&mut self.keys().iter());
+        }
+        let keys = self.size();
+        if let Some(window) = self.flush_policy.undo_window {
+            // Flushing an empty database must not throw away an earlier undo
+            if !self.data.is_empty() {
+                self.tombstone = Some(Tombstone {
+                    data: std::mem::take(&mut self.data),
+                    expires: std::mem::take(&mut self.expires),
+                    field_expires: std::mem::take(&mut self.field_expires),
+                    tags: std::mem::take(&mut self.tags),
+                    expires_at: Instant::now() + window,
+                });
+            }
+        }
+        // clear() only counts the keys still in memory, so count the whole flush once here
+        let dirty = self.dirty;
+        self.clear();
+        self.dirty = dirty + keys as u64;
+        self.audit_flush("FLUSHALL", keys);
+        keys
+    }
+
+    /// Brings back the keys removed by the last FLUSHALL if its undo window is still open.
+    /// Keys written since the flush are kept. Returns how many keys were restored.
+    pub fn undo_flush(&mut self) -> Option<usize> {
+        let mut tombstone = self.tombstone.take().filter(|tombstone| tombstone.expires_at > Instant::now())?;
+        let mut restored = 0;
+        for (key, value) in tombstone.data {
+            if self.exists(&key) {
+                continue;
+            }
+            self.indexes.update(&key, Some(&value));
+            if let Some(at) = tombstone.expires.remove(&key) {
+                self.expires.insert(key.clone(), at);
+            }
+            if let Some(fields) = tombstone.field_expires.remove(&key) {
+                self.field_expires.insert(key.clone(), fields);
+            }
+            let _ = self.tags.add(&key, &tombstone.tags.tags_of(&key));
+            self.memory_manager.track_access(&key);
+            self.announce(&key);
+            self.data.insert(key, value);
+            restored += 1;
+        }
+        self.dirty += restored as u64;
+        self.audit_flush("UNDOFLUSH", restored);
+        Some(restored)
+    }
+
+    fn audit_flush(&self, command: &str, keys: usize) {
+        let path = match &self.flush_policy.audit_log {
+            Some(path) => path,
+            None => return,
+        };
+        let entry = serde_json::json!({ "time_ms": now_millis(), "command": command, "keys": keys });
+        let written = OpenOptions::new().create(true).append(true).open(path)
+            .and_then(|mut file| writeln!(file, "{}", entry));
+        if let Err(e) = written {
+            eprintln!("Failed to write flush audit log {}: {}", path.display(), e);
+        }
     }
 
     pub fn size(&self) -> usize {
-        self.data.len()
+        self.data.len() + self.cold.as_ref().map_or(0, ColdTier::cold_len)
+    }
+
+    /// Keys under `prefix`, hot and cold.
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.keys().into_iter().filter(|key| key.starts_with(prefix)).collect()
     }
 
     pub fn get_memory_info(&self) -> HashMap<String, String> {
@@ -162,16 +896,47 @@ impl RedisDatabase {
     pub fn get_memory_usage(&self) -> usize {
         self.memory_manager.calculate_memory_usage(self)
     }
-}
 
-pub fn create_database() -> Database {
-    Arc::new(RwLock::new(RedisDatabase::new()))
-}
+    /// Drops tracking entries of keys no longer in memory, left behind by a removal that did not
+    /// go through `delete`. Only scans when there are more entries than keys, so the maps never
+    /// outgrow the keyspace. Returns how many entries were dropped.
+    pub fn prune_tracking(&mut self) -> usize {
+        let manager = &self.memory_manager;
+        if manager.access_times.len() <= self.data.len() && manager.pinned.len() <= self.data.len() {
+            return 0;
+        }
+        let data = &self.data;
+        self.memory_manager.prune_tracking(|key| data.contains_key(key))
+    }
 
-pub fn create_database_with_data(db: RedisDatabase) -> Database {
-    Arc::new(RwLock::new(db))
-}
+    /// Recomputes `memory_manager.used_memory`, which the deny-oom check reads.
+    pub fn refresh_memory_usage(&mut self) {
+        self.memory_manager.used_memory = self.memory_manager.calculate_memory_usage(self);
+    }
+
+    pub fn get_prefix_memory_usage(&self, prefix: &str) -> usize {
+        self.memory_manager.calculate_prefix_usage(self, prefix)
+    }
+
+    /// Pinned keys under `prefix` and the bytes they hold.
+    pub fn get_pinned_usage(&self, prefix: &str) -> (usize, usize) {
+        self.memory_manager.calculate_pinned_usage(self, prefix)
+    }
 
-pub fn create_database_with_memory_config(max_memory: Option<usize>, eviction_policy: String) -> Database {
-    Arc::new(RwLock::new(RedisDatabase::new_with_memory_config(max_memory, eviction_policy)))
+    /// Keys, memory and reads per key prefix, largest memory first. Cold keys are not counted.
+    pub fn prefix_summaries(&self) -> Vec<PrefixSummary> {
+        self.prefix_stats.summarize(self.data.iter()
+            .map(|(key, value)| (key.as_str(), key.len() + self.memory_manager.calculate_value_size(value))))
+    }
+
+    /// Expiry statistics for volatile keys under `prefix`. The whole keyspace is answered from
+    /// the TTL index; a tenant's share is counted by walking its deadlines.
+    pub fn ttl_stats(&self, prefix: &str) -> TtlStats {
+        let now = Instant::now();
+        if prefix.is_empty() {
+            self.expires.stats(now)
+        } else {
+            stats_of(self.expires.iter().filter(|(key, _)| key.starts_with(prefix)).map(|(_, at)| at), now)
+        }
+    }
 }
@@ -0,0 +1,82 @@
+// DELPATTERN: deletes every key matching a pattern on a background task, so clients no longer
+// loop over KEYS and DEL themselves. The matching names are collected when the job starts and
+// deleted in small batches with the database lock released in between, paced by the same
+// throttle as MERGE. Each run is a job, so JOB STATUS reports its progress and JOB CANCEL stops
+// it before its next batch.
+use crate::shared::Database;
+use std::time::{Duration, Instant};
+
+// Keys deleted per database lock acquisition
+pub const DELPATTERN_BATCH: usize = 100;
+// Keys per second when no RATE is given
+pub const DEFAULT_DELETE_RATE: u64 = 10_000;
+/// Deletes `keys` for job `id`, one batch per lock acquisition, until done or cancelled.
+pub async fn run(db: Database, id: u64, keys: Vec<String>) {
+    let started = Instant::now();
+    let mut examined = 0;
+    for batch in keys.chunks(DELPATTERN_BATCH) {
+        let throttle = {
+            let mut db_write = db.write().await;
+            if db_write.jobs.running(id).is_none() {
+                return;
+            }
+            let deleted = db_write.delete_many(batch) as u64;
+            examined += batch.len() as u64;
+            let Some(job) = db_write.jobs.running(id) else { return };
+            job.done = examined;
+            *job.counters.entry("deleted").or_default() += deleted;
+            job.throttle
+        };
+        match throttle.delay(examined, 0, started.elapsed()) {
+            Duration::ZERO => tokio::task::yield_now().await,
+            delay => tokio::time::sleep(delay).await,
+        }
+    }
+    db.write().await.jobs.finish(id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::RedisValue;
+    use crate::jobs::{JobKind, JobState};
+    use crate::migration::Throttle;
+    use crate::shared::create_database;
+
+    fn throttle(keys_per_sec: u64) -> Throttle {
+        Throttle { keys_per_sec: Some(keys_per_sec), bytes_per_sec: None }
+    }
+
+    #[tokio::test]
+    async fn test_jobs_delete_in_batches_until_done() {
+        let db = create_database();
+        let keys: Vec<String> = (0..250).map(|i| format!("user:{}", i)).collect();
+        {
+            let mut db_write = db.write().await;
+            for key in &keys[..249] {
+                db_write.set(key.clone(), RedisValue::String("v".into())).unwrap();
+            }
+        }
+        let id = db.write().await.jobs.start(JobKind::DelPattern, "user:*", None, throttle(1_000_000), 250).unwrap();
+        run(db.clone(), id, keys).await;
+
+        let db_read = db.read().await;
+        let job = db_read.jobs.get(id, None).unwrap();
+        assert_eq!((job.state, job.done, job.counters["deleted"]), (JobState::Done, 250, 249));
+        assert_eq!(db_read.size(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_jobs_stop_before_the_next_batch() {
+        let db = create_database();
+        db.write().await.set("k0".to_string(), RedisValue::String("v".into())).unwrap();
+        let id = db.write().await.jobs.start(JobKind::DelPattern, "k*", None, throttle(10), 1000).unwrap();
+        assert!(db.write().await.jobs.cancel(id, None));
+        run(db.clone(), id, (0..1000).map(|i| format!("k{}", i)).collect()).await;
+
+        let db_read = db.read().await;
+        let job = db_read.jobs.get(id, None).unwrap();
+        assert_eq!((job.state, job.done), (JobState::Cancelled, 0));
+        assert_eq!(db_read.size(), 1);
+    }
+}
@@ -0,0 +1,157 @@
+// The __expired__ stream: names of keys removed by expiry or eviction, numbered in order, for
+// systems that mirror this keyspace and must apply deletions they did not cause. Unlike the
+// keyspace notifications a subscriber only sees while connected, the entries are kept (up to a
+// bounded length) and saved next to each snapshot, so a consumer can read on from the last id it
+// applied after a disconnect or a restart.
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const DEFAULT_EXPIRED_STREAM_LEN: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovalReason {
+    Expired,
+    Evicted,
+}
+
+impl std::fmt::Display for RemovalReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemovalReason::Expired => write!(f, "expired"),
+            RemovalReason::Evicted => write!(f, "evicted"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemovedKey {
+    pub id: u64,
+    pub key: String,
+    pub reason: RemovalReason,
+    // Unix time of the removal in milliseconds
+    pub at_ms: u64,
+}
+
+impl std::fmt::Display for RemovedKey {
+    // The key goes last so keys containing spaces stay unambiguous
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {} {}", self.id, self.reason, self.at_ms, self.key)
+    }
+}
+
+#[derive(Debug)]
+pub struct ExpiryLog {
+    entries: VecDeque<RemovedKey>,
+    // Oldest entries are dropped past this many; 0 turns the stream off
+    max_len: usize,
+    next_id: u64,
+}
+
+impl Default for ExpiryLog {
+    fn default() -> Self {
+        Self { entries: VecDeque::new(), max_len: DEFAULT_EXPIRED_STREAM_LEN, next_id: 1 }
+    }
+}
+
+impl ExpiryLog {
+    pub fn record(&mut self, key: &str, reason: RemovalReason, at_ms: u64) {
+        if self.max_len == 0 {
+            return;
+        }
+        self.entries.push_back(RemovedKey { id: self.next_id, key: key.to_string(), reason, at_ms });
+        self.next_id += 1;
+        self.trim();
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.entries.len() > self.max_len {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Up to `count` entries with an id above `after`, oldest first.
+    pub fn read_after(&self, after: u64, count: usize) -> impl Iterator<Item = &RemovedKey> {
+        // Ids are consecutive, so the first wanted entry's position follows from the oldest id
+        let skip = self.entries.front().map_or(0, |first| after.saturating_sub(first.id - 1) as usize);
+        self.entries.iter().skip(skip).take(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The oldest id still held; a consumer whose last id is below this one has missed entries.
+    pub fn first_id(&self) -> Option<u64> {
+        self.entries.front().map(|entry| entry.id)
+    }
+
+    pub fn last_id(&self) -> u64 {
+        self.next_id - 1
+    }
+
+    pub fn entries(&self) -> Vec<RemovedKey> {
+        self.entries.iter().cloned().collect()
+    }
+
+    /// Reloads the entries saved with a snapshot; new ids continue after the last of them.
+    pub fn restore(&mut self, entries: Vec<RemovedKey>) {
+        self.next_id = entries.last().map_or(1, |entry| entry.id + 1);
+        self.entries = entries.into();
+        self.trim();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_after_is_bounded_by_max_len() {
+        let mut log = ExpiryLog::default();
+        log.set_max_len(3);
+        for key in ["a", "b", "c", "d", "e"] {
+            log.record(key, RemovalReason::Expired, 1000);
+        }
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.first_id(), Some(3));
+        assert_eq!(log.last_id(), 5);
+
+        let keys = |after, count| log.read_after(after, count).map(|entry| entry.key.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys(0, 10), vec!["c", "d", "e"]);
+        assert_eq!(keys(3, 10), vec!["d", "e"]);
+        assert_eq!(keys(4, 1), vec!["e"]);
+        assert!(keys(5, 10).is_empty());
+    }
+
+    #[test]
+    fn test_restore_continues_numbering() {
+        let mut log = ExpiryLog::default();
+        log.record("session:1", RemovalReason::Evicted, 42);
+        log.record("my key", RemovalReason::Expired, 43);
+        assert_eq!(log.entries()[1].to_string(), "2 expired 43 my key");
+
+        let mut restored = ExpiryLog::default();
+        restored.restore(log.entries());
+        restored.record("next", RemovalReason::Expired, 44);
+        assert_eq!(restored.read_after(2, 10).map(|entry| entry.id).collect::<Vec<_>>(), vec![3]);
+
+        let mut disabled = ExpiryLog::default();
+        disabled.set_max_len(0);
+        disabled.record("ignored", RemovalReason::Expired, 1);
+        assert!(disabled.is_empty());
+    }
+}
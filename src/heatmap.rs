@@ -0,0 +1,212 @@
+// Which keys are being accessed, in memory that does not grow with the keyspace. A count-min
+// sketch estimates each key's accesses for LFU eviction, halved every minute so old popularity
+// fades. Each of the last HEATMAP_MINUTES minutes also keeps its heaviest keys, a fixed number
+// per minute chosen by space-saving, which HOTKEYS adds up over the window it is asked about.
+use crate::storage::now_millis;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+/// Minutes of history HOTKEYS can look back over.
+pub const HEATMAP_MINUTES: usize = 60;
+// Keys each minute counts; a key seen after that replaces the least accessed one
+const KEYS_PER_MINUTE: usize = 32;
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_WIDTH: usize = 2048;
+
+fn current_minute() -> u64 {
+    now_millis() / 60_000
+}
+
+#[derive(Debug, Clone)]
+struct Minute {
+    minute: u64,
+    counts: HashMap<String, u64>,
+}
+
+impl Minute {
+    fn record(&mut self, key: &str) {
+        if let Some(count) = self.counts.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        // Space-saving: the newcomer inherits the evicted count, so a key that is truly heavy
+        // is never undercounted
+        let mut count = 1;
+        if self.counts.len() >= KEYS_PER_MINUTE {
+            let coldest = self.counts.iter().min_by_key(|(_, count)| **count).map(|(key, count)| (key.clone(), *count));
+            if let Some((coldest, coldest_count)) = coldest {
+                self.counts.remove(&coldest);
+                count += coldest_count;
+            }
+        }
+        self.counts.insert(key.to_string(), count);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessHeatmap {
+    // SKETCH_DEPTH rows of SKETCH_WIDTH counters
+    sketch: Vec<u32>,
+    // The minute the sketch was last halved in
+    decayed_at: u64,
+    // Oldest first
+    minutes: VecDeque<Minute>,
+}
+
+impl Default for AccessHeatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessHeatmap {
+    pub fn new() -> Self {
+        Self { sketch: vec![0; SKETCH_DEPTH * SKETCH_WIDTH], decayed_at: current_minute(), minutes: VecDeque::new() }
+    }
+
+    // The counter `key` maps to in each row of the sketch
+    fn slots(key: &str) -> impl Iterator<Item = usize> + '_ {
+        (0..SKETCH_DEPTH).map(move |row| {
+            let mut hasher = DefaultHasher::new();
+            row.hash(&mut hasher);
+            key.hash(&mut hasher);
+            row * SKETCH_WIDTH + (hasher.finish() % SKETCH_WIDTH as u64) as usize
+        })
+    }
+
+    // Halves the sketch once for every minute gone by and drops minutes outside the window
+    fn advance(&mut self, minute: u64) {
+        let elapsed = minute.saturating_sub(self.decayed_at);
+        if elapsed > 0 {
+            let shift = elapsed.min(u32::BITS as u64) as u32;
+            for counter in &mut self.sketch {
+                *counter = counter.checked_shr(shift).unwrap_or(0);
+            }
+            self.decayed_at = minute;
+        }
+        while self.minutes.front().is_some_and(|oldest| oldest.minute + HEATMAP_MINUTES as u64 <= minute) {
+            self.minutes.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, key: &str) {
+        self.record_at(key, current_minute());
+    }
+
+    fn record_at(&mut self, key: &str, minute: u64) {
+        self.advance(minute);
+        for slot in Self::slots(key) {
+            self.sketch[slot] = self.sketch[slot].saturating_add(1);
+        }
+        if self.minutes.back().is_none_or(|latest| latest.minute != minute) {
+            self.minutes.push_back(Minute { minute, counts: HashMap::new() });
+        }
+        if let Some(latest) = self.minutes.back_mut() {
+            latest.record(key);
+        }
+    }
+
+    /// Recent accesses of `key`, an access a minute ago counting half as much as one now.
+    /// Never below the true figure, but keys sharing counters inflate it.
+    pub fn estimate(&self, key: &str) -> u64 {
+        Self::slots(key).map(|slot| self.sketch[slot]).min().unwrap_or(0) as u64
+    }
+
+    /// Adds `count` to the estimate of `key`, as when restoring counts saved with a snapshot.
+    pub fn seed(&mut self, key: &str, count: u64) {
+        let count = count.min(u32::MAX as u64) as u32;
+        for slot in Self::slots(key) {
+            self.sketch[slot] = self.sketch[slot].saturating_add(count);
+        }
+    }
+
+    /// Up to `count` of the keys accessed most in the last `minutes` minutes, with how often,
+    /// hottest first. Counts are exact unless a minute saw more keys than it keeps.
+    pub fn hottest(&self, count: usize, minutes: usize) -> Vec<(String, u64)> {
+        self.hottest_at(count, minutes, current_minute())
+    }
+
+    fn hottest_at(&self, count: usize, minutes: usize, now: u64) -> Vec<(String, u64)> {
+        let mut totals: HashMap<&str, u64> = HashMap::new();
+        for minute in self.minutes.iter().filter(|minute| minute.minute + (minutes as u64) > now) {
+            for (key, accesses) in &minute.counts {
+                *totals.entry(key).or_insert(0) += accesses;
+            }
+        }
+        let mut hottest: Vec<(String, u64)> = totals.into_iter().map(|(key, accesses)| (key.to_string(), accesses)).collect();
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hottest.truncate(count);
+        hottest
+    }
+
+    /// Drops `key` from the per-minute counts once it is deleted. Its share of the sketch
+    /// cannot be taken out and decays instead.
+    pub fn forget(&mut self, key: &str) {
+        for minute in &mut self.minutes {
+            minute.counts.remove(key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.sketch.len() * std::mem::size_of::<u32>()
+            + self.minutes.iter()
+                .flat_map(|minute| minute.counts.keys())
+                .map(|key| key.len() + std::mem::size_of::<String>() + std::mem::size_of::<u64>())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hottest_keys_over_a_window() {
+        let mut heatmap = AccessHeatmap::new();
+        let start = heatmap.decayed_at;
+        for _ in 0..5 {
+            heatmap.record_at("old", start);
+        }
+        for _ in 0..3 {
+            heatmap.record_at("new", start + 10);
+        }
+        heatmap.record_at("old", start + 10);
+
+        assert_eq!(heatmap.hottest_at(10, 1, start + 10), vec![("new".to_string(), 3), ("old".to_string(), 1)]);
+        assert_eq!(heatmap.hottest_at(1, HEATMAP_MINUTES, start + 10), vec![("old".to_string(), 6)]);
+
+        // Past the window the first minute is dropped
+        heatmap.record_at("new", start + HEATMAP_MINUTES as u64);
+        assert_eq!(heatmap.hottest_at(10, HEATMAP_MINUTES, start + HEATMAP_MINUTES as u64),
+                   vec![("new".to_string(), 4), ("old".to_string(), 1)]);
+
+        heatmap.forget("new");
+        assert_eq!(heatmap.hottest_at(10, HEATMAP_MINUTES, start + HEATMAP_MINUTES as u64), vec![("old".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_memory_is_bounded_and_estimates_decay() {
+        let mut heatmap = AccessHeatmap::new();
+        let start = heatmap.decayed_at;
+        // Space-saving keeps any key with more than 1/KEYS_PER_MINUTE of a minute's accesses
+        for _ in 0..1000 {
+            heatmap.record_at("hot", start);
+        }
+        for i in 0..10_000 {
+            heatmap.record_at(&format!("key:{}", i), start);
+        }
+        assert_eq!(heatmap.minutes[0].counts.len(), KEYS_PER_MINUTE);
+        assert_eq!(heatmap.hottest_at(1, 1, start)[0].0, "hot");
+        assert!(heatmap.estimate("hot") >= 1000);
+
+        // Two idle minutes quarter every count
+        heatmap.record_at("other", start + 2);
+        let hot = heatmap.estimate("hot");
+        assert!((250..300).contains(&hot), "{}", hot);
+    }
+}
@@ -0,0 +1,310 @@
+// Optional HTTP/JSON front end for clients that cannot speak the line protocol. Requests are
+// mapped onto the same Command enum and executor as TCP connections:
+//   GET /keys/{key}       -> GET, 404 if the key is missing
+//   PUT /keys/{key}       -> SET with the request body as the value
+//   DELETE /keys/{key}    -> DEL
+//   POST /command         -> any command, as {"command": ["SET", "k", "v"]} or {"command": "SET k v"}
+// Each connection serves one request, so the per-client write rate limit applies per request and
+// only the global one holds back a busy HTTP client. Credentials go in `Authorization: Bearer <password>`, or
+// `Bearer <tenant>:<password>` for a tenant.
+use crate::auth::{AuthConfig, ClientAuth};
+use crate::command_renames::CommandRenames;
+use crate::commands::{Command, SetCondition};
+use crate::panic_guard::execute_guarded;
+use crate::protocol::Reply;
+use crate::server::{admit_write, WriteAdmission};
+use crate::shared::Database;
+use crate::metrics::Metrics;
+use crate::pub_sub::PubSubManager;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// Larger bodies are refused rather than buffered
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    fn error(status: u16, message: &str) -> Self {
+        Self { status, body: json!({ "error": message }) }
+    }
+}
+
+/// What a request asks for, before authentication.
+#[derive(Debug)]
+pub enum Route {
+    GetKey(String),
+    // Upper-case command name, for the per-command metrics
+    Command { name: String, command: Command },
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn command_line(body: &[u8]) -> Result<String, Response> {
+    let request: Value = serde_json::from_slice(body).map_err(|e| Response::error(400, &format!("invalid JSON: {}", e)))?;
+    match request.get("command") {
+        Some(Value::String(line)) => Ok(line.clone()),
+        Some(Value::Array(args)) => {
+            let mut parts = Vec::with_capacity(args.len());
+            for arg in args {
+                let arg = match arg {
+                    Value::String(s) => s.clone(),
+                    Value::Number(n) => n.to_string(),
+                    _ => return Err(Response::error(400, "command arguments must be strings or numbers")),
+                };
+                // The parser splits on whitespace, so such an argument would become several
+                if arg.is_empty() || arg.contains(char::is_whitespace) {
+                    return Err(Response::error(400, "command arguments cannot be empty or contain whitespace"));
+                }
+                parts.push(arg);
+            }
+            Ok(parts.join(" "))
+        },
+        _ => Err(Response::error(400, "expected {\"command\": [...]}")),
+    }
+}
+
+/// Maps a request onto a command.
+pub fn route(method: &str, path: &str, body: &[u8], command_renames: &CommandRenames) -> Result<Route, Response> {
+    if let Some(key) = path.strip_prefix("/keys/") {
+        let key = percent_decode(key).filter(|key| !key.is_empty()).ok_or_else(|| Response::error(400, "invalid key"))?;
+        return match method {
+            "GET" => Ok(Route::GetKey(key)),
+            "PUT" => {
+                let value = String::from_utf8(body.to_vec()).map_err(|_| Response::error(400, "value must be UTF-8"))?;
+                Ok(Route::Command { name: "SET".to_string(), command: Command::Set { key, value, expiry: None, condition: SetCondition::Always, keep_ttl: false, get: false } })
+            },
+            "DELETE" => Ok(Route::Command { name: "DEL".to_string(), command: Command::Del { keys: vec![key] } }),
+            _ => Err(Response::error(405, "method not allowed")),
+        };
+    }
+    match (method, path) {
+        ("POST", "/command") => {
+            let line = command_line(body)?;
+            let command = command_renames.parse(&line).map_err(|e| Response::error(400, &e))?;
+            let name = line.split_whitespace().next().unwrap_or_default().to_uppercase();
+            match command {
+                Command::Subscribe { .. } | Command::Unsubscribe { .. } | Command::PSubscribe { .. } |
+                Command::PUnsubscribe { .. } | Command::Ack { .. } | Command::SnapshotBegin |
+                Command::SnapshotEnd | Command::Auth { .. } | Command::SessionAuth { .. } |
+                Command::ReadOnly | Command::ReadWrite | Command::ClientList { .. } | Command::ClientId |
+                Command::Atomic { .. } => {
+                    Err(Response::error(400, "command is only available over the line protocol"))
+                },
+                command => Ok(Route::Command { name, command }),
+            }
+        },
+        (_, "/command") => Err(Response::error(405, "method not allowed")),
+        _ => Err(Response::error(404, "not found")),
+    }
+}
+
+fn reply_response(reply: Reply) -> Response {
+    match reply {
+        Reply::Error(error) if error.starts_with("NOAUTH") || error.starts_with("WRONGPASS") => Response::error(401, &error),
+        Reply::Error(error) if error.starts_with("THROTTLED") => Response::error(429, &error),
+        Reply::Error(error) if error.starts_with("BUSY") => Response::error(503, &error),
+        Reply::Error(error) => Response::error(400, &error),
+        reply => Response { status: 200, body: json!({ "reply": reply.to_text() }) },
+    }
+}
+
+async fn authenticate(client_auth: &mut ClientAuth, authorization: Option<&str>) -> bool {
+    let credentials = match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(credentials) => credentials.trim(),
+        None => return !client_auth.requires_auth(),
+    };
+    if let Some((user, password)) = credentials.split_once(':') {
+        if client_auth.authenticate_user(user, password).await {
+            return true;
+        }
+    }
+    client_auth.authenticate(credentials).await
+}
+
+async fn respond(socket: &mut TcpStream, response: Response) -> std::io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Error",
+    };
+    let body = response.body.to_string();
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, reason, body.len()
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.flush().await
+}
+
+async fn handle_request(
+    mut socket: TcpStream,
+    database: Database,
+    auth_config: Arc<AuthConfig>,
+    pubsub: PubSubManager,
+    metrics: Metrics,
+    command_renames: Arc<CommandRenames>,
+    admission: WriteAdmission,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&mut socket);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default().to_string(), parts.next().unwrap_or_default().to_string());
+
+    let mut content_length = 0usize;
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "authorization" => authorization = Some(value.trim().to_string()),
+                _ => {},
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return respond(&mut socket, Response::error(413, "request body too large")).await;
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let route = match route(&method, &path, &body, &command_renames) {
+        Ok(route) => route,
+        Err(response) => return respond(&mut socket, response).await,
+    };
+    let mut client_auth = ClientAuth::new(auth_config);
+    if !authenticate(&mut client_auth, authorization.as_deref()).await {
+        return respond(&mut socket, Response::error(401, "invalid credentials")).await;
+    }
+
+    let name = match &route {
+        Route::GetKey(_) => "GET".to_string(),
+        Route::Command { name, .. } => name.clone(),
+    };
+    let response = match route {
+        Route::GetKey(key) => {
+            let command = Command::Get { key: key.clone() };
+            let reply = execute_guarded(database, command, &mut client_auth, Some(&pubsub), Some(&metrics), &name).await;
+            match reply {
+                Reply::Nil => Response::error(404, "no such key"),
+                Reply::Bulk(value) => Response { status: 200, body: json!({ "key": key, "value": value }) },
+                reply => reply_response(reply),
+            }
+        },
+        Route::Command { command, .. } => {
+            let refused = match command.is_write() {
+                true => admit_write(&admission.write_stalls, &mut admission.write_rate_limit.for_client(), &database, &metrics).await,
+                false => None,
+            };
+            match refused {
+                Some(reply) => reply_response(reply),
+                None => reply_response(execute_guarded(database, command, &mut client_auth, Some(&pubsub), Some(&metrics), &name).await),
+            }
+        },
+    };
+    metrics.write().await.record(&name, request_line.len() + body.len(), response.body.to_string().len());
+    respond(&mut socket, response).await
+}
+
+/// Serves HTTP requests on `listener` until the process exits.
+pub async fn run(
+    listener: TcpListener,
+    database: Database,
+    auth_config: Arc<AuthConfig>,
+    pubsub: PubSubManager,
+    metrics: Metrics,
+    command_renames: Arc<CommandRenames>,
+    admission: WriteAdmission,
+) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                eprintln!("HTTP gateway accept failed: {}", e);
+                continue;
+            },
+        };
+        let database = Arc::clone(&database);
+        let auth_config = Arc::clone(&auth_config);
+        let pubsub = Arc::clone(&pubsub);
+        let metrics = Arc::clone(&metrics);
+        let command_renames = Arc::clone(&command_renames);
+        let admission = admission.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(socket, database, auth_config, pubsub, metrics, command_renames, admission).await {
+                eprintln!("Error handling HTTP request: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes_map_to_commands() {
+        let renames = CommandRenames::default();
+        assert!(matches!(route("GET", "/keys/user%3A1", b"", &renames), Ok(Route::GetKey(key)) if key == "user:1"));
+        assert!(matches!(route("PUT", "/keys/a", b"hello world", &renames),
+            Ok(Route::Command { command: Command::Set { key, value, .. }, .. }) if key == "a" && value == "hello world"));
+        assert!(matches!(route("POST", "/command", br#"{"command": ["INCR", "n"]}"#, &renames),
+            Ok(Route::Command { name, command: Command::Incr { key } }) if name == "INCR" && key == "n"));
+        assert!(matches!(route("POST", "/command", br#"{"command": "DBSIZE"}"#, &renames), Ok(Route::Command { command: Command::DbSize, .. })));
+    }
+
+    #[test]
+    fn test_bad_requests_are_rejected() {
+        let renames = CommandRenames::default();
+        let status = |result: Result<Route, Response>| result.err().map(|response| response.status);
+        assert_eq!(status(route("GET", "/nowhere", b"", &renames)), Some(404));
+        assert_eq!(status(route("PATCH", "/keys/a", b"", &renames)), Some(405));
+        assert_eq!(status(route("POST", "/command", b"not json", &renames)), Some(400));
+        assert_eq!(status(route("POST", "/command", br#"{"command": ["SET", "a", "two words"]}"#, &renames)), Some(400));
+        assert_eq!(status(route("POST", "/command", br#"{"command": ["SUBSCRIBE", "c"]}"#, &renames)), Some(400));
+        assert_eq!(reply_response(Reply::error("NOAUTH Authentication required.")).status, 401);
+    }
+
+    #[tokio::test]
+    async fn test_writes_pass_the_write_rate_limit() {
+        let database = crate::shared::create_database();
+        let metrics = crate::metrics::create_metrics();
+        let admission = WriteAdmission { write_rate_limit: crate::rate_limit::WriteRateLimit::new(Some(1), None), ..Default::default() };
+        let mut limit = admission.write_rate_limit.for_client();
+        assert!(admit_write(&admission.write_stalls, &mut limit, &database, &metrics).await.is_none());
+        let refused = admit_write(&admission.write_stalls, &mut admission.write_rate_limit.for_client(), &database, &metrics).await.unwrap();
+        assert_eq!(reply_response(refused).status, 429);
+        assert_eq!(metrics.read().await.throttled_writes, 1);
+    }
+}
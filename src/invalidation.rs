@@ -0,0 +1,153 @@
+// Near-cache coherence between instances without replication: when a key matching one of the
+// configured patterns is written or deleted here, every peer is sent `INVALIDATE key`, which drops
+// its copy. Peers do not pass invalidations on, so instances can list each other freely.
+use crate::database::KeyInvalidator;
+use crate::protocol::quote_arg;
+use regex::Regex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::time::sleep;
+
+// Invalidations queued for a slow peer beyond this many batches are dropped
+const BUS_CAPACITY: usize = 4096;
+// How long to wait before reconnecting to an unreachable peer
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct InvalidationBus {
+    patterns: Vec<Regex>,
+    sender: broadcast::Sender<Vec<String>>,
+}
+
+// `*` matches any run of characters and `?` any single one; everything else is literal
+pub(crate) fn glob_regex(pattern: &str) -> Regex {
+    let escaped = regex::escape(pattern).replace("\\*", ".*").replace("\\?", ".");
+    Regex::new(&format!("^{}$", escaped)).expect("escaped glob is a valid regex")
+}
+
+impl InvalidationBus {
+    pub fn new(patterns: &[String]) -> Self {
+        let (sender, _) = broadcast::channel(BUS_CAPACITY);
+        Self { patterns: patterns.iter().map(|pattern| glob_regex(pattern)).collect(), sender }
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.is_match(key))
+    }
+
+    /// Queues an invalidation of whichever of `keys` match the patterns for every peer.
+    pub fn publish<'a>(&self, keys: impl IntoIterator<Item = &'a String>) {
+        let keys: Vec<String> = keys.into_iter().filter(|key| self.matches(key)).cloned().collect();
+        if !keys.is_empty() {
+            // Fails only when no peer link is running
+            let _ = self.sender.send(keys);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<String>> {
+        self.sender.subscribe()
+    }
+}
+
+impl KeyInvalidator for InvalidationBus {
+    fn invalidate(&self, keys: &mut dyn Iterator<Item = &String>) {
+        self.publish(keys);
+    }
+}
+
+// The inline command sent to peers; keys are quoted so spaces and quotes in them survive
+fn invalidate_line(keys: &[String]) -> String {
+    let keys: Vec<_> = keys.iter().map(|key| quote_arg(key)).collect();
+    format!("INVALIDATE {}\r\n", keys.join(" "))
+}
+
+/// Forwards invalidations to the instance at `peer` until the process exits, reconnecting
+/// whenever the connection drops. Invalidations raised while it is down are lost.
+pub async fn run_peer(peer: String, password: Option<String>, mut invalidations: broadcast::Receiver<Vec<String>>) {
+    loop {
+        let stream = match TcpStream::connect(&peer).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Invalidation peer {} unreachable: {}", peer, e);
+                sleep(RECONNECT_DELAY).await;
+                continue;
+            },
+        };
+        println!("Sending invalidations to {}", peer);
+        let (reader, mut writer) = stream.into_split();
+        // Replies are only read to surface errors, such as a wrong password
+        let peer_name = peer.clone();
+        let replies = tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.starts_with("(error)") {
+                    eprintln!("Invalidation peer {} replied: {}", peer_name, line);
+                }
+            }
+        });
+
+        if let Some(password) = &password {
+            if writer.write_all(format!("AUTH {}\r\n", quote_arg(password)).as_bytes()).await.is_err() {
+                replies.abort();
+                sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        }
+        // Whatever was raised while disconnected is stale; start from what comes next
+        invalidations = invalidations.resubscribe();
+        loop {
+            let keys = match invalidations.recv().await {
+                Ok(keys) => keys,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    eprintln!("Invalidation peer {} fell behind; {} batches dropped", peer, skipped);
+                    continue;
+                },
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            if let Err(e) = writer.write_all(invalidate_line(&keys).as_bytes()).await {
+                eprintln!("Lost invalidation peer {}: {}", peer, e);
+                break;
+            }
+        }
+        replies.abort();
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::split_inline;
+
+    #[test]
+    fn test_patterns_select_keys() {
+        let bus = InvalidationBus::new(&["user:*".to_string(), "config.v?".to_string()]);
+        assert!(bus.matches("user:42"));
+        assert!(bus.matches("config.v2"));
+        assert!(!bus.matches("config.v10"));
+        assert!(!bus.matches("session:1"));
+        // Regex metacharacters in a pattern are literal
+        assert!(!bus.matches("configXv2"));
+    }
+
+    #[tokio::test]
+    async fn test_publish_skips_unmatched_keys() {
+        let bus = InvalidationBus::new(&["cache:*".to_string()]);
+        let mut receiver = bus.subscribe();
+        bus.publish(&["other".to_string()]);
+        bus.publish(&["cache:a".to_string(), "other".to_string(), "cache:b".to_string()]);
+        assert_eq!(receiver.recv().await.unwrap(), vec!["cache:a", "cache:b"]);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_keys_with_spaces_reach_peers_whole() {
+        let keys = vec!["cache:a b".to_string(), "cache:\"q\"".to_string(), "cache:c".to_string()];
+        let line = invalidate_line(&keys);
+        let parts = split_inline(line.trim_end()).unwrap();
+        assert_eq!(parts[0], "INVALIDATE");
+        assert_eq!(parts[1..], keys[..]);
+    }
+}
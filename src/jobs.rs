@@ -0,0 +1,224 @@
+// Long-running admin operations (DELPATTERN, MERGE BACKGROUND) run as numbered jobs on
+// background tasks instead of holding up the connection that started them. The task reports
+// its progress here between batches and stops at the next one once the job is cancelled.
+// JOB LIST, JOB STATUS and JOB CANCEL work from any connection; a tenant sees only the jobs it
+// started. Only a few jobs run at once, and each paces itself with its throttle.
+use crate::migration::Throttle;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+pub const MAX_RUNNING_JOBS: usize = 4;
+// Finished jobs kept for JOB STATUS; older ones are forgotten
+const FINISHED_JOBS_KEPT: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    DelPattern,
+    Merge,
+}
+
+impl JobKind {
+    fn name(self) -> &'static str {
+        match self {
+            JobKind::DelPattern => "delpattern",
+            JobKind::Merge => "merge",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Done,
+    Cancelled,
+}
+
+impl JobState {
+    fn name(self) -> &'static str {
+        match self {
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub kind: JobKind,
+    // The pattern or file the job works on, as stored (tenant prefix included)
+    pub target: String,
+    // The key prefix of the tenant that started it
+    owner: Option<String>,
+    pub throttle: Throttle,
+    pub state: JobState,
+    // Units of work (keys) to get through, and how many are done
+    pub total: u64,
+    pub done: u64,
+    // Outcome counts particular to the kind of job, e.g. keys actually deleted
+    pub counters: BTreeMap<&'static str, u64>,
+    started: Instant,
+    duration: Option<Duration>,
+}
+
+impl Job {
+    pub fn elapsed(&self) -> Duration {
+        self.duration.unwrap_or_else(|| self.started.elapsed())
+    }
+
+    fn finish(&mut self, state: JobState) {
+        self.state = state;
+        self.duration = Some(self.started.elapsed());
+    }
+
+    fn visible_to(&self, prefix: Option<&str>) -> bool {
+        prefix.is_none_or(|prefix| self.owner.as_deref() == Some(prefix))
+    }
+
+    // The target as a client with `prefix` wrote it; the tenant's own prefix is taken off
+    fn shown_target(&self, prefix: Option<&str>) -> &str {
+        prefix.and_then(|prefix| self.target.strip_prefix(prefix)).unwrap_or(&self.target)
+    }
+
+    /// One line of JOB LIST, for a client with `prefix` as its tenant prefix.
+    pub fn summary(&self, id: u64, prefix: Option<&str>) -> String {
+        format!("id={} type={} status={} target={} progress={}/{}", id, self.kind.name(), self.state.name(), self.shown_target(prefix), self.done, self.total)
+    }
+
+    /// INFO-style lines for JOB STATUS, for a client with `prefix` as its tenant prefix.
+    pub fn render(&self, id: u64, prefix: Option<&str>) -> String {
+        let limit = |limit: Option<u64>| limit.map_or("unlimited".to_string(), |limit| limit.to_string());
+        let mut lines = vec![
+            format!("id:{}", id),
+            format!("type:{}", self.kind.name()),
+            format!("status:{}", self.state.name()),
+            format!("target:{}", self.shown_target(prefix)),
+            format!("done:{}", self.done),
+            format!("total:{}", self.total),
+            format!("elapsed_ms:{}", self.elapsed().as_millis()),
+            format!("keys_per_sec_limit:{}", limit(self.throttle.keys_per_sec)),
+            format!("bytes_per_sec_limit:{}", limit(self.throttle.bytes_per_sec)),
+        ];
+        lines.extend(self.counters.iter().map(|(name, count)| format!("{}:{}", name, count)));
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct JobManager {
+    next_id: u64,
+    jobs: BTreeMap<u64, Job>,
+}
+
+impl JobManager {
+    /// Registers a running job over `total` units of work, returning its id, or an error if
+    /// MAX_RUNNING_JOBS are running already.
+    pub fn start(&mut self, kind: JobKind, target: &str, owner: Option<&str>, throttle: Throttle, total: u64) -> Result<u64, String> {
+        if self.jobs.values().filter(|job| job.state == JobState::Running).count() >= MAX_RUNNING_JOBS {
+            return Err(format!("ERR {} jobs are already running, see JOB LIST", MAX_RUNNING_JOBS));
+        }
+        self.next_id += 1;
+        let job = Job {
+            kind,
+            target: target.to_string(),
+            owner: owner.map(str::to_string),
+            throttle,
+            state: JobState::Running,
+            total,
+            done: 0,
+            counters: BTreeMap::new(),
+            started: Instant::now(),
+            duration: None,
+        };
+        self.jobs.insert(self.next_id, job);
+        Ok(self.next_id)
+    }
+
+    fn forget_old(&mut self) {
+        let finished: Vec<u64> = self.jobs.iter().filter(|(_, job)| job.state != JobState::Running).map(|(id, _)| *id).collect();
+        for id in finished.iter().take(finished.len().saturating_sub(FINISHED_JOBS_KEPT)) {
+            self.jobs.remove(id);
+        }
+    }
+
+    /// A job as seen by a client with `prefix` as its tenant prefix.
+    pub fn get(&self, id: u64, prefix: Option<&str>) -> Option<&Job> {
+        self.jobs.get(&id).filter(|job| job.visible_to(prefix))
+    }
+
+    /// The job for its task to update, while it is running.
+    pub fn running(&mut self, id: u64) -> Option<&mut Job> {
+        self.jobs.get_mut(&id).filter(|job| job.state == JobState::Running)
+    }
+
+    /// Jobs visible to `prefix`, oldest first.
+    pub fn list(&self, prefix: Option<&str>) -> Vec<(u64, &Job)> {
+        self.jobs.iter().filter(|(_, job)| job.visible_to(prefix)).map(|(id, job)| (*id, job)).collect()
+    }
+
+    /// Stops a running job before its next batch. False if it is not running.
+    pub fn cancel(&mut self, id: u64, prefix: Option<&str>) -> bool {
+        match self.jobs.get_mut(&id) {
+            Some(job) if job.state == JobState::Running && job.visible_to(prefix) => {
+                job.finish(JobState::Cancelled);
+                self.forget_old();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Marks a job done once its task has got through the work, unless it was cancelled.
+    pub fn finish(&mut self, id: u64) {
+        if let Some(job) = self.running(id) {
+            job.finish(JobState::Done);
+            self.forget_old();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(jobs: &mut JobManager, target: &str, owner: Option<&str>) -> Result<u64, String> {
+        jobs.start(JobKind::DelPattern, target, owner, Throttle::default(), 10)
+    }
+
+    #[test]
+    fn test_jobs_are_listed_per_tenant_and_cancelled() {
+        let mut jobs = JobManager::default();
+        let mine = start(&mut jobs, "app1:user:*", Some("app1:")).unwrap();
+        let admin = start(&mut jobs, "cache:*", None).unwrap();
+        jobs.running(mine).unwrap().done = 4;
+        jobs.running(mine).unwrap().counters.insert("deleted", 3);
+
+        assert_eq!(jobs.list(Some("app1:")).len(), 1);
+        assert_eq!(jobs.list(None).len(), 2);
+        assert!(jobs.get(admin, Some("app1:")).is_none());
+        assert_eq!(jobs.get(mine, Some("app1:")).unwrap().summary(mine, Some("app1:")), "id=1 type=delpattern status=running target=user:* progress=4/10");
+
+        assert!(!jobs.cancel(admin, Some("app1:")));
+        assert!(jobs.cancel(mine, Some("app1:")));
+        assert!(!jobs.cancel(mine, None));
+        jobs.finish(mine);
+        let status = jobs.get(mine, None).unwrap().render(mine, None);
+        assert!(status.starts_with("id:1\ntype:delpattern\nstatus:cancelled\ntarget:app1:user:*\ndone:4\ntotal:10\n"), "{}", status);
+        assert!(status.ends_with("keys_per_sec_limit:unlimited\nbytes_per_sec_limit:unlimited\ndeleted:3"));
+    }
+
+    #[test]
+    fn test_running_jobs_are_capped_and_old_ones_forgotten() {
+        let mut jobs = JobManager::default();
+        let running: Vec<u64> = (0..MAX_RUNNING_JOBS).map(|_| start(&mut jobs, "k*", None).unwrap()).collect();
+        assert_eq!(start(&mut jobs, "k*", None).unwrap_err(), "ERR 4 jobs are already running, see JOB LIST");
+        running.iter().for_each(|id| jobs.finish(*id));
+
+        for _ in 0..FINISHED_JOBS_KEPT {
+            let id = start(&mut jobs, "k*", None).unwrap();
+            jobs.finish(id);
+        }
+        assert!(jobs.get(running[0], None).is_none());
+        assert_eq!(jobs.list(None).len(), FINISHED_JOBS_KEPT);
+    }
+}
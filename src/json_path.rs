@@ -0,0 +1,202 @@
+// Path addressing for JSON documents. Supports the single-value subset of JSONPath
+// ($, .field, ['field'], [index] with negative indexes) plus the legacy "." root form.
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Field(String),
+    Index(i64),
+}
+
+pub fn parse_path(path: &str) -> Result<Vec<PathSegment>, String> {
+    let invalid = || format!("ERR invalid JSON path '{}'", path);
+
+    let rest = if let Some(rest) = path.strip_prefix('$') {
+        rest
+    } else if path == "." {
+        ""
+    } else if path.starts_with('.') || path.starts_with('[') {
+        path
+    } else {
+        // Legacy paths may omit the leading dot: "a.b" is ".a.b"
+        return parse_path(&format!(".{}", path));
+    };
+
+    let chars: Vec<char> = rest.chars().collect();
+    let mut segments = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '.' => {
+                let start = i + 1;
+                let mut end = start;
+                while end < chars.len() && chars[end] != '.' && chars[end] != '[' {
+                    end += 1;
+                }
+                if end == start {
+                    return Err(invalid());
+                }
+                segments.push(PathSegment::Field(chars[start..end].iter().collect()));
+                i = end;
+            },
+            '[' => {
+                let close = chars[i..].iter().position(|c| *c == ']').map(|offset| i + offset).ok_or_else(invalid)?;
+                let inner: String = chars[i + 1..close].iter().collect();
+                let quoted = inner.len() >= 2
+                    && ((inner.starts_with('\'') && inner.ends_with('\'')) || (inner.starts_with('"') && inner.ends_with('"')));
+
+                if quoted {
+                    segments.push(PathSegment::Field(inner[1..inner.len() - 1].to_string()));
+                } else {
+                    segments.push(PathSegment::Index(inner.trim().parse::<i64>().map_err(|_| invalid())?));
+                }
+                i = close + 1;
+            },
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(segments)
+}
+
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let resolved = if index < 0 { len as i64 + index } else { index };
+    if resolved >= 0 && (resolved as usize) < len {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+pub fn get<'a>(root: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    path.iter().try_fold(root, |current, segment| match (segment, current) {
+        (PathSegment::Field(name), Value::Object(map)) => map.get(name),
+        (PathSegment::Index(index), Value::Array(items)) => resolve_index(*index, items.len()).map(|i| &items[i]),
+        _ => None,
+    })
+}
+
+pub fn get_mut<'a>(root: &'a mut Value, path: &[PathSegment]) -> Option<&'a mut Value> {
+    path.iter().try_fold(root, |current, segment| match (segment, current) {
+        (PathSegment::Field(name), Value::Object(map)) => map.get_mut(name),
+        (PathSegment::Index(index), Value::Array(items)) => {
+            let len = items.len();
+            resolve_index(*index, len).map(move |i| &mut items[i])
+        },
+        _ => None,
+    })
+}
+
+/// Replaces the value at `path`, adding a new object member if only the last field is missing.
+/// Returns false when the parent does not exist or cannot hold the new value.
+pub fn set(root: &mut Value, path: &[PathSegment], new_value: Value) -> bool {
+    let (last, parent_path) = match path.split_last() {
+        Some(split) => split,
+        None => {
+            *root = new_value;
+            return true;
+        },
+    };
+
+    match (last, get_mut(root, parent_path)) {
+        (PathSegment::Field(name), Some(Value::Object(map))) => {
+            map.insert(name.clone(), new_value);
+            true
+        },
+        (PathSegment::Index(index), Some(Value::Array(items))) => match resolve_index(*index, items.len()) {
+            Some(i) => {
+                items[i] = new_value;
+                true
+            },
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Removes the value at `path` from its parent. The root itself cannot be removed this way.
+pub fn delete(root: &mut Value, path: &[PathSegment]) -> bool {
+    let (last, parent_path) = match path.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+
+    match (last, get_mut(root, parent_path)) {
+        (PathSegment::Field(name), Some(Value::Object(map))) => map.remove(name).is_some(),
+        (PathSegment::Index(index), Some(Value::Array(items))) => match resolve_index(*index, items.len()) {
+            Some(i) => {
+                items.remove(i);
+                true
+            },
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Adds `increment` to the number at `path`, keeping integers as integers when both sides are.
+pub fn num_incr_by(root: &mut Value, path: &[PathSegment], increment: &serde_json::Number) -> Result<Value, String> {
+    let target = get_mut(root, path).ok_or_else(|| "ERR Path does not exist".to_string())?;
+    let current = match target {
+        Value::Number(number) => number.clone(),
+        _ => return Err("ERR Path does not point to a number".to_string()),
+    };
+
+    let result = match (current.as_i64(), increment.as_i64()) {
+        (Some(a), Some(b)) => match a.checked_add(b) {
+            Some(sum) => Value::from(sum),
+            None => return Err("ERR result is out of range".to_string()),
+        },
+        _ => {
+            let sum = current.as_f64().unwrap_or(0.0) + increment.as_f64().unwrap_or(0.0);
+            match serde_json::Number::from_f64(sum) {
+                Some(number) => Value::Number(number),
+                None => return Err("ERR result is not a finite number".to_string()),
+            }
+        },
+    };
+
+    *target = result.clone();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_path_forms() {
+        let expected = vec![
+            PathSegment::Field("a".to_string()),
+            PathSegment::Index(-1),
+            PathSegment::Field("b c".to_string()),
+        ];
+        assert_eq!(parse_path("$.a[-1]['b c']").unwrap(), expected);
+        assert_eq!(parse_path(".a[-1][\"b c\"]").unwrap(), expected);
+        assert_eq!(parse_path("$").unwrap(), vec![]);
+        assert_eq!(parse_path(".").unwrap(), vec![]);
+        assert_eq!(parse_path("a").unwrap(), vec![PathSegment::Field("a".to_string())]);
+        assert!(parse_path("$..a").is_err());
+        assert!(parse_path("$[x]").is_err());
+    }
+
+    #[test]
+    fn test_set_delete_and_increment() {
+        let mut doc = json!({"user": {"name": "ann", "scores": [1, 2]}});
+
+        assert!(set(&mut doc, &parse_path("$.user.age").unwrap(), json!(30)));
+        assert!(set(&mut doc, &parse_path("$.user.scores[-1]").unwrap(), json!(5)));
+        assert!(!set(&mut doc, &parse_path("$.missing.field").unwrap(), json!(1)));
+        assert_eq!(get(&doc, &parse_path("$.user.scores").unwrap()), Some(&json!([1, 5])));
+
+        let incremented = num_incr_by(&mut doc, &parse_path("$.user.age").unwrap(), &serde_json::Number::from(2)).unwrap();
+        assert_eq!(incremented, json!(32));
+        assert!(num_incr_by(&mut doc, &parse_path("$.user.name").unwrap(), &serde_json::Number::from(1)).is_err());
+
+        assert!(delete(&mut doc, &parse_path("$.user.scores[0]").unwrap()));
+        assert!(!delete(&mut doc, &parse_path("$").unwrap()));
+        assert_eq!(doc, json!({"user": {"name": "ann", "age": 32, "scores": [5]}}));
+    }
+}
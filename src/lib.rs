@@ -1,16 +1,67 @@
 pub mod database;
+pub mod shared;
 pub mod commands;
 pub mod protocol;
+pub mod resp;
 pub mod data_types;
 pub mod server;
 pub mod auth;
+pub mod auth_backends;
 pub mod persistence_clean;
 pub mod memory;
 pub mod wal;
 pub mod pub_sub;
+pub mod metrics;
+pub mod string_ops;
+pub mod bloom;
+pub mod timeseries;
+pub mod json_path;
+pub mod search;
+pub mod vector;
+pub mod storage;
+pub mod compression;
+pub mod command_renames;
+pub mod locks;
+pub mod tenancy;
+#[cfg(feature = "http-gateway")]
+pub mod http_gateway;
+pub mod memcached;
+pub mod capture;
+pub mod rng;
+pub mod refresh;
+pub mod invalidation;
+pub mod ttl_index;
+pub mod snapshot_diff;
+pub mod warmup;
+pub mod heatmap;
+pub mod prefix_stats;
+pub mod expiry_log;
+pub mod persistence_bench;
+pub mod save_scheduler;
+pub mod migration;
+pub mod panic_guard;
+pub mod rate_limit;
+pub mod clients;
+pub mod compat;
+pub mod aggregate;
+pub mod tags;
+pub mod delpattern;
+pub mod jobs;
+pub mod blocking;
+pub mod trash;
+pub mod config;
+#[cfg(any(test, feature = "test-server"))]
+pub mod test_server;
 
-pub use database::{Database, RedisDatabase};
+pub use database::RedisDatabase;
+pub use shared::Database;
 pub use data_types::RedisValue;
 pub use memory::{MemoryManager, EvictionPolicy};
 pub use auth::{AuthConfig, ClientAuth};
 pub use pub_sub::{PubSubManager, PubSubMessage, create_pubsub_manager};
+pub use metrics::{Metrics, MetricsRing, create_metrics};
+pub use refresh::{RefreshConfig, Refreshed, spawn_refresher};
+pub use persistence_clean::PersistenceError;
+pub use wal::WalError;
+pub use server::ServerError;
+pub use protocol::{Reply, RespCodec};
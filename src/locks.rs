@@ -0,0 +1,102 @@
+use crate::storage::now_millis;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Advisory locks for LOCK/UNLOCK. They live beside the keyspace rather than in it: locking a
+// name does not stop anyone from writing a key of the same name.
+#[derive(Debug)]
+struct Lock {
+    token: String,
+    fence: u64,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct LockTable {
+    locks: HashMap<String, Lock>,
+    last_fence: u64,
+}
+
+impl LockTable {
+    /// Takes the lock on `name` for `token`, or extends it if `token` already holds it.
+    /// Returns the fencing token of the hold, `None` if another token holds the lock.
+    pub fn acquire(&mut self, name: &str, token: &str, ttl: Duration, now: Instant) -> Option<u64> {
+        if let Some(lock) = self.locks.get_mut(name) {
+            if lock.expires_at > now {
+                if lock.token != token {
+                    return None;
+                }
+                lock.expires_at = now + ttl;
+                return Some(lock.fence);
+            }
+        }
+
+        // Fences never go below the wall clock in milliseconds, so they keep increasing
+        // across restarts even though locks are not persisted
+        self.last_fence = (self.last_fence + 1).max(now_millis());
+        self.locks.insert(name.to_string(), Lock { token: token.to_string(), fence: self.last_fence, expires_at: now + ttl });
+        Some(self.last_fence)
+    }
+
+    /// Releases the lock on `name` if `token` holds it.
+    pub fn release(&mut self, name: &str, token: &str, now: Instant) -> bool {
+        match self.locks.get(name) {
+            Some(lock) if lock.token == token && lock.expires_at > now => {
+                self.locks.remove(name);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn purge_expired(&mut self, now: Instant) -> usize {
+        let before = self.locks.len();
+        self.locks.retain(|_, lock| lock.expires_at > now);
+        before - self.locks.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.locks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive_until_released() {
+        let mut locks = LockTable::default();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(10);
+
+        let fence = locks.acquire("job", "a", ttl, now).unwrap();
+        assert_eq!(locks.acquire("job", "b", ttl, now), None);
+        // The holder can extend its lock and keeps its fence
+        assert_eq!(locks.acquire("job", "a", ttl, now + Duration::from_secs(5)), Some(fence));
+
+        assert!(!locks.release("job", "b", now));
+        assert!(locks.release("job", "a", now));
+        assert!(!locks.release("job", "a", now));
+        assert!(locks.acquire("job", "b", ttl, now).unwrap() > fence);
+    }
+
+    #[test]
+    fn test_expired_lock_can_be_taken_over() {
+        let mut locks = LockTable::default();
+        let now = Instant::now();
+        let fence = locks.acquire("job", "a", Duration::from_millis(100), now).unwrap();
+        let later = now + Duration::from_millis(200);
+
+        assert!(!locks.release("job", "a", later));
+        let takeover = locks.acquire("job", "b", Duration::from_millis(100), later).unwrap();
+        assert!(takeover > fence);
+
+        assert_eq!(locks.purge_expired(later + Duration::from_secs(1)), 1);
+        assert!(locks.is_empty());
+    }
+}
@@ -0,0 +1,345 @@
+// Secondary listener speaking the memcached text protocol on the same dataset, so memcached
+// clients can move over without code changes. Supported: get/gets, set/add/replace,
+// append/prepend, delete, incr/decr, touch, version and quit. Values are stored as plain
+// strings; client flags are not kept and always read back as 0.
+use crate::data_types::RedisValue;
+use crate::shared::Database;
+use crate::metrics::Metrics;
+use crate::server::{admit_write, WriteAdmission};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// Same limits as memcached's defaults
+const MAX_KEY_BYTES: usize = 250;
+const MAX_VALUE_BYTES: usize = 1024 * 1024;
+// Larger exptimes are absolute unix timestamps rather than relative seconds
+const RELATIVE_EXPTIME_LIMIT: i64 = 60 * 60 * 24 * 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreMode {
+    Set,
+    Add,
+    Replace,
+    Append,
+    Prepend,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Get { keys: Vec<String>, with_cas: bool },
+    Store { mode: StoreMode, key: String, exptime: i64, bytes: usize, noreply: bool },
+    Delete { key: String, noreply: bool },
+    Incr { key: String, delta: u64, decr: bool, noreply: bool },
+    Touch { key: String, exptime: i64, noreply: bool },
+    Version,
+    Quit,
+}
+
+impl Request {
+    /// Whether the request changes the dataset, so it is held to the write rate limit and stalls.
+    pub fn is_write(&self) -> bool {
+        matches!(self, Request::Store { .. } | Request::Delete { .. } | Request::Incr { .. } | Request::Touch { .. })
+    }
+}
+
+const BAD_FORMAT: &str = "CLIENT_ERROR bad command line format";
+
+fn key(part: &str) -> Result<String, String> {
+    match part.len() <= MAX_KEY_BYTES && !part.chars().any(char::is_control) {
+        true => Ok(part.to_string()),
+        false => Err(BAD_FORMAT.to_string()),
+    }
+}
+
+fn number<T: std::str::FromStr>(part: &str) -> Result<T, String> {
+    part.parse().map_err(|_| BAD_FORMAT.to_string())
+}
+
+/// Parses a command line; the data block of storage commands follows on its own line.
+pub fn parse_request(line: &str) -> Result<Request, String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let name = parts.first().copied().unwrap_or_default();
+    let noreply = parts.last() == Some(&"noreply");
+    let args = if noreply { &parts[1..parts.len() - 1] } else { parts.get(1..).unwrap_or_default() };
+
+    match (name, args) {
+        ("get" | "gets", keys) if !keys.is_empty() => Ok(Request::Get {
+            keys: keys.iter().map(|part| key(part)).collect::<Result<_, _>>()?,
+            with_cas: name == "gets",
+        }),
+        ("set" | "add" | "replace" | "append" | "prepend", [k, flags, exptime, bytes]) => {
+            number::<u32>(flags)?;
+            let mode = match name {
+                "set" => StoreMode::Set,
+                "add" => StoreMode::Add,
+                "replace" => StoreMode::Replace,
+                "append" => StoreMode::Append,
+                _ => StoreMode::Prepend,
+            };
+            Ok(Request::Store { mode, key: key(k)?, exptime: number(exptime)?, bytes: number(bytes)?, noreply })
+        },
+        ("delete", [k]) => Ok(Request::Delete { key: key(k)?, noreply }),
+        ("incr" | "decr", [k, delta]) => {
+            let delta = delta.parse().map_err(|_| "CLIENT_ERROR invalid numeric delta argument".to_string())?;
+            Ok(Request::Incr { key: key(k)?, delta, decr: name == "decr", noreply })
+        },
+        ("touch", [k, exptime]) => Ok(Request::Touch { key: key(k)?, exptime: number(exptime)?, noreply }),
+        ("version", []) => Ok(Request::Version),
+        ("quit", []) => Ok(Request::Quit),
+        ("get" | "gets" | "set" | "add" | "replace" | "append" | "prepend" | "delete" | "incr" | "decr" | "touch" | "version" | "quit", _) => {
+            Err(BAD_FORMAT.to_string())
+        },
+        _ => Err("ERROR".to_string()),
+    }
+}
+
+/// How long a value with memcached `exptime` lives: `None` for no expiry, zero if it has
+/// already expired.
+pub fn exptime_ttl(exptime: i64, now_secs: i64) -> Option<Duration> {
+    match exptime {
+        0 => None,
+        e if e < 0 => Some(Duration::ZERO),
+        e if e <= RELATIVE_EXPTIME_LIMIT => Some(Duration::from_secs(e as u64)),
+        e => Some(Duration::from_secs(e.saturating_sub(now_secs).max(0) as u64)),
+    }
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+fn string_value(value: &RedisValue) -> Option<String> {
+    value.clone().into_string()
+}
+
+async fn store(database: &Database, mode: StoreMode, key: String, exptime: i64, data: String) -> &'static str {
+    let mut db = database.write().await;
+    let current = db.get(&key);
+    let value = match (mode, &current) {
+        (StoreMode::Add, Some(_)) | (StoreMode::Replace | StoreMode::Append | StoreMode::Prepend, None) => return "NOT_STORED",
+        (StoreMode::Append, Some(current)) => match string_value(current) {
+            Some(current) => current + &data,
+            None => return "NOT_STORED",
+        },
+        (StoreMode::Prepend, Some(current)) => match string_value(current) {
+            Some(current) => data + &current,
+            None => return "NOT_STORED",
+        },
+        _ => data,
+    };
+
+    // append and prepend keep the existing expiry, like memcached
+    if matches!(mode, StoreMode::Append | StoreMode::Prepend) {
+        let _ = db.set(key, RedisValue::String(value));
+        return "STORED";
+    }
+    match exptime_ttl(exptime, now_secs()) {
+        Some(ttl) if ttl.is_zero() => {
+            db.delete(&key);
+        },
+        Some(ttl) => {
+            let _ = db.set_with_expiry(key, RedisValue::String(value), ttl);
+        },
+        None => {
+            db.expires.remove(&key);
+            let _ = db.set(key, RedisValue::String(value));
+        },
+    }
+    "STORED"
+}
+
+async fn incr(database: &Database, key: String, delta: u64, decr: bool) -> String {
+    let mut db = database.write().await;
+    let current = match db.get(&key).as_ref().map(string_value) {
+        Some(Some(current)) => current,
+        Some(None) => return "CLIENT_ERROR cannot increment or decrement non-numeric value".to_string(),
+        None => return "NOT_FOUND".to_string(),
+    };
+    let current: u64 = match current.parse() {
+        Ok(current) => current,
+        Err(_) => return "CLIENT_ERROR cannot increment or decrement non-numeric value".to_string(),
+    };
+    // incr wraps at 64 bits and decr stops at 0, as in memcached
+    let value = if decr { current.saturating_sub(delta) } else { current.wrapping_add(delta) };
+    let _ = db.set(key, RedisValue::String(value.to_string()));
+    value.to_string()
+}
+
+async fn execute(database: &Database, request: Request, data: Option<String>) -> String {
+    match request {
+        Request::Get { keys, with_cas } => {
+            let mut db = database.write().await;
+            let mut reply = String::new();
+            for key in keys {
+                if let Some(value) = db.get(&key).as_ref().and_then(string_value) {
+                    let cas = if with_cas { " 0" } else { "" };
+                    reply.push_str(&format!("VALUE {} 0 {}{}\r\n{}\r\n", key, value.len(), cas, value));
+                }
+            }
+            reply + "END"
+        },
+        Request::Store { mode, key, exptime, .. } => {
+            store(database, mode, key, exptime, data.unwrap_or_default()).await.to_string()
+        },
+        Request::Delete { key, .. } => match database.write().await.delete(&key) {
+            true => "DELETED".to_string(),
+            false => "NOT_FOUND".to_string(),
+        },
+        Request::Incr { key, delta, decr, .. } => incr(database, key, delta, decr).await,
+        Request::Touch { key, exptime, .. } => {
+            let mut db = database.write().await;
+            if !db.exists(&key) {
+                return "NOT_FOUND".to_string();
+            }
+            match exptime_ttl(exptime, now_secs()) {
+                Some(ttl) if ttl.is_zero() => {
+                    db.delete(&key);
+                },
+                Some(ttl) => {
+                    db.expire(&key, ttl);
+                },
+                None => {
+                    db.expires.remove(&key);
+                },
+            }
+            "TOUCHED".to_string()
+        },
+        Request::Version => format!("VERSION {}", env!("CARGO_PKG_VERSION")),
+        Request::Quit => String::new(),
+    }
+}
+
+async fn handle_client(mut socket: TcpStream, database: Database, metrics: Metrics, admission: WriteAdmission) -> std::io::Result<()> {
+    let mut write_limit = admission.write_rate_limit.for_client();
+    let (reader, mut writer) = socket.split();
+    let mut reader = BufReader::new(reader);
+    let mut raw = Vec::new();
+
+    loop {
+        raw.clear();
+        if reader.read_until(b'\n', &mut raw).await? == 0 {
+            break;
+        }
+        // Read as bytes, so a command line that is not UTF-8 is refused rather than ending the connection
+        let line = match std::str::from_utf8(&raw) {
+            Ok(line) => line,
+            Err(_) => {
+                writer.write_all(b"CLIENT_ERROR bad command line format\r\n").await?;
+                continue;
+            },
+        };
+        let request = match parse_request(line) {
+            Ok(request) => request,
+            Err(error) => {
+                writer.write_all(format!("{}\r\n", error).as_bytes()).await?;
+                continue;
+            },
+        };
+
+        // Storage commands carry a data block terminated by \r\n
+        let data = match &request {
+            Request::Store { bytes, .. } => {
+                if *bytes > MAX_VALUE_BYTES {
+                    tokio::io::copy(&mut (&mut reader).take(*bytes as u64 + 2), &mut tokio::io::sink()).await?;
+                    writer.write_all(b"SERVER_ERROR object too large for cache\r\n").await?;
+                    continue;
+                }
+                let mut block = vec![0u8; bytes + 2];
+                reader.read_exact(&mut block).await?;
+                if !block.ends_with(b"\r\n") {
+                    writer.write_all(b"CLIENT_ERROR bad data chunk\r\n").await?;
+                    continue;
+                }
+                block.truncate(*bytes);
+                match String::from_utf8(block) {
+                    Ok(data) => Some(data),
+                    Err(_) => {
+                        writer.write_all(b"CLIENT_ERROR binary values are not supported\r\n").await?;
+                        continue;
+                    },
+                }
+            },
+            _ => None,
+        };
+
+        let name = line.split_whitespace().next().unwrap_or_default().to_uppercase();
+        let noreply = matches!(request,
+            Request::Store { noreply: true, .. } | Request::Delete { noreply: true, .. } |
+            Request::Incr { noreply: true, .. } | Request::Touch { noreply: true, .. });
+        let is_quit = request == Request::Quit;
+        let data_len = data.as_ref().map_or(0, String::len);
+        let refused = match request.is_write() {
+            true => admit_write(&admission.write_stalls, &mut write_limit, &database, &metrics).await,
+            false => None,
+        };
+        let reply = match refused {
+            Some(refused) => format!("SERVER_ERROR {}", refused.to_text().trim_start_matches("(error) ")),
+            None => execute(&database, request, data).await,
+        };
+        metrics.write().await.record(&name, line.len() + data_len, reply.len() + 2);
+
+        if is_quit {
+            break;
+        }
+        if !noreply {
+            writer.write_all(reply.as_bytes()).await?;
+            writer.write_all(b"\r\n").await?;
+            writer.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Serves memcached clients on `listener` until the process exits.
+pub async fn run(listener: TcpListener, database: Database, metrics: Metrics, admission: WriteAdmission) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                eprintln!("memcached listener accept failed: {}", e);
+                continue;
+            },
+        };
+        let database = Arc::clone(&database);
+        let metrics = Arc::clone(&metrics);
+        let admission = admission.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(socket, database, metrics, admission).await {
+                eprintln!("Error handling memcached client: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requests() {
+        assert_eq!(parse_request("get a b\r\n"), Ok(Request::Get { keys: vec!["a".to_string(), "b".to_string()], with_cas: false }));
+        assert_eq!(
+            parse_request("add k 5 60 3 noreply\r\n"),
+            Ok(Request::Store { mode: StoreMode::Add, key: "k".to_string(), exptime: 60, bytes: 3, noreply: true })
+        );
+        assert_eq!(parse_request("decr n 2\r\n"), Ok(Request::Incr { key: "n".to_string(), delta: 2, decr: true, noreply: false }));
+        assert_eq!(parse_request("set k 0 0\r\n"), Err(BAD_FORMAT.to_string()));
+        assert_eq!(parse_request("incr n -1\r\n"), Err("CLIENT_ERROR invalid numeric delta argument".to_string()));
+        assert_eq!(parse_request("stats\r\n"), Err("ERROR".to_string()));
+        assert!(parse_request(&format!("get {}\r\n", "k".repeat(MAX_KEY_BYTES + 1))).is_err());
+        assert!(parse_request("touch k 10\r\n").unwrap().is_write());
+        assert!(!parse_request("gets k\r\n").unwrap().is_write());
+    }
+
+    #[test]
+    fn test_exptime_is_relative_or_absolute() {
+        let now = 1_700_000_000;
+        assert_eq!(exptime_ttl(0, now), None);
+        assert_eq!(exptime_ttl(-1, now), Some(Duration::ZERO));
+        assert_eq!(exptime_ttl(100, now), Some(Duration::from_secs(100)));
+        assert_eq!(exptime_ttl(now + 50, now), Some(Duration::from_secs(50)));
+        assert_eq!(exptime_ttl(now - 50, now), Some(Duration::ZERO));
+    }
+}
@@ -1,10 +1,14 @@
 use crate::data_types::RedisValue;
 use crate::database::RedisDatabase;
-use std::collections::HashMap;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
-use rand::Rng;
-
-#[derive(Debug, Clone)]
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+use crate::expiry_log::RemovalReason;
+use crate::heatmap::AccessHeatmap;
+use crate::rng::CommandRng;
+use crate::storage::now_millis;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EvictionPolicy {
     NoEviction,
     AllKeysLru,
@@ -17,15 +21,32 @@ pub enum EvictionPolicy {
 
 impl EvictionPolicy {
     pub fn from_string(policy: &str) -> Self {
+        Self::parse(policy).unwrap_or(EvictionPolicy::AllKeysLru) // Default
+    }
+
+    /// The policy named `policy`, or None if there is no such policy.
+    pub fn parse(policy: &str) -> Option<Self> {
         match policy {
-            "noeviction" => EvictionPolicy::NoEviction,
-            "allkeys-lru" => EvictionPolicy::AllKeysLru,
-            "allkeys-lfu" => EvictionPolicy::AllKeysLfu,
-            "volatile-lru" => EvictionPolicy::VolatileLru,
-            "volatile-lfu" => EvictionPolicy::VolatileLfu,
-            "allkeys-random" => EvictionPolicy::AllKeysRandom,
-            "volatile-random" => EvictionPolicy::VolatileRandom,
-            _ => EvictionPolicy::AllKeysLru, // Default
+            "noeviction" => Some(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Some(EvictionPolicy::AllKeysLru),
+            "allkeys-lfu" => Some(EvictionPolicy::AllKeysLfu),
+            "volatile-lru" => Some(EvictionPolicy::VolatileLru),
+            "volatile-lfu" => Some(EvictionPolicy::VolatileLfu),
+            "allkeys-random" => Some(EvictionPolicy::AllKeysRandom),
+            "volatile-random" => Some(EvictionPolicy::VolatileRandom),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::VolatileLfu => "volatile-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileRandom => "volatile-random",
         }
     }
 }
@@ -35,7 +56,12 @@ pub struct MemoryManager {
     pub max_memory: Option<usize>,
     pub eviction_policy: EvictionPolicy,
     pub access_times: HashMap<String, Instant>,
-    pub access_counts: HashMap<String, u64>,
+    // Recent access counts, for LFU eviction, HOTKEYS and warm-up after a restart
+    pub heatmap: AccessHeatmap,
+    // Keys set by PIN, which no eviction policy may pick; they still expire
+    pub pinned: HashSet<String>,
+    // Usage as of the last refresh; the server refreshes it in the background when a limit is set
+    pub used_memory: usize,
 }
 
 impl MemoryManager {
@@ -44,18 +70,34 @@ impl MemoryManager {
             max_memory,
             eviction_policy: EvictionPolicy::from_string(&eviction_policy),
             access_times: HashMap::new(),
-            access_counts: HashMap::new(),
+            heatmap: AccessHeatmap::new(),
+            pinned: HashSet::new(),
+            used_memory: 0,
         }
     }
 
     pub fn track_access(&mut self, key: &str) {
         self.access_times.insert(key.to_string(), Instant::now());
-        *self.access_counts.entry(key.to_string()).or_insert(0) += 1;
+        self.heatmap.record(key);
     }
 
     pub fn remove_tracking(&mut self, key: &str) {
         self.access_times.remove(key);
-        self.access_counts.remove(key);
+        self.heatmap.forget(key);
+        self.pinned.remove(key);
+    }
+
+    /// Exempts `key` from eviction. Returns false if it was already pinned.
+    pub fn pin(&mut self, key: &str) -> bool {
+        self.pinned.insert(key.to_string())
+    }
+
+    pub fn unpin(&mut self, key: &str) -> bool {
+        self.pinned.remove(key)
+    }
+
+    pub fn is_pinned(&self, key: &str) -> bool {
+        self.pinned.contains(key)
     }
 
     pub fn calculate_memory_usage(&self, db: &RedisDatabase) -> usize {
@@ -68,19 +110,52 @@ impl MemoryManager {
 
         total_size += db.expires.len() * (std::mem::size_of::<String>() + std::mem::size_of::<Instant>());
 
-        // Add tracking overhead
-        total_size += self.access_times.len() * (std::mem::size_of::<String>() + std::mem::size_of::<Instant>());
-        total_size += self.access_counts.len() * (std::mem::size_of::<String>() + std::mem::size_of::<u64>());
+        total_size += self.tracking_overhead();
 
         total_size += 2048; 
 
         total_size
     }
 
-    fn calculate_value_size(&self, value: &RedisValue) -> usize {
+    /// Memory the eviction bookkeeping itself takes: access times, the heatmap and pins.
+    pub fn tracking_overhead(&self) -> usize {
+        let entry = |key: &String, value: usize| key.len() + std::mem::size_of::<String>() + value;
+        self.access_times.keys().map(|key| entry(key, std::mem::size_of::<Instant>())).sum::<usize>()
+            + self.pinned.iter().map(|key| entry(key, 0)).sum::<usize>()
+            + self.heatmap.size_in_bytes()
+    }
+
+    /// Drops access times and pins of keys for which `exists` is false, returning how many
+    /// entries were dropped.
+    pub fn prune_tracking(&mut self, exists: impl Fn(&str) -> bool) -> usize {
+        let before = self.access_times.len() + self.pinned.len();
+        self.access_times.retain(|key, _| exists(key));
+        self.pinned.retain(|key| exists(key));
+        before - self.access_times.len() - self.pinned.len()
+    }
+
+    /// Memory held by the keys under `prefix` and their values, without the shared overhead.
+    pub fn calculate_prefix_usage(&self, db: &RedisDatabase, prefix: &str) -> usize {
+        db.data.iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| key.len() + self.calculate_value_size(value))
+            .sum()
+    }
+
+    /// How many pinned keys are under `prefix` and the memory they hold.
+    pub fn calculate_pinned_usage(&self, db: &RedisDatabase, prefix: &str) -> (usize, usize) {
+        self.pinned.iter()
+            .filter(|key| key.starts_with(prefix))
+            .filter_map(|key| db.data.get(key).map(|value| key.len() + self.calculate_value_size(value)))
+            .fold((0, 0), |(keys, bytes), size| (keys + 1, bytes + size))
+    }
+
+    pub fn calculate_value_size(&self, value: &RedisValue) -> usize {
         match value {
             RedisValue::String(s) => s.len(),
+            RedisValue::CompressedString(s) => s.stored_len(),
             RedisValue::Integer(_) => 8, // i64 size
+            RedisValue::Bytes(bytes) => bytes.len(),
             RedisValue::List(list) => {
                 list.iter().map(|item| item.len()).sum::<usize>() + (list.len() * 8) // Vec overhead
             },
@@ -90,9 +165,25 @@ impl MemoryManager {
             RedisValue::Hash(hash) => {
                 hash.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>() + (hash.len() * 16) // HashMap overhead
             },
+            RedisValue::DelayQueue(queue) => {
+                queue.iter().map(|item| item.member.len()).sum::<usize>() + (queue.len() * 16) // due time + VecDeque overhead
+            },
+            RedisValue::BloomFilter(filter) => filter.size_in_bytes(),
+            RedisValue::VectorSet(set) => set.size_in_bytes(),
+            RedisValue::Json(doc) => doc.to_string().len(), // serialized size approximates the tree
+            RedisValue::TimeSeries(series) => {
+                series.len() * 16 + series.rules.iter().map(|rule| rule.dest.len() + 48).sum::<usize>() // (i64, f64) samples + rule state
+            },
         }
     }
 
+    /// Whether deny-oom commands must be refused: usage is over maxmemory and the policy is
+    /// noeviction, so nothing will make room.
+    pub fn is_over_limit(&self) -> bool {
+        matches!(self.eviction_policy, EvictionPolicy::NoEviction)
+            && self.max_memory.is_some_and(|max_memory| self.used_memory > max_memory)
+    }
+
     pub fn check_memory_limit(&mut self, db: &mut RedisDatabase) -> Result<(), String> {
         if let Some(max_mem) = self.max_memory {
             let current_usage = self.calculate_memory_usage(db);
@@ -122,13 +213,14 @@ impl MemoryManager {
                 EvictionPolicy::AllKeysLfu => self.find_lfu_key(&db.data, false),
                 EvictionPolicy::VolatileLru => self.find_lru_key(&db.data, true),
                 EvictionPolicy::VolatileLfu => self.find_lfu_key(&db.data, true),
-                EvictionPolicy::AllKeysRandom => self.find_random_key(&db.data, false),
-                EvictionPolicy::VolatileRandom => self.find_random_key(&db.data, true),
+                EvictionPolicy::AllKeysRandom => self.find_random_key(&db.data, &mut db.rng, false),
+                EvictionPolicy::VolatileRandom => self.find_random_key(&db.data, &mut db.rng, true),
                 EvictionPolicy::NoEviction => break, // Should not reach here
             };
 
             if let Some(key) = key_to_evict {
                 db.delete(&key);
+                db.expiry_log.record(&key, RemovalReason::Evicted, now_millis());
                 self.remove_tracking(&key);
                 evicted_count += 1;
                 current_usage = self.calculate_memory_usage(db);
@@ -146,12 +238,12 @@ impl MemoryManager {
         Ok(())
     }
 
-    fn find_lru_key(&self, data: &HashMap<String, RedisValue>, volatile_only: bool) -> Option<String> {
+    fn find_lru_key(&self, data: &HashMap<String, Arc<RedisValue>>, volatile_only: bool) -> Option<String> {
         let mut oldest_key: Option<String> = None;
         let mut oldest_time = Instant::now();
 
         for key in data.keys() {
-            if volatile_only && !self.has_expiry(key) {
+            if self.is_pinned(key) || (volatile_only && !self.has_expiry(key)) {
                 continue;
             }
 
@@ -169,18 +261,18 @@ impl MemoryManager {
         oldest_key
     }
 
-    fn find_lfu_key(&self, data: &HashMap<String, RedisValue>, volatile_only: bool) -> Option<String> {
+    fn find_lfu_key(&self, data: &HashMap<String, Arc<RedisValue>>, volatile_only: bool) -> Option<String> {
         let mut least_used_key: Option<String> = None;
         let mut least_count = u64::MAX;
 
         for key in data.keys() {
-            if volatile_only && !self.has_expiry(key) {
+            if self.is_pinned(key) || (volatile_only && !self.has_expiry(key)) {
                 continue;
             }
 
-            let count = self.access_counts.get(key).unwrap_or(&0);
-            if *count < least_count {
-                least_count = *count;
+            let count = self.heatmap.estimate(key);
+            if count < least_count {
+                least_count = count;
                 least_used_key = Some(key.clone());
             }
         }
@@ -188,20 +280,12 @@ impl MemoryManager {
         least_used_key
     }
 
-    fn find_random_key(&self, data: &HashMap<String, RedisValue>, volatile_only: bool) -> Option<String> {
-        let keys: Vec<&String> = if volatile_only {
-            data.keys().filter(|k| self.has_expiry(k)).collect()
-        } else {
-            data.keys().collect()
-        };
-
-        if keys.is_empty() {
-            return None;
-        }
+    fn find_random_key(&self, data: &HashMap<String, Arc<RedisValue>>, rng: &mut CommandRng, volatile_only: bool) -> Option<String> {
+        let keys: Vec<&String> = data.keys()
+            .filter(|k| !self.is_pinned(k) && (!volatile_only || self.has_expiry(k)))
+            .collect();
 
-        let mut rng = rand::thread_rng();
-        let index = rng.gen_range(0..keys.len());
-        Some(keys[index].clone())
+        rng.choose(keys).cloned()
     }
 
     fn has_expiry(&self, _key: &str) -> bool {
@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+pub type Metrics = Arc<RwLock<MetricsRing>>;
+
+// One hour of per-second samples
+const HISTORY_SECONDS: usize = 3600;
+// INFO's instantaneous_* fields average over this many completed seconds
+const INSTANTANEOUS_WINDOW: u64 = 5;
+
+// Upper bounds of the size histogram buckets, growing 4x; larger sizes go in a final +inf bucket
+const SIZE_BUCKETS: [(u64, &str); 11] = [
+    (64, "64B"), (256, "256B"), (1 << 10, "1KB"), (4 << 10, "4KB"), (16 << 10, "16KB"), (64 << 10, "64KB"),
+    (256 << 10, "256KB"), (1 << 20, "1MB"), (4 << 20, "4MB"), (16 << 20, "16MB"), (64 << 20, "64MB"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    counts: [u64; SIZE_BUCKETS.len() + 1],
+    pub total_bytes: u64,
+    pub max: u64,
+}
+
+impl SizeHistogram {
+    pub fn record(&mut self, size: usize) {
+        let size = size as u64;
+        let bucket = SIZE_BUCKETS.iter().position(|(bound, _)| size <= *bound).unwrap_or(SIZE_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.total_bytes += size;
+        self.max = self.max.max(size);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Non-empty buckets as `<=bound:count`, smallest first.
+    pub fn render(&self) -> String {
+        self.counts.iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| match SIZE_BUCKETS.get(bucket) {
+                Some((_, label)) => format!("<={}:{}", label, count),
+                None => format!(">{}:{}", SIZE_BUCKETS[SIZE_BUCKETS.len() - 1].1, count),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Request and reply sizes of one command, in bytes on the wire.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandSizes {
+    pub requests: SizeHistogram,
+    pub replies: SizeHistogram,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSample {
+    pub second: u64,
+    pub commands: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+}
+
+impl MetricsSample {
+    fn empty(second: u64) -> Self {
+        Self { second, commands: 0, input_bytes: 0, output_bytes: 0 }
+    }
+}
+
+#[derive(Debug)]
+pub struct MetricsRing {
+    samples: VecDeque<MetricsSample>,
+    pub total_commands: u64,
+    pub total_input_bytes: u64,
+    pub total_output_bytes: u64,
+    // Keyed by upper-case command name
+    pub command_sizes: BTreeMap<String, CommandSizes>,
+    // Writes delayed or refused because the unsaved backlog was over its threshold
+    pub stalled_writes: u64,
+    pub rejected_writes: u64,
+    // Writes refused for going over a write rate limit
+    pub throttled_writes: u64,
+    // Times a pipelining client used up its command budget and let other connections run
+    pub client_yields: u64,
+    // Commands that panicked and were answered with a generic error
+    pub internal_errors: u64,
+}
+
+impl Default for MetricsRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsRing {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(HISTORY_SECONDS),
+            total_commands: 0,
+            total_input_bytes: 0,
+            total_output_bytes: 0,
+            command_sizes: BTreeMap::new(),
+            stalled_writes: 0,
+            rejected_writes: 0,
+            throttled_writes: 0,
+            client_yields: 0,
+            internal_errors: 0,
+        }
+    }
+
+    pub fn record(&mut self, command: &str, input_bytes: usize, output_bytes: usize) {
+        self.record_at(current_second(), input_bytes, output_bytes);
+
+        let sizes = match self.command_sizes.get_mut(command) {
+            Some(sizes) => sizes,
+            None => self.command_sizes.entry(command.to_uppercase()).or_default(),
+        };
+        sizes.requests.record(input_bytes);
+        sizes.replies.record(output_bytes);
+    }
+
+    fn record_at(&mut self, second: u64, input_bytes: usize, output_bytes: usize) {
+        let needs_new_sample = match self.samples.back() {
+            Some(last) => last.second < second,
+            None => true,
+        };
+        if needs_new_sample {
+            self.samples.push_back(MetricsSample::empty(second));
+            while self.samples.len() > HISTORY_SECONDS {
+                self.samples.pop_front();
+            }
+        }
+
+        if let Some(sample) = self.samples.back_mut() {
+            sample.commands += 1;
+            sample.input_bytes += input_bytes as u64;
+            sample.output_bytes += output_bytes as u64;
+        }
+
+        self.total_commands += 1;
+        self.total_input_bytes += input_bytes as u64;
+        self.total_output_bytes += output_bytes as u64;
+    }
+
+    /// Returns the last `count` seconds (oldest first), filling idle seconds with zeros.
+    pub fn history(&self, count: usize) -> Vec<MetricsSample> {
+        self.history_at(current_second(), count)
+    }
+
+    fn history_at(&self, now: u64, count: usize) -> Vec<MetricsSample> {
+        let count = count.min(HISTORY_SECONDS) as u64;
+        let first = now.saturating_sub(count.saturating_sub(1));
+
+        (first..=now)
+            .map(|second| {
+                self.samples.iter()
+                    .rev()
+                    .find(|sample| sample.second == second)
+                    .copied()
+                    .unwrap_or_else(|| MetricsSample::empty(second))
+            })
+            .collect()
+    }
+
+    // Averages the last completed seconds so a half-finished current second doesn't drag the rate down
+    fn window_totals(&self, now: u64) -> (u64, u64, u64) {
+        let start = now.saturating_sub(INSTANTANEOUS_WINDOW);
+        self.samples.iter()
+            .filter(|sample| sample.second >= start && sample.second < now)
+            .fold((0, 0, 0), |(ops, input, output), sample| {
+                (ops + sample.commands, input + sample.input_bytes, output + sample.output_bytes)
+            })
+    }
+
+    pub fn instantaneous_ops_per_sec(&self) -> u64 {
+        self.window_totals(current_second()).0 / INSTANTANEOUS_WINDOW
+    }
+
+    pub fn instantaneous_input_kbps(&self) -> f64 {
+        self.window_totals(current_second()).1 as f64 / INSTANTANEOUS_WINDOW as f64 / 1024.0
+    }
+
+    pub fn instantaneous_output_kbps(&self) -> f64 {
+        self.window_totals(current_second()).2 as f64 / INSTANTANEOUS_WINDOW as f64 / 1024.0
+    }
+}
+
+fn current_second() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub fn create_metrics() -> Metrics {
+    Arc::new(RwLock::new(MetricsRing::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_fills_idle_seconds() {
+        let mut ring = MetricsRing::new();
+        ring.record_at(100, 10, 20);
+        ring.record_at(100, 5, 5);
+        ring.record_at(102, 1, 1);
+
+        let history = ring.history_at(102, 3);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0], MetricsSample { second: 100, commands: 2, input_bytes: 15, output_bytes: 25 });
+        assert_eq!(history[1], MetricsSample::empty(101));
+        assert_eq!(history[2].commands, 1);
+        assert_eq!(ring.total_commands, 3);
+    }
+
+    #[test]
+    fn test_size_histogram_buckets() {
+        let mut ring = MetricsRing::new();
+        ring.record("SET", 20, 4);
+        ring.record("SET", 50 << 20, 4);
+        ring.record("set", 100 << 20, 4);
+
+        let sizes = &ring.command_sizes["SET"];
+        assert_eq!(sizes.requests.count(), 3);
+        assert_eq!(sizes.requests.max, 100 << 20);
+        assert_eq!(sizes.requests.render(), "<=64B:1,<=64MB:1,>64MB:1");
+        assert_eq!(sizes.replies.render(), "<=64B:3");
+    }
+
+    #[test]
+    fn test_ring_is_bounded() {
+        let mut ring = MetricsRing::new();
+        for second in 0..(HISTORY_SECONDS as u64 + 10) {
+            ring.record_at(second, 1, 1);
+        }
+        assert_eq!(ring.samples.len(), HISTORY_SECONDS);
+        assert_eq!(ring.samples.front().map(|s| s.second), Some(10));
+    }
+}
@@ -0,0 +1,162 @@
+// Pacing and progress of MERGE, which moves another snapshot's keys into this database. Keys
+// are applied in batches with the database lock released in between, and a throttle can cap
+// keys or bytes per second, so a large move does not starve clients. MIGRATION STATUS reports
+// the progress from any other connection while the move runs.
+use std::time::{Duration, Instant};
+
+// Keys applied per database lock acquisition
+pub const MIGRATION_BATCH: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Throttle {
+    pub keys_per_sec: Option<u64>,
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl Throttle {
+    /// How long to wait, `elapsed` into the move, so that `keys` and `bytes` moved so far stay
+    /// within both limits.
+    pub fn delay(&self, keys: u64, bytes: u64, elapsed: Duration) -> Duration {
+        let due = |amount: u64, limit: Option<u64>| limit.map_or(Duration::ZERO, |limit| Duration::from_secs_f64(amount as f64 / limit as f64));
+        due(keys, self.keys_per_sec).max(due(bytes, self.bytes_per_sec)).saturating_sub(elapsed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeOutcome {
+    New,
+    // An existing list, set or hash extended by MERGE's MERGE strategy
+    Combined,
+    Overwritten,
+    Skipped,
+}
+
+#[derive(Debug, Default)]
+pub struct MigrationProgress {
+    pub source: String,
+    pub throttle: Throttle,
+    pub total_keys: u64,
+    pub total_bytes: u64,
+    pub moved_keys: u64,
+    pub moved_bytes: u64,
+    pub new_keys: u64,
+    pub overwritten: u64,
+    pub skipped: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+    started: Option<Instant>,
+    duration: Option<Duration>,
+}
+
+impl MigrationProgress {
+    pub fn start(source: &str, throttle: Throttle, total_keys: u64, total_bytes: u64) -> Self {
+        Self { source: source.to_string(), throttle, total_keys, total_bytes, started: Some(Instant::now()), ..Default::default() }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some() && self.duration.is_none()
+    }
+
+    pub fn status(&self) -> &'static str {
+        match (self.started, self.duration) {
+            (None, _) => "none",
+            (Some(_), None) => "running",
+            (Some(_), Some(_)) => "done",
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.duration.or_else(|| self.started.map(|started| started.elapsed())).unwrap_or_default()
+    }
+
+    /// Counts one key applied (or failed) with its size.
+    pub fn record(&mut self, bytes: u64, outcome: Result<MergeOutcome, String>) {
+        self.moved_keys += 1;
+        self.moved_bytes += bytes;
+        match outcome {
+            // Counted with new keys, as MERGE has always reported them
+            Ok(MergeOutcome::New | MergeOutcome::Combined) => self.new_keys += 1,
+            Ok(MergeOutcome::Overwritten) => self.overwritten += 1,
+            Ok(MergeOutcome::Skipped) => self.skipped += 1,
+            Err(e) => {
+                self.errors += 1;
+                self.last_error = Some(e);
+            },
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.duration = Some(self.elapsed());
+    }
+
+    /// Time left at the byte rate so far; None before anything has moved.
+    pub fn eta(&self) -> Option<Duration> {
+        if !self.is_running() || self.moved_bytes == 0 {
+            return None;
+        }
+        let remaining = self.total_bytes.saturating_sub(self.moved_bytes) as f64;
+        Some(Duration::from_secs_f64(remaining * self.elapsed().as_secs_f64() / self.moved_bytes as f64))
+    }
+
+    /// INFO-style lines for MIGRATION STATUS.
+    pub fn render(&self) -> String {
+        let limit = |limit: Option<u64>| limit.map_or("unlimited".to_string(), |limit| limit.to_string());
+        let percent = match self.total_bytes {
+            0 if self.duration.is_some() => 100.0,
+            0 => 0.0,
+            total => self.moved_bytes as f64 * 100.0 / total as f64,
+        };
+        format!(
+            "status:{}\nsource:{}\nkeys_moved:{}\nkeys_total:{}\nbytes_moved:{}\nbytes_total:{}\nprogress:{:.2}%\nelapsed_ms:{}\neta_ms:{}\nnew_keys:{}\noverwritten:{}\nskipped:{}\nerrors:{}\nlast_error:{}\nkeys_per_sec_limit:{}\nbytes_per_sec_limit:{}",
+            self.status(),
+            self.source,
+            self.moved_keys,
+            self.total_keys,
+            self.moved_bytes,
+            self.total_bytes,
+            percent,
+            self.elapsed().as_millis(),
+            self.eta().map_or(-1, |eta| eta.as_millis() as i64),
+            self.new_keys,
+            self.overwritten,
+            self.skipped,
+            self.errors,
+            self.last_error.as_deref().unwrap_or(""),
+            limit(self.throttle.keys_per_sec),
+            limit(self.throttle.bytes_per_sec)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_waits_for_the_tighter_limit() {
+        let throttle = Throttle { keys_per_sec: Some(100), bytes_per_sec: Some(1000) };
+        // 50 keys may take half a second, but 2000 bytes need two
+        assert_eq!(throttle.delay(50, 2000, Duration::from_millis(500)), Duration::from_millis(1500));
+        assert_eq!(throttle.delay(50, 100, Duration::from_millis(200)), Duration::from_millis(300));
+        assert_eq!(throttle.delay(50, 100, Duration::from_secs(1)), Duration::ZERO);
+        assert_eq!(Throttle::default().delay(1_000_000, 1_000_000, Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_progress_counts_outcomes() {
+        let mut progress = MigrationProgress::start("backup.rdb", Throttle::default(), 3, 300);
+        assert_eq!(progress.status(), "running");
+        progress.record(100, Ok(MergeOutcome::New));
+        progress.record(100, Err("OOM".to_string()));
+        assert!(progress.eta().is_some());
+        progress.record(100, Ok(MergeOutcome::Skipped));
+        progress.finish();
+
+        assert_eq!(progress.status(), "done");
+        assert_eq!(progress.eta(), None);
+        let status = progress.render();
+        for line in ["keys_moved:3", "progress:100.00%", "new_keys:1", "skipped:1", "errors:1", "last_error:OOM", "keys_per_sec_limit:unlimited"] {
+            assert!(status.lines().any(|l| l == line), "{} missing from {}", line, status);
+        }
+    }
+}
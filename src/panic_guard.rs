@@ -0,0 +1,145 @@
+// Keeps a bug in one command from taking its connection down with it. Commands run inside a
+// future that catches panics; the client gets a generic error, the panic is logged with a
+// backtrace, and INFO counts it as internal_errors. Tokio's locks do not poison, so the
+// database stays usable, though a write the panic interrupted may be partly applied.
+use crate::auth::ClientAuth;
+use crate::commands::{execute_command, Command};
+use crate::metrics::Metrics;
+use crate::protocol::Reply;
+use crate::pub_sub::PubSubManager;
+use crate::shared::Database;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Once;
+use std::task::{Context, Poll};
+
+pub const INTERNAL_ERROR: &str = "ERR internal error while running the command, see the server log";
+
+thread_local! {
+    // Set while a guarded future is being polled on this thread
+    static GUARDED: Cell<bool> = const { Cell::new(false) };
+    static LAST_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+// Chains a hook in front of the existing one that keeps the backtrace of panics in guarded
+// futures, which is gone by the time the panic is caught
+fn install_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if GUARDED.with(Cell::get) {
+                LAST_BACKTRACE.with(|backtrace| *backtrace.borrow_mut() = Some(Backtrace::force_capture()));
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// A panic caught by `CatchPanic`.
+#[derive(Debug)]
+pub struct Panicked {
+    pub message: String,
+    pub backtrace: Option<Backtrace>,
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string())
+}
+
+/// Resolves to the future's output, or to the panic it raised while being polled.
+pub struct CatchPanic<F> {
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> CatchPanic<F> {
+    pub fn new(future: F) -> Self {
+        install_hook();
+        Self { future: Box::pin(future) }
+    }
+}
+
+impl<F: Future> Future for CatchPanic<F> {
+    type Output = Result<F::Output, Panicked>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let was_guarded = GUARDED.with(|guarded| guarded.replace(true));
+        let polled = panic::catch_unwind(AssertUnwindSafe(|| self.future.as_mut().poll(cx)));
+        GUARDED.with(|guarded| guarded.set(was_guarded));
+        match polled {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(payload) => Poll::Ready(Err(Panicked {
+                message: panic_message(payload.as_ref()),
+                backtrace: LAST_BACKTRACE.with(|backtrace| backtrace.borrow_mut().take()),
+            })),
+        }
+    }
+}
+
+/// `execute_command`, replying with a generic error instead of unwinding the connection's task
+/// when the command panics. `name` is the command as sent, for the log.
+pub async fn execute_guarded(
+    db: Database,
+    command: Command,
+    client_auth: &mut ClientAuth,
+    pubsub: Option<&PubSubManager>,
+    metrics: Option<&Metrics>,
+    name: &str,
+) -> Reply {
+    match CatchPanic::new(execute_command(db, command, client_auth, pubsub, metrics)).await {
+        Ok(reply) => reply,
+        Err(panicked) => {
+            let backtrace = panicked.backtrace.map(|backtrace| backtrace.to_string()).unwrap_or_default();
+            eprintln!("Internal error running {}: {}\n{}", name.to_uppercase(), panicked.message, backtrace);
+            if let Some(metrics) = metrics {
+                metrics.write().await.internal_errors += 1;
+            }
+            Reply::error(INTERNAL_ERROR)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthConfig;
+    use crate::commands::SetCondition;
+    use crate::metrics::create_metrics;
+    use crate::shared::create_database;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_panics_are_caught_with_a_backtrace() {
+        let caught = CatchPanic::new(async {
+            tokio::task::yield_now().await;
+            if GUARDED.with(Cell::get) {
+                panic!("index {} out of range", 7);
+            }
+        }).await.unwrap_err();
+        assert_eq!(caught.message, "index 7 out of range");
+        assert!(caught.backtrace.is_some());
+        assert!(!GUARDED.with(Cell::get));
+
+        assert_eq!(CatchPanic::new(async { 42 }).await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn test_guarded_execution_counts_internal_errors() {
+        let database = create_database();
+        let metrics = create_metrics();
+        let mut client_auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+        let reply = execute_guarded(Arc::clone(&database), Command::DebugPanic, &mut client_auth, None, Some(&metrics), "debug").await;
+        assert_eq!(reply, Reply::error(INTERNAL_ERROR));
+        // The connection and the database carry on
+        let set = Command::Set { key: "k".into(), value: "v".into(), expiry: None, condition: SetCondition::Always, keep_ttl: false, get: false };
+        assert_eq!(execute_guarded(database, set, &mut client_auth, None, Some(&metrics), "set").await, Reply::ok());
+        assert_eq!(metrics.read().await.internal_errors, 1);
+    }
+}
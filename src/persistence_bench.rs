@@ -0,0 +1,193 @@
+// DEBUG PERSISTENCE-BENCH: times what saving the current dataset costs on the disk the database
+// lives on, so an fsync policy or a disk can be chosen from numbers rather than guesses. The
+// snapshot is encoded and parsed in memory, then written and synced to a scratch file; WAL
+// appends are timed with and without a sync after each. Scratch files are removed afterwards.
+use crate::persistence_clean::Snapshot;
+use crate::wal::{WalEntry, WriteAheadLog};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BENCH_OPS: usize = 100;
+pub const MAX_BENCH_OPS: usize = 100_000;
+// Bounds on the value size of the timed WAL entries, which otherwise follows the dataset
+const MIN_WAL_VALUE: usize = 16;
+const MAX_WAL_VALUE: usize = 64 * 1024;
+
+/// Timings of one kind of operation, in the order they ran.
+#[derive(Debug, Default)]
+pub struct Latencies {
+    samples: Vec<Duration>,
+}
+
+impl Latencies {
+    pub fn record(&mut self, sample: Duration) {
+        self.samples.push(sample);
+    }
+
+    fn percentile(&self, percent: usize) -> Duration {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        match sorted.len() {
+            0 => Duration::ZERO,
+            len => sorted[(len * percent).div_ceil(100).max(1) - 1],
+        }
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.samples.len() {
+            0 => Duration::ZERO,
+            len => self.samples.iter().sum::<Duration>() / len as u32,
+        }
+    }
+
+    pub fn p99(&self) -> Duration {
+        self.percentile(99)
+    }
+
+    pub fn max(&self) -> Duration {
+        self.samples.iter().max().copied().unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+pub struct BenchReport {
+    pub keys: usize,
+    pub snapshot_bytes: usize,
+    pub serialize: Duration,
+    pub deserialize: Duration,
+    pub write: Duration,
+    pub fsync: Duration,
+    pub wal_value_bytes: usize,
+    pub wal_append: Latencies,
+    pub wal_append_fsync: Latencies,
+}
+
+fn megabytes_per_sec(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64().max(1e-9)
+}
+
+impl BenchReport {
+    /// INFO-style lines, one section for the snapshot and one for the WAL.
+    pub fn render(&self) -> String {
+        let ms = |elapsed: Duration| elapsed.as_secs_f64() * 1000.0;
+        let us = |elapsed: Duration| elapsed.as_micros();
+        format!(
+            "# Snapshot\nkeys:{}\nsnapshot_bytes:{}\nserialize_ms:{:.2}\nserialize_mb_per_sec:{:.2}\ndeserialize_ms:{:.2}\ndeserialize_mb_per_sec:{:.2}\nwrite_ms:{:.2}\nfsync_ms:{:.2}\n# WAL\nwal_value_bytes:{}\nwal_appends:{}\nwal_append_avg_us:{}\nwal_append_p99_us:{}\nwal_append_max_us:{}\nwal_fsync_appends:{}\nwal_fsync_avg_us:{}\nwal_fsync_p99_us:{}\nwal_fsync_max_us:{}",
+            self.keys,
+            self.snapshot_bytes,
+            ms(self.serialize),
+            megabytes_per_sec(self.snapshot_bytes, self.serialize),
+            ms(self.deserialize),
+            megabytes_per_sec(self.snapshot_bytes, self.deserialize),
+            ms(self.write),
+            ms(self.fsync),
+            self.wal_value_bytes,
+            self.wal_append.samples.len(),
+            us(self.wal_append.mean()),
+            us(self.wal_append.p99()),
+            us(self.wal_append.max()),
+            self.wal_append_fsync.samples.len(),
+            us(self.wal_append_fsync.mean()),
+            us(self.wal_append_fsync.p99()),
+            us(self.wal_append_fsync.max()),
+        )
+    }
+}
+
+/// Runs the benchmark with scratch files named after `scratch_prefix`, timing `ops` WAL appends
+/// of each kind.
+pub fn run(mut snapshot: Snapshot, scratch_prefix: &Path, ops: usize) -> Result<BenchReport, String> {
+    let snapshot_path = PathBuf::from(format!("{}.bench-snapshot", scratch_prefix.display()));
+    let wal_path = PathBuf::from(format!("{}.bench-wal", scratch_prefix.display()));
+    let result = run_with(&mut snapshot, &snapshot_path, &wal_path, ops);
+    let _ = fs::remove_file(&snapshot_path);
+    let _ = fs::remove_file(&wal_path);
+    result
+}
+
+fn run_with(snapshot: &mut Snapshot, snapshot_path: &Path, wal_path: &Path, ops: usize) -> Result<BenchReport, String> {
+    let started = Instant::now();
+    let json = snapshot.encode().map_err(|e| e.to_string())?;
+    let serialize = started.elapsed();
+
+    let started = Instant::now();
+    let keys = Snapshot::decode(&json).map_err(|e| e.to_string())?.len();
+    let deserialize = started.elapsed();
+
+    let mut file = File::create(snapshot_path).map_err(|e| e.to_string())?;
+    let started = Instant::now();
+    file.write_all(json.as_bytes()).map_err(|e| e.to_string())?;
+    let write = started.elapsed();
+    let started = Instant::now();
+    file.sync_all().map_err(|e| e.to_string())?;
+    let fsync = started.elapsed();
+
+    // WAL entries as big as the dataset's average key
+    let wal_value_bytes = json.len().checked_div(keys).unwrap_or(0).clamp(MIN_WAL_VALUE, MAX_WAL_VALUE);
+    let value = "x".repeat(wal_value_bytes);
+    let mut wal = WriteAheadLog::new(wal_path.display().to_string()).map_err(|e| e.to_string())?;
+    let mut wal_append = Latencies::default();
+    let mut wal_append_fsync = Latencies::default();
+    for i in 0..ops * 2 {
+        let entry = WalEntry::Set { key: format!("bench:{}", i), value: value.clone(), timestamp: WriteAheadLog::get_current_timestamp() };
+        let started = Instant::now();
+        wal.log_entry(&entry).map_err(|e| e.to_string())?;
+        if i < ops {
+            wal_append.record(started.elapsed());
+        } else {
+            wal.sync().map_err(|e| e.to_string())?;
+            wal_append_fsync.record(started.elapsed());
+        }
+    }
+
+    Ok(BenchReport {
+        keys,
+        snapshot_bytes: json.len(),
+        serialize,
+        deserialize,
+        write,
+        fsync,
+        wal_value_bytes,
+        wal_append,
+        wal_append_fsync,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::RedisDatabase;
+    use crate::data_types::RedisValue;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_percentiles() {
+        let mut latencies = Latencies::default();
+        assert_eq!(latencies.p99(), Duration::ZERO);
+        for ms in (1..=100).rev() {
+            latencies.record(Duration::from_millis(ms));
+        }
+        assert_eq!(latencies.p99(), Duration::from_millis(99));
+        assert_eq!(latencies.max(), Duration::from_millis(100));
+        assert_eq!(latencies.mean(), Duration::from_micros(50_500));
+    }
+
+    #[test]
+    fn test_run_reports_the_dataset_and_cleans_up() {
+        let mut db = RedisDatabase::new();
+        for i in 0..50 {
+            db.data.insert(format!("key:{}", i), Arc::new(RedisValue::String("value".repeat(20))));
+        }
+        let prefix = std::env::temp_dir().join(format!("persistence_bench_{}", std::process::id()));
+        let report = run(Snapshot::capture(&db), &prefix, 5).unwrap();
+        assert_eq!(report.keys, 50);
+        assert!(report.snapshot_bytes > 50 * 100);
+        assert_eq!(report.wal_append.samples.len(), 5);
+        assert_eq!(report.wal_append_fsync.samples.len(), 5);
+        assert!(report.render().contains("\nwal_fsync_appends:5\n"));
+        assert!(!Path::new(&format!("{}.bench-snapshot", prefix.display())).exists());
+        assert!(!Path::new(&format!("{}.bench-wal", prefix.display())).exists());
+    }
+}
@@ -1,29 +1,308 @@
 use crate::data_types::RedisValue;
 use crate::database::RedisDatabase;
+use crate::expiry_log::RemovedKey;
+use crate::heatmap::HEATMAP_MINUTES;
+use crate::tags::TagIndex;
+use crate::wal::{WalEntry, WriteAheadLog};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
-use std::io::{BufWriter, Write, BufReader, Read};
-use std::path::Path;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use sha2::{Sha256, Digest};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PersistenceError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Database file is empty")]
+    Empty,
+    #[error("Database file {} does not exist", .0.display())]
+    Missing(PathBuf),
+    #[error("Unsupported database version: {0}. Current version: 1")]
+    UnsupportedVersion(u32),
+    #[error("Checksum verification failed - database file may be corrupted")]
+    ChecksumMismatch,
+    #[error("{} does not match the checksum in the manifest", .0.display())]
+    ManifestChecksumMismatch(PathBuf),
+    #[error("No snapshot has been committed yet")]
+    NoSnapshot,
+    #[error("Invalid WAL segment path {0}")]
+    InvalidSegmentPath(String),
+    #[error("No backup file available for recovery")]
+    NoBackup,
+    // A WAL entry that parsed but could not be applied
+    #[error("Failed to replay WAL entry: {0}")]
+    Replay(String),
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct PersistedData {
     version: u32,
-    data: HashMap<String, RedisValue>,
+    data: HashMap<String, Arc<RedisValue>>,
     expires: HashMap<String, u64>,
+    // Hash field expiry times in unix milliseconds; omitted when empty so older snapshots verify
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    field_expires: HashMap<String, HashMap<String, u64>>,
+    // TAG SET annotations by key, likewise omitted when there are none
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    tags: HashMap<String, Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     checksum: Option<String>,
 }
 
+/// Point-in-time copy of a database for saving. Values are shared with the live database
+/// rather than cloned, so capturing is cheap and the write can happen after the lock is released.
+pub struct Snapshot {
+    data: PersistedData,
+    // Written to sidecars rather than the snapshot, which stays checksummed data only
+    access_counts: HashMap<String, u64>,
+    removed_keys: Vec<RemovedKey>,
+}
+
+impl Snapshot {
+    pub fn capture(db: &RedisDatabase) -> Self {
+        let now_instant = std::time::Instant::now();
+        let now_system = SystemTime::now();
+
+        let expires_serializable: HashMap<String, u64> = db
+            .expires
+            .iter()
+            .filter_map(|(key, instant)| {
+                if *instant > now_instant {
+                    let duration_left = *instant - now_instant;
+                    if let Ok(now_secs) = now_system.duration_since(UNIX_EPOCH) {
+                        let future_secs = now_secs.as_secs() + duration_left.as_secs();
+                        return Some((key.clone(), future_secs));
+                    }
+                }
+                None
+            })
+            .collect();
+
+        let now_millis = now_system.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let field_expires: HashMap<String, HashMap<String, u64>> = db
+            .field_expires
+            .iter()
+            .map(|(key, fields)| {
+                let fields = fields.iter()
+                    .filter(|(_, instant)| **instant > now_instant)
+                    .map(|(field, instant)| (field.clone(), now_millis + (*instant - now_instant).as_millis() as u64))
+                    .collect::<HashMap<_, _>>();
+                (key.clone(), fields)
+            })
+            .filter(|(_, fields)| !fields.is_empty())
+            .collect();
+
+        Self {
+            data: PersistedData {
+                version: 1,
+                data: db.data.clone(),
+                expires: expires_serializable,
+                field_expires,
+                tags: db.tags.to_map(),
+                checksum: None,
+            },
+            // Only keys that stood out over the last hour, so the file stays small
+            access_counts: db.memory_manager.heatmap.hottest(usize::MAX, HEATMAP_MINUTES).into_iter().collect(),
+            removed_keys: db.expiry_log.entries(),
+        }
+    }
+
+    /// The snapshot file contents, checksum included, as a save writes them.
+    pub fn encode(&mut self) -> Result<String, PersistenceError> {
+        self.data.checksum = None;
+        let json_data = MmapPersistence::canonical_json(&self.data)?;
+        self.data.checksum = Some(MmapPersistence::calculate_checksum(&json_data));
+        Ok(serde_json::to_string_pretty(&self.data)?)
+    }
+
+    /// Parses what `encode` wrote, without verifying the checksum.
+    pub fn decode(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(Self { data: serde_json::from_str(json)?, access_counts: HashMap::new(), removed_keys: Vec::new() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.data.is_empty()
+    }
+}
+
+/// Names the snapshot generation recovery loads and the WAL segments written after it. A save
+/// writes the new generation first and then replaces the manifest in one rename, so a crash at
+/// any point leaves it naming a complete, checksummed set of files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    generation: u64,
+    snapshot: ManifestFile,
+    // The generation before, kept in case the current one fails to load
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous: Option<ManifestFile>,
+    #[serde(default)]
+    wal_segments: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestFile {
+    // File name, in the directory of the manifest
+    name: String,
+    // SHA-256 of the file contents
+    checksum: String,
+}
+
+/// Points inside `save_database` where the `simulate_crash` test mode aborts the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashPoint {
+    // Before the new generation is written; the current one is its backup
+    AfterBackup,
+    MidWrite,
+    // The new generation and manifest are written, but the manifest is not yet replaced
+    BeforeRename,
+    AfterRename,
+}
+
+impl CrashPoint {
+    pub fn from_string(point: &str) -> Option<Self> {
+        match point {
+            "after-backup" => Some(CrashPoint::AfterBackup),
+            "mid-write" => Some(CrashPoint::MidWrite),
+            "before-rename" => Some(CrashPoint::BeforeRename),
+            "after-rename" => Some(CrashPoint::AfterRename),
+            _ => None,
+        }
+    }
+}
+
+/// What `sanity_check` found in a snapshot (and optionally a WAL) without loading it.
+#[derive(Debug, Default)]
+pub struct SanityReport {
+    // None when the snapshot predates checksums
+    pub checksum_verified: Option<bool>,
+    pub keys_by_type: BTreeMap<&'static str, usize>,
+    pub expired_keys: usize,
+    // Expiry times for keys the snapshot does not hold
+    pub orphaned_expires: usize,
+    // Field expiry times on missing or non-hash keys, or for fields the hash does not hold
+    pub orphaned_field_expires: usize,
+    pub manifest_generation: Option<u64>,
+    pub wal_entries: usize,
+    pub wal_corrupt_records: usize,
+}
+
+impl SanityReport {
+    pub fn is_healthy(&self) -> bool {
+        self.checksum_verified != Some(false)
+            && self.orphaned_expires == 0
+            && self.orphaned_field_expires == 0
+            && self.wal_corrupt_records == 0
+    }
+}
+
+/// Save once at least `changes` changes are unsaved and `seconds` have passed since the last
+/// successful save, like `save 300 10` in redis.conf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Progress and outcome of background saves, reported by INFO Persistence.
+#[derive(Debug, Clone)]
+pub struct SaveStats {
+    // When the save in progress started; None while idle
+    pub started: Option<std::time::Instant>,
+    pub last_ok: bool,
+    // Unix seconds of the last successful save, or of startup before the first one
+    pub last_save_time: u64,
+    pub last_duration: Option<Duration>,
+    pub last_bytes: usize,
+}
+
+impl Default for SaveStats {
+    fn default() -> Self {
+        Self {
+            started: None,
+            last_ok: true,
+            last_save_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            last_duration: None,
+            last_bytes: 0,
+        }
+    }
+}
+
+impl SaveStats {
+    pub fn begin(&mut self) {
+        self.started = Some(std::time::Instant::now());
+    }
+
+    /// Records how the save started by `begin` ended, returning the event to announce.
+    pub fn finish(&mut self, result: Result<usize, String>) -> String {
+        let duration = self.started.take().map(|started| started.elapsed()).unwrap_or_default();
+        self.last_duration = Some(duration);
+        match result {
+            Ok(bytes) => {
+                self.last_ok = true;
+                self.last_save_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                self.last_bytes = bytes;
+                format!("bgsave ok bytes:{} duration_ms:{}", bytes, duration.as_millis())
+            },
+            Err(e) => {
+                self.last_ok = false;
+                format!("bgsave err {}", e)
+            },
+        }
+    }
+
+    /// Whether any rule calls for a save with `dirty` changes unsaved.
+    pub fn rule_due(&self, rules: &[SaveRule], dirty: u64) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let since_save = now.saturating_sub(self.last_save_time);
+        rules.iter().any(|rule| dirty >= rule.changes && since_save >= rule.seconds)
+    }
+
+    /// The `rdb_*` lines of INFO Persistence, except the change count.
+    pub fn render(&self) -> String {
+        let millis = |duration: Option<Duration>| duration.map_or(-1, |duration| duration.as_millis() as i64);
+        format!(
+            "rdb_bgsave_in_progress:{}\nrdb_last_save_time:{}\nrdb_last_bgsave_status:{}\nrdb_last_bgsave_time_ms:{}\nrdb_current_bgsave_time_ms:{}\nrdb_last_bgsave_bytes:{}",
+            self.started.is_some() as u8,
+            self.last_save_time,
+            if self.last_ok { "ok" } else { "err" },
+            millis(self.last_duration),
+            millis(self.started.map(|started| started.elapsed())),
+            self.last_bytes
+        )
+    }
+}
+
 pub struct MmapPersistence {
     pub file_path: String,
+    crash_point: Option<CrashPoint>,
 }
 
 impl MmapPersistence {
     pub fn new(file_path: String) -> Self {
-        Self { file_path }
+        Self { file_path, crash_point: None }
+    }
+
+    pub fn with_crash_point(mut self, crash_point: CrashPoint) -> Self {
+        self.crash_point = Some(crash_point);
+        self
+    }
+
+    fn crash_if(&self, point: CrashPoint) {
+        if self.crash_point == Some(point) {
+            eprintln!("Simulating crash at {:?}", point);
+            std::process::abort();
+        }
     }
 
     fn calculate_checksum(data: &str) -> String {
@@ -34,115 +313,272 @@ impl MmapPersistence {
         result.iter().map(|b| format!("{:02x}", b)).collect()
     }
 
+    // HashMap/HashSet iteration order differs between processes, so the checksum is
+    // computed over a form with sorted object keys and sorted set members
+    fn canonical_json(data: &PersistedData) -> Result<String, PersistenceError> {
+        let mut value = serde_json::to_value(data)?;
+        if let Some(serde_json::Value::Object(entries)) = value.get_mut("data") {
+            for entry in entries.values_mut() {
+                if let Some(serde_json::Value::Array(members)) = entry.get_mut("Set") {
+                    members.sort_by_key(|member| member.to_string());
+                }
+            }
+        }
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    fn restore_field_expires(
+        persisted: HashMap<String, HashMap<String, u64>>,
+        now_system: SystemTime,
+        now_instant: std::time::Instant,
+    ) -> HashMap<String, HashMap<String, std::time::Instant>> {
+        let now_millis = now_system.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        persisted.into_iter()
+            .map(|(key, fields)| {
+                // Fields that expired while the server was down keep a past deadline, so the
+                // first access or expiry cycle removes them from the hash
+                let fields = fields.into_iter()
+                    .map(|(field, expire_millis)| (field, now_instant + Duration::from_millis(expire_millis.saturating_sub(now_millis))))
+                    .collect::<HashMap<_, _>>();
+                (key, fields)
+            })
+            .collect()
+    }
+
     fn verify_checksum(data: &str, expected_checksum: &str) -> bool {
         let actual_checksum = Self::calculate_checksum(data);
         actual_checksum == expected_checksum
     }
 
-    fn create_backup(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if Path::new(&self.file_path).exists() {
-            let backup_path = format!("{}.bak", &self.file_path);
-            fs::copy(&self.file_path, &backup_path)?;
-            println!("Created backup at {}", backup_path);
+    fn manifest_path(&self) -> String {
+        format!("{}.manifest", self.file_path)
+    }
+
+    // Files named in the manifest live next to it
+    fn sibling_path(&self, name: &str) -> PathBuf {
+        Path::new(&self.file_path).with_file_name(name)
+    }
+
+    fn base_name(&self) -> String {
+        Path::new(&self.file_path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default()
+    }
+
+    fn read_manifest(&self) -> Result<Option<Manifest>, PersistenceError> {
+        match fs::read_to_string(self.manifest_path()) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
         }
-        Ok(())
     }
 
-    fn cleanup_temp_files(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let tmp_path = format!("{}.tmp", &self.file_path);
-        if Path::new(&tmp_path).exists() {
-            println!("Found stale temporary file, cleaning up: {}", tmp_path);
-            fs::remove_file(&tmp_path)?;
+    // The commit point of a save: the manifest is replaced in one rename, so readers see either
+    // the old set of files or the new one
+    fn write_manifest(&self, manifest: &Manifest) -> Result<(), PersistenceError> {
+        let tmp_path = format!("{}.tmp", self.manifest_path());
+        let file = File::create(&tmp_path)?;
+        let mut writer = BufWriter::new(&file);
+        writer.write_all(serde_json::to_string_pretty(manifest)?.as_bytes())?;
+        writer.flush()?;
+        file.sync_all()?;
+        self.crash_if(CrashPoint::BeforeRename);
+
+        fs::rename(&tmp_path, self.manifest_path())?;
+        self.crash_if(CrashPoint::AfterRename);
+
+        if let Some(parent_dir) = Path::new(&self.file_path).parent() {
+            if let Ok(dir) = File::open(parent_dir) {
+                let _ = dir.sync_all();
+            }
         }
         Ok(())
     }
 
-    pub fn save_database(&self, db: &RedisDatabase) -> Result<(), Box<dyn std::error::Error>> {
-        self.create_backup()?;
+    // Access counts of the hottest keys as of the last save, read back to warm the cold tier on startup
+    fn access_counts_path(&self) -> String {
+        format!("{}.access", self.file_path)
+    }
 
-        let now_instant = std::time::Instant::now();
-        let now_system = SystemTime::now();
+    fn write_access_counts(&self, access_counts: &HashMap<String, u64>) -> Result<(), PersistenceError> {
+        let tmp_path = format!("{}.tmp", self.access_counts_path());
+        fs::write(&tmp_path, serde_json::to_string(access_counts)?)?;
+        fs::rename(&tmp_path, self.access_counts_path())?;
+        Ok(())
+    }
 
-        let expires_serializable: HashMap<String, u64> = db
-            .expires
-            .iter()
-            .filter_map(|(key, instant)| {
-                if *instant > now_instant {
-                    let duration_left = *instant - now_instant;
-                    if let Ok(now_secs) = now_system.duration_since(UNIX_EPOCH) {
-                        let future_secs = now_secs.as_secs() + duration_left.as_secs();
-                        return Some((key.clone(), future_secs));
-                    }
-                }
-                None
-            })
-            .collect();
+    /// The access counts saved with the last snapshot; empty if there are none or they are
+    /// unreadable.
+    pub fn load_access_counts(&self) -> HashMap<String, u64> {
+        let path = self.access_counts_path();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return HashMap::new(),
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Ignoring unreadable access counts {}: {}", path, e);
+            HashMap::new()
+        })
+    }
 
-        let mut persisted_data = PersistedData {
-            version: 1,
-            data: db.data.clone(),
-            expires: expires_serializable,
-            checksum: None,
+    // The __expired__ stream as of the last save, so consumers can read on after a restart
+    fn expired_stream_path(&self) -> String {
+        format!("{}.expired", self.file_path)
+    }
+
+    fn write_expired_stream(&self, removed_keys: &[RemovedKey]) -> Result<(), PersistenceError> {
+        let tmp_path = format!("{}.tmp", self.expired_stream_path());
+        fs::write(&tmp_path, serde_json::to_string(removed_keys)?)?;
+        fs::rename(&tmp_path, self.expired_stream_path())?;
+        Ok(())
+    }
+
+    /// The __expired__ entries saved with the last snapshot; empty if there are none or they are
+    /// unreadable.
+    pub fn load_expired_stream(&self) -> Vec<RemovedKey> {
+        let path = self.expired_stream_path();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Vec::new(),
         };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Ignoring unreadable expired stream {}: {}", path, e);
+            Vec::new()
+        })
+    }
 
-        let json_data = serde_json::to_string_pretty(&persisted_data)?;
+    // Reads a file named in the manifest, refusing it if it changed since it was committed
+    fn read_verified(&self, file: &ManifestFile) -> Result<String, PersistenceError> {
+        let path = self.sibling_path(&file.name);
+        let contents = fs::read_to_string(&path)?;
+        if Self::calculate_checksum(&contents) != file.checksum {
+            return Err(PersistenceError::ManifestChecksumMismatch(path));
+        }
+        Ok(contents)
+    }
 
-        let checksum = Self::calculate_checksum(&json_data);
-        persisted_data.checksum = Some(checksum);
+    // Removes snapshot generations the manifest no longer names, such as the one a crash left
+    // half-written or those older than `previous`
+    fn remove_unreferenced_generations(&self, manifest: &Manifest) {
+        let prefix = format!("{}.", self.base_name());
+        let dir = match Path::new(&self.file_path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
 
-        let json_data_with_checksum = serde_json::to_string_pretty(&persisted_data)?;
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_generation = name.strip_prefix(&prefix).is_some_and(|rest| rest.parse::<u64>().is_ok());
+            let referenced = name == manifest.snapshot.name || manifest.previous.as_ref().is_some_and(|previous| name == previous.name);
+            if is_generation && !referenced {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
 
-        let tmp_path = format!("{}.tmp", &self.file_path);
-        let file = File::create(&tmp_path)?;
+    fn cleanup_temp_files(&self) -> Result<(), PersistenceError> {
+        for tmp_path in [format!("{}.tmp", &self.file_path), format!("{}.tmp", self.manifest_path()), format!("{}.tmp", self.access_counts_path()),
+                         format!("{}.tmp", self.expired_stream_path())] {
+            if Path::new(&tmp_path).exists() {
+                println!("Found stale temporary file, cleaning up: {}", tmp_path);
+                fs::remove_file(&tmp_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_database(&self, db: &RedisDatabase) -> Result<(), PersistenceError> {
+        self.save_snapshot(Snapshot::capture(db)).map(|_| ())
+    }
+
+    /// Writes a snapshot taken earlier with `Snapshot::capture`; needs no access to the database.
+    /// The generation before is kept as a fallback. Returns the size of the snapshot file.
+    pub fn save_snapshot(&self, mut snapshot: Snapshot) -> Result<usize, PersistenceError> {
+        let current = self.read_manifest()?;
+        let generation = current.as_ref().map_or(1, |manifest| manifest.generation + 1);
+        self.crash_if(CrashPoint::AfterBackup);
+
+        let json_data_with_checksum = snapshot.encode()?;
+
+        let name = format!("{}.{}", self.base_name(), generation);
+        let file = File::create(self.sibling_path(&name))?;
         let mut writer = BufWriter::new(&file);
 
+        if self.crash_point == Some(CrashPoint::MidWrite) {
+            let half = json_data_with_checksum.len() / 2;
+            writer.write_all(&json_data_with_checksum.as_bytes()[..half])?;
+            writer.flush()?;
+            file.sync_all()?;
+            self.crash_if(CrashPoint::MidWrite);
+        }
+
         writer.write_all(json_data_with_checksum.as_bytes())?;
         writer.flush()?;
         file.sync_all()?;
 
-        fs::rename(&tmp_path, &self.file_path)?;
-
-        if let Some(parent_dir) = Path::new(&self.file_path).parent() {
-            if let Ok(dir) = File::open(parent_dir) {
-                let _ = dir.sync_all();
-            }
+        let manifest = Manifest {
+            generation,
+            snapshot: ManifestFile { name, checksum: Self::calculate_checksum(&json_data_with_checksum) },
+            previous: current.map(|manifest| manifest.snapshot),
+            // The snapshot covers every write logged so far
+            wal_segments: Vec::new(),
+        };
+        self.write_manifest(&manifest)?;
+        self.remove_unreferenced_generations(&manifest);
+        // Only a hint for warm-up, so a failure here does not fail the save
+        if let Err(e) = self.write_access_counts(&snapshot.access_counts) {
+            eprintln!("Failed to write {}: {}", self.access_counts_path(), e);
+        }
+        if let Err(e) = self.write_expired_stream(&snapshot.removed_keys) {
+            eprintln!("Failed to write {}: {}", self.expired_stream_path(), e);
         }
 
         println!(
-            "Database saved to {} ({} keys, checksum: {})",
-            self.file_path,
-            db.data.len(),
-            persisted_data.checksum.unwrap_or_default()
+            "Database saved to {} (generation {}, {} keys, checksum: {})",
+            self.sibling_path(&manifest.snapshot.name).display(),
+            generation,
+            snapshot.len(),
+            snapshot.data.checksum.unwrap_or_default()
         );
 
-        Ok(())
+        Ok(json_data_with_checksum.len())
     }
 
-    fn try_recover_from_backup(&self) -> Result<RedisDatabase, Box<dyn std::error::Error>> {
-        let backup_path = format!("{}.bak", &self.file_path);
+    /// Registers a sealed WAL segment, written after the current snapshot, to be replayed on
+    /// top of it during recovery.
+    pub fn add_wal_segment(&self, path: &str) -> Result<(), PersistenceError> {
+        let mut manifest = self.read_manifest()?.ok_or(PersistenceError::NoSnapshot)?;
+        let contents = fs::read_to_string(path)?;
+        let name = Path::new(path).file_name().ok_or_else(|| PersistenceError::InvalidSegmentPath(path.to_string()))?.to_string_lossy().to_string();
+        manifest.wal_segments.push(ManifestFile { name, checksum: Self::calculate_checksum(&contents) });
+        self.write_manifest(&manifest)
+    }
 
-        if !Path::new(&backup_path).exists() {
-            return Err("No backup file available for recovery".into());
+    // Parses snapshot contents and verifies the checksum embedded in them
+    fn database_from_json(json_data: &str) -> Result<RedisDatabase, PersistenceError> {
+        if json_data.trim().is_empty() {
+            return Err(PersistenceError::Empty);
         }
 
-        println!("Attempting recovery from backup: {}", backup_path);
+        let persisted_data: PersistedData = serde_json::from_str(json_data)?;
 
-        let json_data = fs::read_to_string(&backup_path)?;
-        if json_data.trim().is_empty() {
-            return Err("Backup file is empty".into());
+        if persisted_data.version > 1 {
+            return Err(PersistenceError::UnsupportedVersion(persisted_data.version));
         }
 
-        let persisted_data: PersistedData = serde_json::from_str(&json_data)?;
-
         if let Some(expected_checksum) = &persisted_data.checksum {
             let mut data_without_checksum = persisted_data.clone();
             data_without_checksum.checksum = None;
-            let json_without_checksum = serde_json::to_string_pretty(&data_without_checksum)?;
+            let json_without_checksum = Self::canonical_json(&data_without_checksum)?;
 
             if !Self::verify_checksum(&json_without_checksum, expected_checksum) {
-                return Err("Backup file checksum verification failed".into());
+                return Err(PersistenceError::ChecksumMismatch);
             }
-            println!("Backup checksum verified successfully");
+            println!("Database checksum verified successfully");
+        } else {
+            println!("Warning: No checksum found in database file (older format)");
         }
 
         let now_system = SystemTime::now();
@@ -160,15 +596,119 @@ impl MmapPersistence {
 
         let mut db = RedisDatabase::new();
         db.data = persisted_data.data;
-        db.expires = expires;
+        db.expires = expires.into_iter().collect();
+        db.field_expires = Self::restore_field_expires(persisted_data.field_expires, now_system, now_instant);
+        db.tags = TagIndex::from_map(persisted_data.tags);
+        Ok(db)
+    }
+
+    fn replay_wal_segment(db: &mut RedisDatabase, contents: &str) -> Result<usize, PersistenceError> {
+        let now_secs = WriteAheadLog::get_current_timestamp();
+        let mut replayed = 0;
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<WalEntry>(line)? {
+                WalEntry::Set { key, value, .. } => db.set(key, RedisValue::String(value)).map_err(PersistenceError::Replay)?,
+                WalEntry::Delete { key, .. } => {
+                    db.delete(&key);
+                },
+                WalEntry::Expire { key, ttl_seconds, timestamp } => {
+                    let remaining = (timestamp + ttl_seconds).saturating_sub(now_secs);
+                    if remaining == 0 {
+                        db.delete(&key);
+                    } else {
+                        db.expire(&key, Duration::from_secs(remaining));
+                    }
+                },
+                WalEntry::Clear { .. } => db.clear(),
+            }
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    fn load_from_manifest(&self, manifest: &Manifest) -> RedisDatabase {
+        let loaded = self.read_verified(&manifest.snapshot).and_then(|json| Self::database_from_json(&json));
+        let mut db = match loaded {
+            Ok(db) => db,
+            Err(e) => {
+                eprintln!("Failed to load generation {} ({}): {}", manifest.generation, manifest.snapshot.name, e);
+                let previous = match &manifest.previous {
+                    Some(previous) => previous,
+                    None => {
+                        eprintln!("No previous generation to recover from, starting with empty database");
+                        return RedisDatabase::new();
+                    },
+                };
+                return match self.read_verified(previous).and_then(|json| Self::database_from_json(&json)) {
+                    Ok(db) => {
+                        // The WAL segments follow the failed generation, so they cannot be
+                        // replayed on top of this one
+                        println!("Recovered from previous generation {} ({} keys)", previous.name, db.data.len());
+                        let rolled_back = Manifest { generation: manifest.generation, snapshot: previous.clone(), previous: None, wal_segments: Vec::new() };
+                        if let Err(e) = self.write_manifest(&rolled_back) {
+                            eprintln!("Warning: Failed to roll back manifest: {}", e);
+                        }
+                        db
+                    },
+                    Err(e) => {
+                        eprintln!("Previous generation also failed: {}", e);
+                        eprintln!("Starting with empty database");
+                        RedisDatabase::new()
+                    },
+                };
+            },
+        };
+
+        for segment in &manifest.wal_segments {
+            match self.read_verified(segment).and_then(|contents| Self::replay_wal_segment(&mut db, &contents)) {
+                Ok(replayed) => println!("Replayed {} WAL entries from {}", replayed, segment.name),
+                Err(e) => {
+                    // Later segments depend on this one, so replay stops here
+                    eprintln!("Failed to replay WAL segment {}: {}", segment.name, e);
+                    break;
+                },
+            }
+        }
+
+        println!(
+            "Database loaded from {} (generation {}, {} keys)",
+            self.sibling_path(&manifest.snapshot.name).display(),
+            manifest.generation,
+            db.data.len()
+        );
+        db
+    }
+
+    fn try_recover_from_backup(&self) -> Result<RedisDatabase, PersistenceError> {
+        let backup_path = format!("{}.bak", &self.file_path);
+
+        if !Path::new(&backup_path).exists() {
+            return Err(PersistenceError::NoBackup);
+        }
 
+        println!("Attempting recovery from backup: {}", backup_path);
+
+        let db = Self::database_from_json(&fs::read_to_string(&backup_path)?)?;
         println!("Successfully recovered from backup ({} keys)", db.data.len());
         Ok(db)
     }
 
-    pub fn load_database(&self) -> Result<RedisDatabase, Box<dyn std::error::Error>> {
+    /// Loads the generation the manifest names, replaying its WAL segments. Snapshots written
+    /// before manifests existed are loaded from the file itself, falling back to its `.bak`.
+    /// Whether there is a snapshot to load, either committed through a manifest or a bare file.
+    pub fn has_snapshot(&self) -> bool {
+        Path::new(&self.manifest_path()).exists() || Path::new(&self.file_path).exists()
+    }
+
+    pub fn load_database(&self) -> Result<RedisDatabase, PersistenceError> {
         self.cleanup_temp_files()?;
 
+        match self.read_manifest() {
+            Ok(Some(manifest)) => return Ok(self.load_from_manifest(&manifest)),
+            Ok(None) => {},
+            Err(e) => eprintln!("Failed to read manifest {}: {}", self.manifest_path(), e),
+        }
+
         if !Path::new(&self.file_path).exists() {
             println!(
                 "Database file {} not found, starting with empty DB",
@@ -201,72 +741,104 @@ impl MmapPersistence {
         }
     }
 
-    fn try_load_main_file(&self) -> Result<RedisDatabase, Box<dyn std::error::Error>> {
-        let json_data = fs::read_to_string(&self.file_path)?;
+    fn try_load_main_file(&self) -> Result<RedisDatabase, PersistenceError> {
+        let db = Self::database_from_json(&fs::read_to_string(&self.file_path)?)?;
 
-        if json_data.trim().is_empty() {
-            return Err("Database file is empty".into());
-        }
+        println!(
+            "Database loaded from {} ({} keys)",
+            self.file_path,
+            db.data.len()
+        );
+        Ok(db)
+    }
 
-        let persisted_data: PersistedData = serde_json::from_str(&json_data)?;
+    // The snapshot recovery would load: the manifest's current generation, or the file itself
+    // for snapshots written before manifests existed
+    fn current_snapshot(&self) -> Result<(PathBuf, Option<Manifest>), PersistenceError> {
+        Ok(match self.read_manifest()? {
+            Some(manifest) => (self.sibling_path(&manifest.snapshot.name), Some(manifest)),
+            None => (PathBuf::from(&self.file_path), None),
+        })
+    }
 
+    /// Reads the current snapshot and checks its checksums, TTL invariants and the WAL segments
+    /// the manifest names. Unlike `load_database` nothing is recovered from older generations,
+    /// so problems are reported rather than repaired.
+    pub fn sanity_check(&self) -> Result<SanityReport, PersistenceError> {
+        let (path, manifest) = self.current_snapshot()?;
+        let json_data = fs::read_to_string(&path)?;
+        let persisted_data: PersistedData = serde_json::from_str(&json_data)?;
         if persisted_data.version > 1 {
-            return Err(format!(
-                "Unsupported database version: {}. Current version: 1",
-                persisted_data.version
-            ).into());
+            return Err(PersistenceError::UnsupportedVersion(persisted_data.version));
         }
 
+        let mut report = SanityReport { manifest_generation: manifest.as_ref().map(|manifest| manifest.generation), ..Default::default() };
         if let Some(expected_checksum) = &persisted_data.checksum {
             let mut data_without_checksum = persisted_data.clone();
             data_without_checksum.checksum = None;
-            let json_without_checksum = serde_json::to_string_pretty(&data_without_checksum)?;
-
-            if !Self::verify_checksum(&json_without_checksum, expected_checksum) {
-                return Err("Checksum verification failed - database file may be corrupted".into());
+            let json_without_checksum = Self::canonical_json(&data_without_checksum)?;
+            report.checksum_verified = Some(Self::verify_checksum(&json_without_checksum, expected_checksum));
+        }
+        if let Some(manifest) = &manifest {
+            if Self::calculate_checksum(&json_data) != manifest.snapshot.checksum {
+                report.checksum_verified = Some(false);
+            }
+            for segment in &manifest.wal_segments {
+                match self.read_verified(segment) {
+                    Ok(contents) => {
+                        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                            match serde_json::from_str::<WalEntry>(line) {
+                                Ok(_) => report.wal_entries += 1,
+                                Err(_) => report.wal_corrupt_records += 1,
+                            }
+                        }
+                    },
+                    Err(_) => report.wal_corrupt_records += 1,
+                }
             }
-            println!("Database checksum verified successfully");
-        } else {
-            println!("Warning: No checksum found in database file (older format)");
         }
 
-        let now_system = SystemTime::now();
-        let now_instant = std::time::Instant::now();
+        for value in persisted_data.data.values() {
+            *report.keys_by_type.entry(value.type_name()).or_insert(0) += 1;
+        }
 
-        let mut expires = HashMap::new();
-        if let Ok(current_secs) = now_system.duration_since(UNIX_EPOCH) {
-            for (key, expire_timestamp) in persisted_data.expires {
-                if expire_timestamp > current_secs.as_secs() {
-                    let seconds_until_expiry = expire_timestamp - current_secs.as_secs();
-                    expires.insert(key, now_instant + Duration::from_secs(seconds_until_expiry));
-                }
+        let now_secs = WriteAheadLog::get_current_timestamp();
+        for (key, expire_timestamp) in &persisted_data.expires {
+            if !persisted_data.data.contains_key(key) {
+                report.orphaned_expires += 1;
+            } else if *expire_timestamp <= now_secs {
+                report.expired_keys += 1;
             }
         }
 
-        let mut db = RedisDatabase::new();
-        db.data = persisted_data.data;
-        db.expires = expires;
+        for (key, fields) in &persisted_data.field_expires {
+            match persisted_data.data.get(key).map(|value| &**value) {
+                Some(RedisValue::Hash(hash)) => {
+                    report.orphaned_field_expires += fields.keys().filter(|field| !hash.contains_key(*field)).count();
+                },
+                _ => report.orphaned_field_expires += fields.len(),
+            }
+        }
 
-        println!(
-            "Database loaded from {} ({} keys)",
-            self.file_path,
-            db.data.len()
-        );
-        Ok(db)
+        Ok(report)
     }
 
-    pub fn verify_integrity(&self) -> Result<bool, Box<dyn std::error::Error>> {
-        if !Path::new(&self.file_path).exists() {
-            return Err("Database file does not exist".into());
+    pub fn verify_integrity(&self) -> Result<bool, PersistenceError> {
+        let (path, manifest) = self.current_snapshot()?;
+        if !path.exists() {
+            return Err(PersistenceError::Missing(path));
         }
 
-        let json_data = fs::read_to_string(&self.file_path)?;
+        let json_data = fs::read_to_string(&path)?;
+        if manifest.is_some_and(|manifest| Self::calculate_checksum(&json_data) != manifest.snapshot.checksum) {
+            return Ok(false);
+        }
         let persisted_data: PersistedData = serde_json::from_str(&json_data)?;
 
         if let Some(expected_checksum) = &persisted_data.checksum {
             let mut data_without_checksum = persisted_data.clone();
             data_without_checksum.checksum = None;
-            let json_without_checksum = serde_json::to_string_pretty(&data_without_checksum)?;
+            let json_without_checksum = Self::canonical_json(&data_without_checksum)?;
 
             Ok(Self::verify_checksum(&json_without_checksum, expected_checksum))
         } else {
@@ -281,6 +853,8 @@ impl Clone for PersistedData {
             version: self.version,
             data: self.data.clone(),
             expires: self.expires.clone(),
+            field_expires: self.field_expires.clone(),
+            tags: self.tags.clone(),
             checksum: self.checksum.clone(),
         }
     }
@@ -0,0 +1,129 @@
+// Keyspace usage grouped by key prefix, for instances shared by several teams: the prefix of
+// `user:123:name` is `user`, up to the first delimiter. Hits and misses are counted per prefix
+// as reads happen; key counts and memory are added up when DBSTATS BY PREFIX asks.
+use std::collections::HashMap;
+
+pub const DEFAULT_PREFIX_DELIMITER: char = ':';
+// Reads of keys outside the first this many prefixes are counted under OTHER_PREFIX, so lookups
+// of made-up keys cannot grow the table without bound
+const MAX_TRACKED_PREFIXES: usize = 1024;
+// Groups keys that contain no delimiter
+pub const NO_PREFIX: &str = "(none)";
+pub const OTHER_PREFIX: &str = "(other)";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Lookups {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug)]
+pub struct PrefixStats {
+    pub delimiter: char,
+    lookups: HashMap<String, Lookups>,
+}
+
+impl Default for PrefixStats {
+    fn default() -> Self {
+        Self { delimiter: DEFAULT_PREFIX_DELIMITER, lookups: HashMap::new() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixSummary {
+    pub prefix: String,
+    pub keys: usize,
+    pub bytes: usize,
+    pub lookups: Lookups,
+}
+
+impl PrefixSummary {
+    /// Hits as a percentage of reads, None before the first read.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let reads = self.lookups.hits + self.lookups.misses;
+        (reads > 0).then(|| self.lookups.hits as f64 * 100.0 / reads as f64)
+    }
+}
+
+impl PrefixStats {
+    pub fn with_delimiter(delimiter: char) -> Self {
+        Self { delimiter, lookups: HashMap::new() }
+    }
+
+    pub fn prefix_of<'a>(&self, key: &'a str) -> &'a str {
+        match key.split_once(self.delimiter) {
+            Some((prefix, _)) => prefix,
+            None => NO_PREFIX,
+        }
+    }
+
+    pub fn record(&mut self, key: &str, hit: bool) {
+        let mut prefix = self.prefix_of(key);
+        if !self.lookups.contains_key(prefix) && self.lookups.len() >= MAX_TRACKED_PREFIXES {
+            prefix = OTHER_PREFIX;
+        }
+        let lookups = self.lookups.entry(prefix.to_string()).or_default();
+        if hit {
+            lookups.hits += 1;
+        } else {
+            lookups.misses += 1;
+        }
+    }
+
+    /// One summary per prefix among `keys` (each with its size in bytes) or with recorded reads,
+    /// largest memory first.
+    pub fn summarize<'a>(&self, keys: impl IntoIterator<Item = (&'a str, usize)>) -> Vec<PrefixSummary> {
+        let mut groups: HashMap<&str, PrefixSummary> = HashMap::new();
+        let empty = |prefix: &str| PrefixSummary { prefix: prefix.to_string(), keys: 0, bytes: 0, lookups: Lookups::default() };
+        for (key, bytes) in keys {
+            let prefix = self.prefix_of(key);
+            let group = groups.entry(prefix).or_insert_with(|| empty(prefix));
+            group.keys += 1;
+            group.bytes += bytes;
+        }
+        for (prefix, lookups) in &self.lookups {
+            groups.entry(prefix).or_insert_with(|| empty(prefix)).lookups = *lookups;
+        }
+        let mut summaries: Vec<PrefixSummary> = groups.into_values().collect();
+        summaries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.prefix.cmp(&b.prefix)));
+        summaries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_group_by_first_segment() {
+        let mut stats = PrefixStats::default();
+        stats.record("user:1", true);
+        stats.record("user:2:name", false);
+        stats.record("user:3", true);
+        stats.record("ghost:1", false);
+
+        let summaries = stats.summarize([("user:1", 10), ("user:2:name", 30), ("session:9", 50), ("counter", 5)]);
+        let rows: Vec<(&str, usize, usize)> = summaries.iter().map(|s| (s.prefix.as_str(), s.keys, s.bytes)).collect();
+        assert_eq!(rows, vec![("session", 1, 50), ("user", 2, 40), ("(none)", 1, 5), ("ghost", 0, 0)]);
+
+        let user = &summaries[1];
+        assert_eq!(user.lookups, Lookups { hits: 2, misses: 1 });
+        assert_eq!(user.hit_rate().map(|rate| rate.round()), Some(67.0));
+        assert_eq!(summaries[0].hit_rate(), None);
+    }
+
+    #[test]
+    fn test_untracked_prefixes_fold_into_other() {
+        let mut stats = PrefixStats::with_delimiter('/');
+        for i in 0..MAX_TRACKED_PREFIXES {
+            stats.record(&format!("p{}/k", i), false);
+        }
+        stats.record("p0/k", true);
+        stats.record("new/k", true);
+        let summaries = stats.summarize([]);
+        let find = |prefix: &str| summaries.iter().find(|s| s.prefix == prefix).map(|s| s.lookups);
+        assert_eq!(find("p0"), Some(Lookups { hits: 1, misses: 1 }));
+        assert_eq!(find("new"), None);
+        assert_eq!(find(OTHER_PREFIX), Some(Lookups { hits: 1, misses: 0 }));
+    }
+}
@@ -1,7 +1,133 @@
-use crate::commands::Command;
+use crate::auth::MAX_SESSION_TTL;
+use crate::commands::{Command, ExpireCondition, ListEnd, SetCondition};
+use crate::timeseries::Aggregation;
+use crate::json_path::{self, PathSegment};
+use crate::heatmap::HEATMAP_MINUTES;
+use crate::migration::Throttle;
+use crate::persistence_bench::{DEFAULT_BENCH_OPS, MAX_BENCH_OPS};
+#[cfg(feature = "search")]
+use crate::search::{self, FieldKind};
+use crate::vector::DistanceMetric;
+use crate::pub_sub::RetentionPolicy;
+use crate::clients::ClientType;
+use crate::aggregate::Reducer;
+use crate::bloom::{filter_bytes, MAX_FILTER_BYTES};
+use crate::delpattern::DEFAULT_DELETE_RATE;
+use crate::string_ops::{BitOp, MAX_BIT_OFFSET};
+use crate::resp::{Frame, ProtocolLimits, Request, RequestDecoder};
+use bytes::BytesMut;
+
+const DEFAULT_RELIABLE_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+// Entries EXPIRED READ returns when no COUNT is given
+const DEFAULT_EXPIRED_READ_COUNT: usize = 100;
+// Keys HOTKEYS lists when no COUNT is given
+const DEFAULT_HOTKEYS_COUNT: usize = 10;
+// Channels and subscribers each PUBSUB STATS lists when no COUNT is given
+const DEFAULT_PUBSUB_STATS_COUNT: usize = 10;
+// Commands one ATOMIC block may hold, so a client cannot keep the write lock indefinitely
+const MAX_ATOMIC_COMMANDS: usize = 1000;
+// Picks a negative HRANDFIELD count may ask for; repeats come from no field limit, only the reply
+const MAX_HRANDFIELD_REPEATS: u64 = 10_000_000;
+// Longest TTL accepted, in milliseconds; adding a longer one to the clock could overflow it
+const MAX_TTL_MS: u64 = i64::MAX as u64 / 2;
+use std::borrow::Cow;
+use std::time::Duration;
 
 pub fn parse_command(input: &str) -> Result<Command, String> {
-    let parts: Vec<&str> = input.trim().split_whitespace().collect();
+    parse_args(&split_inline(input)?)
+}
+
+/// Splits an inline command into arguments the way redis-cli does. Arguments are separated by
+/// whitespace unless quoted: double quotes take the escapes \n, \r, \t, \b, \a and \xHH, and a
+/// backslash before any other character keeps that character; single quotes only take \'.
+pub fn split_inline(line: &str) -> Result<Vec<String>, String> {
+    let unbalanced = || "ERR Protocol error: unbalanced quotes in request".to_string();
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(args);
+        }
+        // Bytes rather than chars, since \xHH can spell out a multi-byte character
+        let mut arg = Vec::new();
+        let mut quote = None;
+        loop {
+            match (quote, chars.next()) {
+                (Some(_), None) => return Err(unbalanced()),
+                (None, None) => break,
+                (None, Some(c)) if c.is_whitespace() => break,
+                (None, Some(c @ ('"' | '\''))) => quote = Some(c),
+                (Some(q), Some(c)) if c == q => {
+                    // A closing quote must end the argument
+                    if chars.peek().is_some_and(|next| !next.is_whitespace()) {
+                        return Err(unbalanced());
+                    }
+                    break;
+                },
+                (Some('"'), Some('\\')) => {
+                    let escaped = chars.next().ok_or_else(unbalanced)?;
+                    let hex: String = chars.clone().take(2).collect();
+                    match escaped {
+                        'x' if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                            arg.push(u8::from_str_radix(&hex, 16).unwrap_or_default());
+                            chars.nth(1);
+                        },
+                        'n' => arg.push(b'\n'),
+                        'r' => arg.push(b'\r'),
+                        't' => arg.push(b'\t'),
+                        'b' => arg.push(0x08),
+                        'a' => arg.push(0x07),
+                        other => arg.extend_from_slice(other.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                },
+                (Some('\''), Some('\\')) if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    arg.push(b'\'');
+                },
+                (_, Some(c)) => arg.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        let arg = String::from_utf8(arg).map_err(|_| "ERR invalid UTF-8 in request, keys and values must be text".to_string())?;
+        args.push(arg);
+    }
+}
+
+/// `arg` as it would be typed in an inline command: quoted and escaped when it is empty or
+/// holds whitespace, quotes or backslashes, so `split_inline` reads it back unchanged.
+pub fn quote_arg(arg: &str) -> Cow<'_, str> {
+    let plain = !arg.is_empty() && !arg.chars().any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\');
+    if plain {
+        return Cow::Borrowed(arg);
+    }
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_whitespace() && c != ' ' => {
+                for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                    quoted.push_str(&format!("\\x{:02x}", byte));
+                }
+            },
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+/// Parses a command already split into arguments, such as a RESP array, whose arguments may
+/// contain whitespace.
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    parse_parts(&args.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+fn parse_parts(parts: &[&str]) -> Result<Command, String> {
     if parts.is_empty() {
         return Err("Empty command".to_string());
     }
@@ -17,27 +143,109 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             Ok(Command::Get { key: parts[1].to_string() })
         },
 
+        "GETWITHMETA" => {
+            if parts.len() != 2 {
+                return Err("ERR wrong number of arguments for 'getwithmeta' command".to_string());
+            }
+            Ok(Command::GetWithMeta { key: parts[1].to_string() })
+        },
+
         "SET" => {
             if parts.len() < 3 {
                 return Err("ERR wrong number of arguments for 'set' command".to_string());
             }
-            if parts.len() == 3 {
-                Ok(Command::Set {
-                    key: parts[1].to_string(),
-                    value: parts[2].to_string()
-                })
-            } else if parts.len() == 5 && parts[3].to_uppercase() == "EX" {
-                match parts[4].parse::<u64>() {
-                    Ok(seconds) => Ok(Command::SetEx {
+            if parts.len() == 5 && parts[3].to_uppercase() == "EX" {
+                return match expire_ttl(parts[4], false) {
+                    Some(ttl) => Ok(Command::SetEx {
                         key: parts[1].to_string(),
                         value: parts[2].to_string(),
-                        seconds,
+                        ttl,
                     }),
-                    Err(_) => Err("ERR invalid expire time in set".to_string()),
+                    None => Err("ERR invalid expire time in set".to_string()),
+                };
+            }
+
+            let mut expiry = None;
+            let mut condition = SetCondition::Always;
+            let mut keep_ttl = false;
+            let mut get = false;
+
+            let mut i = 3;
+            while i < parts.len() {
+                match parts[i].to_uppercase().as_str() {
+                    "NX" if condition != SetCondition::IfExists => condition = SetCondition::IfNotExists,
+                    "XX" if condition != SetCondition::IfNotExists => condition = SetCondition::IfExists,
+                    "KEEPTTL" if expiry.is_none() => keep_ttl = true,
+                    "GET" => get = true,
+                    "EX" | "PX" if expiry.is_none() && !keep_ttl && i + 1 < parts.len() => {
+                        match expire_ttl(parts[i + 1], parts[i].eq_ignore_ascii_case("PX")) {
+                            Some(ttl) => expiry = Some(ttl),
+                            None => return Err("ERR invalid expire time in set".to_string()),
+                        }
+                        i += 1;
+                    },
+                    _ => return Err("ERR syntax error".to_string()),
                 }
-            } else {
-                Err("ERR syntax error".to_string())
+                i += 1;
+            }
+
+            Ok(Command::Set {
+                key: parts[1].to_string(),
+                value: parts[2].to_string(),
+                expiry,
+                condition,
+                keep_ttl,
+                get,
+            })
+        },
+
+        "SETNX" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'setnx' command".to_string());
+            }
+            Ok(Command::SetNx {
+                key: parts[1].to_string(),
+                value: parts[2].to_string()
+            })
+        },
+
+        "DELIFEQ" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'delifeq' command".to_string());
+            }
+            Ok(Command::DelIfEq {
+                key: parts[1].to_string(),
+                value: parts[2].to_string()
+            })
+        },
+
+        "CAS" => {
+            if parts.len() != 4 && parts.len() != 6 {
+                return Err("ERR wrong number of arguments for 'cas' command".to_string());
+            }
+            let expiry = match parts.get(4) {
+                Some(option) if option.eq_ignore_ascii_case("EX") => match parts[5].parse::<u64>() {
+                    Ok(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+                    _ => return Err("ERR invalid expire time in cas".to_string()),
+                },
+                Some(_) => return Err("ERR syntax error".to_string()),
+                None => None,
+            };
+            Ok(Command::Cas {
+                key: parts[1].to_string(),
+                expected: parts[2].to_string(),
+                value: parts[3].to_string(),
+                expiry,
+            })
+        },
+
+        "INVALIDATE" => {
+            if parts.len() < 2 {
+                return Err("ERR wrong number of arguments for 'invalidate' command".to_string());
             }
+            Ok(Command::Invalidate {
+                keys: parts[1..].iter().map(|s| s.to_string()).collect()
+            })
         },
 
         "DEL" => {
@@ -72,6 +280,39 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             Ok(Command::Decr { key: parts[1].to_string() })
         },
 
+        "INCRBY" | "DECRBY" => {
+            if parts.len() != 3 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            let amount = parts[2].parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+            let key = parts[1].to_string();
+            Ok(if cmd == "INCRBY" { Command::IncrBy { key, increment: amount } } else { Command::DecrBy { key, decrement: amount } })
+        },
+
+        "INCRBYFLOAT" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'incrbyfloat' command".to_string());
+            }
+            match parts[2].parse::<f64>() {
+                Ok(increment) if increment.is_finite() => Ok(Command::IncrByFloat { key: parts[1].to_string(), increment }),
+                _ => Err("ERR value is not a valid float".to_string()),
+            }
+        },
+
+        "RATELIMIT" => {
+            if parts.len() != 4 {
+                return Err("ERR wrong number of arguments for 'ratelimit' command".to_string());
+            }
+            match (parts[2].parse::<u64>(), parts[3].parse::<u64>()) {
+                (Ok(max), Ok(window_secs)) if max > 0 && window_secs > 0 => Ok(Command::RateLimit {
+                    key: parts[1].to_string(),
+                    max,
+                    window_secs,
+                }),
+                _ => Err("ERR max and window must be positive integers".to_string()),
+            }
+        },
+
         "APPEND" => {
             if parts.len() != 3 {
                 return Err("ERR wrong number of arguments for 'append' command".to_string());
@@ -103,6 +344,105 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             }
         },
 
+        "LCS" => {
+            if parts.len() < 3 {
+                return Err("ERR wrong number of arguments for 'lcs' command".to_string());
+            }
+
+            let mut len = false;
+            let mut idx = false;
+            let mut min_match_len = 0;
+            let mut with_match_len = false;
+
+            let mut i = 3;
+            while i < parts.len() {
+                match parts[i].to_uppercase().as_str() {
+                    "LEN" => len = true,
+                    "IDX" => idx = true,
+                    "WITHMATCHLEN" => with_match_len = true,
+                    "MINMATCHLEN" if i + 1 < parts.len() => {
+                        min_match_len = match parts[i + 1].parse::<i64>() {
+                            Ok(n) => n.max(0) as usize,
+                            Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+                        };
+                        i += 1;
+                    },
+                    _ => return Err("ERR syntax error".to_string()),
+                }
+                i += 1;
+            }
+
+            if len && idx {
+                return Err("ERR If you want both the length and indexes, please just use IDX.".to_string());
+            }
+
+            Ok(Command::Lcs {
+                key1: parts[1].to_string(),
+                key2: parts[2].to_string(),
+                len,
+                idx,
+                min_match_len,
+                with_match_len,
+            })
+        },
+
+        // Bitmap commands
+        "SETBIT" => {
+            if parts.len() != 4 {
+                return Err("ERR wrong number of arguments for 'setbit' command".to_string());
+            }
+            let offset = parse_bit_offset(parts[2])?;
+            let on = match parts[3] {
+                "0" => false,
+                "1" => true,
+                _ => return Err("ERR bit is not an integer or out of range".to_string()),
+            };
+            Ok(Command::SetBit { key: parts[1].to_string(), offset, on })
+        },
+
+        "GETBIT" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'getbit' command".to_string());
+            }
+            Ok(Command::GetBit { key: parts[1].to_string(), offset: parse_bit_offset(parts[2])? })
+        },
+
+        "BITCOUNT" => {
+            let (range, bits) = match &parts[1..] {
+                [_] => (None, false),
+                [_, start, end] | [_, start, end, _] => {
+                    let bits = match parts.get(4).map(|unit| unit.to_uppercase()) {
+                        None => false,
+                        Some(unit) if unit == "BYTE" => false,
+                        Some(unit) if unit == "BIT" => true,
+                        Some(_) => return Err("ERR syntax error".to_string()),
+                    };
+                    match (start.parse::<i64>(), end.parse::<i64>()) {
+                        (Ok(start), Ok(end)) => (Some((start, end)), bits),
+                        _ => return Err("ERR value is not an integer or out of range".to_string()),
+                    }
+                },
+                [] => return Err("ERR wrong number of arguments for 'bitcount' command".to_string()),
+                _ => return Err("ERR syntax error".to_string()),
+            };
+            Ok(Command::BitCount { key: parts[1].to_string(), range, bits })
+        },
+
+        "BITOP" => {
+            if parts.len() < 4 {
+                return Err("ERR wrong number of arguments for 'bitop' command".to_string());
+            }
+            let op = BitOp::from_string(parts[1]).ok_or_else(|| "ERR syntax error".to_string())?;
+            if op == BitOp::Not && parts.len() != 4 {
+                return Err("ERR BITOP NOT must be called with a single source key.".to_string());
+            }
+            Ok(Command::BitOp {
+                op,
+                destkey: parts[2].to_string(),
+                keys: parts[3..].iter().map(|key| key.to_string()).collect(),
+            })
+        },
+
         // List commands
         "LPUSH" => {
             if parts.len() < 3 {
@@ -124,18 +464,65 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             })
         },
 
-        "LPOP" => {
-            if parts.len() != 2 {
-                return Err("ERR wrong number of arguments for 'lpop' command".to_string());
+        "LPOP" | "RPOP" => {
+            if parts.len() != 2 && parts.len() != 3 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
             }
-            Ok(Command::LPop { key: parts[1].to_string() })
+            let key = parts[1].to_string();
+            let count = match parts.get(2) {
+                Some(count) => Some(count.parse::<usize>().map_err(|_| "ERR value is out of range, must be positive".to_string())?),
+                None => None,
+            };
+            Ok(if cmd == "LPOP" { Command::LPop { key, count } } else { Command::RPop { key, count } })
         },
 
-        "RPOP" => {
-            if parts.len() != 2 {
-                return Err("ERR wrong number of arguments for 'rpop' command".to_string());
+        "LMOVE" => {
+            if parts.len() != 5 {
+                return Err("ERR wrong number of arguments for 'lmove' command".to_string());
+            }
+            Ok(Command::LMove { source: parts[1].to_string(), destination: parts[2].to_string(), from: parse_list_end(parts[3])?, to: parse_list_end(parts[4])? })
+        },
+
+        "RPOPLPUSH" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'rpoplpush' command".to_string());
+            }
+            Ok(Command::LMove { source: parts[1].to_string(), destination: parts[2].to_string(), from: ListEnd::Right, to: ListEnd::Left })
+        },
+
+        "BLPOP" | "BRPOP" => {
+            if parts.len() < 3 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
             }
-            Ok(Command::RPop { key: parts[1].to_string() })
+            let end = if cmd == "BLPOP" { ListEnd::Left } else { ListEnd::Right };
+            let keys = parts[1..parts.len() - 1].iter().map(|key| key.to_string()).collect();
+            Ok(Command::BPop { keys, end, timeout: parse_block_timeout(parts[parts.len() - 1])? })
+        },
+
+        "BLMOVE" => {
+            if parts.len() != 6 {
+                return Err("ERR wrong number of arguments for 'blmove' command".to_string());
+            }
+            Ok(Command::BLMove {
+                source: parts[1].to_string(),
+                destination: parts[2].to_string(),
+                from: parse_list_end(parts[3])?,
+                to: parse_list_end(parts[4])?,
+                timeout: parse_block_timeout(parts[5])?,
+            })
+        },
+
+        "BRPOPLPUSH" => {
+            if parts.len() != 4 {
+                return Err("ERR wrong number of arguments for 'brpoplpush' command".to_string());
+            }
+            Ok(Command::BLMove {
+                source: parts[1].to_string(),
+                destination: parts[2].to_string(),
+                from: ListEnd::Right,
+                to: ListEnd::Left,
+                timeout: parse_block_timeout(parts[3])?,
+            })
         },
 
         "LLEN" => {
@@ -186,6 +573,346 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             }
         },
 
+        // Delay queue commands
+        "DELAYQ" => {
+            if parts.len() < 3 {
+                return Err("ERR wrong number of arguments for 'delayq' command".to_string());
+            }
+
+            let key = parts[2].to_string();
+            match parts[1].to_uppercase().as_str() {
+                "PUSH" => {
+                    if parts.len() < 5 {
+                        return Err("ERR wrong number of arguments for 'delayq push' command".to_string());
+                    }
+                    match parts[3].parse::<u64>() {
+                        Ok(delay_ms) => Ok(Command::DelayQPush {
+                            key,
+                            delay_ms,
+                            members: parts[4..].iter().map(|s| s.to_string()).collect(),
+                        }),
+                        Err(_) => Err("ERR invalid delay".to_string()),
+                    }
+                },
+                "POP" => {
+                    let count = match parts.get(3) {
+                        Some(count) => match count.parse::<usize>() {
+                            Ok(count) if count > 0 => Some(count),
+                            _ => return Err("ERR value is out of range, must be positive".to_string()),
+                        },
+                        None => None,
+                    };
+                    Ok(Command::DelayQPop { key, count })
+                },
+                "BPOP" => {
+                    if parts.len() != 4 {
+                        return Err("ERR wrong number of arguments for 'delayq bpop' command".to_string());
+                    }
+                    match parts[3].parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()) {
+                        Some(timeout) => Ok(Command::DelayQBPop { key, timeout }),
+                        None => Err("ERR timeout is not a float or out of range".to_string()),
+                    }
+                },
+                "LEN" => Ok(Command::DelayQLen { key }),
+                _ => Err(format!("ERR unknown DELAYQ subcommand '{}'", parts[1])),
+            }
+        },
+
+        // Bloom filter commands
+        "BF.RESERVE" => {
+            if parts.len() != 4 {
+                return Err("ERR wrong number of arguments for 'bf.reserve' command".to_string());
+            }
+            let error_rate = match parts[2].parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate < 1.0 => rate,
+                _ => return Err("ERR (0 < error rate range < 1)".to_string()),
+            };
+            let capacity = match parts[3].parse::<u64>() {
+                Ok(capacity) if capacity > 0 => capacity,
+                _ => return Err("ERR (capacity should be larger than 0)".to_string()),
+            };
+            if filter_bytes(error_rate, capacity) > MAX_FILTER_BYTES {
+                return Err(format!("ERR filter would be larger than {} bytes, lower the capacity or raise the error rate", MAX_FILTER_BYTES));
+            }
+            Ok(Command::BfReserve { key: parts[1].to_string(), error_rate, capacity })
+        },
+
+        "BF.ADD" | "BF.EXISTS" => {
+            if parts.len() != 3 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            let key = parts[1].to_string();
+            let items = vec![parts[2].to_string()];
+            if cmd == "BF.ADD" {
+                Ok(Command::BfAdd { key, items, multi: false })
+            } else {
+                Ok(Command::BfExists { key, items, multi: false })
+            }
+        },
+
+        "BF.MADD" | "BF.MEXISTS" => {
+            if parts.len() < 3 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            let key = parts[1].to_string();
+            let items = parts[2..].iter().map(|s| s.to_string()).collect();
+            if cmd == "BF.MADD" {
+                Ok(Command::BfAdd { key, items, multi: true })
+            } else {
+                Ok(Command::BfExists { key, items, multi: true })
+            }
+        },
+
+        "BF.INFO" => {
+            if parts.len() != 2 {
+                return Err("ERR wrong number of arguments for 'bf.info' command".to_string());
+            }
+            Ok(Command::BfInfo { key: parts[1].to_string() })
+        },
+
+        // Time series commands
+        "TS.CREATE" => {
+            if parts.len() != 2 && parts.len() != 4 {
+                return Err("ERR wrong number of arguments for 'ts.create' command".to_string());
+            }
+            let retention_ms = if parts.len() == 4 {
+                parse_retention(parts[2], parts[3])?
+            } else {
+                0
+            };
+            Ok(Command::TsCreate { key: parts[1].to_string(), retention_ms })
+        },
+
+        "TS.ADD" => {
+            if parts.len() != 4 && parts.len() != 6 {
+                return Err("ERR wrong number of arguments for 'ts.add' command".to_string());
+            }
+            let timestamp = if parts[2] == "*" {
+                None
+            } else {
+                match parts[2].parse::<i64>() {
+                    Ok(timestamp) if timestamp >= 0 => Some(timestamp),
+                    _ => return Err("ERR TSDB: invalid timestamp".to_string()),
+                }
+            };
+            let value = match parts[3].parse::<f64>() {
+                Ok(value) if value.is_finite() => value,
+                _ => return Err("ERR TSDB: invalid value".to_string()),
+            };
+            let retention_ms = if parts.len() == 6 {
+                Some(parse_retention(parts[4], parts[5])?)
+            } else {
+                None
+            };
+            Ok(Command::TsAdd { key: parts[1].to_string(), timestamp, value, retention_ms })
+        },
+
+        "TS.GET" => {
+            if parts.len() != 2 {
+                return Err("ERR wrong number of arguments for 'ts.get' command".to_string());
+            }
+            Ok(Command::TsGet { key: parts[1].to_string() })
+        },
+
+        "TS.RANGE" => {
+            if parts.len() != 4 && parts.len() != 7 {
+                return Err("ERR wrong number of arguments for 'ts.range' command".to_string());
+            }
+            let parse_bound = |bound: &str, open: i64| -> Result<i64, String> {
+                match bound {
+                    "-" | "+" => Ok(open),
+                    _ => bound.parse::<i64>().map_err(|_| "ERR TSDB: invalid range timestamp".to_string()),
+                }
+            };
+            let from = parse_bound(parts[2], i64::MIN)?;
+            let to = parse_bound(parts[3], i64::MAX)?;
+            let aggregation = if parts.len() == 7 {
+                if parts[4].to_uppercase() != "AGGREGATION" {
+                    return Err("ERR syntax error".to_string());
+                }
+                Some(parse_aggregation(parts[5], parts[6])?)
+            } else {
+                None
+            };
+            Ok(Command::TsRange { key: parts[1].to_string(), from, to, aggregation })
+        },
+
+        "TS.CREATERULE" => {
+            if parts.len() != 6 || parts[3].to_uppercase() != "AGGREGATION" {
+                return Err("ERR wrong number of arguments for 'ts.createrule' command".to_string());
+            }
+            let (aggregation, bucket_ms) = parse_aggregation(parts[4], parts[5])?;
+            Ok(Command::TsCreateRule {
+                source: parts[1].to_string(),
+                dest: parts[2].to_string(),
+                aggregation,
+                bucket_ms,
+            })
+        },
+
+        // JSON document commands
+        "JSON.SET" => {
+            if parts.len() < 4 {
+                return Err("ERR wrong number of arguments for 'json.set' command".to_string());
+            }
+            // The value may have been split on whitespace, so rejoin everything up to an optional NX/XX
+            let (value_parts, condition) = match parts[parts.len() - 1].to_uppercase().as_str() {
+                "NX" if parts.len() > 4 => (&parts[3..parts.len() - 1], SetCondition::IfNotExists),
+                "XX" if parts.len() > 4 => (&parts[3..parts.len() - 1], SetCondition::IfExists),
+                _ => (&parts[3..], SetCondition::Always),
+            };
+            let value = serde_json::from_str(&value_parts.join(" "))
+                .map_err(|e| format!("ERR invalid JSON value: {}", e))?;
+
+            Ok(Command::JsonSet {
+                key: parts[1].to_string(),
+                path: json_path::parse_path(parts[2])?,
+                value,
+                condition,
+            })
+        },
+
+        "JSON.GET" | "JSON.DEL" => {
+            if parts.len() != 2 && parts.len() != 3 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            let key = parts[1].to_string();
+            let path = match parts.get(2) {
+                Some(path) => json_path::parse_path(path)?,
+                None => Vec::<PathSegment>::new(),
+            };
+
+            if cmd == "JSON.GET" {
+                Ok(Command::JsonGet { key, path })
+            } else {
+                Ok(Command::JsonDel { key, path })
+            }
+        },
+
+        "JSON.NUMINCRBY" => {
+            if parts.len() != 4 {
+                return Err("ERR wrong number of arguments for 'json.numincrby' command".to_string());
+            }
+            let increment = match serde_json::from_str::<serde_json::Value>(parts[3]) {
+                Ok(serde_json::Value::Number(number)) => number,
+                _ => return Err("ERR value is not a number".to_string()),
+            };
+            Ok(Command::JsonNumIncrBy {
+                key: parts[1].to_string(),
+                path: json_path::parse_path(parts[2])?,
+                increment,
+            })
+        },
+
+        // Secondary index commands
+        #[cfg(feature = "search")]
+        "FT.CREATE" => {
+            if parts.len() < 5 {
+                return Err("ERR wrong number of arguments for 'ft.create' command".to_string());
+            }
+
+            let mut prefixes = Vec::new();
+            let mut i = 2;
+            while i < parts.len() && parts[i].to_uppercase() != "SCHEMA" {
+                match parts[i].to_uppercase().as_str() {
+                    "ON" if i + 1 < parts.len() && parts[i + 1].eq_ignore_ascii_case("HASH") => i += 2,
+                    "PREFIX" if i + 1 < parts.len() => {
+                        let count = parts[i + 1].parse::<usize>()
+                            .map_err(|_| "ERR invalid PREFIX count".to_string())?;
+                        if i + 2 + count > parts.len() {
+                            return Err("ERR syntax error".to_string());
+                        }
+                        prefixes.extend(parts[i + 2..i + 2 + count].iter().map(|prefix| prefix.to_string()));
+                        i += 2 + count;
+                    },
+                    _ => return Err("ERR syntax error".to_string()),
+                }
+            }
+
+            let schema = parts.get(i + 1..).unwrap_or_default();
+            if schema.is_empty() || schema.len() % 2 != 0 {
+                return Err("ERR syntax error: SCHEMA expects field TAG|NUMERIC pairs".to_string());
+            }
+            let mut fields = Vec::new();
+            for pair in schema.chunks(2) {
+                let kind = FieldKind::from_string(pair[1])
+                    .ok_or_else(|| format!("ERR unsupported field type '{}'", pair[1]))?;
+                fields.push((pair[0].to_string(), kind));
+            }
+
+            Ok(Command::FtCreate { index: parts[1].to_string(), prefixes, fields })
+        },
+
+        #[cfg(feature = "search")]
+        "FT.SEARCH" => {
+            if parts.len() < 3 {
+                return Err("ERR wrong number of arguments for 'ft.search' command".to_string());
+            }
+            Ok(Command::FtSearch {
+                index: parts[1].to_string(),
+                predicates: search::parse_query(&parts[2..].join(" "))?,
+            })
+        },
+
+        #[cfg(feature = "search")]
+        "FT.DROPINDEX" | "FT.INFO" => {
+            if parts.len() != 2 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            let index = parts[1].to_string();
+            if cmd == "FT.INFO" {
+                Ok(Command::FtInfo { index })
+            } else {
+                Ok(Command::FtDropIndex { index })
+            }
+        },
+
+        // Vector commands
+        "VECTOR.ADD" => {
+            if parts.len() < 4 {
+                return Err("ERR wrong number of arguments for 'vector.add' command".to_string());
+            }
+            Ok(Command::VectorAdd {
+                key: parts[1].to_string(),
+                element: parts[2].to_string(),
+                vector: parse_vector(&parts[3..])?,
+            })
+        },
+
+        "VECTOR.SEARCH" => {
+            if parts.len() < 4 {
+                return Err("ERR wrong number of arguments for 'vector.search' command".to_string());
+            }
+            let k = match parts[2].parse::<usize>() {
+                Ok(k) if k > 0 => k,
+                _ => return Err("ERR K must be a positive integer".to_string()),
+            };
+            let (metric, components) = if parts[3].eq_ignore_ascii_case("METRIC") {
+                let metric = parts.get(4)
+                    .and_then(|name| DistanceMetric::from_string(name))
+                    .ok_or_else(|| "ERR METRIC must be COSINE or L2".to_string())?;
+                (metric, &parts[5..])
+            } else {
+                (DistanceMetric::Cosine, &parts[3..])
+            };
+            if components.is_empty() {
+                return Err("ERR wrong number of arguments for 'vector.search' command".to_string());
+            }
+            Ok(Command::VectorSearch {
+                key: parts[1].to_string(),
+                k,
+                metric,
+                vector: parse_vector(components)?,
+            })
+        },
+
+        "VECTOR.REM" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'vector.rem' command".to_string());
+            }
+            Ok(Command::VectorRem { key: parts[1].to_string(), element: parts[2].to_string() })
+        },
+
         // Set commands
         "SADD" => {
             if parts.len() < 3 {
@@ -342,47 +1069,269 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             }
         },
 
-        // Generic commands
-        "KEYS" => {
-            let pattern = if parts.len() > 1 { parts[1].to_string() } else { "*".to_string() };
-            Ok(Command::Keys { pattern })
-        },
-
-        "TYPE" => {
-            if parts.len() != 2 {
+        "HRANDFIELD" => {
+            if parts.len() < 2 || parts.len() > 4 {
+                return Err("ERR wrong number of arguments for 'hrandfield' command".to_string());
+            }
+            let count = if parts.len() > 2 {
+                match parts[2].parse::<i64>() {
+                    Ok(count) if count < 0 && count.unsigned_abs() > MAX_HRANDFIELD_REPEATS => {
+                        return Err("ERR value is out of range".to_string());
+                    },
+                    Ok(count) => Some(count),
+                    Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+                }
+            } else {
+                None
+            };
+            let with_values = if parts.len() == 4 {
+                if parts[3].to_uppercase() != "WITHVALUES" {
+                    return Err("ERR syntax error".to_string());
+                }
+                true
+            } else {
+                false
+            };
+            Ok(Command::HRandField {
+                key: parts[1].to_string(),
+                count,
+                with_values,
+            })
+        },
+
+        "HEXPIRE" | "HPEXPIRE" => {
+            if parts.len() < 6 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            let amount = parts[2].parse::<u64>()
+                .map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+            let ttl = if cmd == "HEXPIRE" { Duration::from_secs(amount) } else { Duration::from_millis(amount) };
+
+            let (condition, fields_at) = match parts[3].to_uppercase().as_str() {
+                "NX" => (ExpireCondition::IfNoTtl, 4),
+                "XX" => (ExpireCondition::IfHasTtl, 4),
+                "GT" => (ExpireCondition::IfGreater, 4),
+                "LT" => (ExpireCondition::IfLess, 4),
+                _ => (ExpireCondition::Always, 3),
+            };
+            Ok(Command::HExpire {
+                key: parts[1].to_string(),
+                ttl,
+                condition,
+                fields: parse_fields_clause(&parts[fields_at..])?,
+            })
+        },
+
+        "HTTL" | "HPTTL" | "HPERSIST" => {
+            if parts.len() < 5 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            let key = parts[1].to_string();
+            let fields = parse_fields_clause(&parts[2..])?;
+            match cmd.as_str() {
+                "HPERSIST" => Ok(Command::HPersist { key, fields }),
+                _ => Ok(Command::HTtl { key, fields, millis: cmd == "HPTTL" }),
+            }
+        },
+
+        // Generic commands
+        "KEYS" => {
+            let pattern = if parts.len() > 1 { parts[1].to_string() } else { "*".to_string() };
+            Ok(Command::Keys { pattern })
+        },
+
+        "AGGREGATE" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'aggregate' command".to_string());
+            }
+            let reducer = Reducer::from_string(parts[1])
+                .ok_or_else(|| format!("ERR unknown reducer '{}', expected COUNT, SUM, MIN, MAX or AVG", parts[1]))?;
+            Ok(Command::Aggregate { reducer, pattern: parts[2].to_string() })
+        },
+
+        "DELPATTERN" => match parts.get(1) {
+            Some(_) => {
+                let keys_per_sec = match &parts[2..] {
+                    [] => DEFAULT_DELETE_RATE,
+                    [option, rate] if option.eq_ignore_ascii_case("RATE") => rate.parse::<u64>().ok().filter(|rate| *rate > 0)
+                        .ok_or_else(|| "ERR RATE needs a positive integer".to_string())?,
+                    _ => return Err("ERR syntax error".to_string()),
+                };
+                let throttle = Throttle { keys_per_sec: Some(keys_per_sec), bytes_per_sec: None };
+                Ok(Command::DelPattern { pattern: parts[1].to_string(), throttle })
+            },
+            None => Err("ERR wrong number of arguments for 'delpattern' command".to_string()),
+        },
+
+        "JOB" => {
+            if parts.len() < 2 {
+                return Err("ERR wrong number of arguments for 'job' command".to_string());
+            }
+            match parts[1].to_uppercase().as_str() {
+                "LIST" if parts.len() == 2 => Ok(Command::JobList),
+                "LIST" => Err("ERR wrong number of arguments for 'job|list' command".to_string()),
+                sub @ ("STATUS" | "CANCEL") => {
+                    if parts.len() != 3 {
+                        return Err(format!("ERR wrong number of arguments for 'job|{}' command", sub.to_lowercase()));
+                    }
+                    let id = parts[2].parse::<u64>().map_err(|_| "ERR job id must be a positive integer".to_string())?;
+                    Ok(if sub == "STATUS" { Command::JobStatus { id } } else { Command::JobCancel { id } })
+                },
+                _ => Err(format!("ERR unknown JOB subcommand '{}'", parts[1])),
+            }
+        },
+
+        "TAG" => {
+            if parts.len() < 2 {
+                return Err("ERR wrong number of arguments for 'tag' command".to_string());
+            }
+            match parts[1].to_uppercase().as_str() {
+                sub @ ("SET" | "DEL") => {
+                    if parts.len() < 4 {
+                        return Err(format!("ERR wrong number of arguments for 'tag|{}' command", sub.to_lowercase()));
+                    }
+                    let key = parts[2].to_string();
+                    let tags = parts[3..].iter().map(|tag| tag.to_string()).collect();
+                    Ok(if sub == "SET" { Command::TagSet { key, tags } } else { Command::TagDel { key, tags } })
+                },
+                "GET" => {
+                    if parts.len() != 3 {
+                        return Err("ERR wrong number of arguments for 'tag|get' command".to_string());
+                    }
+                    Ok(Command::TagGet { key: parts[2].to_string() })
+                },
+                _ => Err(format!("ERR unknown TAG subcommand '{}'", parts[1])),
+            }
+        },
+
+        "KEYSBYTAG" => {
+            if parts.len() != 2 {
+                return Err("ERR wrong number of arguments for 'keysbytag' command".to_string());
+            }
+            Ok(Command::KeysByTag { tag: parts[1].to_string() })
+        },
+
+        "TYPE" => {
+            if parts.len() != 2 {
                 return Err("ERR wrong number of arguments for 'type' command".to_string());
             }
             Ok(Command::Type { key: parts[1].to_string() })
         },
 
-        "EXPIRE" => {
+        "EXPIRE" | "PEXPIRE" => {
             if parts.len() != 3 {
-                return Err("ERR wrong number of arguments for 'expire' command".to_string());
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            match parts[2].parse::<u64>().ok().and_then(|amount| ttl_within_range(amount, cmd == "PEXPIRE")) {
+                Some(ttl) => Ok(Command::Expire { key: parts[1].to_string(), ttl }),
+                None => Err("ERR invalid expire time".to_string()),
+            }
+        },
+
+        "PEXPIREAT" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'pexpireat' command".to_string());
             }
             match parts[2].parse::<u64>() {
-                Ok(seconds) => Ok(Command::Expire {
+                Ok(unix_ms) => Ok(Command::ExpireAt { key: parts[1].to_string(), unix_ms }),
+                Err(_) => Err("ERR invalid expire time".to_string()),
+            }
+        },
+
+        "SETEX" | "PSETEX" => {
+            if parts.len() != 4 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            match expire_ttl(parts[2], cmd == "PSETEX") {
+                Some(ttl) => Ok(Command::SetEx {
                     key: parts[1].to_string(),
-                    seconds,
+                    value: parts[3].to_string(),
+                    ttl,
                 }),
-                Err(_) => Err("ERR invalid expire time".to_string()),
+                None => Err(format!("ERR invalid expire time in '{}' command", cmd.to_lowercase())),
             }
         },
 
-        "TTL" => {
+        "LOCK" => {
+            if parts.len() != 4 {
+                return Err("ERR wrong number of arguments for 'lock' command".to_string());
+            }
+            match parts[3].parse::<u64>() {
+                Ok(ttl_ms) if ttl_ms > 0 => Ok(Command::Lock {
+                    key: parts[1].to_string(),
+                    token: parts[2].to_string(),
+                    ttl: Duration::from_millis(ttl_ms),
+                }),
+                _ => Err("ERR lock ttl must be a positive number of milliseconds".to_string()),
+            }
+        },
+
+        "UNLOCK" => {
+            if parts.len() != 3 {
+                return Err("ERR wrong number of arguments for 'unlock' command".to_string());
+            }
+            Ok(Command::Unlock { key: parts[1].to_string(), token: parts[2].to_string() })
+        },
+
+        "TTL" | "PTTL" => {
             if parts.len() != 2 {
-                return Err("ERR wrong number of arguments for 'ttl' command".to_string());
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            Ok(Command::Ttl { key: parts[1].to_string(), millis: cmd == "PTTL" })
+        },
+
+        "TTLMANY" | "PTTLMANY" => {
+            if parts.len() < 2 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
             }
-            Ok(Command::Ttl { key: parts[1].to_string() })
+            Ok(Command::TtlMany {
+                keys: parts[1..].iter().map(|s| s.to_string()).collect(),
+                millis: cmd == "PTTLMANY",
+            })
         },
 
         "FLUSHALL" => {
             Ok(Command::FlushAll)
         },
 
+        "UNDOFLUSH" => {
+            Ok(Command::UndoFlush)
+        },
+
+        "RESTOREKEY" => match &parts[1..] {
+            [key] => Ok(Command::RestoreKey { key: key.to_string(), replace: false }),
+            [key, option] if option.eq_ignore_ascii_case("REPLACE") => Ok(Command::RestoreKey { key: key.to_string(), replace: true }),
+            [_, _] => Err("ERR syntax error".to_string()),
+            _ => Err("ERR wrong number of arguments for 'restorekey' command".to_string()),
+        },
+
         "DBSIZE" => {
             Ok(Command::DbSize)
         },
 
+        "TTLSTATS" => {
+            Ok(Command::TtlStats)
+        },
+
+        "HOTKEYS" => {
+            let mut count = DEFAULT_HOTKEYS_COUNT;
+            let mut minutes = HEATMAP_MINUTES;
+            for option in parts[1..].chunks(2) {
+                match option {
+                    [name, value] if name.eq_ignore_ascii_case("COUNT") => {
+                        count = value.parse::<usize>().ok().filter(|count| *count > 0)
+                            .ok_or_else(|| "ERR COUNT must be a positive integer".to_string())?;
+                    },
+                    [name, value] if name.eq_ignore_ascii_case("MINUTES") => {
+                        minutes = value.parse::<usize>().ok().filter(|minutes| (1..=HEATMAP_MINUTES).contains(minutes))
+                            .ok_or_else(|| format!("ERR MINUTES must be between 1 and {}", HEATMAP_MINUTES))?;
+                    },
+                    _ => return Err("ERR syntax error".to_string()),
+                }
+            }
+            Ok(Command::HotKeys { count, minutes })
+        },
+
         "PERSIST" => {
             if parts.len() != 2 {
                 return Err("ERR wrong number of arguments for 'persist' command".to_string());
@@ -390,6 +1339,28 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             Ok(Command::Persist { key: parts[1].to_string() })
         },
 
+        "PIN" | "UNPIN" => {
+            if parts.len() < 2 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()));
+            }
+            let keys = parts[1..].iter().map(|s| s.to_string()).collect();
+            Ok(if cmd == "PIN" { Command::Pin { keys } } else { Command::Unpin { keys } })
+        },
+
+        "COPY" => {
+            let replace = match parts.len() {
+                3 => false,
+                4 if parts[3].to_uppercase() == "REPLACE" => true,
+                4 => return Err("ERR syntax error".to_string()),
+                _ => return Err("ERR wrong number of arguments for 'copy' command".to_string()),
+            };
+            Ok(Command::Copy {
+                source: parts[1].to_string(),
+                destination: parts[2].to_string(),
+                replace,
+            })
+        },
+
         "RENAME" => {
             if parts.len() != 3 {
                 return Err("ERR wrong number of arguments for 'rename' command".to_string());
@@ -419,8 +1390,26 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             if parts.len() < 2 {
                 return Err("ERR wrong number of arguments for 'subscribe' command".to_string());
             }
+            // SUBSCRIBE ch [ch ...] [REPLAY n] [CONSUMER name]: REPLAY first delivers up to n
+            // retained messages per channel, CONSUMER keeps the subscriptions across reconnects
+            let mut channels = &parts[1..];
+            let (mut replay, mut consumer) = (None, None);
+            while channels.len() >= 3 {
+                let (option, value) = (channels[channels.len() - 2], channels[channels.len() - 1]);
+                if option.eq_ignore_ascii_case("REPLAY") && replay.is_none() {
+                    replay = Some(value.parse::<usize>()
+                        .map_err(|_| "ERR REPLAY count must be a non-negative integer".to_string())?);
+                } else if option.eq_ignore_ascii_case("CONSUMER") && consumer.is_none() {
+                    consumer = Some(value.to_string());
+                } else {
+                    break;
+                }
+                channels = &channels[..channels.len() - 2];
+            }
             Ok(Command::Subscribe {
-                channels: parts[1..].iter().map(|s| s.to_string()).collect(),
+                channels: channels.iter().map(|s| s.to_string()).collect(),
+                replay,
+                consumer,
             })
         },
 
@@ -434,6 +1423,16 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             })
         },
 
+        "ACK" => {
+            if parts.len() < 2 {
+                return Err("ERR wrong number of arguments for 'ack' command".to_string());
+            }
+            let ids = parts[1..].iter()
+                .map(|id| id.parse::<u64>().map_err(|_| "ERR message id is not an integer".to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Command::Ack { ids })
+        },
+
         "PSUBSCRIBE" => {
             if parts.len() < 2 {
                 return Err("ERR wrong number of arguments for 'psubscribe' command".to_string());
@@ -478,6 +1477,72 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
                     })
                 },
                 "NUMPAT" => Ok(Command::PubSubNumPat),
+                "RELIABLE" => {
+                    if parts.len() != 3 && parts.len() != 4 && parts.len() != 5 {
+                        return Err("ERR wrong number of arguments for 'pubsub|reliable' command".to_string());
+                    }
+                    let channel = parts[2].to_string();
+                    match parts.get(3).map(|option| option.to_uppercase()).as_deref() {
+                        None => Ok(Command::PubSubReliable { channel, ack_timeout: Some(DEFAULT_RELIABLE_ACK_TIMEOUT) }),
+                        Some("OFF") if parts.len() == 4 => Ok(Command::PubSubReliable { channel, ack_timeout: None }),
+                        Some("ACKTIMEOUT") if parts.len() == 5 => match parts[4].parse::<u64>() {
+                            Ok(millis) if millis > 0 => Ok(Command::PubSubReliable { channel, ack_timeout: Some(Duration::from_millis(millis)) }),
+                            _ => Err("ERR ACKTIMEOUT must be a positive number of milliseconds".to_string()),
+                        },
+                        _ => Err("ERR syntax error".to_string()),
+                    }
+                },
+                "CONSUMERS" => {
+                    if parts.len() != 2 {
+                        return Err("ERR wrong number of arguments for 'pubsub|consumers' command".to_string());
+                    }
+                    Ok(Command::PubSubConsumers)
+                },
+                "STATS" => {
+                    match &parts[2..] {
+                        [] => Ok(Command::PubSubStats { count: DEFAULT_PUBSUB_STATS_COUNT }),
+                        [name, value] if name.eq_ignore_ascii_case("COUNT") => {
+                            let count = value.parse::<usize>().ok().filter(|count| *count > 0)
+                                .ok_or_else(|| "ERR COUNT must be a positive integer".to_string())?;
+                            Ok(Command::PubSubStats { count })
+                        },
+                        _ => Err("ERR syntax error".to_string()),
+                    }
+                },
+                "PENDING" => {
+                    if parts.len() > 3 {
+                        return Err("ERR wrong number of arguments for 'pubsub|pending' command".to_string());
+                    }
+                    Ok(Command::PubSubPending { channel: parts.get(2).map(|channel| channel.to_string()) })
+                },
+                "RETENTION" => {
+                    if parts.len() < 4 {
+                        return Err("ERR wrong number of arguments for 'pubsub|retention' command".to_string());
+                    }
+                    let channel = parts[2].to_string();
+                    if parts.len() == 4 && parts[3].eq_ignore_ascii_case("OFF") {
+                        return Ok(Command::PubSubRetention { channel, policy: None });
+                    }
+
+                    let mut max_messages = None;
+                    let mut max_age = None;
+                    for option in parts[3..].chunks(2) {
+                        let value = option.get(1).and_then(|value| value.parse::<u64>().ok())
+                            .ok_or_else(|| "ERR syntax error".to_string())?;
+                        match option[0].to_uppercase().as_str() {
+                            "MAXLEN" => max_messages = Some(value as usize),
+                            "MAXAGE" => max_age = Some(Duration::from_secs(value)),
+                            _ => return Err("ERR syntax error".to_string()),
+                        }
+                    }
+                    match max_messages {
+                        Some(max_messages) if max_messages > 0 => Ok(Command::PubSubRetention {
+                            channel,
+                            policy: Some(RetentionPolicy { max_messages, max_age }),
+                        }),
+                        _ => Err("ERR RETENTION requires MAXLEN greater than 0".to_string()),
+                    }
+                },
                 _ => Err(format!("ERR unknown PUBSUB subcommand '{}'", parts[1])),
             }
         },
@@ -504,18 +1569,202 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
         },
 
         "AUTH" => {
-            if parts.len() != 2 {
-                return Err("ERR wrong number of arguments for 'auth' command".to_string());
+            match parts.len() {
+                2 => Ok(Command::Auth { username: None, password: parts[1].to_string() }),
+                3 => Ok(Command::Auth { username: Some(parts[1].to_string()), password: parts[2].to_string() }),
+                _ => Err("ERR wrong number of arguments for 'auth' command".to_string()),
+            }
+        },
+
+        "SESSION" => {
+            let subcommand = parts.get(1).map(|sub| sub.to_uppercase()).unwrap_or_default();
+            match (subcommand.as_str(), parts.len()) {
+                ("CREATE", 2) => Ok(Command::SessionCreate { ttl: None }),
+                ("CREATE", 3) => {
+                    let ttl = parts[2].parse::<u64>().ok()
+                        .filter(|seconds| (1..=MAX_SESSION_TTL.as_secs()).contains(seconds))
+                        .ok_or_else(|| format!("ERR session lifetime must be between 1 and {} seconds", MAX_SESSION_TTL.as_secs()))?;
+                    Ok(Command::SessionCreate { ttl: Some(ttl) })
+                },
+                ("AUTH", 3) => Ok(Command::SessionAuth { token: parts[2].to_string() }),
+                ("REVOKE", 3) => Ok(Command::SessionRevoke { token: parts[2].to_string() }),
+                ("CREATE" | "AUTH" | "REVOKE", _) => {
+                    Err(format!("ERR wrong number of arguments for 'session|{}' command", subcommand.to_lowercase()))
+                },
+                _ => Err(format!("ERR unknown SESSION subcommand '{}'", parts.get(1).unwrap_or(&""))),
+            }
+        },
+
+        "HELLO" => {
+            if parts.len() > 2 {
+                return Err("ERR wrong number of arguments for 'hello' command".to_string());
             }
-            Ok(Command::Auth { password: parts[1].to_string() })
+            let protover = match parts.get(1) {
+                Some(version) => Some(version.parse::<u8>()
+                    .map_err(|_| "ERR Protocol version is not an integer or out of range".to_string())?),
+                None => None,
+            };
+            Ok(Command::Hello { protover })
         },
 
         "INFO" => {
             Ok(Command::Info)
         },
 
+        "STAT" => {
+            if parts.len() < 2 {
+                return Err("ERR wrong number of arguments for 'stat' command".to_string());
+            }
+
+            match parts[1].to_uppercase().as_str() {
+                "HISTORY" => {
+                    let count = if parts.len() > 2 {
+                        match parts[2].parse::<usize>() {
+                            Ok(count) if count > 0 => count,
+                            _ => return Err("ERR value is out of range, must be positive".to_string()),
+                        }
+                    } else {
+                        60
+                    };
+                    Ok(Command::StatHistory { count })
+                },
+                "SIZES" => {
+                    if parts.len() > 3 {
+                        return Err("ERR wrong number of arguments for 'stat|sizes' command".to_string());
+                    }
+                    Ok(Command::StatSizes { command: parts.get(2).map(|command| command.to_string()) })
+                },
+                _ => Err(format!("ERR unknown STAT subcommand '{}'", parts[1])),
+            }
+        },
+
+        "DBSTATS" => {
+            match parts.get(1..).map(|rest| rest.iter().map(|part| part.to_uppercase()).collect::<Vec<_>>()) {
+                Some(rest) if rest == ["BY", "PREFIX"] => Ok(Command::DbStatsByPrefix),
+                _ => Err("ERR syntax error".to_string()),
+            }
+        },
+
+        "EXPIRED" => {
+            match parts.get(1).map(|sub| sub.to_uppercase()).as_deref() {
+                Some("READ") => {
+                    let after = parts.get(2)
+                        .ok_or_else(|| "ERR wrong number of arguments for 'expired|read' command".to_string())?
+                        .parse::<u64>()
+                        .map_err(|_| "ERR the id must be a non-negative integer".to_string())?;
+                    let count = match parts.get(3..) {
+                        Some([]) | None => DEFAULT_EXPIRED_READ_COUNT,
+                        Some([option, count]) if option.eq_ignore_ascii_case("COUNT") => {
+                            count.parse::<usize>().ok().filter(|count| *count > 0)
+                                .ok_or_else(|| "ERR COUNT must be a positive integer".to_string())?
+                        },
+                        Some(_) => return Err("ERR syntax error".to_string()),
+                    };
+                    Ok(Command::ExpiredRead { after, count })
+                },
+                Some("INFO") if parts.len() == 2 => Ok(Command::ExpiredInfo),
+                Some("INFO") => Err("ERR wrong number of arguments for 'expired|info' command".to_string()),
+                Some(_) => Err(format!("ERR unknown EXPIRED subcommand '{}'", parts[1])),
+                None => Err("ERR wrong number of arguments for 'expired' command".to_string()),
+            }
+        },
+
         "MEMORY" => {
-            Ok(Command::Memory)
+            match parts.get(1).map(|sub| sub.to_uppercase()) {
+                None => Ok(Command::Memory),
+                Some(sub) if sub == "STATS" => Ok(Command::MemoryStats),
+                Some(_) => Err(format!("ERR unknown MEMORY subcommand '{}'", parts[1])),
+            }
+        },
+
+        "SNAPSHOT" => {
+            if parts.len() != 2 {
+                return Err("ERR wrong number of arguments for 'snapshot' command".to_string());
+            }
+            match parts[1].to_uppercase().as_str() {
+                "BEGIN" => Ok(Command::SnapshotBegin),
+                "END" => Ok(Command::SnapshotEnd),
+                _ => Err(format!("ERR unknown SNAPSHOT subcommand '{}'", parts[1])),
+            }
+        },
+
+        "ATOMIC" => {
+            if parts.len() != 2 {
+                return Err("ERR wrong number of arguments for 'atomic' command".to_string());
+            }
+            let count = parts[1].parse::<usize>().ok().filter(|count| (1..=MAX_ATOMIC_COMMANDS).contains(count))
+                .ok_or_else(|| format!("ERR ATOMIC count must be between 1 and {}", MAX_ATOMIC_COMMANDS))?;
+            Ok(Command::Atomic { count })
+        },
+
+        "CLIENT" => {
+            if parts.len() < 2 {
+                return Err("ERR wrong number of arguments for 'client' command".to_string());
+            }
+            match parts[1].to_uppercase().as_str() {
+                "LIST" => match &parts[2..] {
+                    [] => Ok(Command::ClientList { kind: None }),
+                    [option, kind] if option.eq_ignore_ascii_case("TYPE") => match ClientType::from_string(kind) {
+                        Some(kind) => Ok(Command::ClientList { kind: Some(kind) }),
+                        None => Err(format!("ERR Unknown client type '{}'", kind)),
+                    },
+                    _ => Err("ERR syntax error".to_string()),
+                },
+                "ID" if parts.len() == 2 => Ok(Command::ClientId),
+                "ID" => Err("ERR wrong number of arguments for 'client|id' command".to_string()),
+                _ => Err(format!("ERR unknown CLIENT subcommand '{}'", parts[1])),
+            }
+        },
+
+        "OBJECT" => {
+            if parts.len() < 2 {
+                return Err("ERR wrong number of arguments for 'object' command".to_string());
+            }
+            match parts[1].to_uppercase().as_str() {
+                "ENCODING" => {
+                    if parts.len() != 3 {
+                        return Err("ERR wrong number of arguments for 'object|encoding' command".to_string());
+                    }
+                    Ok(Command::ObjectEncoding { key: parts[2].to_string() })
+                },
+                _ => Err(format!("ERR unknown OBJECT subcommand '{}'", parts[1])),
+            }
+        },
+
+        "DEBUG" => {
+            if parts.len() < 2 {
+                return Err("ERR wrong number of arguments for 'debug' command".to_string());
+            }
+            match parts[1].to_uppercase().as_str() {
+                "SET-RNG-SEED" => {
+                    if parts.len() != 3 {
+                        return Err("ERR wrong number of arguments for 'debug|set-rng-seed' command".to_string());
+                    }
+                    // RANDOM goes back to unseeded picks
+                    let seed = match parts[2].to_uppercase().as_str() {
+                        "RANDOM" => None,
+                        seed => Some(seed.parse::<u64>().map_err(|_| "ERR seed must be a non-negative integer or RANDOM".to_string())?),
+                    };
+                    Ok(Command::DebugSetRngSeed { seed })
+                },
+                "PERSISTENCE-BENCH" => {
+                    let ops = match parts.get(2..) {
+                        Some([]) | None => DEFAULT_BENCH_OPS,
+                        Some([ops]) => ops.parse::<usize>().ok()
+                            .filter(|ops| (1..=MAX_BENCH_OPS).contains(ops))
+                            .ok_or_else(|| format!("ERR ops must be between 1 and {}", MAX_BENCH_OPS))?,
+                        Some(_) => return Err("ERR wrong number of arguments for 'debug|persistence-bench' command".to_string()),
+                    };
+                    Ok(Command::DebugPersistenceBench { ops })
+                },
+                "PANIC" => {
+                    if parts.len() != 2 {
+                        return Err("ERR wrong number of arguments for 'debug|panic' command".to_string());
+                    }
+                    Ok(Command::DebugPanic)
+                },
+                _ => Err(format!("ERR unknown DEBUG subcommand '{}'", parts[1])),
+            }
         },
 
         "SHOWALL" => {
@@ -528,18 +1777,50 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
             }
 
             let file_path = parts[1].to_string();
-            let strategy = if parts.len() > 2 {
-                match parts[2].to_uppercase().as_str() {
-                    "OVERWRITE" => crate::commands::MergeStrategy::Overwrite,
-                    "SKIP" => crate::commands::MergeStrategy::Skip,
-                    "MERGE" => crate::commands::MergeStrategy::Merge,
-                    _ => return Err("ERR invalid merge strategy. Use OVERWRITE, SKIP, or MERGE".to_string()),
-                }
-            } else {
-                crate::commands::MergeStrategy::Overwrite
+            let mut options = &parts[2..];
+            let background = options.last().is_some_and(|option| option.eq_ignore_ascii_case("BACKGROUND"));
+            if background {
+                options = &options[..options.len() - 1];
+            }
+            let strategy = match options.first().map(|strategy| strategy.to_uppercase()).as_deref() {
+                Some("OVERWRITE") => crate::commands::MergeStrategy::Overwrite,
+                Some("SKIP") => crate::commands::MergeStrategy::Skip,
+                Some("MERGE") => crate::commands::MergeStrategy::Merge,
+                Some("KEYSPERSEC" | "BYTESPERSEC") | None => crate::commands::MergeStrategy::Overwrite,
+                Some(_) => return Err("ERR invalid merge strategy. Use OVERWRITE, SKIP, or MERGE".to_string()),
             };
+            if options.first().is_some_and(|option| !option.eq_ignore_ascii_case("KEYSPERSEC") && !option.eq_ignore_ascii_case("BYTESPERSEC")) {
+                options = &options[1..];
+            }
+
+            let mut throttle = Throttle::default();
+            for option in options.chunks(2) {
+                let limit = option.get(1).and_then(|limit| limit.parse::<u64>().ok()).filter(|limit| *limit > 0)
+                    .ok_or_else(|| "ERR KEYSPERSEC and BYTESPERSEC need a positive integer".to_string())?;
+                match option[0].to_uppercase().as_str() {
+                    "KEYSPERSEC" => throttle.keys_per_sec = Some(limit),
+                    "BYTESPERSEC" => throttle.bytes_per_sec = Some(limit),
+                    _ => return Err("ERR syntax error".to_string()),
+                }
+            }
 
-            Ok(Command::Merge { file_path, strategy })
+            Ok(Command::Merge { file_path, strategy, throttle, background })
+        },
+
+        "MIGRATION" => {
+            match parts.get(1).map(|sub| sub.to_uppercase()).as_deref() {
+                Some("STATUS") if parts.len() == 2 => Ok(Command::MigrationStatus),
+                Some("STATUS") => Err("ERR wrong number of arguments for 'migration|status' command".to_string()),
+                Some(_) => Err(format!("ERR unknown MIGRATION subcommand '{}'", parts[1])),
+                None => Err("ERR wrong number of arguments for 'migration' command".to_string()),
+            }
+        },
+
+        "READONLY" | "READWRITE" => {
+            if parts.len() != 1 {
+                return Err(format!("ERR wrong number of arguments for '{}' command", parts[0].to_lowercase()));
+            }
+            Ok(if cmd == "READONLY" { Command::ReadOnly } else { Command::ReadWrite })
         },
 
         "QUIT" => {
@@ -549,3 +1830,343 @@ pub fn parse_command(input: &str) -> Result<Command, String> {
         _ => Err(format!("ERR unknown command '{}'", cmd)),
     }
 }
+
+fn parse_retention(keyword: &str, value: &str) -> Result<u64, String> {
+    if keyword.to_uppercase() != "RETENTION" {
+        return Err("ERR syntax error".to_string());
+    }
+    value.parse::<u64>().map_err(|_| "ERR TSDB: invalid retention".to_string())
+}
+
+fn parse_aggregation(name: &str, bucket: &str) -> Result<(Aggregation, i64), String> {
+    let aggregation = Aggregation::from_string(name)
+        .ok_or_else(|| "ERR TSDB: unknown aggregation type".to_string())?;
+    match bucket.parse::<i64>() {
+        Ok(bucket_ms) if bucket_ms > 0 => Ok((aggregation, bucket_ms)),
+        _ => Err("ERR TSDB: invalid time bucket".to_string()),
+    }
+}
+
+fn parse_list_end(end: &str) -> Result<ListEnd, String> {
+    match end.to_uppercase().as_str() {
+        "LEFT" => Ok(ListEnd::Left),
+        "RIGHT" => Ok(ListEnd::Right),
+        _ => Err("ERR syntax error".to_string()),
+    }
+}
+
+// A relative TTL given in seconds, or milliseconds with `millis`, if it is positive and no
+// longer than MAX_TTL_MS
+fn expire_ttl(amount: &str, millis: bool) -> Option<Duration> {
+    amount.parse::<u64>().ok().filter(|amount| *amount > 0).and_then(|amount| ttl_within_range(amount, millis))
+}
+
+fn ttl_within_range(amount: u64, millis: bool) -> Option<Duration> {
+    let ms = if millis { amount } else { amount.checked_mul(1000)? };
+    (ms <= MAX_TTL_MS).then(|| Duration::from_millis(ms))
+}
+
+// Seconds, fractions allowed; zero blocks for good
+fn parse_block_timeout(timeout: &str) -> Result<Duration, String> {
+    match timeout.parse::<f64>() {
+        Ok(secs) if secs < 0.0 => Err("ERR timeout is negative".to_string()),
+        Ok(secs) => Duration::try_from_secs_f64(secs).map_err(|_| "ERR timeout is not a float or out of range".to_string()),
+        Err(_) => Err("ERR timeout is not a float or out of range".to_string()),
+    }
+}
+
+fn parse_bit_offset(offset: &str) -> Result<u64, String> {
+    offset.parse::<u64>().ok().filter(|offset| *offset <= MAX_BIT_OFFSET)
+        .ok_or_else(|| "ERR bit offset is not an integer or out of range".to_string())
+}
+
+fn parse_vector(components: &[&str]) -> Result<Vec<f32>, String> {
+    components.iter()
+        .map(|component| match component.parse::<f32>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => Err(format!("ERR invalid vector component '{}'", component)),
+        })
+        .collect()
+}
+
+// Parses the `FIELDS numfields field [field ...]` tail shared by the hash field TTL commands
+fn parse_fields_clause(parts: &[&str]) -> Result<Vec<String>, String> {
+    if parts.len() < 3 || parts[0].to_uppercase() != "FIELDS" {
+        return Err("ERR Mandatory argument FIELDS is missing or not at the right position".to_string());
+    }
+    match parts[1].parse::<usize>() {
+        Ok(count) if count > 0 && count == parts.len() - 2 => Ok(parts[2..].iter().map(|field| field.to_string()).collect()),
+        _ => Err("ERR Parameter `numFields` should be greater than 0 and match the provided number of fields".to_string()),
+    }
+}
+
+/// How replies are written to a connection. Each reply follows the request it answers: RESP
+/// arrays, as client libraries send, get RESP; inline commands typed into telnet or netcat get
+/// the reply as redis-cli would print it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplyFormat {
+    #[default]
+    Text,
+    Resp,
+}
+
+impl ReplyFormat {
+    /// Frames a reply, line ending included.
+    pub fn render(self, reply: &Reply) -> Vec<u8> {
+        match self {
+            ReplyFormat::Text => format!("{}\r\n", reply.to_text()).into_bytes(),
+            ReplyFormat::Resp => reply.encode(),
+        }
+    }
+
+    /// Frames an error. Parser errors lack the `(error) ` prefix executor errors carry, and
+    /// text clients have always seen them that way.
+    pub fn render_error(self, error: &str) -> Vec<u8> {
+        match self {
+            ReplyFormat::Text => format!("{}\r\n", error).into_bytes(),
+            ReplyFormat::Resp => Reply::error(error.strip_prefix("(error) ").unwrap_or(error)).encode(),
+        }
+    }
+
+    /// Frames a pub/sub message; RESP3 connections get it as a push.
+    pub fn render_push(self, reply: Reply, protocol: u8) -> Vec<u8> {
+        match (self, reply) {
+            (ReplyFormat::Resp, Reply::Array(items)) if protocol == 3 => Reply::Push(items).encode(),
+            (_, reply) => self.render(&reply),
+        }
+    }
+}
+
+/// A command's reply. RESP frames it as is; text clients get it as redis-cli prints it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    // A bulk string printed without quotes, such as INFO's sections
+    Verbatim(String),
+    Nil,
+    Array(Vec<Reply>),
+    // RESP3 out-of-band message, such as a pub/sub message on a HELLO 3 connection
+    Push(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn ok() -> Reply {
+        Reply::Simple("OK".to_string())
+    }
+
+    /// An error reply; `message` starts with its code, as in `ERR syntax error`.
+    pub fn error(message: impl Into<String>) -> Reply {
+        Reply::Error(message.into())
+    }
+
+    pub fn bulk(value: impl Into<String>) -> Reply {
+        Reply::Bulk(value.into())
+    }
+
+    /// An integer reply, saturating anything beyond the 64-bit range.
+    pub fn integer<T: TryInto<i64>>(value: T) -> Reply {
+        Reply::Integer(value.try_into().unwrap_or(i64::MAX))
+    }
+
+    /// An array of bulk strings.
+    pub fn bulks<S: Into<String>>(values: impl IntoIterator<Item = S>) -> Reply {
+        Reply::Array(values.into_iter().map(Reply::bulk).collect())
+    }
+
+    pub fn empty() -> Reply {
+        Reply::Array(Vec::new())
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Reply::Error(_))
+    }
+
+    /// The reply as redis-cli prints it: `OK`, `(integer) 3`, `"value"`, `(nil)`,
+    /// `(error) ...`, `(empty array)` and numbered arrays, nested items indented under their
+    /// number.
+    pub fn to_text(&self) -> String {
+        match self {
+            Reply::Simple(text) | Reply::Verbatim(text) => text.clone(),
+            Reply::Error(text) => format!("(error) {}", text),
+            Reply::Integer(integer) => format!("(integer) {}", integer),
+            Reply::Bulk(text) => format!("\"{}\"", text),
+            Reply::Nil => "(nil)".to_string(),
+            Reply::Array(items) | Reply::Push(items) if items.is_empty() => "(empty array)".to_string(),
+            Reply::Array(items) | Reply::Push(items) => items.iter().enumerate()
+                .map(|(i, item)| {
+                    let number = format!("{}) ", i + 1);
+                    let indented = item.to_text().replace('\n', &format!("\n{}", " ".repeat(number.len())));
+                    format!("{}{}", number, indented)
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Reads a reply in the text format, as captured from redis-cli: `OK`, `(integer) 3`,
+    /// `"value"`, `(nil)`, `(error) ...`, the `(empty ...)` markers and numbered arrays, nested
+    /// the way redis-cli indents them. Unquoted text spanning lines becomes a bulk string, since a
+    /// simple string cannot hold a line break. Values holding a line that looks like an array
+    /// item cannot be told apart, so replies are never built this way, only fixtures read.
+    pub fn from_text(text: &str) -> Reply {
+        if let Some(error) = text.strip_prefix("(error) ") {
+            return Reply::Error(error.replace(['\r', '\n'], " "));
+        }
+        if let Some(integer) = text.strip_prefix("(integer) ").and_then(|integer| integer.parse().ok()) {
+            return Reply::Integer(integer);
+        }
+        match text {
+            "(nil)" => return Reply::Nil,
+            "(empty array)" | "(empty list or set)" | "(empty set)" | "(empty hash)" | "(empty database)" => return Reply::Array(Vec::new()),
+            _ => {},
+        }
+        if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+            return Reply::Bulk(text[1..text.len() - 1].to_string());
+        }
+        if text.starts_with("1) ") {
+            return Reply::Array(split_items(text).iter().map(|item| Reply::from_text(item)).collect());
+        }
+        if text.contains(['\r', '\n']) {
+            Reply::Bulk(text.to_string())
+        } else {
+            Reply::Simple(text.to_string())
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            // Neither can hold a line break, which would end the frame early
+            Reply::Simple(text) => out.extend_from_slice(format!("+{}\r\n", text.replace(['\r', '\n'], " ")).as_bytes()),
+            Reply::Error(text) => out.extend_from_slice(format!("-{}\r\n", text.replace(['\r', '\n'], " ")).as_bytes()),
+            Reply::Integer(integer) => out.extend_from_slice(format!(":{}\r\n", integer).as_bytes()),
+            Reply::Bulk(text) | Reply::Verbatim(text) => {
+                out.extend_from_slice(format!("${}\r\n", text.len()).as_bytes());
+                out.extend_from_slice(text.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            },
+            Reply::Nil => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Array(items) | Reply::Push(items) => {
+                let marker = if matches!(self, Reply::Push(_)) { '>' } else { '*' };
+                out.extend_from_slice(format!("{}{}\r\n", marker, items.len()).as_bytes());
+                for item in items {
+                    item.encode_into(out);
+                }
+            },
+        }
+    }
+}
+
+// Splits a numbered array into its items' text. An item runs until the line numbered after it;
+// its continuation lines lose the indentation that lines them up under its first line.
+fn split_items(text: &str) -> Vec<String> {
+    let mut items: Vec<(usize, String)> = Vec::new();
+    for line in text.split('\n') {
+        let marker = format!("{}) ", items.len() + 1);
+        if let Some(first) = line.strip_prefix(&marker) {
+            items.push((marker.len(), first.to_string()));
+            continue;
+        }
+        match items.last_mut() {
+            Some((indent, item)) => {
+                let indentation = " ".repeat(*indent);
+                item.push('\n');
+                item.push_str(line.strip_prefix(&indentation).unwrap_or(line));
+            },
+            None => items.push((0, line.to_string())),
+        }
+    }
+    items.into_iter().map(|(_, item)| item).collect()
+}
+
+/// The wire format as a streaming codec, for proxies, test clients and tooling built on this
+/// crate: requests are decoded exactly as the server reads them, limits and partial frames
+/// included, and replies encoded as it writes them. It implements tokio_util's `Decoder` and
+/// `Encoder<Reply>`, so it can drive a `Framed` stream directly.
+#[derive(Debug, Default)]
+pub struct RespCodec {
+    decoder: RequestDecoder,
+}
+
+impl RespCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: ProtocolLimits) -> Self {
+        Self { decoder: RequestDecoder::with_limits(limits) }
+    }
+
+    /// Takes all of `src` and returns the next complete request, if any. A partial frame is
+    /// kept inside the codec, so calling again with an empty `src` may still return requests
+    /// that arrived together.
+    pub fn decode(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Frame>> {
+        if !src.is_empty() {
+            self.decoder.extend(&src.split());
+        }
+        Ok(self.decoder.decode())
+    }
+
+    /// `decode` at end of input: a trailing inline command without its line ending still
+    /// counts, a truncated array is an UnexpectedEof error.
+    pub fn decode_eof(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Frame>> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None => self.decoder.finish(),
+        }
+    }
+
+    pub fn encode(&mut self, reply: Reply, dst: &mut BytesMut) -> std::io::Result<()> {
+        dst.extend_from_slice(&reply.encode());
+        Ok(())
+    }
+
+    /// Writes a request the way it was received, as for a proxy passing it on: an array as a
+    /// RESP array of bulk strings, an inline command as its line.
+    pub fn encode_request(&mut self, request: &Request, dst: &mut BytesMut) -> std::io::Result<()> {
+        match request {
+            Request::Inline(line) => {
+                dst.extend_from_slice(line.as_bytes());
+                dst.extend_from_slice(b"\r\n");
+            },
+            Request::Array(args) => {
+                dst.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+                for arg in args {
+                    dst.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+                    dst.extend_from_slice(arg.as_bytes());
+                    dst.extend_from_slice(b"\r\n");
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+impl tokio_util::codec::Decoder for RespCodec {
+    type Item = Frame;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Frame>> {
+        RespCodec::decode(self, src)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Frame>> {
+        RespCodec::decode_eof(self, src)
+    }
+}
+
+impl tokio_util::codec::Encoder<Reply> for RespCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, reply: Reply, dst: &mut BytesMut) -> std::io::Result<()> {
+        RespCodec::encode(self, reply, dst)
+    }
+}
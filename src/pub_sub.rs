@@ -1,27 +1,243 @@
-    use std::collections::{HashMap, HashSet};
+    use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
     use std::sync::Arc;
+    use std::time::{Duration, Instant};
     use tokio::sync::{RwLock, mpsc};
     use regex::Regex;
+    use crate::protocol::Reply;
+    use crate::storage::now_millis;
 
     pub type PubSubManager = Arc<RwLock<PubSubState>>;
 
+    // Used when a reliable channel is switched off while it still has pending messages
+    pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(30);
+    // Message rates average over this many completed seconds, like INFO's instantaneous_* fields
+    const RATE_WINDOW: u64 = 5;
+    // Past this many channels with statistics, those with no subscribers and no recent
+    // messages are forgotten
+    const MAX_CHANNEL_STATS: usize = 10_000;
+
     #[derive(Debug, Clone)]
     pub enum PubSubMessage {
-        Message { channel: String, message: String },
+        // `id` is set on reliable channels; the subscriber must ACK it or it is delivered again.
+        // Channel and payload are shared between all recipients of one publish rather than copied.
+        Message { channel: Arc<str>, message: Arc<str>, id: Option<u64> },
         Subscribe { channel: String, count: usize },
         Unsubscribe { channel: String, count: usize },
         PSubscribe { pattern: String, count: usize },
         PUnsubscribe { pattern: String, count: usize },
     }
 
+    impl PubSubMessage {
+        // The push message as an array, the way redis-cli prints it
+        pub fn format_reply(&self) -> Reply {
+            let (kind, target, payload) = match self {
+                PubSubMessage::Message { channel, message, id: Some(id) } => {
+                    return Reply::Array(vec![Reply::bulk("message"), Reply::bulk(&**channel), Reply::bulk(&**message), Reply::integer(*id)]);
+                },
+                PubSubMessage::Message { channel, message, id: None } => ("message", &**channel, Reply::bulk(&**message)),
+                PubSubMessage::Subscribe { channel, count } => ("subscribe", channel.as_str(), Reply::integer(*count)),
+                PubSubMessage::Unsubscribe { channel, count } => ("unsubscribe", channel.as_str(), Reply::integer(*count)),
+                PubSubMessage::PSubscribe { pattern, count } => ("psubscribe", pattern.as_str(), Reply::integer(*count)),
+                PubSubMessage::PUnsubscribe { pattern, count } => ("punsubscribe", pattern.as_str(), Reply::integer(*count)),
+            };
+            Reply::Array(vec![Reply::bulk(kind), Reply::bulk(target), payload])
+        }
+    }
+
+    // Messages sent to one subscriber and how many it has yet to take. Sends happen under the
+    // state lock, but the connection takes messages without it, so these are atomics
+    #[derive(Debug, Default)]
+    struct QueueDepth {
+        queued: AtomicUsize,
+        peak: AtomicUsize,
+        sent: AtomicU64,
+    }
+
+    struct Subscriber {
+        sender: mpsc::UnboundedSender<PubSubMessage>,
+        depth: Arc<QueueDepth>,
+    }
+
+    impl Subscriber {
+        fn send(&self, message: PubSubMessage) -> bool {
+            if self.sender.send(message).is_err() {
+                return false;
+            }
+            let queued = self.depth.queued.fetch_add(1, Ordering::Relaxed) + 1;
+            self.depth.peak.fetch_max(queued, Ordering::Relaxed);
+            self.depth.sent.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+    }
+
+    /// The receiving end of a subscriber's messages. Taking a message through it is what
+    /// PUBSUB STATS counts as delivered, so a connection that falls behind shows a growing queue.
+    pub struct SubscriberQueue {
+        receiver: mpsc::UnboundedReceiver<PubSubMessage>,
+        depth: Arc<QueueDepth>,
+    }
+
+    impl SubscriberQueue {
+        pub async fn recv(&mut self) -> Option<PubSubMessage> {
+            let message = self.receiver.recv().await;
+            self.taken(message.is_some());
+            message
+        }
+
+        pub fn try_recv(&mut self) -> Result<PubSubMessage, mpsc::error::TryRecvError> {
+            let message = self.receiver.try_recv();
+            self.taken(message.is_ok());
+            message
+        }
+
+        fn taken(&self, taken: bool) {
+            if taken {
+                self.depth.queued.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Publishes on one channel, with a count per second for the last RATE_WINDOW seconds
+    #[derive(Debug, Default)]
+    struct ChannelActivity {
+        published: u64,
+        delivered: u64,
+        // (second, messages), oldest first
+        seconds: VecDeque<(u64, u64)>,
+    }
+
+    impl ChannelActivity {
+        fn record(&mut self, second: u64, recipients: usize) {
+            self.published += 1;
+            self.delivered += recipients as u64;
+            match self.seconds.back_mut() {
+                Some((latest, count)) if *latest == second => *count += 1,
+                _ => self.seconds.push_back((second, 1)),
+            }
+            while self.seconds.front().is_some_and(|(oldest, _)| oldest + RATE_WINDOW < second) {
+                self.seconds.pop_front();
+            }
+        }
+
+        // Messages per second over the completed seconds of the window
+        fn rate(&self, now: u64) -> f64 {
+            let start = now.saturating_sub(RATE_WINDOW);
+            let messages: u64 = self.seconds.iter()
+                .filter(|(second, _)| *second >= start && *second < now)
+                .map(|(_, count)| count)
+                .sum();
+            messages as f64 / RATE_WINDOW as f64
+        }
+
+        fn is_recent(&self, now: u64) -> bool {
+            self.seconds.back().is_some_and(|(latest, _)| latest + RATE_WINDOW >= now)
+        }
+    }
+
+    /// A channel as PUBSUB STATS reports it.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ChannelStats {
+        pub channel: String,
+        pub subscribers: usize,
+        pub published: u64,
+        // Copies handed to subscribers, pattern subscribers included
+        pub delivered: u64,
+        pub per_sec: f64,
+    }
+
+    /// A subscriber as PUBSUB STATS reports it.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SubscriberStats {
+        pub id: usize,
+        pub subscriptions: usize,
+        // Messages sent to it that its connection has not written out yet
+        pub queued: usize,
+        pub peak_queued: usize,
+        pub sent: u64,
+        pub pending: usize,
+    }
+
+    /// Opt-in per-channel buffer of recent messages that new subscribers can replay.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RetentionPolicy {
+        pub max_messages: usize,
+        pub max_age: Option<Duration>,
+    }
+
+    struct RetainedMessages {
+        policy: RetentionPolicy,
+        messages: VecDeque<(Instant, Arc<str>)>,
+    }
+
+    impl RetainedMessages {
+        fn trim(&mut self, now: Instant) {
+            while self.messages.len() > self.policy.max_messages {
+                self.messages.pop_front();
+            }
+            if let Some(max_age) = self.policy.max_age {
+                while self.messages.front().is_some_and(|(at, _)| now.duration_since(*at) > max_age) {
+                    self.messages.pop_front();
+                }
+            }
+        }
+    }
+
+    // A reliable-channel message awaiting ACK from one subscriber
+    struct PendingDelivery {
+        channel: Arc<str>,
+        message: Arc<str>,
+        redeliver_at: Instant,
+    }
+
+    // Subscriptions of a durable consumer (SUBSCRIBE ... CONSUMER name), kept across reconnects.
+    // While a connection holds it they live in `channels`/`patterns` like any other subscriber's.
+    #[derive(Default)]
+    struct DurableConsumer {
+        // Connection currently holding it, None while it is away
+        subscriber: Option<usize>,
+        channels: Vec<String>,
+        patterns: Vec<String>,
+        // When its connection went away; retained messages published since are replayed on resume
+        detached_at: Option<Instant>,
+        pending: BTreeMap<u64, PendingDelivery>,
+    }
+
+    /// A durable consumer as PUBSUB CONSUMERS reports it.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ConsumerInfo {
+        pub name: String,
+        pub attached: bool,
+        pub subscriptions: usize,
+        pub pending: usize,
+    }
+
     pub struct PubSubState {
         // Channel -> Set of subscriber IDs
         pub channels: HashMap<String, HashSet<usize>>,
         // Pattern -> Set of subscriber IDs
         pub patterns: HashMap<String, HashSet<usize>>,
-        // Subscriber ID -> Sender channel
-        pub subscribers: HashMap<usize, mpsc::UnboundedSender<PubSubMessage>>,
+        // Subscriber ID -> Sender channel and its queue depth
+        subscribers: HashMap<usize, Subscriber>,
+        // Channel -> publish counts, for PUBSUB STATS and INFO
+        activity: HashMap<String, ChannelActivity>,
+        published: u64,
+        // Channel -> retained recent messages, only for channels with a retention policy
+        retained: HashMap<String, RetainedMessages>,
+        // Reliable channel -> ACK timeout before redelivery
+        reliable: HashMap<String, Duration>,
+        // Subscriber ID -> unacknowledged messages by message ID
+        pending: HashMap<usize, BTreeMap<u64, PendingDelivery>>,
+        // Durable consumer name -> its subscriptions
+        consumers: HashMap<String, DurableConsumer>,
         next_subscriber_id: usize,
+        next_message_id: u64,
+    }
+
+    impl Default for PubSubState {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl PubSubState {
@@ -30,22 +246,36 @@
                 channels: HashMap::new(),
                 patterns: HashMap::new(),
                 subscribers: HashMap::new(),
+                activity: HashMap::new(),
+                published: 0,
+                retained: HashMap::new(),
+                reliable: HashMap::new(),
+                pending: HashMap::new(),
+                consumers: HashMap::new(),
                 next_subscriber_id: 1,
+                next_message_id: 1,
             }
         }
 
-        pub fn create_subscriber(&mut self) -> (usize, mpsc::UnboundedReceiver<PubSubMessage>) {
+        pub fn create_subscriber(&mut self) -> (usize, SubscriberQueue) {
             let id = self.next_subscriber_id;
             self.next_subscriber_id += 1;
 
-            let (tx, rx) = mpsc::unbounded_channel();
-            self.subscribers.insert(id, tx);
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let depth = Arc::new(QueueDepth::default());
+            self.subscribers.insert(id, Subscriber { sender, depth: Arc::clone(&depth) });
 
-            (id, rx)
+            (id, SubscriberQueue { receiver, depth })
+        }
+
+        pub fn has_subscriber(&self, subscriber_id: usize) -> bool {
+            self.subscribers.contains_key(&subscriber_id)
         }
 
         pub fn remove_subscriber(&mut self, subscriber_id: usize) {
+            self.detach_consumer(subscriber_id);
             self.subscribers.remove(&subscriber_id);
+            self.pending.remove(&subscriber_id);
 
             // Remove from all channels
             for subscribers in self.channels.values_mut() {
@@ -62,10 +292,108 @@
             self.patterns.retain(|_, subs| !subs.is_empty());
         }
 
+        /// Binds the durable consumer `name` to `subscriber_id`, creating it if needed. A consumer
+        /// that was away gets its channels and patterns back, the retained messages published
+        /// since it left and its unacknowledged messages; one still held by another connection
+        /// is taken over, as that connection is presumably dead. Returns the replies to send.
+        pub fn attach_consumer(&mut self, name: &str, subscriber_id: usize) -> Vec<PubSubMessage> {
+            let previous = self.consumers.get(name).and_then(|consumer| consumer.subscriber);
+            match previous {
+                Some(id) if id == subscriber_id => return Vec::new(),
+                Some(id) => {
+                    self.detach_consumer(id);
+                    // Nothing was missed while the old connection held it, so nothing is replayed
+                    if let Some(consumer) = self.consumers.get_mut(name) {
+                        consumer.detached_at = None;
+                    }
+                    self.remove_subscriber(id);
+                },
+                None => {},
+            }
+
+            let consumer = self.consumers.entry(name.to_string()).or_default();
+            consumer.subscriber = Some(subscriber_id);
+            let channels = std::mem::take(&mut consumer.channels);
+            let patterns = std::mem::take(&mut consumer.patterns);
+            let pending = std::mem::take(&mut consumer.pending);
+            let detached_at = consumer.detached_at.take();
+
+            let mut replies = Vec::new();
+            for channel in channels {
+                let count = self.subscribe(subscriber_id, channel.clone());
+                replies.push(PubSubMessage::Subscribe { channel: channel.clone(), count });
+                if let Some(since) = detached_at {
+                    for message in self.retained_since(&channel, since) {
+                        replies.push(PubSubMessage::Message { channel: Arc::from(channel.as_str()), message, id: None });
+                    }
+                }
+            }
+            for pattern in patterns {
+                let count = self.psubscribe(subscriber_id, pattern.clone());
+                replies.push(PubSubMessage::PSubscribe { pattern, count });
+            }
+            let now = Instant::now();
+            for (id, mut delivery) in pending {
+                replies.push(PubSubMessage::Message {
+                    channel: Arc::clone(&delivery.channel),
+                    message: Arc::clone(&delivery.message),
+                    id: Some(id),
+                });
+                delivery.redeliver_at = now + self.reliable.get(&*delivery.channel).copied().unwrap_or(DEFAULT_ACK_TIMEOUT);
+                self.pending.entry(subscriber_id).or_default().insert(id, delivery);
+            }
+            replies
+        }
+
+        // Moves the subscriptions of the durable consumer held by `subscriber_id`, if any, into
+        // its registry entry. A consumer left with no subscriptions is forgotten.
+        fn detach_consumer(&mut self, subscriber_id: usize) {
+            let name = match self.consumers.iter().find(|(_, consumer)| consumer.subscriber == Some(subscriber_id)) {
+                Some((name, _)) => name.clone(),
+                None => return,
+            };
+            let channels = self.subscribed_channels(subscriber_id);
+            let patterns = self.subscribed_patterns(subscriber_id);
+            if channels.is_empty() && patterns.is_empty() {
+                self.consumers.remove(&name);
+                return;
+            }
+            let pending = self.pending.remove(&subscriber_id).unwrap_or_default();
+            self.consumers.insert(name, DurableConsumer {
+                subscriber: None,
+                channels,
+                patterns,
+                detached_at: Some(Instant::now()),
+                pending,
+            });
+        }
+
+        /// Durable consumers sorted by name.
+        pub fn consumers(&self) -> Vec<ConsumerInfo> {
+            let mut consumers: Vec<ConsumerInfo> = self.consumers.iter()
+                .map(|(name, consumer)| match consumer.subscriber {
+                    Some(id) => ConsumerInfo {
+                        name: name.clone(),
+                        attached: true,
+                        subscriptions: self.get_subscription_count(id),
+                        pending: self.pending.get(&id).map_or(0, BTreeMap::len),
+                    },
+                    None => ConsumerInfo {
+                        name: name.clone(),
+                        attached: false,
+                        subscriptions: consumer.channels.len() + consumer.patterns.len(),
+                        pending: consumer.pending.len(),
+                    },
+                })
+                .collect();
+            consumers.sort_by(|a, b| a.name.cmp(&b.name));
+            consumers
+        }
+
         pub fn subscribe(&mut self, subscriber_id: usize, channel: String) -> usize {
             self.channels
                 .entry(channel.clone())
-                .or_insert_with(HashSet::new)
+                .or_default()
                 .insert(subscriber_id);
 
             self.get_subscription_count(subscriber_id)
@@ -78,6 +406,10 @@
                     self.channels.remove(channel);
                 }
             }
+            // Leaving a channel also gives up its unacknowledged messages
+            if let Some(pending) = self.pending.get_mut(&subscriber_id) {
+                pending.retain(|_, delivery| &*delivery.channel != channel);
+            }
 
             self.get_subscription_count(subscriber_id)
         }
@@ -85,7 +417,7 @@
         pub fn psubscribe(&mut self, subscriber_id: usize, pattern: String) -> usize {
             self.patterns
                 .entry(pattern.clone())
-                .or_insert_with(HashSet::new)
+                .or_default()
                 .insert(subscriber_id);
 
             self.get_subscription_count(subscriber_id)
@@ -102,33 +434,64 @@
             self.get_subscription_count(subscriber_id)
         }
 
-        pub fn publish(&self, channel: &str, message: String) -> usize {
+        pub fn publish(&mut self, channel: &str, message: String) -> usize {
+            let recipients = self.deliver(channel, message);
+            self.record_publish(channel, recipients, now_millis() / 1000);
+            recipients
+        }
+
+        fn record_publish(&mut self, channel: &str, recipients: usize, second: u64) {
+            self.published += 1;
+            if !self.activity.contains_key(channel) && self.activity.len() >= MAX_CHANNEL_STATS {
+                let channels = &self.channels;
+                self.activity.retain(|channel, activity| channels.contains_key(channel) || activity.is_recent(second));
+            }
+            self.activity.entry(channel.to_string()).or_default().record(second, recipients);
+        }
+
+        fn deliver(&mut self, channel: &str, message: String) -> usize {
             let mut recipient_count = 0;
+            let message: Arc<str> = Arc::from(message);
+            let channel_name: Arc<str> = Arc::from(channel);
 
-            // Send to exact channel subscribers
-            if let Some(subscribers) = self.channels.get(channel) {
-                for &subscriber_id in subscribers {
-                    if let Some(tx) = self.subscribers.get(&subscriber_id) {
-                        let _ = tx.send(PubSubMessage::Message {
-                            channel: channel.to_string(),
-                            message: message.clone(),
-                        });
-                        recipient_count += 1;
-                    }
-                }
+            if let Some(retained) = self.retained.get_mut(channel) {
+                let now = Instant::now();
+                retained.messages.push_back((now, Arc::clone(&message)));
+                retained.trim(now);
             }
 
-            // Send to pattern subscribers
+            // Exact subscribers first, then pattern subscribers (a client matching both gets it twice)
+            let mut recipients: Vec<usize> = self.channels.get(channel)
+                .map(|subscribers| subscribers.iter().copied().collect())
+                .unwrap_or_default();
             for (pattern, subscribers) in &self.patterns {
                 if pattern_matches(pattern, channel) {
-                    for &subscriber_id in subscribers {
-                        if let Some(tx) = self.subscribers.get(&subscriber_id) {
-                            let _ = tx.send(PubSubMessage::Message {
-                                channel: channel.to_string(),
-                                message: message.clone(),
-                            });
-                            recipient_count += 1;
-                        }
+                    recipients.extend(subscribers.iter().copied());
+                }
+            }
+
+            let ack_timeout = self.reliable.get(channel).copied();
+            let id = ack_timeout.map(|_| {
+                let id = self.next_message_id;
+                self.next_message_id += 1;
+                id
+            });
+
+            for subscriber_id in recipients {
+                if let Some(subscriber) = self.subscribers.get(&subscriber_id) {
+                    subscriber.send(PubSubMessage::Message {
+                        channel: Arc::clone(&channel_name),
+                        message: Arc::clone(&message),
+                        id,
+                    });
+                    recipient_count += 1;
+
+                    if let (Some(id), Some(timeout)) = (id, ack_timeout) {
+                        self.pending.entry(subscriber_id).or_default().insert(id, PendingDelivery {
+                            channel: Arc::clone(&channel_name),
+                            message: Arc::clone(&message),
+                            redeliver_at: Instant::now() + timeout,
+                        });
                     }
                 }
             }
@@ -136,6 +499,190 @@
             recipient_count
         }
 
+        /// Marks a channel reliable with the given ACK timeout, or (with `None`) fire-and-forget again.
+        pub fn set_reliable(&mut self, channel: &str, ack_timeout: Option<Duration>) {
+            match ack_timeout {
+                Some(timeout) => {
+                    self.reliable.insert(channel.to_string(), timeout);
+                },
+                None => {
+                    self.reliable.remove(channel);
+                },
+            }
+        }
+
+        /// Acknowledges messages for a subscriber, returning how many were pending.
+        pub fn ack(&mut self, subscriber_id: usize, ids: &[u64]) -> usize {
+            let pending = match self.pending.get_mut(&subscriber_id) {
+                Some(pending) => pending,
+                None => return 0,
+            };
+            let acked = ids.iter().filter(|id| pending.remove(id).is_some()).count();
+            if pending.is_empty() {
+                self.pending.remove(&subscriber_id);
+            }
+            acked
+        }
+
+        /// Re-sends every pending message whose ACK timeout has passed, returning how many.
+        pub fn redeliver_due(&mut self, now: Instant) -> usize {
+            let mut redelivered = 0;
+            for (subscriber_id, pending) in &mut self.pending {
+                let subscriber = match self.subscribers.get(subscriber_id) {
+                    Some(subscriber) => subscriber,
+                    None => continue,
+                };
+                for (id, delivery) in pending.iter_mut() {
+                    if delivery.redeliver_at > now {
+                        continue;
+                    }
+                    subscriber.send(PubSubMessage::Message {
+                        channel: Arc::clone(&delivery.channel),
+                        message: Arc::clone(&delivery.message),
+                        id: Some(*id),
+                    });
+                    let timeout = self.reliable.get(&*delivery.channel).copied().unwrap_or(DEFAULT_ACK_TIMEOUT);
+                    delivery.redeliver_at = now + timeout;
+                    redelivered += 1;
+                }
+            }
+            redelivered
+        }
+
+        /// Unacknowledged message count per subscriber (sorted by ID), optionally for one channel.
+        pub fn pending_counts(&self, channel: Option<&str>) -> Vec<(usize, usize)> {
+            let mut counts: Vec<(usize, usize)> = self.pending.iter()
+                .map(|(subscriber_id, pending)| {
+                    let count = pending.values()
+                        .filter(|delivery| channel.is_none_or(|channel| &*delivery.channel == channel))
+                        .count();
+                    (*subscriber_id, count)
+                })
+                .filter(|(_, count)| *count > 0)
+                .collect();
+            counts.sort();
+            counts
+        }
+
+        /// Channels that saw messages, busiest first by recent rate and then by total.
+        pub fn channel_stats(&self) -> Vec<ChannelStats> {
+            self.channel_stats_at(now_millis() / 1000)
+        }
+
+        fn channel_stats_at(&self, now: u64) -> Vec<ChannelStats> {
+            let mut stats: Vec<ChannelStats> = self.activity.iter()
+                .map(|(channel, activity)| ChannelStats {
+                    channel: channel.clone(),
+                    subscribers: self.get_channel_subscribers(channel),
+                    published: activity.published,
+                    delivered: activity.delivered,
+                    per_sec: activity.rate(now),
+                })
+                .collect();
+            stats.sort_by(|a, b| b.per_sec.total_cmp(&a.per_sec)
+                .then_with(|| b.published.cmp(&a.published))
+                .then_with(|| a.channel.cmp(&b.channel)));
+            stats
+        }
+
+        /// Connected subscribers, the one furthest behind first.
+        pub fn subscriber_stats(&self) -> Vec<SubscriberStats> {
+            let mut stats: Vec<SubscriberStats> = self.subscribers.iter()
+                .map(|(id, subscriber)| SubscriberStats {
+                    id: *id,
+                    subscriptions: self.get_subscription_count(*id),
+                    queued: subscriber.depth.queued.load(Ordering::Relaxed),
+                    peak_queued: subscriber.depth.peak.load(Ordering::Relaxed),
+                    sent: subscriber.depth.sent.load(Ordering::Relaxed),
+                    pending: self.pending.get(id).map_or(0, BTreeMap::len),
+                })
+                .collect();
+            stats.sort_by(|a, b| b.queued.cmp(&a.queued).then_with(|| a.id.cmp(&b.id)));
+            stats
+        }
+
+        /// The # Pubsub section of INFO.
+        pub fn render_info(&self) -> String {
+            let now = now_millis() / 1000;
+            let busiest = self.channel_stats_at(now).into_iter().next();
+            let subscribers = self.subscriber_stats();
+            let deepest = subscribers.first().filter(|subscriber| subscriber.queued > 0);
+            format!(
+                "pubsub_channels:{}\npubsub_patterns:{}\npubsub_subscribers:{}\npubsub_messages_published:{}\npubsub_messages_per_sec:{:.2}\npubsub_busiest_channel:{}\npubsub_queued_messages:{}\npubsub_max_queued:{}\npubsub_max_queued_subscriber:{}",
+                self.channels.len(),
+                self.patterns.len(),
+                self.subscribers.len(),
+                self.published,
+                self.activity.values().map(|activity| activity.rate(now)).sum::<f64>(),
+                busiest.map_or_else(|| "none".to_string(), |channel| channel.channel),
+                subscribers.iter().map(|subscriber| subscriber.queued).sum::<usize>(),
+                deepest.map_or(0, |subscriber| subscriber.queued),
+                deepest.map_or_else(|| "none".to_string(), |subscriber| subscriber.id.to_string())
+            )
+        }
+
+        /// Enables, replaces or (with `None`) removes the retention buffer of a channel.
+        pub fn set_retention(&mut self, channel: &str, policy: Option<RetentionPolicy>) {
+            match policy {
+                Some(policy) => {
+                    let retained = self.retained.entry(channel.to_string()).or_insert_with(|| RetainedMessages {
+                        policy,
+                        messages: VecDeque::new(),
+                    });
+                    retained.policy = policy;
+                    retained.trim(Instant::now());
+                },
+                None => {
+                    self.retained.remove(channel);
+                },
+            }
+        }
+
+        pub fn retention(&self, channel: &str) -> Option<RetentionPolicy> {
+            self.retained.get(channel).map(|retained| retained.policy)
+        }
+
+        /// Up to `count` of the most recent retained messages of `channel`, oldest first.
+        pub fn replay(&mut self, channel: &str, count: usize) -> Vec<Arc<str>> {
+            match self.retained.get_mut(channel) {
+                Some(retained) => {
+                    retained.trim(Instant::now());
+                    let skip = retained.messages.len().saturating_sub(count);
+                    retained.messages.iter().skip(skip).map(|(_, message)| Arc::clone(message)).collect()
+                },
+                None => Vec::new(),
+            }
+        }
+
+        // Retained messages of `channel` published after `since`, oldest first
+        fn retained_since(&mut self, channel: &str, since: Instant) -> Vec<Arc<str>> {
+            match self.retained.get_mut(channel) {
+                Some(retained) => {
+                    retained.trim(Instant::now());
+                    retained.messages.iter().filter(|(at, _)| *at > since).map(|(_, message)| Arc::clone(message)).collect()
+                },
+                None => Vec::new(),
+            }
+        }
+
+        pub fn subscribed_channels(&self, subscriber_id: usize) -> Vec<String> {
+            self.channels.iter()
+                .filter(|(_, subscribers)| subscribers.contains(&subscriber_id))
+                .map(|(channel, _)| channel.clone())
+                .collect()
+        }
+
+        pub fn subscribed_patterns(&self, subscriber_id: usize) -> Vec<String> {
+            self.patterns.iter()
+                .filter(|(_, subscribers)| subscribers.contains(&subscriber_id))
+                .map(|(pattern, _)| pattern.clone())
+                .collect()
+        }
+
+        pub fn subscription_count(&self, subscriber_id: usize) -> usize {
+            self.get_subscription_count(subscriber_id)
+        }
+
         fn get_subscription_count(&self, subscriber_id: usize) -> usize {
             let mut count = 0;
 
@@ -204,4 +751,134 @@
             assert!(pattern_matches("news*", "news"));
             assert!(pattern_matches("news*", "newsletter"));
         }
+
+        #[test]
+        fn test_retention_replay() {
+            let mut state = PubSubState::new();
+            state.publish("news", "before retention".to_string());
+            state.set_retention("news", Some(RetentionPolicy { max_messages: 2, max_age: None }));
+            for message in ["one", "two", "three"] {
+                state.publish("news", message.to_string());
+            }
+
+            let replayed: Vec<String> = state.replay("news", 10).iter().map(|message| message.to_string()).collect();
+            assert_eq!(replayed, vec!["two", "three"]);
+            assert_eq!(state.replay("news", 1).len(), 1);
+            assert!(state.replay("sports", 10).is_empty());
+
+            state.set_retention("news", None);
+            assert!(state.replay("news", 10).is_empty());
+        }
+
+        #[test]
+        fn test_fan_out_shares_payload() {
+            let mut state = PubSubState::new();
+            let mut receivers = Vec::new();
+            for _ in 0..100 {
+                let (subscriber_id, rx) = state.create_subscriber();
+                state.subscribe(subscriber_id, "hot".to_string());
+                receivers.push(rx);
+            }
+
+            assert_eq!(state.publish("hot", "x".repeat(1024)), 100);
+            let payloads: Vec<Arc<str>> = receivers.iter_mut()
+                .map(|rx| match rx.try_recv() {
+                    Ok(PubSubMessage::Message { message, .. }) => message,
+                    other => panic!("expected a message, got {:?}", other),
+                })
+                .collect();
+            assert!(payloads.iter().all(|payload| Arc::ptr_eq(payload, &payloads[0])));
+        }
+
+        #[test]
+        fn test_reliable_redelivery_until_ack() {
+            let mut state = PubSubState::new();
+            let (subscriber_id, mut rx) = state.create_subscriber();
+            state.subscribe(subscriber_id, "orders".to_string());
+            state.set_reliable("orders", Some(Duration::from_millis(50)));
+
+            state.publish("orders", "order-1".to_string());
+            let id = match rx.try_recv() {
+                Ok(PubSubMessage::Message { id: Some(id), .. }) => id,
+                other => panic!("expected a reliable message, got {:?}", other),
+            };
+            assert_eq!(state.pending_counts(None), vec![(subscriber_id, 1)]);
+
+            assert_eq!(state.redeliver_due(Instant::now()), 0);
+            assert_eq!(state.redeliver_due(Instant::now() + Duration::from_millis(60)), 1);
+            assert!(matches!(rx.try_recv(), Ok(PubSubMessage::Message { id: Some(again), .. }) if again == id));
+
+            assert_eq!(state.ack(subscriber_id, &[id, id + 100]), 1);
+            assert!(state.pending_counts(None).is_empty());
+        }
+
+        #[test]
+        fn test_durable_consumer_resumes_after_reconnect() {
+            let mut state = PubSubState::new();
+            state.set_retention("orders", Some(RetentionPolicy { max_messages: 10, max_age: None }));
+            state.publish("orders", "before".to_string());
+
+            let (first, _rx) = state.create_subscriber();
+            state.attach_consumer("worker", first);
+            state.subscribe(first, "orders".to_string());
+            state.psubscribe(first, "audit.*".to_string());
+            state.remove_subscriber(first);
+            assert!(state.channels.is_empty());
+            state.publish("orders", "while away".to_string());
+
+            let (second, _rx) = state.create_subscriber();
+            let replies: Vec<String> = state.attach_consumer("worker", second).iter().map(|message| message.format_reply().to_text()).collect();
+            assert_eq!(replies, vec![
+                "1) \"subscribe\"\n2) \"orders\"\n3) (integer) 1",
+                "1) \"message\"\n2) \"orders\"\n3) \"while away\"",
+                "1) \"psubscribe\"\n2) \"audit.*\"\n3) (integer) 2",
+            ]);
+            assert_eq!(state.publish("audit.login", "x".to_string()), 1);
+        }
+
+        #[test]
+        fn test_stats_show_rates_and_queue_depth() {
+            let mut state = PubSubState::new();
+            let (fast, mut fast_rx) = state.create_subscriber();
+            let (slow, _slow_rx) = state.create_subscriber();
+            state.subscribe(fast, "news".to_string());
+            state.subscribe(slow, "news".to_string());
+            state.psubscribe(slow, "n*".to_string());
+
+            for second in [100, 100, 101, 104, 106] {
+                let recipients = state.deliver("news", "x".to_string());
+                state.record_publish("news", recipients, second);
+            }
+            state.record_publish("quiet", 0, 90);
+            while fast_rx.try_recv().is_ok() {}
+
+            // 101 and 104 fall in the five seconds before 106; 106 itself is not complete
+            let channels = state.channel_stats_at(106);
+            assert_eq!(channels[0], ChannelStats { channel: "news".to_string(), subscribers: 2, published: 5, delivered: 15, per_sec: 0.4 });
+            assert_eq!((channels[1].channel.as_str(), channels[1].per_sec), ("quiet", 0.0));
+
+            let subscribers = state.subscriber_stats();
+            assert_eq!(subscribers[0], SubscriberStats { id: slow, subscriptions: 2, queued: 10, peak_queued: 10, sent: 10, pending: 0 });
+            assert_eq!((subscribers[1].id, subscribers[1].queued, subscribers[1].peak_queued), (fast, 0, 5));
+            assert!(state.render_info().contains(&format!("pubsub_max_queued:10\npubsub_max_queued_subscriber:{}", slow)));
+        }
+
+        #[test]
+        fn test_durable_consumer_taken_over_or_forgotten() {
+            let mut state = PubSubState::new();
+            let (first, mut first_rx) = state.create_subscriber();
+            state.attach_consumer("worker", first);
+            state.subscribe(first, "orders".to_string());
+
+            // A reconnect before the old connection is noticed takes the consumer over
+            let (second, _rx) = state.create_subscriber();
+            assert_eq!(state.attach_consumer("worker", second).len(), 1);
+            assert_eq!(state.subscribed_channels(second), vec!["orders"]);
+            assert!(first_rx.try_recv().is_err() && !state.has_subscriber(first));
+
+            // Unsubscribing from everything ends it
+            state.unsubscribe(second, "orders");
+            state.remove_subscriber(second);
+            assert!(state.consumers().is_empty());
+        }
     }
@@ -0,0 +1,137 @@
+// Caps on write commands per second, for an instance fronting a downstream that cannot take
+// bursts. Each cap is a token bucket that refills at the rate and holds one second's worth, so a
+// client can burst up to the rate after a quiet spell but not sustain more.
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    per_sec: u32,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: u32) -> Self {
+        Self { per_sec, tokens: per_sec as f64, refilled_at: Instant::now() }
+    }
+
+    pub fn per_sec(&self) -> u32 {
+        self.per_sec
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec as f64).min(self.per_sec as f64);
+        self.refilled_at = now;
+    }
+
+    /// Whether an operation may run at `now` without taking its token.
+    pub fn allows(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    /// Takes a token for an operation at `now`, or returns false if the cap is used up.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        if !self.allows(now) {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// The write caps configured for the server. Zero means no cap.
+#[derive(Debug, Clone, Default)]
+pub struct WriteRateLimit {
+    // One bucket every connection draws from
+    global: Option<Arc<Mutex<RateLimiter>>>,
+    per_client: Option<u32>,
+}
+
+impl WriteRateLimit {
+    pub fn new(global: Option<u32>, per_client: Option<u32>) -> Self {
+        Self {
+            global: global.filter(|rate| *rate > 0).map(|rate| Arc::new(Mutex::new(RateLimiter::new(rate)))),
+            per_client: per_client.filter(|rate| *rate > 0),
+        }
+    }
+
+    /// The caps as seen by one connection, with a bucket of its own for the per-client cap.
+    pub fn for_client(&self) -> ClientWriteLimit {
+        ClientWriteLimit { global: self.global.clone(), own: self.per_client.map(RateLimiter::new) }
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientWriteLimit {
+    global: Option<Arc<Mutex<RateLimiter>>>,
+    own: Option<RateLimiter>,
+}
+
+impl ClientWriteLimit {
+    pub fn is_enabled(&self) -> bool {
+        self.global.is_some() || self.own.is_some()
+    }
+
+    /// Takes a token from each cap for one write, or none of them if any is used up.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        // A client over its own cap must not use up the shared one
+        if self.own.as_mut().is_some_and(|own| !own.allows(now)) {
+            return false;
+        }
+        if let Some(global) = &self.global {
+            // The bucket is left consistent at every step, so a poisoned lock is still usable
+            let mut global = global.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !global.try_acquire(now) {
+                return false;
+            }
+        }
+        if let Some(own) = &mut self.own {
+            own.try_acquire(now);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bursts_up_to_the_rate_then_refills() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(10);
+        limiter.refilled_at = start;
+        assert_eq!((0..15).filter(|_| limiter.try_acquire(start)).count(), 10);
+        assert!(!limiter.try_acquire(start));
+
+        // A tenth of a second brings back one write
+        assert!(limiter.try_acquire(start + Duration::from_millis(100)));
+        assert!(!limiter.try_acquire(start + Duration::from_millis(100)));
+
+        // Idle time never banks more than one second's worth
+        let later = start + Duration::from_secs(60);
+        assert_eq!((0..100).filter(|_| limiter.try_acquire(later)).count(), 10);
+    }
+
+    #[test]
+    fn test_client_cap_does_not_use_up_the_global_cap() {
+        let now = Instant::now();
+        let limit = WriteRateLimit::new(Some(5), Some(2));
+        let (mut busy, mut quiet) = (limit.for_client(), limit.for_client());
+        assert!(busy.is_enabled());
+        assert_eq!((0..10).filter(|_| busy.try_acquire_at(now)).count(), 2);
+        // The busy client's refused writes left the rest of the global cap to others
+        assert_eq!((0..10).filter(|_| quiet.try_acquire_at(now)).count(), 2);
+        assert_eq!((0..10).filter(|_| limit.for_client().try_acquire_at(now)).count(), 1);
+
+        assert!(!WriteRateLimit::new(Some(0), None).for_client().is_enabled());
+    }
+}
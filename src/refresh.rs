@@ -0,0 +1,124 @@
+// Refresh-ahead for embedders: a handler registered through the library API is called for each
+// key shortly before it expires, and the value it returns replaces the key's value and restarts
+// its TTL. Keys the handler declines simply expire.
+use crate::data_types::RedisValue;
+use crate::shared::Database;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+
+// How often keys are checked for entering their lead time
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What a refresh handler returns for a key: its new value and the TTL to give it.
+#[derive(Debug, Clone)]
+pub struct Refreshed {
+    pub value: RedisValue,
+    pub ttl: Duration,
+}
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Option<Refreshed>> + Send>>;
+
+#[derive(Clone)]
+pub struct RefreshConfig {
+    // The handler is called once a key has this long or less left to live
+    pub lead_time: Duration,
+    handler: Arc<dyn Fn(String) -> HandlerFuture + Send + Sync>,
+}
+
+impl std::fmt::Debug for RefreshConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefreshConfig").field("lead_time", &self.lead_time).finish_non_exhaustive()
+    }
+}
+
+impl RefreshConfig {
+    /// `handler` receives the key and returns its fresh value, or None to let it expire.
+    pub fn new<F, Fut>(lead_time: Duration, handler: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<Refreshed>> + Send + 'static,
+    {
+        Self { lead_time, handler: Arc::new(move |key| Box::pin(handler(key))) }
+    }
+}
+
+/// Runs `config`'s handler against `database` until the returned task is aborted. The handler
+/// is called at most once per key and deadline, without the database lock held, and its result
+/// is only applied if the key still has the deadline it had when the handler was called; a key
+/// rewritten, deleted or expired in the meantime keeps what happened to it.
+pub fn spawn_refresher(database: Database, config: RefreshConfig) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut asked: HashMap<String, Instant> = HashMap::new();
+        let mut interval = interval(REFRESH_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let due = database.read().await.expiring_within(config.lead_time, now);
+            asked.retain(|_, deadline| *deadline > now);
+
+            for (key, deadline) in due {
+                if asked.get(&key) == Some(&deadline) {
+                    continue;
+                }
+                asked.insert(key.clone(), deadline);
+                let database = Arc::clone(&database);
+                let refresh = (config.handler)(key.clone());
+                tokio::spawn(async move {
+                    let Some(refreshed) = refresh.await else { return };
+                    let mut db = database.write().await;
+                    if db.expires.get(&key) == Some(&deadline) && Instant::now() < deadline {
+                        if let Err(e) = db.set_with_expiry(key.clone(), refreshed.value, refreshed.ttl) {
+                            eprintln!("Failed to refresh {}: {}", key, e);
+                        }
+                    }
+                });
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::create_database;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_handler_refreshes_key_before_it_expires() {
+        let database = create_database();
+        database.write().await.set_with_expiry("k".to_string(), RedisValue::String("old".to_string()), Duration::from_millis(200)).unwrap();
+        let config = RefreshConfig::new(Duration::from_millis(150), |_| async {
+            Some(Refreshed { value: RedisValue::String("new".to_string()), ttl: Duration::from_secs(60) })
+        });
+        let refresher = spawn_refresher(database.clone(), config);
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        refresher.abort();
+        let mut db = database.write().await;
+        assert!(matches!(db.get("k"), Some(RedisValue::String(s)) if s == "new"));
+        assert!(db.ttl("k").unwrap() > Duration::from_secs(50));
+    }
+
+    #[tokio::test]
+    async fn test_declined_key_expires_after_one_call() {
+        let database = create_database();
+        database.write().await.set_with_expiry("k".to_string(), RedisValue::String("v".to_string()), Duration::from_millis(200)).unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&calls);
+        let config = RefreshConfig::new(Duration::from_millis(150), move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            async { None }
+        });
+        let refresher = spawn_refresher(database.clone(), config);
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        refresher.abort();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(database.write().await.get("k").is_none());
+    }
+}
@@ -0,0 +1,502 @@
+// Request framing. Client libraries send every command as a RESP2 array of bulk strings,
+//   *<count>\r\n$<length>\r\n<bytes>\r\n ...
+// so arguments may hold spaces or newlines. A line not starting with * is an inline command,
+// split on whitespace, as typed into telnet or netcat.
+use crate::command_renames::CommandRenames;
+use crate::commands::Command;
+use crate::protocol::{quote_arg, Reply, ReplyFormat};
+use std::borrow::Cow;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Inline(String),
+    Array(Vec<String>),
+}
+
+impl Request {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Request::Inline(line) => line.trim().is_empty(),
+            Request::Array(args) => args.is_empty(),
+        }
+    }
+
+    /// The command name as sent, before renames are applied.
+    pub fn name(&self) -> &str {
+        match self {
+            Request::Inline(line) => line.split_whitespace().next().unwrap_or_default().trim_matches(['"', '\'']),
+            Request::Array(args) => args.first().map_or("", String::as_str),
+        }
+    }
+
+    /// The request as one line, for logs and captures. Array arguments are quoted where needed,
+    /// so the line parses back to the same arguments.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Request::Inline(line) => Cow::Borrowed(line.trim()),
+            Request::Array(args) => Cow::Owned(args.iter().map(|arg| quote_arg(arg)).collect::<Vec<_>>().join(" ")),
+        }
+    }
+
+    pub fn parse(&self, renames: &CommandRenames) -> Result<Command, String> {
+        match self {
+            Request::Inline(line) => renames.parse(line.trim()),
+            Request::Array(args) => renames.parse_args(args),
+        }
+    }
+}
+
+/// Why a request was not run. A malformed frame leaves the stream out of step, so the
+/// connection is closed after the reply; text that is not UTF-8 was read in full and the
+/// connection carries on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BadRequest {
+    Malformed(String),
+    NotUtf8,
+}
+
+impl BadRequest {
+    pub fn message(&self) -> String {
+        match self {
+            BadRequest::Malformed(detail) => format!("ERR Protocol error: {}", detail),
+            BadRequest::NotUtf8 => "ERR invalid UTF-8 in request, keys and values must be text".to_string(),
+        }
+    }
+
+    pub fn reply(&self) -> Reply {
+        Reply::error(self.message())
+    }
+
+    pub fn closes_connection(&self) -> bool {
+        matches!(self, BadRequest::Malformed(_))
+    }
+}
+
+/// The element count of an array header such as `*3`, or None when `line` is an inline command.
+/// A null array (`*-1`) counts as empty.
+pub fn array_len(line: &[u8]) -> Option<Result<usize, BadRequest>> {
+    let count = line.strip_prefix(b"*")?;
+    Some(parse_number::<i64>(count).map(|count| count.max(0) as usize).ok_or_else(|| BadRequest::Malformed("invalid multibulk length".to_string())))
+}
+
+/// The length announced by a bulk string header such as `$5`.
+pub fn bulk_len(line: &[u8]) -> Result<usize, BadRequest> {
+    let length = line.strip_prefix(b"$").ok_or_else(|| {
+        BadRequest::Malformed(format!("expected '$', got '{}'", line.first().map_or(' ', |b| *b as char)))
+    })?;
+    parse_number(length).ok_or_else(|| BadRequest::Malformed("invalid bulk length".to_string()))
+}
+
+fn parse_number<T: std::str::FromStr>(digits: &[u8]) -> Option<T> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// A request read off the connection, or why it was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub request: Result<Request, BadRequest>,
+    // Bytes the request took on the wire
+    pub size: usize,
+    // Whether it was sent as a RESP array rather than an inline command
+    pub array: bool,
+}
+
+impl Frame {
+    /// RESP arrays get RESP replies; inline commands get the text format.
+    pub fn reply_format(&self) -> ReplyFormat {
+        if self.array { ReplyFormat::Resp } else { ReplyFormat::Text }
+    }
+}
+
+/// Caps on what one request can make the server buffer. Going over one is a protocol error that
+/// closes the connection, as in Redis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolLimits {
+    // Longest inline command, and longest header line within an array
+    pub max_inline_len: usize,
+    // Largest bulk string argument, Redis' proto-max-bulk-len
+    pub max_bulk_len: usize,
+    // Most arguments in one array
+    pub max_multibulk_len: usize,
+}
+
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        Self { max_inline_len: 64 * 1024, max_bulk_len: 512 * 1024 * 1024, max_multibulk_len: 1024 * 1024 }
+    }
+}
+
+// The line at the start of `input` without its line ending, and the bytes it takes with it
+fn split_line(input: &[u8]) -> Option<(&[u8], usize)> {
+    let end = input.iter().position(|b| *b == b'\n')?;
+    let line = &input[..end];
+    Some((line.strip_suffix(b"\r").unwrap_or(line), end + 1))
+}
+
+/// Turns bytes into requests however they were split into reads: a read may hold several
+/// pipelined requests, or part of one, which is kept until the rest arrives.
+#[derive(Debug, Default)]
+pub struct RequestDecoder {
+    buffer: Vec<u8>,
+    // Bytes at the front of `buffer` already decoded
+    start: usize,
+    // An array whose arguments are still arriving; those decoded so far are not parsed again
+    partial: Option<PartialArray>,
+    limits: ProtocolLimits,
+}
+
+#[derive(Debug)]
+struct PartialArray {
+    remaining: usize,
+    args: Vec<String>,
+    not_utf8: bool,
+    size: usize,
+}
+
+impl RequestDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: ProtocolLimits) -> Self {
+        Self { limits, ..Self::default() }
+    }
+
+    // A line still missing its line ending once it is over the limit is refused rather than
+    // buffered further
+    fn line_too_long(&self, what: &str) -> Option<BadRequest> {
+        (self.buffer.len() - self.start > self.limits.max_inline_len).then(|| BadRequest::Malformed(format!("too big {}", what)))
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.compact();
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Whether input not yet returned as a request is buffered, as when a client pipelines.
+    pub fn has_buffered(&self) -> bool {
+        self.start < self.buffer.len()
+    }
+
+    // Drops the decoded bytes, done before more are added rather than after every request
+    fn compact(&mut self) {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+    }
+
+    /// The next complete request, or None until more input arrives.
+    pub fn decode(&mut self) -> Option<Frame> {
+        if self.partial.is_none() {
+            let Some((line, used)) = split_line(&self.buffer[self.start..]) else {
+                let what = if self.buffer.get(self.start) == Some(&b'*') { "mbulk count string" } else { "inline request" };
+                let too_long = self.line_too_long(what)?;
+                return Some(self.malformed(too_long));
+            };
+            if line.len() > self.limits.max_inline_len {
+                return Some(self.malformed(BadRequest::Malformed("too big inline request".to_string())));
+            }
+            let count = match array_len(line) {
+                None => {
+                    let request = String::from_utf8(line.to_vec()).map(Request::Inline).map_err(|_| BadRequest::NotUtf8);
+                    self.start += used;
+                    return Some(Frame { request, size: used, array: false });
+                },
+                Some(Err(error)) => return Some(self.malformed(error)),
+                Some(Ok(count)) if count > self.limits.max_multibulk_len => {
+                    return Some(self.malformed(BadRequest::Malformed("invalid multibulk length".to_string())));
+                },
+                Some(Ok(count)) => count,
+            };
+            self.start += used;
+            // Grown as arguments arrive rather than sized from the header a client controls
+            self.partial = Some(PartialArray { remaining: count, args: Vec::new(), not_utf8: false, size: used });
+        }
+
+        while self.partial.as_ref().is_some_and(|partial| partial.remaining > 0) {
+            let input = &self.buffer[self.start..];
+            let Some((header, used)) = split_line(input) else {
+                let too_long = self.line_too_long("bulk count string")?;
+                return Some(self.malformed(too_long));
+            };
+            let length = match bulk_len(header) {
+                Ok(length) if length > self.limits.max_bulk_len => {
+                    return Some(self.malformed(BadRequest::Malformed("invalid bulk length".to_string())));
+                },
+                Ok(length) => length,
+                Err(error) => return Some(self.malformed(error)),
+            };
+            let end = match used.checked_add(length).and_then(|end| end.checked_add(2)) {
+                Some(end) => end,
+                None => return Some(self.malformed(BadRequest::Malformed("invalid bulk length".to_string()))),
+            };
+            // The payload is taken by length, so it may contain line breaks
+            let data = input.get(used..end)?;
+            if !data.ends_with(b"\r\n") {
+                return Some(self.malformed(BadRequest::Malformed("bulk string is not terminated by CRLF".to_string())));
+            }
+            let arg = std::str::from_utf8(&data[..length]).map(str::to_string);
+            let partial = self.partial.as_mut()?;
+            // The remaining arguments are still read, so the next request starts in step
+            match arg {
+                Ok(arg) => partial.args.push(arg),
+                Err(_) => partial.not_utf8 = true,
+            }
+            partial.remaining -= 1;
+            partial.size += end;
+            self.start += end;
+        }
+
+        let partial = self.partial.take()?;
+        let request = if partial.not_utf8 { Err(BadRequest::NotUtf8) } else { Ok(Request::Array(partial.args)) };
+        Some(Frame { request, size: partial.size, array: true })
+    }
+
+    // Nothing after a malformed frame can be trusted, so the buffered input is dropped with it
+    fn malformed(&mut self, error: BadRequest) -> Frame {
+        let array = self.partial.is_some() || self.buffer.get(self.start) == Some(&b'*');
+        let size = self.buffer.len() - self.start;
+        self.buffer.clear();
+        self.start = 0;
+        self.partial = None;
+        Frame { request: Err(error), size, array }
+    }
+
+    /// Called at end of input: a last inline command missing its line ending still counts,
+    /// anything else left over is a truncated frame.
+    pub fn finish(&mut self) -> std::io::Result<Option<Frame>> {
+        if self.partial.is_none() {
+            if !self.has_buffered() {
+                return Ok(None);
+            }
+            self.buffer.push(b'\n');
+            if let Some(frame) = self.decode() {
+                return Ok(Some(frame));
+            }
+        }
+        Err(std::io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+// Read size when the buffer has no room left
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Reads requests off a connection as bytes, so input that is not UTF-8 can be refused without
+/// failing the read.
+pub struct RequestReader<R> {
+    reader: R,
+    decoder: RequestDecoder,
+}
+
+impl<R: AsyncRead + Unpin> RequestReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_limits(reader, ProtocolLimits::default())
+    }
+
+    pub fn with_limits(reader: R, limits: ProtocolLimits) -> Self {
+        Self { reader, decoder: RequestDecoder::with_limits(limits) }
+    }
+
+    /// Whether more input has already arrived, as when a client pipelines.
+    pub fn has_buffered(&self) -> bool {
+        self.decoder.has_buffered()
+    }
+
+    /// The next request, or None at end of input. Cancel safe, which the select! loops in the
+    /// server rely on: bytes read before a cancellation stay in the decoder.
+    pub async fn next_request(&mut self) -> std::io::Result<Option<Frame>> {
+        loop {
+            if let Some(frame) = self.decoder.decode() {
+                return Ok(Some(frame));
+            }
+            self.decoder.compact();
+            self.decoder.buffer.reserve(READ_CHUNK);
+            if self.reader.read_buf(&mut self.decoder.buffer).await? == 0 {
+                return self.decoder.finish();
+            }
+        }
+    }
+
+    /// Resolves once the peer closes the connection, so a command blocked for it can be
+    /// dropped. Input arriving meanwhile is kept for next_request, up to a chunk; beyond that
+    /// it is left unread. Cancel safe, like next_request.
+    pub async fn closed(&mut self) -> std::io::Result<()> {
+        loop {
+            if self.decoder.buffer.len() - self.decoder.start >= READ_CHUNK {
+                return std::future::pending().await;
+            }
+            self.decoder.compact();
+            self.decoder.buffer.reserve(READ_CHUNK);
+            if self.reader.read_buf(&mut self.decoder.buffer).await? == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::split_inline;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    async fn read_all(input: &[u8]) -> Vec<Result<(Request, usize), BadRequest>> {
+        let mut reader = RequestReader::new(input);
+        let mut requests = Vec::new();
+        while let Ok(Some(frame)) = reader.next_request().await {
+            let request = frame.request.map(|request| (request, frame.size));
+            let closes = request.as_ref().is_err_and(BadRequest::closes_connection);
+            requests.push(request);
+            if closes {
+                break;
+            }
+        }
+        requests
+    }
+
+    #[tokio::test]
+    async fn test_arrays_and_inline_commands_interleave() {
+        let input = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$12\r\nhello\r\nworld\r\nGET key\r\n*0\r\n";
+        let requests = read_all(input).await;
+        assert_eq!(requests[0], Ok((Request::Array(vec!["SET".into(), "key".into(), "hello\r\nworld".into()]), 41)));
+        assert_eq!(requests[1], Ok((Request::Inline("GET key".into()), 9)));
+        assert!(requests[2].as_ref().unwrap().0.is_empty());
+
+        let request = &requests[0].as_ref().unwrap().0;
+        assert_eq!(request.name(), "SET");
+        let command = request.parse(&CommandRenames::default());
+        assert!(matches!(command, Ok(Command::Set { ref key, ref value, .. }) if key == "key" && value == "hello\r\nworld"));
+    }
+
+    #[test]
+    fn test_inline_arguments_can_be_quoted() {
+        let renames = CommandRenames::default();
+        let set = |line: &str| match Request::Inline(line.into()).parse(&renames) {
+            Ok(Command::Set { key, value, .. }) => Ok((key, value)),
+            Ok(other) => panic!("parsed as {:?}", other),
+            Err(error) => Err(error),
+        };
+        assert_eq!(set(r#"SET "my key" "hello world""#), Ok(("my key".into(), "hello world".into())));
+        assert_eq!(set(r#"set k 'it\'s "quoted"'"#), Ok(("k".into(), "it's \"quoted\"".into())));
+        assert_eq!(set(r#"SET k "tab\there\nline \x41\xc3\xa9 \\ \q""#), Ok(("k".into(), "tab\there\nline Aé \\ q".into())));
+        assert_eq!(set(r#"SET k """#), Ok(("k".into(), "".into())));
+        for unbalanced in [r#"SET k "open"#, r#"SET k 'open"#, r#"SET k "a"b"#] {
+            assert_eq!(set(unbalanced), Err("ERR Protocol error: unbalanced quotes in request".into()));
+        }
+
+        // Logged array requests read back as the same arguments
+        let args: Vec<String> = vec!["SET".into(), "k k".into(), "".into(), "a\"b\\c\r\n\u{a0}'".into()];
+        let request = Request::Array(args.clone());
+        assert_eq!(split_inline(&request.text()), Ok(args));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_frames_are_protocol_errors() {
+        for (input, detail) in [
+            (&b"*x\r\n"[..], "invalid multibulk length"),
+            (b"*1\r\n:5\r\n", "expected '$', got ':'"),
+            (b"*1\r\n$-3\r\n", "invalid bulk length"),
+            (b"*1\r\n$2\r\nabc\r\n", "bulk string is not terminated by CRLF"),
+        ] {
+            let error = read_all(input).await.pop().unwrap().unwrap_err();
+            assert!(error.closes_connection());
+            assert_eq!(error.reply(), Reply::error(format!("ERR Protocol error: {}", detail)));
+        }
+
+        let mut reader = RequestReader::new(&b"*2\r\n$3\r\nGET\r\n"[..]);
+        assert!(reader.next_request().await.is_err());
+    }
+
+    #[test]
+    fn test_frames_split_across_reads_decode_the_same() {
+        let input = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$11\r\nhello\nworld\r\nPING\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n";
+        let mut whole = RequestDecoder::new();
+        whole.extend(input);
+        let pipelined: Vec<Frame> = std::iter::from_fn(|| whole.decode()).collect();
+        assert_eq!(pipelined.len(), 3);
+        assert_eq!(pipelined[1].request, Ok(Request::Inline("PING".into())));
+        assert_eq!(pipelined[2].reply_format(), ReplyFormat::Resp);
+        assert!(!whole.has_buffered());
+
+        // Every way of cutting the input in two, and one byte per read
+        for cut in 0..input.len() {
+            let mut decoder = RequestDecoder::new();
+            let mut frames = Vec::new();
+            for part in [&input[..cut], &input[cut..]] {
+                decoder.extend(part);
+                frames.extend(std::iter::from_fn(|| decoder.decode()));
+            }
+            assert_eq!(frames, pipelined, "cut at {}", cut);
+        }
+        let mut decoder = RequestDecoder::new();
+        let mut frames = Vec::new();
+        for byte in input {
+            decoder.extend(std::slice::from_ref(byte));
+            frames.extend(std::iter::from_fn(|| decoder.decode()));
+        }
+        assert_eq!(frames, pipelined);
+    }
+
+    #[test]
+    fn test_requests_over_the_limits_are_refused() {
+        let limits = ProtocolLimits { max_inline_len: 16, max_bulk_len: 8, max_multibulk_len: 3 };
+        let decode = |input: &[u8]| {
+            let mut decoder = RequestDecoder::with_limits(limits);
+            decoder.extend(input);
+            let frame = decoder.decode();
+            (frame, decoder.has_buffered())
+        };
+        for (input, detail, array) in [
+            (&b"GET aaaaaaaaaaaaaaaaaaaaaaaaa"[..], "too big inline request", false),
+            (b"GET aaaaaaaaaaaaaaaaaaaaaaaaa\r\n", "too big inline request", false),
+            (b"*4\r\n", "invalid multibulk length", true),
+            (b"*1\r\n$9\r\n", "invalid bulk length", true),
+            (b"*1\r\n$000000000000000000000000", "too big bulk count string", true),
+        ] {
+            let (frame, buffered) = decode(input);
+            let frame = frame.unwrap();
+            assert_eq!(frame.request, Err(BadRequest::Malformed(detail.to_string())), "{:?}", input);
+            assert_eq!(frame.array, array);
+            assert!(!buffered);
+        }
+
+        // At the limits, requests still go through, and a partial line under the limit waits
+        let (frame, _) = decode(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$8\r\n12345678\r\n");
+        assert!(frame.unwrap().request.is_ok());
+        assert_eq!(decode(b"GET aaaaaaaaaaaa"), (None, true));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_is_refused_and_the_connection_stays_in_step() {
+        let input = b"SET k \xff\xfe\r\n*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$2\r\n\xc3\x28\r\nGET k\r\n";
+        let requests = read_all(input).await;
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0], Err(BadRequest::NotUtf8));
+        assert_eq!(requests[1], Err(BadRequest::NotUtf8));
+        assert!(!BadRequest::NotUtf8.closes_connection());
+        assert_eq!(requests[2], Ok((Request::Inline("GET k".into()), 7)));
+    }
+
+    // Random bytes and randomly corrupted frames must only ever produce requests or errors
+    #[tokio::test]
+    async fn test_fuzzed_input_never_panics() {
+        let mut rng = StdRng::seed_from_u64(3502);
+        let valid = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\nPING\r\n*1\r\n$4\r\nQUIT\r\n".to_vec();
+        for round in 0..2000 {
+            let input: Vec<u8> = if round % 2 == 0 {
+                (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect()
+            } else {
+                let mut input = valid.clone();
+                for _ in 0..rng.gen_range(1..4) {
+                    let at = rng.gen_range(0..input.len());
+                    input[at] = rng.gen();
+                }
+                input
+            };
+            for request in read_all(&input).await.into_iter().flatten() {
+                let _ = request.0.parse(&CommandRenames::default());
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+// Randomness for commands that pick at random: RANDOMKEY, HRANDFIELD and random eviction.
+// Seeded with --rng-seed or DEBUG SET-RNG-SEED, the same commands against the same dataset make
+// the same picks, so integration tests and replays are reproducible.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Debug)]
+pub struct CommandRng {
+    rng: StdRng,
+    seed: Option<u64>,
+}
+
+impl Default for CommandRng {
+    fn default() -> Self {
+        Self { rng: StdRng::from_entropy(), seed: None }
+    }
+}
+
+impl CommandRng {
+    /// Makes picks reproducible from `seed`, or random again with `None`.
+    pub fn reseed(&mut self, seed: Option<u64>) {
+        self.rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        self.seed = seed;
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// Hash map iteration order differs between runs, so seeded picks must start from a
+    /// sorted order to repeat.
+    pub fn canonical_order<T: Ord>(&self, items: &mut [T]) {
+        if self.seed.is_some() {
+            items.sort_unstable();
+        }
+    }
+
+    /// One of `items` at random.
+    pub fn choose<T: Ord>(&mut self, mut items: Vec<T>) -> Option<T> {
+        if items.is_empty() {
+            return None;
+        }
+        self.canonical_order(&mut items);
+        let index = self.rng.gen_range(0..items.len());
+        Some(items.swap_remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_picks_repeat_whatever_the_input_order() {
+        let picks = |seed: u64, items: Vec<&'static str>| {
+            let mut rng = CommandRng::default();
+            rng.reseed(Some(seed));
+            (0..5).map(|_| rng.choose(items.clone()).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(7, vec!["a", "b", "c", "d"]), picks(7, vec!["d", "c", "b", "a"]));
+    }
+
+    #[test]
+    fn test_reseed_with_none_forgets_the_seed() {
+        let mut rng = CommandRng::default();
+        assert_eq!(rng.seed(), None);
+        rng.reseed(Some(1));
+        assert_eq!(rng.seed(), Some(1));
+        rng.reseed(None);
+        assert_eq!(rng.seed(), None);
+        assert_eq!(rng.choose(Vec::<u8>::new()), None);
+    }
+}
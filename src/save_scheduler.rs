@@ -0,0 +1,104 @@
+// Spaces background saves by how fast the data is changing instead of a fixed tick: a busy
+// instance saves often, so a crash loses little, and an idle one backs off towards the maximum
+// interval, so nothing is rewritten for a handful of changes. A database with no unsaved
+// changes is never saved.
+use std::time::Duration;
+
+pub const DEFAULT_MIN_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_SAVE_INTERVAL: Duration = Duration::from_secs(300);
+// Saves are spaced to come roughly every this many changes, within the bounds
+const CHANGES_PER_SAVE: f64 = 1000.0;
+// Weight of the newest sample in the smoothed change rate
+const RATE_SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, Clone)]
+pub struct SaveScheduler {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    // Changes per second, smoothed over recent observations
+    rate: f64,
+    last_dirty: u64,
+}
+
+impl SaveScheduler {
+    pub fn new(min_interval: Duration, max_interval: Duration) -> Self {
+        Self { min_interval, max_interval, rate: 0.0, last_dirty: 0 }
+    }
+
+    /// Folds in the unsaved change count seen `elapsed` after the previous observation.
+    pub fn observe(&mut self, dirty: u64, elapsed: Duration) {
+        let changes = dirty.saturating_sub(self.last_dirty) as f64;
+        let sample = changes / elapsed.as_secs_f64().max(1e-3);
+        self.rate += RATE_SMOOTHING * (sample - self.rate);
+        self.last_dirty = dirty;
+    }
+
+    /// Call after a save with the changes still unsaved, so the ones it wrote are not counted
+    /// as new.
+    pub fn saved(&mut self, dirty: u64) {
+        self.last_dirty = dirty;
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn interval(&self) -> Duration {
+        if self.rate <= 0.0 {
+            return self.max_interval;
+        }
+        Duration::from_secs_f64((CHANGES_PER_SAVE / self.rate).min(self.max_interval.as_secs_f64()))
+            .clamp(self.min_interval, self.max_interval)
+    }
+
+    pub fn due(&self, dirty: u64, since_last_save: Duration) -> bool {
+        dirty > 0 && since_last_save >= self.interval()
+    }
+}
+
+impl Default for SaveScheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_SAVE_INTERVAL, DEFAULT_MAX_SAVE_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_busier_means_sooner_within_bounds() {
+        let mut scheduler = SaveScheduler::new(Duration::from_secs(5), Duration::from_secs(300));
+        assert_eq!(scheduler.interval(), Duration::from_secs(300));
+
+        // 20 changes a second settles near one save every 50 seconds
+        for second in 1..=30 {
+            scheduler.observe(second * 20, SECOND);
+        }
+        assert!((scheduler.rate() - 20.0).abs() < 0.1);
+        assert_eq!(scheduler.interval().as_secs(), 50);
+
+        // Far beyond 1000 changes a second, the minimum holds
+        for second in 1..=30 {
+            scheduler.observe(600 + second * 100_000, SECOND);
+        }
+        assert_eq!(scheduler.interval(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_idle_backs_off_and_clean_never_saves() {
+        let mut scheduler = SaveScheduler::default();
+        scheduler.observe(5000, SECOND);
+        assert!(scheduler.due(5000, DEFAULT_MIN_SAVE_INTERVAL));
+
+        scheduler.saved(0);
+        for _ in 0..60 {
+            scheduler.observe(0, SECOND);
+        }
+        assert_eq!(scheduler.interval(), DEFAULT_MAX_SAVE_INTERVAL);
+        assert!(!scheduler.due(0, Duration::from_secs(3600)));
+        assert!(scheduler.due(1, DEFAULT_MAX_SAVE_INTERVAL));
+    }
+}
@@ -0,0 +1,338 @@
+// Secondary indexes over hash fields. Each index covers the hashes whose key starts with one of
+// its prefixes and keeps an inverted index per declared field, updated on every write.
+use crate::data_types::RedisValue;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Tag,
+    Numeric,
+}
+
+impl FieldKind {
+    pub fn from_string(kind: &str) -> Option<Self> {
+        match kind.to_uppercase().as_str() {
+            "TAG" => Some(FieldKind::Tag),
+            "NUMERIC" => Some(FieldKind::Numeric),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldKind::Tag => "TAG",
+            FieldKind::Numeric => "NUMERIC",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Equals { field: String, value: String },
+    Range { field: String, min: f64, max: f64 },
+}
+
+// f64 ordered with total_cmp so numeric values can key a BTreeMap
+#[derive(Debug, Clone, Copy)]
+struct NumericKey(f64);
+
+impl PartialEq for NumericKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for NumericKey {}
+
+impl PartialOrd for NumericKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NumericKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    pub prefixes: Vec<String>,
+    pub fields: Vec<(String, FieldKind)>,
+    tags: HashMap<String, HashMap<String, HashSet<String>>>,
+    numbers: HashMap<String, BTreeMap<NumericKey, HashSet<String>>>,
+    // Field values currently indexed for each key, so a rewrite can drop the stale entries
+    docs: HashMap<String, Vec<(String, String)>>,
+}
+
+impl SearchIndex {
+    pub fn new(prefixes: Vec<String>, fields: Vec<(String, FieldKind)>) -> Self {
+        Self { prefixes, fields, ..Default::default() }
+    }
+
+    fn covers(&self, key: &str) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| key.starts_with(prefix.as_str()))
+    }
+
+    pub fn num_docs(&self) -> usize {
+        self.docs.len()
+    }
+
+    fn kind_of(&self, field: &str) -> Option<FieldKind> {
+        self.fields.iter().find(|(name, _)| name == field).map(|(_, kind)| *kind)
+    }
+
+    fn unindex(&mut self, key: &str) {
+        let entries = match self.docs.remove(key) {
+            Some(entries) => entries,
+            None => return,
+        };
+
+        for (field, value) in entries {
+            match self.kind_of(&field) {
+                Some(FieldKind::Tag) => {
+                    if let Some(values) = self.tags.get_mut(&field) {
+                        if let Some(keys) = values.get_mut(&value) {
+                            keys.remove(key);
+                            if keys.is_empty() {
+                                values.remove(&value);
+                            }
+                        }
+                    }
+                },
+                Some(FieldKind::Numeric) => {
+                    let number = NumericKey(value.parse::<f64>().unwrap_or(f64::NAN));
+                    if let Some(values) = self.numbers.get_mut(&field) {
+                        if let Some(keys) = values.get_mut(&number) {
+                            keys.remove(key);
+                            if keys.is_empty() {
+                                values.remove(&number);
+                            }
+                        }
+                    }
+                },
+                None => {},
+            }
+        }
+    }
+
+    fn index(&mut self, key: &str, hash: &HashMap<String, String>) {
+        let mut entries = Vec::new();
+
+        for (field, kind) in &self.fields {
+            let value = match hash.get(field) {
+                Some(value) => value,
+                None => continue,
+            };
+            match kind {
+                FieldKind::Tag => {
+                    self.tags.entry(field.clone()).or_default()
+                        .entry(value.clone()).or_default()
+                        .insert(key.to_string());
+                },
+                FieldKind::Numeric => {
+                    // Non-numeric values are simply not indexed, as in RediSearch
+                    let number = match value.parse::<f64>() {
+                        Ok(number) if !number.is_nan() => number,
+                        _ => continue,
+                    };
+                    self.numbers.entry(field.clone()).or_default()
+                        .entry(NumericKey(number)).or_default()
+                        .insert(key.to_string());
+                },
+            }
+            entries.push((field.clone(), value.clone()));
+        }
+
+        if !entries.is_empty() {
+            self.docs.insert(key.to_string(), entries);
+        }
+    }
+
+    fn matching(&self, predicate: &Predicate) -> Result<HashSet<String>, String> {
+        match predicate {
+            Predicate::Equals { field, value } => {
+                if self.kind_of(field) != Some(FieldKind::Tag) {
+                    return Err(format!("ERR field '{}' is not a TAG field of this index", field));
+                }
+                Ok(self.tags.get(field).and_then(|values| values.get(value)).cloned().unwrap_or_default())
+            },
+            Predicate::Range { field, min, max } => {
+                if self.kind_of(field) != Some(FieldKind::Numeric) {
+                    return Err(format!("ERR field '{}' is not a NUMERIC field of this index", field));
+                }
+                if min > max {
+                    return Ok(HashSet::new());
+                }
+                Ok(self.numbers.get(field)
+                    .map(|values| values.range(NumericKey(*min)..=NumericKey(*max)).flat_map(|(_, keys)| keys.iter().cloned()).collect())
+                    .unwrap_or_default())
+            },
+        }
+    }
+
+    /// Keys matching every predicate, sorted.
+    pub fn search(&self, predicates: &[Predicate]) -> Result<Vec<String>, String> {
+        let mut result: Option<HashSet<String>> = None;
+        for predicate in predicates {
+            let keys = self.matching(predicate)?;
+            result = Some(match result {
+                Some(current) => current.intersection(&keys).cloned().collect(),
+                None => keys,
+            });
+        }
+
+        let mut keys: Vec<String> = result.unwrap_or_default().into_iter().collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IndexRegistry {
+    pub indexes: HashMap<String, SearchIndex>,
+}
+
+impl IndexRegistry {
+    /// Brings every index covering `key` in line with its new value (`None` when the key is gone).
+    pub fn update(&mut self, key: &str, value: Option<&RedisValue>) {
+        for index in self.indexes.values_mut() {
+            if !index.covers(key) {
+                continue;
+            }
+            index.unindex(key);
+            if let Some(RedisValue::Hash(hash)) = value {
+                index.index(key, hash);
+            }
+        }
+    }
+
+    /// Registers an index and backfills it from the existing keys.
+    pub fn create<'a>(&mut self, name: String, mut index: SearchIndex, existing: impl Iterator<Item = (&'a String, &'a RedisValue)>) {
+        for (key, value) in existing {
+            if let RedisValue::Hash(hash) = value {
+                if index.covers(key) {
+                    index.index(key, hash);
+                }
+            }
+        }
+        self.indexes.insert(name, index);
+    }
+
+    pub fn clear_documents(&mut self) {
+        for index in self.indexes.values_mut() {
+            let SearchIndex { prefixes, fields, .. } = std::mem::take(index);
+            *index = SearchIndex::new(prefixes, fields);
+        }
+    }
+}
+
+fn parse_bound(bound: &str) -> Result<f64, String> {
+    match bound.to_lowercase().as_str() {
+        "-inf" => Ok(f64::NEG_INFINITY),
+        "inf" | "+inf" => Ok(f64::INFINITY),
+        _ => bound.parse::<f64>().map_err(|_| format!("ERR invalid numeric bound '{}'", bound)),
+    }
+}
+
+/// Parses a query of space-separated clauses: `@field:{value}` or `@field:value` for tag
+/// equality and `@field:[min max]` for an inclusive numeric range (-inf/+inf allowed).
+pub fn parse_query(query: &str) -> Result<Vec<Predicate>, String> {
+    let invalid = || format!("ERR syntax error in query '{}'", query);
+    let mut predicates = Vec::new();
+    let mut rest = query.trim();
+
+    while !rest.is_empty() {
+        let clause = rest.strip_prefix('@').ok_or_else(invalid)?;
+        let colon = clause.find(':').ok_or_else(invalid)?;
+        let field = clause[..colon].to_string();
+        let body = &clause[colon + 1..];
+        if field.is_empty() {
+            return Err(invalid());
+        }
+
+        let consumed = if let Some(range) = body.strip_prefix('[') {
+            let close = range.find(']').ok_or_else(invalid)?;
+            let bounds: Vec<&str> = range[..close].split_whitespace().collect();
+            if bounds.len() != 2 {
+                return Err(invalid());
+            }
+            predicates.push(Predicate::Range { field, min: parse_bound(bounds[0])?, max: parse_bound(bounds[1])? });
+            close + 2
+        } else if let Some(tag) = body.strip_prefix('{') {
+            let close = tag.find('}').ok_or_else(invalid)?;
+            predicates.push(Predicate::Equals { field, value: tag[..close].trim().to_string() });
+            close + 2
+        } else {
+            let end = body.find(char::is_whitespace).unwrap_or(body.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            predicates.push(Predicate::Equals { field, value: body[..end].to_string() });
+            end
+        };
+        rest = body[consumed..].trim_start();
+    }
+
+    if predicates.is_empty() {
+        return Err(invalid());
+    }
+    Ok(predicates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(pairs: &[(&str, &str)]) -> RedisValue {
+        RedisValue::Hash(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn test_index_tracks_writes() {
+        let mut registry = IndexRegistry::default();
+        let existing = HashMap::from([("user:1".to_string(), hash(&[("city", "paris"), ("age", "31")]))]);
+        let index = SearchIndex::new(
+            vec!["user:".to_string()],
+            vec![("city".to_string(), FieldKind::Tag), ("age".to_string(), FieldKind::Numeric)],
+        );
+        registry.create("users".to_string(), index, existing.iter());
+
+        registry.update("user:2", Some(&hash(&[("city", "paris"), ("age", "25")])));
+        registry.update("other:1", Some(&hash(&[("city", "paris")])));
+
+        let users = &registry.indexes["users"];
+        let paris = Predicate::Equals { field: "city".to_string(), value: "paris".to_string() };
+        assert_eq!(users.search(std::slice::from_ref(&paris)).unwrap(), vec!["user:1", "user:2"]);
+
+        let young = Predicate::Range { field: "age".to_string(), min: 0.0, max: 30.0 };
+        assert_eq!(users.search(&[paris.clone(), young.clone()]).unwrap(), vec!["user:2"]);
+
+        registry.update("user:2", Some(&hash(&[("city", "rome"), ("age", "25")])));
+        registry.update("user:1", None);
+        let users = &registry.indexes["users"];
+        assert!(users.search(&[paris]).unwrap().is_empty());
+        assert_eq!(users.search(&[young]).unwrap(), vec!["user:2"]);
+        assert_eq!(users.num_docs(), 1);
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(parse_query("@city:{new york} @age:[-inf 30]").unwrap(), vec![
+            Predicate::Equals { field: "city".to_string(), value: "new york".to_string() },
+            Predicate::Range { field: "age".to_string(), min: f64::NEG_INFINITY, max: 30.0 },
+        ]);
+        assert_eq!(parse_query("@city:paris").unwrap().len(), 1);
+        assert!(parse_query("city:paris").is_err());
+        assert!(parse_query("@age:[1]").is_err());
+    }
+
+    #[test]
+    fn test_search_rejects_wrong_field_kind() {
+        let index = SearchIndex::new(vec![], vec![("age".to_string(), FieldKind::Numeric)]);
+        let predicate = Predicate::Equals { field: "age".to_string(), value: "3".to_string() };
+        assert!(index.search(&[predicate]).is_err());
+    }
+}
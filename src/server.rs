@@ -1,19 +1,207 @@
-use crate::commands::execute_command;
-use crate::database::{create_database_with_memory_config, create_database_with_data, Database};
-use crate::protocol::parse_command;
-use crate::auth::{AuthConfig, ClientAuth};
-use crate::persistence_clean::MmapPersistence;
+use crate::commands::{reply_too_large_error, Command};
+use crate::database::{FlushPolicy, RedisDatabase};
+use crate::shared::{create_database_with_data, Database};
+use crate::command_renames::CommandRenames;
+use crate::resp::{ProtocolLimits, RequestReader};
+#[cfg(feature = "http-gateway")]
+use crate::http_gateway;
+use crate::memcached;
+use crate::capture::{Capture, ClientCapture};
+use crate::clients::{ClientHandle, ClientRegistry, ClientType};
+use crate::refresh::{spawn_refresher, RefreshConfig};
+use crate::invalidation::{self, InvalidationBus};
+use crate::auth::{AuthConfig, Authenticator, ClientAuth, Tenant};
+use crate::persistence_clean::{MmapPersistence, SaveRule, Snapshot};
+use crate::save_scheduler::SaveScheduler;
+use crate::memory::format_bytes;
+use crate::panic_guard::execute_guarded;
+use crate::rate_limit::{ClientWriteLimit, WriteRateLimit};
+use crate::protocol::{Reply, ReplyFormat};
+use crate::metrics::{create_metrics, Metrics};
+use crate::prefix_stats::PrefixStats;
+use crate::pub_sub::{create_pubsub_manager, PubSubManager, PubSubMessage, SubscriberQueue};
+use crate::storage::{now_millis, StorageConfig};
+use crate::tenancy;
+use crate::warmup::WARMUP_BATCH;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use thiserror::Error;
 use tokio::time::{interval, Duration};
 
+// How often expired keys and hash fields are reclaimed without waiting for an access, and
+// keys beyond the cold tier's limit are spilled to it; used memory is re-measured then too when
+// maxmemory is set
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+// How often the save rules are checked
+const SAVE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// After a failed save, rules wait this long before triggering another attempt
+const SAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+// Background saves announce their outcome here, e.g. "bgsave ok bytes:1024 duration_ms:3"
+const PERSISTENCE_EVENTS_CHANNEL: &str = "__events__:persistence";
+// Connections announce themselves here, e.g. "connect id:7 addr:10.0.0.5:52114"
+const CLIENT_EVENTS_CHANNEL: &str = "__events__:clients";
+// How often unacknowledged messages on reliable channels are checked for redelivery
+const REDELIVERY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("Failed to listen on {addr}: {source}")]
+    Bind { addr: String, source: std::io::Error },
+    #[error("Failed to accept a connection: {0}")]
+    Accept(#[source] std::io::Error),
+    #[error("Refusing to start: {0}")]
+    MemoryPreflight(String),
+    // A listener was configured that this build leaves out
+    #[error("{0} needs a build with the {1} feature")]
+    FeatureDisabled(&'static str, &'static str),
+}
+
+/// Holds writers back while the unsaved backlog (`RedisDatabase::dirty`) is large, so clients
+/// slow down when background saves fall behind instead of widening what a crash would lose.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteStalls {
+    // Each write waits `delay` once this many changes are unsaved
+    pub stall_after: Option<u64>,
+    pub delay: Duration,
+    // Writes are refused with BUSY once this many changes are unsaved
+    pub reject_after: Option<u64>,
+}
+
+/// What the side listeners (HTTP gateway, memcached) need to hold their writes to the same
+/// rate limit and stalls as RESP connections.
+#[derive(Debug, Clone, Default)]
+pub struct WriteAdmission {
+    pub write_stalls: WriteStalls,
+    pub write_rate_limit: WriteRateLimit,
+}
+
+// Commands a pipelining client may run back to back before other connections get a turn
+pub const DEFAULT_CLIENT_COMMAND_BUDGET: usize = 64;
+
+// Per-connection settings handle_client needs besides the shared state
+#[derive(Debug)]
+struct ClientOptions {
+    command_renames: Arc<CommandRenames>,
+    write_stalls: WriteStalls,
+    write_rate_limit: WriteRateLimit,
+    protocol_limits: ProtocolLimits,
+    capture: Option<ClientCapture>,
+    max_reply_bytes: Option<usize>,
+    command_budget: Option<usize>,
+    banner: Option<Arc<str>>,
+    events: ClientEvents,
+    client: ClientHandle,
+}
+
+// One connection's view of the client events: who it is, and where the events log is if any
+#[derive(Debug, Clone)]
+struct ClientEvents {
+    id: u64,
+    addr: SocketAddr,
+    log: Option<Arc<PathBuf>>,
+}
+
+impl ClientEvents {
+    // Publishes e.g. "disconnect id:7 addr:10.0.0.5:52114 duration_ms:1200", `detail` being
+    // everything after the address
+    async fn emit(&self, pubsub: &PubSubManager, event: &str, detail: &str) {
+        let message = format!("{} id:{} addr:{}{}", event, self.id, self.addr, detail);
+        if let Some(path) = &self.log {
+            let written = OpenOptions::new().create(true).append(true).open(path.as_path())
+                .and_then(|mut file| writeln!(file, "{} {}", now_millis(), message));
+            if let Err(e) = written {
+                eprintln!("Failed to write client events log {}: {}", path.display(), e);
+            }
+        }
+        pubsub.write().await.publish(CLIENT_EVENTS_CHANNEL, message);
+    }
+}
+
+// Aborts the server's background tasks once serve() stops, e.g. when a test drops its server
+#[derive(Default)]
+struct BackgroundTasks(Vec<JoinHandle<()>>);
+
+impl Drop for BackgroundTasks {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+/// What to do when the snapshot loaded at startup already uses more than maxmemory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemoryPreflight {
+    // Start anyway and say so
+    #[default]
+    Warn,
+    // Refuse to start
+    Refuse,
+}
+
+impl MemoryPreflight {
+    pub fn from_string(policy: &str) -> Option<Self> {
+        match policy {
+            "warn" => Some(MemoryPreflight::Warn),
+            "refuse" => Some(MemoryPreflight::Refuse),
+            _ => None,
+        }
+    }
+}
+
+/// When to save: as the scheduler spaces saves by write rate, and whenever a rule calls for it.
+#[derive(Debug, Clone, Default)]
+pub struct SavePolicy {
+    pub scheduler: SaveScheduler,
+    pub rules: Vec<SaveRule>,
+    // Save unsaved changes as soon as the last client disconnects
+    pub on_last_disconnect: bool,
+}
+
 pub struct Server {
     host: String,
     port: u16,
     database: Database,
     auth_config: Arc<AuthConfig>,
     persistence: Arc<MmapPersistence>,
+    pubsub: PubSubManager,
+    metrics: Metrics,
+    command_renames: Arc<CommandRenames>,
+    write_stalls: WriteStalls,
+    write_rate_limit: WriteRateLimit,
+    protocol_limits: ProtocolLimits,
+    save_policy: SavePolicy,
+    // Port of the HTTP/JSON gateway, if it is enabled
+    http_port: Option<u16>,
+    // Port of the memcached text protocol listener, if it is enabled
+    memcached_port: Option<u16>,
+    capture: Option<Arc<Capture>>,
+    max_reply_bytes: Option<usize>,
+    // Refresh-ahead handler registered by an embedder
+    refresh: Option<RefreshConfig>,
+    // Instances sent INVALIDATE for keys set or deleted here
+    invalidation_peers: Vec<String>,
+    invalidation: Option<InvalidationBus>,
+    command_budget: Option<usize>,
+    // Greeting for text-mode clients; RESP clients never get it
+    banner: Option<Arc<str>>,
+    // Connect, disconnect and auth failure events are also appended here
+    client_events_log: Option<Arc<PathBuf>>,
+    clients: Arc<ClientRegistry>,
+    // Keys and bytes the snapshot held when it was loaded
+    loaded_keys: usize,
+    loaded_bytes: usize,
+    memory_preflight: MemoryPreflight,
 }
 
 impl Server {
@@ -23,21 +211,33 @@ impl Server {
         password: Option<String>,
         dbfilename: String,
         max_memory: Option<usize>,
-        eviction_policy: String
+        eviction_policy: String,
+        storage: StorageConfig,
     ) -> Self {
         let auth_config = Arc::new(AuthConfig::new(password));
+        let snapshot_path = PathBuf::from(&dbfilename);
         let persistence = Arc::new(MmapPersistence::new(dbfilename));
 
-        let database = match persistence.load_database() {
+        let mut db = match persistence.load_database() {
             Ok(mut db) => {
                 db.memory_manager = crate::memory::MemoryManager::new(max_memory, eviction_policy);
-                create_database_with_data(db)
+                db
             },
             Err(e) => {
                 eprintln!("Failed to load database: {}", e);
-                create_database_with_memory_config(max_memory, eviction_policy)
+                RedisDatabase::new_with_memory_config(max_memory, eviction_policy)
             }
         };
+        db.compression_threshold = storage.compression_threshold;
+        db.refresh_memory_usage();
+        if let Some(tier) = storage.cold_tier {
+            db.attach_cold_tier(tier);
+        }
+        db.restore_access_counts(persistence.load_access_counts());
+        db.expiry_log.restore(persistence.load_expired_stream());
+        db.snapshot_path = Some(snapshot_path);
+        let (loaded_keys, loaded_bytes) = (db.data.len(), db.memory_manager.used_memory);
+        let database = create_database_with_data(db);
 
         Self {
             host,
@@ -45,14 +245,250 @@ impl Server {
             database,
             auth_config,
             persistence,
+            pubsub: create_pubsub_manager(),
+            metrics: create_metrics(),
+            command_renames: Arc::new(CommandRenames::default()),
+            write_stalls: WriteStalls::default(),
+            write_rate_limit: WriteRateLimit::default(),
+            protocol_limits: ProtocolLimits::default(),
+            save_policy: SavePolicy::default(),
+            http_port: None,
+            memcached_port: None,
+            capture: None,
+            max_reply_bytes: None,
+            refresh: None,
+            invalidation_peers: Vec::new(),
+            invalidation: None,
+            command_budget: Some(DEFAULT_CLIENT_COMMAND_BUDGET),
+            banner: None,
+            client_events_log: None,
+            clients: Arc::new(ClientRegistry::new()),
+            loaded_keys,
+            loaded_bytes,
+            memory_preflight: MemoryPreflight::default(),
         }
     }
 
-    pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn with_command_renames(mut self, command_renames: CommandRenames) -> Self {
+        self.command_renames = Arc::new(command_renames);
+        self
+    }
+
+    /// Replaces the static password as the check for AUTH; `None` keeps it.
+    pub fn with_authenticator(mut self, authenticator: Option<Arc<dyn Authenticator>>) -> Self {
+        if authenticator.is_some() {
+            Arc::make_mut(&mut self.auth_config).authenticator = authenticator;
+        }
+        self
+    }
+
+    pub fn with_tenants(mut self, tenants: HashMap<String, Tenant>) -> Self {
+        Arc::make_mut(&mut self.auth_config).tenants = tenants;
+        self
+    }
+
+    pub fn with_write_stalls(mut self, write_stalls: WriteStalls) -> Self {
+        self.write_stalls = write_stalls;
+        self
+    }
+
+    /// Caps write commands per second across all clients and for each client; `None` or zero
+    /// leaves a cap off. Writes over a cap are refused with THROTTLED.
+    pub fn with_write_rate_limit(mut self, global: Option<u32>, per_client: Option<u32>) -> Self {
+        self.write_rate_limit = WriteRateLimit::new(global, per_client);
+        self
+    }
+
+    /// Caps what one request may make the server buffer; see `ProtocolLimits`.
+    pub fn with_protocol_limits(mut self, protocol_limits: ProtocolLimits) -> Self {
+        self.protocol_limits = protocol_limits;
+        self
+    }
+
+    pub fn with_flush_policy(self, flush_policy: FlushPolicy) -> Self {
+        // Nothing else holds the database before run()
+        if let Ok(mut db) = self.database.try_write() {
+            db.flush_policy = flush_policy;
+        }
+        self
+    }
+
+    /// Makes DEL keep deleted keys for `retention`, for RESTOREKEY; None deletes them at once.
+    pub fn with_trash_retention(self, retention: Option<Duration>) -> Self {
+        // Nothing else holds the database before run()
+        if let Ok(mut db) = self.database.try_write() {
+            db.trash.retention = retention;
+        }
+        self
+    }
+
+    pub fn with_max_reply_bytes(mut self, max_reply_bytes: Option<usize>) -> Self {
+        // Nothing else holds the database before run()
+        if let Ok(mut db) = self.database.try_write() {
+            db.max_reply_bytes = max_reply_bytes;
+        }
+        self.max_reply_bytes = max_reply_bytes;
+        self
+    }
+
+    /// Makes a client yield to other connections after `budget` pipelined commands in a row;
+    /// None lets it drain its whole backlog first.
+    pub fn with_client_command_budget(mut self, budget: Option<usize>) -> Self {
+        self.command_budget = budget;
+        self
+    }
+
+    /// Greets clients that type inline commands with `banner`, just before the reply to their
+    /// first one. Connections are silent until then, so RESP clients, whose first reply must
+    /// be the answer to their first command, never see it.
+    pub fn with_banner(mut self, banner: Option<String>) -> Self {
+        self.banner = banner.map(Arc::from);
+        self
+    }
+
+    pub fn with_memory_preflight(mut self, memory_preflight: MemoryPreflight) -> Self {
+        self.memory_preflight = memory_preflight;
+        self
+    }
+
+    /// Calls `refresh`'s handler for keys about to expire while the server runs.
+    pub fn with_refresh(mut self, refresh: Option<RefreshConfig>) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Sends `peers` an INVALIDATE for every key matching `patterns` that is set or deleted here.
+    pub fn with_invalidation(mut self, peers: Vec<String>, patterns: &[String]) -> Self {
+        if peers.is_empty() {
+            return self;
+        }
+        // Nothing else holds the database before run()
+        let bus = InvalidationBus::new(patterns);
+        if let Ok(mut db) = self.database.try_write() {
+            db.invalidation = Some(Arc::new(bus.clone()));
+        }
+        self.invalidation = Some(bus);
+        self.invalidation_peers = peers;
+        self
+    }
+
+    pub fn with_max_keys_per_command(self, max_keys: Option<usize>) -> Self {
+        // Nothing else holds the database before run()
+        if let Ok(mut db) = self.database.try_write() {
+            db.max_keys_per_command = max_keys;
+        }
+        self
+    }
+
+    pub fn with_prefix_delimiter(self, delimiter: char) -> Self {
+        // Nothing else holds the database before run()
+        if let Ok(mut db) = self.database.try_write() {
+            db.prefix_stats = PrefixStats::with_delimiter(delimiter);
+        }
+        self
+    }
+
+    pub fn with_expired_stream_len(self, max_len: usize) -> Self {
+        // Nothing else holds the database before run()
+        if let Ok(mut db) = self.database.try_write() {
+            db.expiry_log.set_max_len(max_len);
+        }
+        self
+    }
+
+    pub fn with_rng_seed(self, seed: Option<u64>) -> Self {
+        // Nothing else holds the database before run()
+        if let Ok(mut db) = self.database.try_write() {
+            db.rng.reseed(seed);
+        }
+        self
+    }
+
+    pub fn with_save_policy(mut self, save_policy: SavePolicy) -> Self {
+        self.save_policy = save_policy;
+        self
+    }
+
+    pub fn with_http_gateway(mut self, http_port: Option<u16>) -> Self {
+        self.http_port = http_port;
+        self
+    }
+
+    pub fn with_memcached(mut self, memcached_port: Option<u16>) -> Self {
+        self.memcached_port = memcached_port;
+        self
+    }
+
+    pub fn with_client_events_log(mut self, path: Option<PathBuf>) -> Self {
+        self.client_events_log = path.map(Arc::new);
+        self
+    }
+
+    pub fn with_capture(mut self, capture: Option<Capture>) -> Self {
+        self.capture = capture.map(Arc::new);
+        self
+    }
+
+    fn write_admission(&self) -> WriteAdmission {
+        WriteAdmission { write_stalls: self.write_stalls, write_rate_limit: self.write_rate_limit.clone() }
+    }
+
+    pub async fn run(&self) -> Result<(), ServerError> {
         let addr = format!("{}:{}", self.host, self.port);
-        let listener = TcpListener::bind(&addr).await?;
+        let listener = bind(addr.clone()).await?;
 
         println!("Redis-clone server listening on {}", addr);
+        self.serve(listener).await
+    }
+
+    /// Accepts clients on `listener` until the returned future is dropped, which also stops
+    /// the background tasks and the secondary listeners.
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), ServerError> {
+        let max_memory = self.database.read().await.memory_manager.max_memory;
+        if let Some(max_memory) = max_memory.filter(|max_memory| self.loaded_bytes > *max_memory) {
+            let message = format!(
+                "the loaded snapshot uses {} ({} bytes), more than maxmemory {} ({} bytes)",
+                format_bytes(self.loaded_bytes), self.loaded_bytes, format_bytes(max_memory), max_memory
+            );
+            if self.memory_preflight == MemoryPreflight::Refuse {
+                return Err(ServerError::MemoryPreflight(message));
+            }
+            eprintln!("Warning: {}; under noeviction, writes that add data are refused until usage drops", message);
+        }
+
+        let mut background = BackgroundTasks::default();
+
+        #[cfg(not(feature = "http-gateway"))]
+        if self.http_port.is_some() {
+            return Err(ServerError::FeatureDisabled("--http-port", "http-gateway"));
+        }
+        #[cfg(feature = "http-gateway")]
+        if let Some(http_port) = self.http_port {
+            let http_addr = format!("{}:{}", self.host, http_port);
+            let http_listener = bind(http_addr.clone()).await?;
+            println!("HTTP gateway listening on {}", http_addr);
+            background.0.push(tokio::spawn(http_gateway::run(
+                http_listener,
+                Arc::clone(&self.database),
+                Arc::clone(&self.auth_config),
+                Arc::clone(&self.pubsub),
+                Arc::clone(&self.metrics),
+                Arc::clone(&self.command_renames),
+                self.write_admission(),
+            )));
+        }
+
+        if let Some(memcached_port) = self.memcached_port {
+            let memcached_addr = format!("{}:{}", self.host, memcached_port);
+            let memcached_listener = bind(memcached_addr.clone()).await?;
+            println!("memcached protocol listening on {}", memcached_addr);
+            background.0.push(tokio::spawn(memcached::run(
+                memcached_listener,
+                Arc::clone(&self.database),
+                Arc::clone(&self.metrics),
+                self.write_admission(),
+            )));
+        }
 
         {
             let db = self.database.read().await;
@@ -63,6 +499,7 @@ impl Server {
                     println!("Eviction policy: {}", memory_info.get("maxmemory_policy").unwrap_or(&"unknown".to_string()));
                 }
             }
+            println!("Loaded {} keys using {} ({} bytes)", self.loaded_keys, format_bytes(self.loaded_bytes), self.loaded_bytes);
             println!("Current memory usage: {}", memory_info.get("used_memory_human").unwrap_or(&"unknown".to_string()));
         }
 
@@ -70,92 +507,746 @@ impl Server {
 
         let db_clone = Arc::clone(&self.database);
         let persistence_clone = Arc::clone(&self.persistence);
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(60));
+        let pubsub_clone = Arc::clone(&self.pubsub);
+        let save_rules = self.save_policy.rules.clone();
+        let mut scheduler = self.save_policy.scheduler.clone();
+        let save_now = Arc::new(Notify::new());
+        let save_now_clone = Arc::clone(&save_now);
+        background.0.push(tokio::spawn(async move {
+            let mut interval = interval(SAVE_CHECK_INTERVAL);
+            let mut last_attempt: Option<Instant> = None;
+            let started = Instant::now();
+            let mut last_observed = started;
+            loop {
+                let requested = tokio::select! {
+                    _ = interval.tick() => false,
+                    _ = save_now_clone.notified() => true,
+                };
+                let due = {
+                    let db = db_clone.read().await;
+                    scheduler.observe(db.dirty, last_observed.elapsed());
+                    last_observed = Instant::now();
+                    let retry_ok = db.save_stats.last_ok || last_attempt.is_none_or(|at| at.elapsed() >= SAVE_RETRY_DELAY);
+                    (requested && db.dirty > 0)
+                        || scheduler.due(db.dirty, last_attempt.unwrap_or(started).elapsed())
+                        || (retry_ok && db.save_stats.rule_due(&save_rules, db.dirty))
+                };
+                if due {
+                    last_attempt = Some(Instant::now());
+                    background_save(&db_clone, &persistence_clone, &pubsub_clone).await;
+                    scheduler.saved(db_clone.read().await.dirty);
+                }
+            }
+        }));
+
+        let db_clone = Arc::clone(&self.database);
+        background.0.push(tokio::spawn(async move {
+            let mut interval = interval(ACTIVE_EXPIRE_INTERVAL);
             loop {
                 interval.tick().await;
-                let db = db_clone.read().await;
-                if let Err(e) = persistence_clone.save_database(&db) {
-                    eprintln!("Background save failed: {}", e);
+                let mut db = db_clone.write().await;
+                db.active_expire_cycle();
+                db.spill_cold_keys();
+                db.prune_tracking();
+                if db.memory_manager.max_memory.is_some() {
+                    db.refresh_memory_usage();
                 }
             }
-        });
+        }));
+
+        if self.database.read().await.warmup.status() == "running" {
+            background.0.push(tokio::spawn(warm_up(Arc::clone(&self.database))));
+        }
+
+        if let Some(bus) = &self.invalidation {
+            for peer in &self.invalidation_peers {
+                let password = self.auth_config.password.clone();
+                background.0.push(tokio::spawn(invalidation::run_peer(peer.clone(), password, bus.subscribe())));
+            }
+        }
 
+        if let Some(refresh) = &self.refresh {
+            background.0.push(spawn_refresher(Arc::clone(&self.database), refresh.clone()));
+        }
+
+        let pubsub_clone = Arc::clone(&self.pubsub);
+        background.0.push(tokio::spawn(async move {
+            let mut interval = interval(REDELIVERY_INTERVAL);
+            loop {
+                interval.tick().await;
+                pubsub_clone.write().await.redeliver_due(std::time::Instant::now());
+            }
+        }));
+
+        let clients = Arc::new(AtomicUsize::new(0));
+        let mut next_client_id = 0;
         loop {
-            let (socket, addr) = listener.accept().await?;
+            let (socket, addr) = listener.accept().await.map_err(ServerError::Accept)?;
+            next_client_id += 1;
             let db = Arc::clone(&self.database);
             let auth_config = Arc::clone(&self.auth_config);
+            let pubsub = Arc::clone(&self.pubsub);
+            let metrics = Arc::clone(&self.metrics);
+            let options = ClientOptions {
+                command_renames: Arc::clone(&self.command_renames),
+                write_stalls: self.write_stalls,
+                write_rate_limit: self.write_rate_limit.clone(),
+                protocol_limits: self.protocol_limits,
+                capture: self.capture.as_ref().map(Capture::client),
+                max_reply_bytes: self.max_reply_bytes,
+                command_budget: self.command_budget,
+                banner: self.banner.clone(),
+                events: ClientEvents { id: next_client_id, addr, log: self.client_events_log.clone() },
+                client: self.clients.register(next_client_id, addr),
+            };
+            let clients = Arc::clone(&clients);
+            let save_now = Arc::clone(&save_now);
+            let save_on_last_disconnect = self.save_policy.on_last_disconnect;
+            clients.fetch_add(1, Ordering::SeqCst);
 
             println!("New client connected: {}", addr);
 
             tokio::spawn(async move {
-                if let Err(e) = handle_client(socket, db, auth_config).await {
+                let events = options.events.clone();
+                let connected = Instant::now();
+                events.emit(&pubsub, "connect", "").await;
+                if let Err(e) = handle_client(socket, db, auth_config, Arc::clone(&pubsub), metrics, options).await {
                     eprintln!("Error handling client: {}", e);
                 }
+                events.emit(&pubsub, "disconnect", &format!(" duration_ms:{}", connected.elapsed().as_millis())).await;
+                if clients.fetch_sub(1, Ordering::SeqCst) == 1 && save_on_last_disconnect {
+                    save_now.notify_one();
+                }
             });
         }
     }
 }
 
+async fn bind(addr: String) -> Result<TcpListener, ServerError> {
+    TcpListener::bind(&addr).await.map_err(|source| ServerError::Bind { addr, source })
+}
+
+// Promotes the hottest cold keys a batch at a time, letting clients in between batches
+async fn warm_up(database: Database) {
+    loop {
+        let mut db = database.write().await;
+        db.warm_up_batch(WARMUP_BATCH);
+        if db.warmup.status() != "running" {
+            println!("Warm-up loaded {} of {} keys from the cold tier", db.warmup.loaded, db.warmup.total);
+            return;
+        }
+        drop(db);
+        tokio::task::yield_now().await;
+    }
+}
+
+// Saves a snapshot and records the outcome. Only the capture holds the lock; values are
+// shared, so writers are not blocked while the snapshot is serialized and written
+async fn background_save(database: &Database, persistence: &Arc<MmapPersistence>, pubsub: &PubSubManager) {
+    let (snapshot, dirty, promotion_mark) = {
+        let mut db = database.write().await;
+        db.save_stats.begin();
+        (Snapshot::capture(&db), db.dirty, db.promotion_mark())
+    };
+    let persistence = Arc::clone(persistence);
+    let saved = tokio::task::spawn_blocking(move || persistence.save_snapshot(snapshot).map_err(|e| e.to_string())).await;
+    let saved = saved.unwrap_or_else(|e| Err(e.to_string()));
+    if let Err(e) = &saved {
+        eprintln!("Background save failed: {}", e);
+    }
+
+    let event = {
+        let mut db = database.write().await;
+        // Changes made while the snapshot was written are still unsaved
+        if saved.is_ok() {
+            db.dirty = db.dirty.saturating_sub(dirty);
+            db.release_promoted(promotion_mark);
+        }
+        db.save_stats.finish(saved)
+    };
+    pubsub.write().await.publish(PERSISTENCE_EVENTS_CHANNEL, event);
+}
+
 async fn handle_client(
     mut socket: TcpStream,
     database: Database,
     auth_config: Arc<AuthConfig>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    pubsub: PubSubManager,
+    metrics: Metrics,
+    options: ClientOptions,
+) -> std::io::Result<()> {
+    let ClientOptions { command_renames, write_stalls, write_rate_limit, protocol_limits, capture, max_reply_bytes, command_budget, mut banner, events, client } = options;
+    let mut write_limit = write_rate_limit.for_client();
     let (reader, mut writer) = socket.split();
-    let mut reader = BufReader::new(reader);
+    let mut requests = RequestReader::with_limits(reader, protocol_limits);
     let mut client_auth = ClientAuth::new(auth_config);
-    let mut buffer = String::new();
-
-    writer.write_all(b"Welcome to Redis-clone!\r\n").await?;
-    writer.flush().await?;
+    // Created on the first subscription made over RESP3; its pushes are interleaved with replies
+    let mut push_subscriber: Option<(usize, SubscriberQueue)> = None;
+    // Frozen copy of the dataset reads go to between SNAPSHOT BEGIN and SNAPSHOT END
+    let mut snapshot: Option<Database> = None;
+    // Commands run since this connection last waited for input
+    let mut burst = 0;
+    // Follows the latest request, so pushes are framed the way the client last spoke
+    let mut format = ReplyFormat::Text;
 
     loop {
-        buffer.clear();
-
-        match reader.read_line(&mut buffer).await? {
-            0 => {
-                // Client disconnected
-                break;
+        let frame = tokio::select! {
+            Some(message) = next_push(&mut push_subscriber) => {
+                let message = push_reply(message, client_auth.key_prefix.as_deref());
+                write_reply(&mut writer, &format.render_push(message, client_auth.protocol)).await?;
+                continue;
+            },
+            frame = requests.next_request() => frame?,
+        };
+        let frame = match frame {
+            Some(frame) => frame,
+            None => break,
+        };
+        format = frame.reply_format();
+        // Only decided by the first request, which tells a person at a terminal from a library
+        if let Some(banner) = banner.take().filter(|_| format == ReplyFormat::Text) {
+            write_reply(&mut writer, &format.render(&Reply::Verbatim(banner.to_string()))).await?;
+        }
+        let (request, request_len) = match frame.request {
+            Ok(request) => (request, frame.size),
+            Err(error) => {
+                write_reply(&mut writer, &format.render(&error.reply())).await?;
+                // The rest of the stream cannot be framed reliably after a malformed request
+                if error.closes_connection() {
+                    break;
+                }
+                continue;
             },
-            _ => {
-                let command_str = buffer.trim();
-                println!("[v0] Received raw input: {:?}", buffer);
-                println!("[v0] Trimmed command: {:?}", command_str);
+        };
+        let command_str = request.text();
+
+        if request.is_empty() {
+            continue;
+        }
+        // More input already buffered means the client is pipelining; past its budget it lets
+        // the other connections' tasks run before taking the next command
+        if !requests.has_buffered() {
+            burst = 0;
+        } else if let Some(budget) = command_budget {
+            burst += 1;
+            if burst >= budget {
+                burst = 0;
+                metrics.write().await.client_yields += 1;
+                tokio::task::yield_now().await;
+            }
+        }
+        if let Some(capture) = &capture {
+            capture.record(&command_str);
+        }
 
-                if command_str.is_empty() {
+        match request.parse(&command_renames).and_then(|command| scope_subscription(command, client_auth.key_prefix.as_deref())) {
+            Ok(command) => {
+                let name = request.name();
+                client.command(name);
+
+                let is_subscription = matches!(command,
+                    Command::Subscribe { .. } | Command::Unsubscribe { .. } |
+                    Command::PSubscribe { .. } | Command::PUnsubscribe { .. } | Command::Ack { .. });
+                if is_subscription && client_auth.protocol == 3 && !client_auth.requires_auth() {
+                    let subscriber_id = match &push_subscriber {
+                        Some((subscriber_id, _)) => *subscriber_id,
+                        None => {
+                            let (subscriber_id, receiver) = pubsub.write().await.create_subscriber();
+                            push_subscriber = Some((subscriber_id, receiver));
+                            client.set_subscriber(Some(subscriber_id));
+                            subscriber_id
+                        },
+                    };
+
+                    let replies = match command {
+                        Command::Ack { ids } => vec![format.render(&Reply::integer(pubsub.write().await.ack(subscriber_id, &ids)))],
+                        command => {
+                            let (replies, count) = apply_subscription(&pubsub, subscriber_id, command).await;
+                            client.set_type(if count > 0 { ClientType::PubSub } else { ClientType::Normal });
+                            let prefix = client_auth.key_prefix.as_deref();
+                            replies.into_iter().map(|reply| format.render_push(push_reply(reply, prefix), client_auth.protocol)).collect()
+                        },
+                    };
+                    let mut written = 0;
+                    for reply in replies {
+                        write_reply(&mut writer, &reply).await?;
+                        written += reply.len();
+                    }
+                    metrics.write().await.record(name, request_len, written);
                     continue;
                 }
 
-                match parse_command(command_str) {
-                    Ok(command) => {
-                        println!("[v0] Parsed command: {:?}", command);
-                        let is_quit = matches!(command, crate::commands::Command::Quit);
-                        let response = execute_command(
-                            Arc::clone(&database),
-                            command,
-                            &mut client_auth,
-                            None
-                        ).await;
-
-                        writer.write_all(response.as_bytes()).await?;
-                        writer.write_all(b"\r\n").await?;
-                        writer.flush().await?;
+                if matches!(command, Command::Subscribe { .. } | Command::PSubscribe { .. }) && !client_auth.requires_auth() {
+                    metrics.write().await.record(name, request_len, 0);
+                    let context = SubscriberContext {
+                        pubsub: &pubsub,
+                        command_renames: &command_renames,
+                        capture: capture.as_ref(),
+                        client: &client,
+                        key_prefix: client_auth.key_prefix.as_deref(),
+                    };
+                    if subscriber_mode(&mut requests, &mut writer, format, context, command).await? {
+                        break;
+                    }
+                    continue;
+                }
 
-                        if is_quit {
+                if let Command::Atomic { count } = command {
+                    let (block, block_len) = read_atomic_block(&mut requests, count, &command_renames, capture.as_ref()).await?;
+                    let commands = match block {
+                        AtomicBlock::Closed(error) => {
+                            if let Some(error) = error {
+                                write_reply(&mut writer, &format.render(&error)).await?;
+                            }
                             break;
-                        }
+                        },
+                        _ if client_auth.requires_auth() => Err(Reply::error("NOAUTH Authentication required.")),
+                        _ if snapshot.is_some() => Err(Reply::error("ERR ATOMIC is not allowed in a snapshot session")),
+                        AtomicBlock::Aborted(error) => Err(error),
+                        AtomicBlock::Ready(commands) => Ok(commands),
+                    };
+                    let response = match commands {
+                        Ok(commands) => {
+                            // The block is refused as a whole, so it never runs partly
+                            let writes = commands.iter().filter(|(_, command)| command.is_write()).count();
+                            if write_limit.is_enabled() && (0..writes).any(|_| !write_limit.try_acquire()) {
+                                metrics.write().await.throttled_writes += 1;
+                                Reply::error("THROTTLED write rate limit exceeded, try again later")
+                            } else {
+                                let rejected = match writes {
+                                    0 => None,
+                                    _ => stall_write(&write_stalls, &database, &metrics).await,
+                                };
+                                match rejected {
+                                    Some(response) => response,
+                                    None => run_atomic(&database, commands, &mut client_auth, &pubsub, &metrics).await,
+                                }
+                            }
+                        },
+                        Err(error) => error,
+                    };
+                    let response = format.render(&response);
+                    write_reply(&mut writer, &response).await?;
+                    metrics.write().await.record(name, request_len + block_len, response.len());
+                    continue;
+                }
+
+                if command.is_write() && snapshot.is_none() && !client_auth.requires_auth() {
+                    if let Some(response) = admit_write(&write_stalls, &mut write_limit, &database, &metrics).await {
+                        let response = format.render(&response);
+                        write_reply(&mut writer, &response).await?;
+                        metrics.write().await.record(name, request_len, response.len());
+                        continue;
+                    }
+                }
+
+                let client_reply = match &command {
+                    _ if client_auth.requires_auth() => None,
+                    Command::ClientId => Some(Reply::integer(client.id())),
+                    Command::ClientList { kind } => Some(list_clients(&client, &pubsub, *kind, client_auth.key_prefix.as_deref()).await),
+                    _ => None,
+                };
+                if let Some(response) = client_reply {
+                    let response = format.render(&response);
+                    write_reply(&mut writer, &response).await?;
+                    metrics.write().await.record(name, request_len, response.len());
+                    continue;
+                }
+
+                let snapshot_reply = match &command {
+                    _ if client_auth.requires_auth() => None,
+                    Command::SnapshotBegin if snapshot.is_some() => Some(Reply::error("ERR snapshot already active")),
+                    Command::SnapshotBegin => {
+                        let frozen = database.read().await.frozen_copy();
+                        snapshot = Some(create_database_with_data(frozen));
+                        Some(Reply::ok())
                     },
-                    Err(error) => {
-                        println!("[v0] Parse error: {}", error);
-                        writer.write_all(error.as_bytes()).await?;
-                        writer.write_all(b"\r\n").await?;
-                        writer.flush().await?;
+                    Command::SnapshotEnd => match snapshot.take() {
+                        Some(_) => Some(Reply::ok()),
+                        None => Some(Reply::error("ERR no snapshot active")),
+                    },
+                    command if snapshot.is_some() && command.is_write() => {
+                        Some(Reply::error("ERR write commands are not allowed in a snapshot session"))
+                    },
+                    _ => None,
+                };
+                if let Some(response) = snapshot_reply {
+                    let response = format.render(&response);
+                    write_reply(&mut writer, &response).await?;
+                    metrics.write().await.record(name, request_len, response.len());
+                    continue;
+                }
+
+                let is_quit = matches!(command, Command::Quit);
+                let changes_tenant = matches!(command, Command::Auth { .. } | Command::SessionAuth { .. });
+                let auth_user = match &command {
+                    Command::Auth { username, .. } => Some(username.clone()),
+                    Command::SessionAuth { .. } => Some(None),
+                    _ => None,
+                };
+                let is_blocking = command.is_blocking();
+                let execution = execute_guarded(
+                    Arc::clone(snapshot.as_ref().unwrap_or(&database)),
+                    command,
+                    &mut client_auth,
+                    Some(&pubsub),
+                    Some(&metrics),
+                    name,
+                );
+                // A client that hangs up while blocked gives up its place, rather than taking
+                // an element nobody will read
+                let response = if is_blocking {
+                    tokio::select! {
+                        response = execution => response,
+                        closed = requests.closed() => {
+                            closed?;
+                            break;
+                        },
                     }
+                } else {
+                    execution.await
+                };
+                if changes_tenant {
+                    client.set_tenant(client_auth.key_prefix.clone());
+                }
+                if let Some(username) = auth_user.filter(|_| response.is_error()) {
+                    let detail = username.map(|username| format!(" user:{}", username)).unwrap_or_default();
+                    events.emit(&pubsub, "auth-failure", &detail).await;
+                }
+
+                let mut response = format.render(&response);
+                // Catches replies the executor could not estimate up front
+                if let Some(limit) = max_reply_bytes.filter(|limit| response.len() > *limit) {
+                    response = format.render(&reply_too_large_error(response.len(), limit));
                 }
+                write_reply(&mut writer, &response).await?;
+                metrics.write().await.record(name, request_len, response.len());
+
+                if is_quit {
+                    break;
+                }
+            },
+            Err(error) => {
+                println!("[v0] Parse error: {}", error);
+                write_reply(&mut writer, &format.render_error(&error)).await?;
             }
         }
     }
 
+    if let Some((subscriber_id, _)) = push_subscriber {
+        pubsub.write().await.remove_subscriber(subscriber_id);
+    }
     Ok(())
 }
+
+// Waits for the next push on a RESP3 connection; never resolves if it has not subscribed
+async fn next_push(subscriber: &mut Option<(usize, SubscriberQueue)>) -> Option<PubSubMessage> {
+    match subscriber {
+        Some((_, receiver)) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Subscriptions are served here rather than by the executor, so a tenant's are scoped here
+fn scope_subscription(command: Command, key_prefix: Option<&str>) -> Result<Command, String> {
+    match key_prefix {
+        Some(prefix) if matches!(command, Command::Subscribe { .. } | Command::Unsubscribe { .. } |
+                                          Command::PSubscribe { .. } | Command::PUnsubscribe { .. }) => {
+            tenancy::scope_command(command, prefix)
+        },
+        _ => Ok(command),
+    }
+}
+
+// A pub/sub message as the connection's tenant, if any, named its channel or pattern
+fn push_reply(message: PubSubMessage, key_prefix: Option<&str>) -> Reply {
+    match key_prefix {
+        Some(prefix) => tenancy::unscope_message(prefix, message).format_reply(),
+        None => message.format_reply(),
+    }
+}
+
+// Applies a (P)SUBSCRIBE/(P)UNSUBSCRIBE under one lock, so retained messages replayed for a
+// new subscription can neither be missed nor duplicated by concurrent publishes.
+async fn apply_subscription(pubsub: &PubSubManager, subscriber_id: usize, command: Command) -> (Vec<PubSubMessage>, usize) {
+    let mut state = pubsub.write().await;
+    let mut replies = Vec::new();
+
+    match command {
+        Command::Subscribe { channels, replay, consumer } => {
+            if let Some(name) = consumer {
+                replies.extend(state.attach_consumer(&name, subscriber_id));
+            }
+            for channel in channels {
+                let count = state.subscribe(subscriber_id, channel.clone());
+                replies.push(PubSubMessage::Subscribe { channel: channel.clone(), count });
+                if let Some(replay) = replay {
+                    for message in state.replay(&channel, replay) {
+                        replies.push(PubSubMessage::Message { channel: Arc::from(channel.as_str()), message, id: None });
+                    }
+                }
+            }
+        },
+        Command::Unsubscribe { channels } => {
+            let channels = if channels.is_empty() { state.subscribed_channels(subscriber_id) } else { channels };
+            for channel in channels {
+                let count = state.unsubscribe(subscriber_id, &channel);
+                replies.push(PubSubMessage::Unsubscribe { channel, count });
+            }
+        },
+        Command::PSubscribe { patterns } => {
+            for pattern in patterns {
+                let count = state.psubscribe(subscriber_id, pattern.clone());
+                replies.push(PubSubMessage::PSubscribe { pattern, count });
+            }
+        },
+        Command::PUnsubscribe { patterns } => {
+            let patterns = if patterns.is_empty() { state.subscribed_patterns(subscriber_id) } else { patterns };
+            for pattern in patterns {
+                let count = state.punsubscribe(subscriber_id, &pattern);
+                replies.push(PubSubMessage::PUnsubscribe { pattern, count });
+            }
+        },
+        _ => {},
+    }
+
+    (replies, state.subscription_count(subscriber_id))
+}
+
+// Writes a reply already framed by ReplyFormat
+async fn write_reply<W: AsyncWriteExt + Unpin>(writer: &mut W, reply: &[u8]) -> std::io::Result<()> {
+    writer.write_all(reply).await?;
+    writer.flush().await
+}
+
+/// Checks one write against the write rate limit, then the write stalls. Returns the refusal
+/// to send instead of running it; a stalled write is only delayed.
+pub(crate) async fn admit_write(write_stalls: &WriteStalls, write_limit: &mut ClientWriteLimit, database: &Database, metrics: &Metrics) -> Option<Reply> {
+    if write_limit.is_enabled() && !write_limit.try_acquire() {
+        metrics.write().await.throttled_writes += 1;
+        return Some(Reply::error("THROTTLED write rate limit exceeded, try again later"));
+    }
+    stall_write(write_stalls, database, metrics).await
+}
+
+async fn stall_write(write_stalls: &WriteStalls, database: &Database, metrics: &Metrics) -> Option<Reply> {
+    if write_stalls.stall_after.is_none() && write_stalls.reject_after.is_none() {
+        return None;
+    }
+    let dirty = database.read().await.dirty;
+    if write_stalls.reject_after.is_some_and(|limit| dirty >= limit) {
+        metrics.write().await.rejected_writes += 1;
+        return Some(Reply::error("BUSY persistence is behind, try again later"));
+    }
+    if write_stalls.stall_after.is_some_and(|limit| dirty >= limit) {
+        metrics.write().await.stalled_writes += 1;
+        tokio::time::sleep(write_stalls.delay).await;
+    }
+    None
+}
+
+// The requests an ATOMIC block covers, all read before any of them runs
+enum AtomicBlock {
+    Ready(Vec<(String, Command)>),
+    // One of them could not be queued, so the whole block is discarded with this reply
+    Aborted(Reply),
+    // The connection ended, or lost its framing with this error, partway through the block
+    Closed(Option<Reply>),
+}
+
+// Commands that wait, run in the background, or change the connection rather than the dataset;
+// none of them can run while the block holds the write lock
+fn allowed_in_atomic(command: &Command) -> bool {
+    !matches!(command,
+        Command::Subscribe { .. } | Command::Unsubscribe { .. } | Command::PSubscribe { .. } |
+        Command::PUnsubscribe { .. } | Command::Ack { .. } | Command::DelayQBPop { .. } | Command::BPop { .. } | Command::BLMove { .. } |
+        Command::Auth { .. } | Command::SessionAuth { .. } | Command::Hello { .. } |
+        Command::SnapshotBegin | Command::SnapshotEnd | Command::ClientList { .. } | Command::ClientId |
+        Command::Atomic { .. } | Command::Merge { .. } | Command::DelPattern { .. } | Command::DebugPersistenceBench { .. } | Command::Quit)
+}
+
+/// Reads the `count` requests following ATOMIC. They are all consumed even when one is
+/// refused, so none of them runs on its own afterwards. Also returns their size in bytes.
+async fn read_atomic_block<R: AsyncRead + Unpin>(
+    requests: &mut RequestReader<R>,
+    count: usize,
+    command_renames: &CommandRenames,
+    capture: Option<&ClientCapture>,
+) -> std::io::Result<(AtomicBlock, usize)> {
+    let mut commands = Vec::with_capacity(count);
+    let mut error = None;
+    let mut read = 0;
+    let mut size = 0;
+    while read < count {
+        let Some(frame) = requests.next_request().await? else {
+            return Ok((AtomicBlock::Closed(None), size));
+        };
+        size += frame.size;
+        let request = match frame.request {
+            Ok(request) if request.is_empty() => continue,
+            Ok(request) => request,
+            Err(frame_error) if frame_error.closes_connection() => return Ok((AtomicBlock::Closed(Some(frame_error.reply())), size)),
+            Err(frame_error) => {
+                read += 1;
+                error.get_or_insert(frame_error.message());
+                continue;
+            },
+        };
+        read += 1;
+        if let Some(capture) = capture {
+            capture.record(&request.text());
+        }
+        let name = request.name().to_string();
+        match request.parse(command_renames) {
+            Ok(command) if allowed_in_atomic(&command) => commands.push((name, command)),
+            Ok(_) => {
+                error.get_or_insert(format!("ERR '{}' is not allowed in ATOMIC", name.to_lowercase()));
+            },
+            Err(parse_error) => {
+                error.get_or_insert(parse_error);
+            },
+        }
+    }
+    let block = match error {
+        Some(error) => AtomicBlock::Aborted(Reply::error(format!("EXECABORT ATOMIC discarded because of a previous error: {}", error))),
+        None => AtomicBlock::Ready(commands),
+    };
+    Ok((block, size))
+}
+
+/// Runs an ATOMIC block's commands back to back under one write lock, so no other client sees
+/// the dataset between them, and replies with an array of their replies.
+async fn run_atomic(
+    database: &Database,
+    commands: Vec<(String, Command)>,
+    client_auth: &mut ClientAuth,
+    pubsub: &PubSubManager,
+    metrics: &Metrics,
+) -> Reply {
+    let mut guard = database.write().await;
+    // The executor takes the lock itself for each command, so the dataset is moved behind a
+    // handle only this block can reach and put back when it is done
+    let block_db = create_database_with_data(std::mem::take(&mut *guard));
+    let mut replies = Vec::with_capacity(commands.len());
+    for (name, command) in commands {
+        replies.push(execute_guarded(Arc::clone(&block_db), command, client_auth, Some(pubsub), Some(metrics), &name).await);
+    }
+    *guard = std::mem::take(&mut *block_db.write().await);
+
+    Reply::Array(replies)
+}
+
+// What subscriber mode uses of the connection besides its socket
+struct SubscriberContext<'a> {
+    pubsub: &'a PubSubManager,
+    command_renames: &'a CommandRenames,
+    capture: Option<&'a ClientCapture>,
+    client: &'a ClientHandle,
+    key_prefix: Option<&'a str>,
+}
+
+/// Runs the connection in subscriber mode until it has no subscriptions left. Only
+/// (P)SUBSCRIBE, (P)UNSUBSCRIBE, ACK, PING and QUIT are accepted meanwhile. Returns true if the
+/// client quit or disconnected.
+async fn subscriber_mode<R, W>(
+    requests: &mut RequestReader<R>,
+    writer: &mut W,
+    mut format: ReplyFormat,
+    context: SubscriberContext<'_>,
+    first_command: Command,
+) -> std::io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    let SubscriberContext { pubsub, command_renames, capture, client, key_prefix } = context;
+    let (subscriber_id, mut receiver) = pubsub.write().await.create_subscriber();
+    client.set_subscriber(Some(subscriber_id));
+    client.set_type(ClientType::PubSub);
+
+    let (replies, mut count) = apply_subscription(pubsub, subscriber_id, first_command).await;
+    for reply in replies {
+        write_reply(writer, &format.render(&push_reply(reply, key_prefix))).await?;
+    }
+
+    let mut disconnected = false;
+    while count > 0 && !disconnected {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(message) => write_reply(writer, &format.render(&push_reply(message, key_prefix))).await?,
+                None => break,
+            },
+            frame = requests.next_request() => {
+                let frame = match frame? {
+                    Some(frame) => frame,
+                    None => {
+                        disconnected = true;
+                        continue;
+                    },
+                };
+                format = frame.reply_format();
+                let request = match frame.request {
+                    Ok(request) => request,
+                    Err(error) => {
+                        write_reply(writer, &format.render(&error.reply())).await?;
+                        disconnected = error.closes_connection();
+                        continue;
+                    },
+                };
+                if request.is_empty() {
+                    continue;
+                }
+                if let Some(capture) = capture {
+                    capture.record(&request.text());
+                }
+                client.command(request.name());
+
+                match request.parse(command_renames).and_then(|command| scope_subscription(command, key_prefix)) {
+                    Ok(command @ (Command::Subscribe { .. } | Command::Unsubscribe { .. } |
+                                  Command::PSubscribe { .. } | Command::PUnsubscribe { .. })) => {
+                        let (replies, new_count) = apply_subscription(pubsub, subscriber_id, command).await;
+                        count = new_count;
+                        for reply in replies {
+                            write_reply(writer, &format.render(&push_reply(reply, key_prefix))).await?;
+                        }
+                    },
+                    Ok(Command::Ack { ids }) => {
+                        let acked = pubsub.write().await.ack(subscriber_id, &ids);
+                        write_reply(writer, &format.render(&Reply::integer(acked))).await?;
+                    },
+                    Ok(Command::Ping { .. }) => write_reply(writer, &format.render(&Reply::bulks(["pong", ""]))).await?,
+                    Ok(Command::Quit) => {
+                        write_reply(writer, &format.render(&Reply::ok())).await?;
+                        disconnected = true;
+                    },
+                    Ok(_) => write_reply(writer, &format.render(&Reply::error("ERR only (P)SUBSCRIBE / (P)UNSUBSCRIBE / ACK / PING / QUIT are allowed in this context"))).await?,
+                    Err(error) => write_reply(writer, &format.render_error(&error)).await?,
+                }
+            },
+        }
+    }
+
+    pubsub.write().await.remove_subscriber(subscriber_id);
+    client.set_subscriber(None);
+    client.set_type(ClientType::Normal);
+    Ok(disconnected)
+}
+
+// CLIENT LIST: one line per connection, in connection order; a tenant only sees its own
+async fn list_clients(client: &ClientHandle, pubsub: &PubSubManager, kind: Option<ClientType>, key_prefix: Option<&str>) -> Reply {
+    let mut clients = client.registry().list(kind);
+    if let Some(prefix) = key_prefix {
+        clients.retain(|client| client.tenant.as_deref() == Some(prefix));
+    }
+    let state = pubsub.read().await;
+    let now = Instant::now();
+    let lines: Vec<String> = clients.iter()
+        .map(|client| {
+            let subscriptions = client.subscriber.map_or((0, 0), |id| {
+                (state.subscribed_channels(id).len(), state.subscribed_patterns(id).len())
+            });
+            client.render(subscriptions, now)
+        })
+        .collect();
+    Reply::bulk(lines.join("\n"))
+}
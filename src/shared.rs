@@ -0,0 +1,20 @@
+// The handle the async layer shares the storage core through. RedisDatabase itself has no
+// runtime dependency and can be driven synchronously by an embedder; the server, the command
+// executor and the other listeners hold it behind a tokio lock so connections can share it.
+use crate::database::RedisDatabase;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub type Database = Arc<RwLock<RedisDatabase>>;
+
+pub fn create_database() -> Database {
+    Arc::new(RwLock::new(RedisDatabase::new()))
+}
+
+pub fn create_database_with_data(db: RedisDatabase) -> Database {
+    Arc::new(RwLock::new(db))
+}
+
+pub fn create_database_with_memory_config(max_memory: Option<usize>, eviction_policy: String) -> Database {
+    Arc::new(RwLock::new(RedisDatabase::new_with_memory_config(max_memory, eviction_policy)))
+}
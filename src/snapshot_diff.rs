@@ -0,0 +1,177 @@
+// Compares two databases key by key, for checking that a backup or a migrated copy holds what
+// the original did. Values are compared by type: strings by content, lists by position, sets by
+// member, hashes by field; other types are compared whole.
+use crate::data_types::RedisValue;
+use crate::database::RedisDatabase;
+use std::collections::BTreeSet;
+
+// Details listed for one key before the rest are summarized as a count
+const MAX_DETAILS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added { type_name: &'static str },
+    Removed { type_name: &'static str },
+    Changed { details: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyDiff {
+    pub key: String,
+    pub change: Change,
+}
+
+impl std::fmt::Display for KeyDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.change {
+            Change::Added { type_name } => write!(f, "+ {} ({})", self.key, type_name),
+            Change::Removed { type_name } => write!(f, "- {} ({})", self.key, type_name),
+            Change::Changed { details } => write!(f, "~ {}: {}", self.key, details.join(", ")),
+        }
+    }
+}
+
+/// Every key added, removed or changed going from `old` to `new`, sorted by key.
+pub fn diff_databases(old: &RedisDatabase, new: &RedisDatabase) -> Vec<KeyDiff> {
+    let keys: BTreeSet<&String> = old.data.keys().chain(new.data.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let change = match (old.data.get(key), new.data.get(key)) {
+                (Some(value), None) => Change::Removed { type_name: value.type_name() },
+                (None, Some(value)) => Change::Added { type_name: value.type_name() },
+                (Some(before), Some(after)) => {
+                    let mut details = diff_values(before, after);
+                    match (old.expires.contains_key(key), new.expires.contains_key(key)) {
+                        (false, true) => details.push("ttl added".to_string()),
+                        (true, false) => details.push("ttl removed".to_string()),
+                        _ => {},
+                    }
+                    if details.is_empty() {
+                        return None;
+                    }
+                    Change::Changed { details: truncate(details) }
+                },
+                (None, None) => return None,
+            };
+            Some(KeyDiff { key: key.clone(), change })
+        })
+        .collect()
+}
+
+fn truncate(mut details: Vec<String>) -> Vec<String> {
+    if details.len() > MAX_DETAILS {
+        let more = details.len() - MAX_DETAILS;
+        details.truncate(MAX_DETAILS);
+        details.push(format!("... {} more", more));
+    }
+    details
+}
+
+/// What differs between two values of a key; empty when they are equal.
+pub fn diff_values(before: &RedisValue, after: &RedisValue) -> Vec<String> {
+    if before.type_name() != after.type_name() {
+        return vec![format!("type {} -> {}", before.type_name(), after.type_name())];
+    }
+    match (before, after) {
+        (RedisValue::List(before), RedisValue::List(after)) => {
+            let mut details = Vec::new();
+            if before.len() != after.len() {
+                details.push(format!("length {} -> {}", before.len(), after.len()));
+            }
+            if let Some(index) = before.iter().zip(after).position(|(a, b)| a != b) {
+                details.push(format!("index {} {:?} -> {:?}", index, before[index], after[index]));
+            }
+            details
+        },
+        (RedisValue::Set(before), RedisValue::Set(after)) => {
+            let removed: BTreeSet<&String> = before.difference(after).collect();
+            let added: BTreeSet<&String> = after.difference(before).collect();
+            added.into_iter().map(|member| format!("+member {:?}", member))
+                .chain(removed.into_iter().map(|member| format!("-member {:?}", member)))
+                .collect()
+        },
+        (RedisValue::Hash(before), RedisValue::Hash(after)) => {
+            let fields: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+            fields.into_iter()
+                .filter_map(|field| match (before.get(field), after.get(field)) {
+                    (None, Some(_)) => Some(format!("+field {:?}", field)),
+                    (Some(_), None) => Some(format!("-field {:?}", field)),
+                    (Some(a), Some(b)) if a != b => Some(format!("field {:?} {:?} -> {:?}", field, a, b)),
+                    _ => None,
+                })
+                .collect()
+        },
+        // Strings and integers, including compressed strings, compare by what GET returns
+        (RedisValue::String(_) | RedisValue::CompressedString(_) | RedisValue::Integer(_) | RedisValue::Bytes(_), _) => {
+            let (before, after) = (before.to_string(), after.to_string());
+            if before == after { Vec::new() } else { vec![format!("{:?} -> {:?}", before, after)] }
+        },
+        _ => {
+            if serde_json::to_value(before).ok() == serde_json::to_value(after).ok() {
+                return Vec::new();
+            }
+            let (before, after) = (before.to_string(), after.to_string());
+            if before == after {
+                vec![format!("{} contents changed", after)]
+            } else {
+                vec![format!("{} -> {}", before, after)]
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn database(entries: Vec<(&str, RedisValue)>) -> RedisDatabase {
+        let mut db = RedisDatabase::new();
+        for (key, value) in entries {
+            db.data.insert(key.to_string(), Arc::new(value));
+        }
+        db
+    }
+
+    #[test]
+    fn test_reports_added_removed_and_changed_keys() {
+        let old = database(vec![
+            ("same", RedisValue::String("v".to_string())),
+            ("gone", RedisValue::List(["a".to_string()].into())),
+            ("retyped", RedisValue::String("1".to_string())),
+            ("volatile", RedisValue::Integer(5)),
+        ]);
+        let mut new = database(vec![
+            ("same", RedisValue::String("v".to_string())),
+            ("new", RedisValue::Set(["m".to_string()].into())),
+            ("retyped", RedisValue::List(["1".to_string()].into())),
+            ("volatile", RedisValue::Integer(5)),
+        ]);
+        new.expires.insert("volatile".to_string(), Instant::now() + Duration::from_secs(60));
+
+        let lines: Vec<String> = diff_databases(&old, &new).iter().map(|diff| diff.to_string()).collect();
+        assert_eq!(lines, vec![
+            "- gone (list)",
+            "+ new (set)",
+            "~ retyped: type string -> list",
+            "~ volatile: ttl added",
+        ]);
+    }
+
+    #[test]
+    fn test_value_diffs_follow_the_type() {
+        let hash = |pairs: &[(&str, &str)]| RedisValue::Hash(pairs.iter().map(|(f, v)| (f.to_string(), v.to_string())).collect());
+        assert_eq!(
+            diff_values(&hash(&[("a", "1"), ("b", "2")]), &hash(&[("b", "3"), ("c", "4")])),
+            vec!["-field \"a\"", "field \"b\" \"2\" -> \"3\"", "+field \"c\""]
+        );
+
+        let list = |items: &[&str]| RedisValue::List(items.iter().map(|item| item.to_string()).collect());
+        assert_eq!(diff_values(&list(&["a", "b"]), &list(&["a", "x", "c"])), vec!["length 2 -> 3", "index 1 \"b\" -> \"x\""]);
+        assert!(diff_values(&list(&["a"]), &list(&["a"])).is_empty());
+
+        let set = |members: &[&str]| RedisValue::Set(members.iter().map(|member| member.to_string()).collect());
+        assert_eq!(diff_values(&set(&["a", "b"]), &set(&["b", "c"])), vec!["+member \"c\"", "-member \"a\""]);
+    }
+}
@@ -0,0 +1,389 @@
+// Backing stores for keys that do not fit in memory. The in-memory map in RedisDatabase stays
+// the hot tier; when a cold tier is attached, the least recently used keys beyond its
+// `max_hot_keys` are moved to the engine and brought back on their next access.
+use crate::data_types::RedisValue;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Compaction is skipped for logs smaller than this, however much of them is garbage
+const COMPACT_MIN_BYTES: u64 = 1024 * 1024;
+
+pub trait StorageEngine: std::fmt::Debug + Send + Sync {
+    /// The value stored under `key` with its expiry (unix ms), `None` if absent or expired.
+    fn get(&mut self, key: &str) -> Result<Option<(RedisValue, Option<u64>)>, String>;
+    fn set(&mut self, key: &str, value: &RedisValue, expires_at_ms: Option<u64>) -> Result<(), String>;
+    fn delete(&mut self, key: &str) -> Result<bool, String>;
+    /// Replaces the expiry of `key`; returns false if the key is not stored.
+    fn expire(&mut self, key: &str, expires_at_ms: Option<u64>) -> Result<bool, String>;
+    /// Every stored key that has not expired.
+    fn scan(&self) -> Vec<String>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn clear(&mut self) -> Result<(), String>;
+}
+
+#[derive(Debug)]
+pub struct ColdTier {
+    pub engine: Box<dyn StorageEngine>,
+    pub max_hot_keys: usize,
+    // Keys brought back into memory whose record is kept until a snapshot holds them, so a crash
+    // before the next save cannot lose them. Each maps to the order it was promoted in
+    promoted: HashMap<String, u64>,
+    promotions: u64,
+}
+
+impl ColdTier {
+    pub fn new(engine: Box<dyn StorageEngine>, max_hot_keys: usize) -> Self {
+        Self { engine, max_hot_keys, promoted: HashMap::new(), promotions: 0 }
+    }
+
+    /// Records that `key` is in memory again while its record stays in the engine.
+    pub fn mark_promoted(&mut self, key: &str) {
+        self.promotions += 1;
+        self.promoted.insert(key.to_string(), self.promotions);
+    }
+
+    /// Stops tracking `key` as promoted, once its record was deleted or replaced.
+    pub fn unmark_promoted(&mut self, key: &str) {
+        self.promoted.remove(key);
+    }
+
+    pub fn is_promoted(&self, key: &str) -> bool {
+        self.promoted.contains_key(key)
+    }
+
+    /// Taken with a snapshot: `release_promoted` with it only drops records of keys the
+    /// snapshot captured.
+    pub fn promotion_mark(&self) -> u64 {
+        self.promotions
+    }
+
+    /// Deletes the kept records of keys promoted up to `mark`, once a snapshot holding them is
+    /// on disk. Returns how many were deleted.
+    pub fn release_promoted(&mut self, mark: u64) -> Result<usize, String> {
+        let released: Vec<String> = self.promoted.iter()
+            .filter(|(_, order)| **order <= mark)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &released {
+            self.engine.delete(key)?;
+            self.promoted.remove(key);
+        }
+        Ok(released.len())
+    }
+
+    /// Keys only the engine holds, leaving out promoted ones.
+    pub fn cold_len(&self) -> usize {
+        self.engine.len().saturating_sub(self.promoted.len())
+    }
+
+    pub fn clear(&mut self) -> Result<(), String> {
+        self.promoted.clear();
+        self.engine.clear()
+    }
+}
+
+/// How the data plane stores values, set at startup.
+#[derive(Debug, Default)]
+pub struct StorageConfig {
+    pub cold_tier: Option<ColdTier>,
+    // Strings at least this long are kept LZ4-compressed in memory
+    pub compression_threshold: Option<usize>,
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+#[derive(Deserialize)]
+struct Record {
+    key: String,
+    // None marks a deletion
+    value: Option<RedisValue>,
+    #[serde(default)]
+    expires_at_ms: Option<u64>,
+}
+
+// Borrowing counterpart of Record, so writes do not clone the value
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    key: &'a str,
+    value: Option<&'a RedisValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    offset: u64,
+    len: u64,
+    expires_at_ms: Option<u64>,
+}
+
+/// Append-only log of JSON records, one per line. Only the offset of each key's latest record
+/// is kept in memory, so every read costs a seek; the log is rewritten once more than half of
+/// it is superseded records.
+#[derive(Debug)]
+pub struct DiskEngine {
+    path: PathBuf,
+    file: File,
+    index: HashMap<String, Location>,
+    end: u64,
+    live_bytes: u64,
+}
+
+impl DiskEngine {
+    /// Opens (or creates) the log in `dir` and rebuilds the index from it. A torn record at
+    /// the end, left by a crash mid-write, is cut off.
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join("storage.log");
+        let file = Self::open_log(&path)?;
+
+        let mut engine = Self { path, file, index: HashMap::new(), end: 0, live_bytes: 0 };
+        engine.rebuild_index()?;
+        Ok(engine)
+    }
+
+    fn open_log(path: &Path) -> Result<File, String> {
+        OpenOptions::new().read(true).append(true).create(true).open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+    }
+
+    fn rebuild_index(&mut self) -> Result<(), String> {
+        let mut reader = BufReader::new(self.file.try_clone().map_err(|e| e.to_string())?);
+        let mut offset = 0u64;
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line).map_err(|e| e.to_string())? as u64;
+            if read == 0 {
+                break;
+            }
+            let record = match line.strip_suffix(b"\n").map(serde_json::from_slice::<Record>) {
+                Some(Ok(record)) => record,
+                _ => {
+                    eprintln!("Truncating torn record at offset {} of {}", offset, self.path.display());
+                    self.file.set_len(offset).map_err(|e| e.to_string())?;
+                    break;
+                },
+            };
+
+            self.forget(&record.key);
+            if record.value.is_some() {
+                let location = Location { offset, len: read - 1, expires_at_ms: record.expires_at_ms };
+                self.live_bytes += location.len;
+                self.index.insert(record.key, location);
+            }
+            offset += read;
+        }
+
+        self.end = offset;
+        Ok(())
+    }
+
+    fn forget(&mut self, key: &str) -> bool {
+        match self.index.remove(key) {
+            Some(location) => {
+                self.live_bytes -= location.len;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn append(&mut self, record: &RecordRef) -> Result<Location, String> {
+        let mut line = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+        let len = line.len() as u64;
+        line.push(b'\n');
+        self.file.write_all(&line).map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))?;
+
+        let location = Location { offset: self.end, len, expires_at_ms: record.expires_at_ms };
+        self.end += len + 1;
+        Ok(location)
+    }
+
+    fn read_record(&mut self, location: Location) -> Result<Record, String> {
+        let mut buffer = vec![0u8; location.len as usize];
+        self.file.seek(SeekFrom::Start(location.offset)).map_err(|e| e.to_string())?;
+        self.file.read_exact(&mut buffer).map_err(|e| e.to_string())?;
+        serde_json::from_slice(&buffer).map_err(|e| format!("Corrupt record in {}: {}", self.path.display(), e))
+    }
+
+    fn maybe_compact(&mut self) -> Result<(), String> {
+        if self.end < COMPACT_MIN_BYTES || self.live_bytes * 2 > self.end {
+            return Ok(());
+        }
+
+        let temp_path = self.path.with_extension("log.tmp");
+        let mut temp = File::create(&temp_path).map_err(|e| e.to_string())?;
+        let mut index = HashMap::with_capacity(self.index.len());
+        let mut end = 0u64;
+
+        let locations: Vec<(String, Location)> = self.index.iter().map(|(key, location)| (key.clone(), *location)).collect();
+        for (key, location) in locations {
+            let mut buffer = vec![0u8; location.len as usize + 1];
+            self.file.seek(SeekFrom::Start(location.offset)).map_err(|e| e.to_string())?;
+            self.file.read_exact(&mut buffer).map_err(|e| e.to_string())?;
+            temp.write_all(&buffer).map_err(|e| e.to_string())?;
+            index.insert(key, Location { offset: end, ..location });
+            end += buffer.len() as u64;
+        }
+        temp.sync_all().map_err(|e| e.to_string())?;
+        fs::rename(&temp_path, &self.path).map_err(|e| e.to_string())?;
+
+        self.file = Self::open_log(&self.path)?;
+        self.index = index;
+        self.end = end;
+        Ok(())
+    }
+}
+
+impl StorageEngine for DiskEngine {
+    fn get(&mut self, key: &str) -> Result<Option<(RedisValue, Option<u64>)>, String> {
+        let location = match self.index.get(key) {
+            Some(location) => *location,
+            None => return Ok(None),
+        };
+        if location.expires_at_ms.is_some_and(|at| now_millis() >= at) {
+            self.delete(key)?;
+            return Ok(None);
+        }
+
+        let record = self.read_record(location)?;
+        Ok(record.value.map(|value| (value, record.expires_at_ms)))
+    }
+
+    fn set(&mut self, key: &str, value: &RedisValue, expires_at_ms: Option<u64>) -> Result<(), String> {
+        let location = self.append(&RecordRef { key, value: Some(value), expires_at_ms })?;
+        self.forget(key);
+        self.live_bytes += location.len;
+        self.index.insert(key.to_string(), location);
+        self.maybe_compact()
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool, String> {
+        if !self.forget(key) {
+            return Ok(false);
+        }
+        self.append(&RecordRef { key, value: None, expires_at_ms: None })?;
+        self.maybe_compact()?;
+        Ok(true)
+    }
+
+    fn expire(&mut self, key: &str, expires_at_ms: Option<u64>) -> Result<bool, String> {
+        match self.get(key)? {
+            Some((value, _)) => {
+                self.set(key, &value, expires_at_ms)?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn scan(&self) -> Vec<String> {
+        let now = now_millis();
+        self.index.iter()
+            .filter(|(_, location)| location.expires_at_ms.is_none_or(|at| now < at))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn clear(&mut self) -> Result<(), String> {
+        self.file.set_len(0).map_err(|e| e.to_string())?;
+        self.index.clear();
+        self.end = 0;
+        self.live_bytes = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust_redis_storage_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_disk_engine_survives_reopen() {
+        let dir = temp_dir("reopen");
+        {
+            let mut engine = DiskEngine::open(&dir).unwrap();
+            engine.set("a", &RedisValue::String("1".to_string()), None).unwrap();
+            engine.set("b", &RedisValue::Integer(2), Some(now_millis() + 60_000)).unwrap();
+            engine.set("a", &RedisValue::String("3".to_string()), None).unwrap();
+            engine.set("gone", &RedisValue::Integer(0), None).unwrap();
+            assert!(engine.delete("gone").unwrap());
+            assert!(!engine.delete("gone").unwrap());
+        }
+        // A torn write at the tail must not lose the records before it
+        OpenOptions::new().append(true).open(dir.join("storage.log")).unwrap().write_all(b"{\"key\":\"c\"").unwrap();
+
+        let mut engine = DiskEngine::open(&dir).unwrap();
+        let mut keys = engine.scan();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b"]);
+        assert!(matches!(engine.get("a").unwrap(), Some((RedisValue::String(s), None)) if s == "3"));
+        assert!(matches!(engine.get("b").unwrap(), Some((RedisValue::Integer(2), Some(_)))));
+
+        assert!(engine.expire("b", Some(now_millis() - 1)).unwrap());
+        assert!(engine.get("b").unwrap().is_none());
+        assert_eq!(engine.len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_disk_engine_compacts_superseded_records() {
+        let dir = temp_dir("compact");
+        let mut engine = DiskEngine::open(&dir).unwrap();
+        let value = RedisValue::String("x".repeat(4096));
+        for _ in 0..600 {
+            engine.set("hot", &value, None).unwrap();
+        }
+        engine.set("other", &RedisValue::Integer(7), None).unwrap();
+
+        assert!(engine.end < COMPACT_MIN_BYTES);
+        assert!(matches!(engine.get("hot").unwrap(), Some((RedisValue::String(s), None)) if s.len() == 4096));
+        drop(engine);
+
+        let mut engine = DiskEngine::open(&dir).unwrap();
+        assert_eq!(engine.len(), 2);
+        assert!(matches!(engine.get("other").unwrap(), Some((RedisValue::Integer(7), None))));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_promoted_records_wait_for_a_snapshot() {
+        let dir = temp_dir("promoted");
+        let mut tier = ColdTier::new(Box::new(DiskEngine::open(&dir).unwrap()), 10);
+        for key in ["a", "b", "c"] {
+            tier.engine.set(key, &RedisValue::Integer(1), None).unwrap();
+        }
+        tier.mark_promoted("a");
+        tier.mark_promoted("b");
+        let mark = tier.promotion_mark();
+        // Promoted after the snapshot was captured, so it is not in it
+        tier.mark_promoted("c");
+        assert_eq!(tier.cold_len(), 0);
+
+        assert_eq!(tier.release_promoted(mark).unwrap(), 2);
+        assert_eq!(tier.engine.scan(), vec!["c"]);
+        assert!(tier.is_promoted("c"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
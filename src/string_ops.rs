@@ -0,0 +1,263 @@
+// Byte-level string algorithms shared by the string command family
+
+// Same cap Redis applies to the transient LCS table (proto-max-bulk-len)
+const MAX_LCS_TABLE_BYTES: usize = 512 * 1024 * 1024;
+// SETBIT offsets stay within a 512MB string, as in Redis
+pub const MAX_BIT_OFFSET: u64 = 512 * 1024 * 1024 * 8 - 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LcsMatch {
+    pub a_range: (usize, usize),
+    pub b_range: (usize, usize),
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LcsResult {
+    pub subsequence: Vec<u8>,
+    pub matches: Vec<LcsMatch>,
+}
+
+/// Longest common subsequence of `a` and `b`. Matching ranges are reported from the end of
+/// the strings backwards, skipping ranges shorter than `min_match_len`.
+pub fn lcs(a: &[u8], b: &[u8], min_match_len: usize) -> Result<LcsResult, String> {
+    let alen = a.len();
+    let blen = b.len();
+
+    let cells = (alen + 1).checked_mul(blen + 1)
+        .filter(|cells| cells.saturating_mul(std::mem::size_of::<u32>()) <= MAX_LCS_TABLE_BYTES)
+        .ok_or_else(|| "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len".to_string())?;
+
+    let mut table = vec![0u32; cells];
+    let at = |i: usize, j: usize| i * (blen + 1) + j;
+
+    for i in 1..=alen {
+        for j in 1..=blen {
+            table[at(i, j)] = if a[i - 1] == b[j - 1] {
+                table[at(i - 1, j - 1)] + 1
+            } else {
+                table[at(i - 1, j)].max(table[at(i, j - 1)])
+            };
+        }
+    }
+
+    let mut subsequence = vec![0u8; table[at(alen, blen)] as usize];
+    let mut idx = subsequence.len();
+    let mut matches = Vec::new();
+
+    // alen doubles as the "no range open" sentinel
+    let (mut arange_start, mut arange_end, mut brange_start, mut brange_end) = (alen, 0, 0, 0);
+    let (mut i, mut j) = (alen, blen);
+
+    while i > 0 && j > 0 {
+        let mut emit_range = false;
+
+        if a[i - 1] == b[j - 1] {
+            subsequence[idx - 1] = a[i - 1];
+
+            if arange_start == alen {
+                arange_start = i - 1;
+                arange_end = i - 1;
+                brange_start = j - 1;
+                brange_end = j - 1;
+            } else if arange_start == i && brange_start == j {
+                // Contiguous with the open range, extend it backwards
+                arange_start -= 1;
+                brange_start -= 1;
+            } else {
+                emit_range = true;
+            }
+
+            if arange_start == 0 || brange_start == 0 {
+                emit_range = true;
+            }
+            idx -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[at(i - 1, j)] > table[at(i, j - 1)] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            if arange_start != alen {
+                emit_range = true;
+            }
+        }
+
+        if emit_range {
+            let len = arange_end - arange_start + 1;
+            if min_match_len == 0 || len >= min_match_len {
+                matches.push(LcsMatch {
+                    a_range: (arange_start, arange_end),
+                    b_range: (brange_start, brange_end),
+                    len,
+                });
+            }
+            arange_start = alen;
+        }
+    }
+
+    Ok(LcsResult { subsequence, matches })
+}
+
+/// Bit `offset` of `bytes`, counting from the most significant bit of the first byte. Bits
+/// past the end are 0.
+pub fn get_bit(bytes: &[u8], offset: u64) -> u8 {
+    match bytes.get((offset / 8) as usize) {
+        Some(byte) => (byte >> (7 - offset % 8)) & 1,
+        None => 0,
+    }
+}
+
+/// Sets bit `offset` of `bytes`, zero-padding them as needed, and returns what it was.
+pub fn set_bit(bytes: &mut Vec<u8>, offset: u64, on: bool) -> u8 {
+    let index = (offset / 8) as usize;
+    if index >= bytes.len() {
+        bytes.resize(index + 1, 0);
+    }
+    let mask = 1 << (7 - offset % 8);
+    let old = (bytes[index] & mask != 0) as u8;
+    if on {
+        bytes[index] |= mask;
+    } else {
+        bytes[index] &= !mask;
+    }
+    old
+}
+
+/// Set bits of `bytes` between `start` and `end` inclusive, counted in bytes or, with `bits`,
+/// in bits. Negative positions count from the end, as in GETRANGE.
+pub fn bit_count(bytes: &[u8], range: Option<(i64, i64)>, bits: bool) -> u64 {
+    let unit = if bits { 8 } else { 1 };
+    let len = bytes.len() as i64 * unit;
+    let (start, end) = match range {
+        None => (0, len - 1),
+        Some((start, end)) => {
+            let start = if start < 0 { (len + start).max(0) } else { start };
+            let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+            (start, end)
+        },
+    };
+    if len == 0 || start > end {
+        return 0;
+    }
+    // Whole bytes in between, with the first and last masked down to the bits in range
+    let (first_bit, last_bit) = if bits { (start, end) } else { (start * 8, end * 8 + 7) };
+    let (first, last) = ((first_bit / 8) as usize, (last_bit / 8) as usize);
+    let first_mask = 0xFFu8 >> (first_bit % 8);
+    let last_mask = 0xFFu8 << (7 - last_bit % 8);
+    if first == last {
+        return (bytes[first] & first_mask & last_mask).count_ones() as u64;
+    }
+    let middle: u64 = bytes[first + 1..last].iter().map(|byte| byte.count_ones() as u64).sum();
+    (bytes[first] & first_mask).count_ones() as u64 + middle + (bytes[last] & last_mask).count_ones() as u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl BitOp {
+    pub fn from_string(op: &str) -> Option<Self> {
+        match op.to_uppercase().as_str() {
+            "AND" => Some(BitOp::And),
+            "OR" => Some(BitOp::Or),
+            "XOR" => Some(BitOp::Xor),
+            "NOT" => Some(BitOp::Not),
+            _ => None,
+        }
+    }
+}
+
+/// BITOP over `sources`, as long as the longest of them; shorter ones are zero-padded. NOT
+/// takes exactly one source.
+pub fn bit_op(op: BitOp, sources: &[Vec<u8>]) -> Vec<u8> {
+    let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            let mut bytes = sources.iter().map(|source| source.get(i).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                BitOp::And => bytes.fold(first, |acc, byte| acc & byte),
+                BitOp::Or => bytes.fold(first, |acc, byte| acc | byte),
+                BitOp::Xor => bytes.fold(first, |acc, byte| acc ^ byte),
+                BitOp::Not => !first,
+            }
+        })
+        .collect()
+}
+
+/// Bytes that are not valid UTF-8 as redis-cli prints them: printable ASCII as is, quotes and
+/// backslashes escaped, and everything else as \xHH.
+pub fn escape_bytes(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'\\' => escaped.push_str("\\\\"),
+            b'"' => escaped.push_str("\\\""),
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lcs_matches_redis_example() {
+        let result = lcs(b"ohmytext", b"mynewtext", 0).unwrap();
+        assert_eq!(result.subsequence, b"mytext");
+        assert_eq!(result.matches, vec![
+            LcsMatch { a_range: (4, 7), b_range: (5, 8), len: 4 },
+            LcsMatch { a_range: (2, 3), b_range: (0, 1), len: 2 },
+        ]);
+
+        let filtered = lcs(b"ohmytext", b"mynewtext", 4).unwrap();
+        assert_eq!(filtered.matches.len(), 1);
+    }
+
+    #[test]
+    fn test_lcs_empty_input() {
+        let result = lcs(b"", b"abc", 0).unwrap();
+        assert!(result.subsequence.is_empty());
+        assert!(result.matches.is_empty());
+    }
+
+    #[test]
+    fn test_bits_are_set_counted_and_combined() {
+        let mut bytes = Vec::new();
+        assert_eq!(set_bit(&mut bytes, 7, true), 0);
+        assert_eq!(set_bit(&mut bytes, 7, true), 1);
+        set_bit(&mut bytes, 9, true);
+        assert_eq!(bytes, vec![0x01, 0x40]);
+        assert_eq!((get_bit(&bytes, 9), get_bit(&bytes, 10), get_bit(&bytes, 1000)), (1, 0, 0));
+
+        // "foobar" as in the BITCOUNT documentation
+        let foobar = b"foobar";
+        assert_eq!(bit_count(foobar, None, false), 26);
+        assert_eq!(bit_count(foobar, Some((0, 0)), false), 4);
+        assert_eq!(bit_count(foobar, Some((1, 1)), false), 6);
+        assert_eq!(bit_count(foobar, Some((1, 1)), true), 1);
+        assert_eq!(bit_count(foobar, Some((5, 30)), true), 17);
+        assert_eq!(bit_count(foobar, Some((-2, -1)), false), 7);
+        assert_eq!(bit_count(foobar, Some((3, 1)), false), 0);
+        assert_eq!(bit_count(b"", None, false), 0);
+
+        let sources = vec![b"foof".to_vec(), b"ab".to_vec()];
+        assert_eq!(bit_op(BitOp::And, &sources), vec![b'f' & b'a', b'o' & b'b', 0, 0]);
+        assert_eq!(bit_op(BitOp::Or, &sources), vec![b'f' | b'a', b'o' | b'b', b'o', b'f']);
+        assert_eq!(bit_op(BitOp::Not, &[vec![0x0f]]), vec![0xf0]);
+        assert_eq!(escape_bytes(&[b'a', 0xff, b'"', b'\n']), "a\\xff\\\"\\n");
+    }
+}
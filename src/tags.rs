@@ -0,0 +1,135 @@
+// Tags attached to keys with TAG SET, kept in both directions so KEYSBYTAG finds a group of keys
+// without walking the keyspace. The database drops a key's tags whenever the key itself goes
+// away, and they are saved in the snapshot next to the data.
+use std::collections::{BTreeSet, HashMap};
+
+pub const MAX_TAG_LEN: usize = 128;
+pub const MAX_TAGS_PER_KEY: usize = 64;
+
+#[derive(Debug, Clone, Default)]
+pub struct TagIndex {
+    by_key: HashMap<String, BTreeSet<String>>,
+    by_tag: HashMap<String, BTreeSet<String>>,
+}
+
+impl TagIndex {
+    /// Tags `key`, returning how many of `tags` it did not have yet.
+    pub fn add(&mut self, key: &str, tags: &[String]) -> Result<usize, String> {
+        if let Some(tag) = tags.iter().find(|tag| tag.len() > MAX_TAG_LEN) {
+            return Err(format!("ERR tag '{}' is longer than {} bytes", tag, MAX_TAG_LEN));
+        }
+        let current = self.by_key.get(key);
+        let new: BTreeSet<&String> = tags.iter().filter(|tag| !current.is_some_and(|current| current.contains(*tag))).collect();
+        if current.map_or(0, BTreeSet::len) + new.len() > MAX_TAGS_PER_KEY {
+            return Err(format!("ERR a key can have at most {} tags", MAX_TAGS_PER_KEY));
+        }
+        for tag in &new {
+            self.by_key.entry(key.to_string()).or_default().insert(tag.to_string());
+            self.by_tag.entry(tag.to_string()).or_default().insert(key.to_string());
+        }
+        Ok(new.len())
+    }
+
+    /// Untags `key`, returning how many of `tags` it had.
+    pub fn remove(&mut self, key: &str, tags: &[String]) -> usize {
+        let Some(current) = self.by_key.get_mut(key) else { return 0 };
+        let mut removed = 0;
+        for tag in tags {
+            if current.remove(tag) {
+                removed += 1;
+                if let Some(keys) = self.by_tag.get_mut(tag) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.by_tag.remove(tag);
+                    }
+                }
+            }
+        }
+        if current.is_empty() {
+            self.by_key.remove(key);
+        }
+        removed
+    }
+
+    /// Drops every tag of a key that no longer exists, returning them.
+    pub fn remove_key(&mut self, key: &str) -> Vec<String> {
+        let tags: Vec<String> = self.by_key.get(key).map(|tags| tags.iter().cloned().collect()).unwrap_or_default();
+        self.remove(key, &tags);
+        tags
+    }
+
+    /// A key's tags in sorted order.
+    pub fn tags_of(&self, key: &str) -> Vec<String> {
+        self.by_key.get(key).map(|tags| tags.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// The keys carrying `tag` in sorted order.
+    pub fn keys_with(&self, tag: &str) -> Vec<String> {
+        self.by_tag.get(tag).map(|keys| keys.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_key.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.by_key.clear();
+        self.by_tag.clear();
+    }
+
+    /// Every tagged key with its tags, for the snapshot.
+    pub fn to_map(&self) -> HashMap<String, Vec<String>> {
+        self.by_key.iter().map(|(key, tags)| (key.clone(), tags.iter().cloned().collect())).collect()
+    }
+
+    pub fn from_map(map: HashMap<String, Vec<String>>) -> Self {
+        let mut index = Self::default();
+        for (key, tags) in map {
+            for tag in tags {
+                index.by_tag.entry(tag.clone()).or_default().insert(key.clone());
+                index.by_key.entry(key.clone()).or_default().insert(tag);
+            }
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tags_are_indexed_both_ways() {
+        let mut index = TagIndex::default();
+        assert_eq!(index.add("s:1", &tags(&["session", "eu"])), Ok(2));
+        assert_eq!(index.add("s:2", &tags(&["session", "session"])), Ok(1));
+        assert_eq!(index.add("s:1", &tags(&["eu"])), Ok(0));
+        assert_eq!(index.keys_with("session"), tags(&["s:1", "s:2"]));
+        assert_eq!(index.tags_of("s:1"), tags(&["eu", "session"]));
+
+        assert_eq!(index.remove("s:1", &tags(&["session", "missing"])), 1);
+        assert_eq!(index.keys_with("session"), tags(&["s:2"]));
+        assert_eq!(index.remove_key("s:2"), tags(&["session"]));
+        assert!(index.keys_with("session").is_empty());
+
+        let restored = TagIndex::from_map(index.to_map());
+        assert_eq!(restored.keys_with("eu"), tags(&["s:1"]));
+        index.remove_key("s:1");
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn test_tag_limits() {
+        let mut index = TagIndex::default();
+        let long = "t".repeat(MAX_TAG_LEN + 1);
+        assert!(index.add("k", &[long]).unwrap_err().contains("longer than 128 bytes"));
+        let many: Vec<String> = (0..MAX_TAGS_PER_KEY).map(|i| i.to_string()).collect();
+        assert_eq!(index.add("k", &many), Ok(MAX_TAGS_PER_KEY));
+        assert_eq!(index.add("k", &tags(&["0"])), Ok(0));
+        assert_eq!(index.add("k", &tags(&["extra"])).unwrap_err(), "ERR a key can have at most 64 tags");
+    }
+}
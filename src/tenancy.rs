@@ -0,0 +1,269 @@
+// Confines a tenant's commands to its key prefix. Every key a command names is prefixed before
+// it runs, and the executor strips the prefix from keys it lists back, so a tenant sees its own
+// keyspace as if it had the server to itself. Channels, patterns and named consumers are
+// prefixed the same way, and the server's own event channels are kept from tenants.
+use crate::commands::Command;
+use crate::pub_sub::PubSubMessage;
+use std::sync::Arc;
+
+// Where the server publishes its own events, such as client connects and saves
+const SYSTEM_CHANNEL_PREFIX: &str = "__events__:";
+
+fn scope(prefix: &str, key: String) -> String {
+    format!("{}{}", prefix, key)
+}
+
+fn scope_all(prefix: &str, keys: Vec<String>) -> Vec<String> {
+    keys.into_iter().map(|key| scope(prefix, key)).collect()
+}
+
+/// Whether `key` belongs to the tenant with `prefix`, and its name as the tenant knows it.
+pub fn unscope<'a>(prefix: &str, key: &'a str) -> Option<&'a str> {
+    key.strip_prefix(prefix)
+}
+
+// Prefixes channel names or patterns, refusing any that reach for the server's event channels
+fn scope_channels(prefix: &str, channels: Vec<String>) -> Result<Vec<String>, String> {
+    if channels.iter().any(|channel| channel.starts_with(SYSTEM_CHANNEL_PREFIX)) {
+        return Err("NOPERM the server's event channels are not available to tenants".to_string());
+    }
+    Ok(scope_all(prefix, channels))
+}
+
+fn scope_channel(prefix: &str, channel: String) -> Result<String, String> {
+    Ok(scope_channels(prefix, vec![channel])?.remove(0))
+}
+
+/// `message` as the tenant with `prefix` subscribed to it, with the prefix taken off its
+/// channel or pattern.
+pub fn unscope_message(prefix: &str, message: PubSubMessage) -> PubSubMessage {
+    let strip = |name: String| match unscope(prefix, &name) {
+        Some(name) => name.to_string(),
+        None => name,
+    };
+    match message {
+        PubSubMessage::Message { channel, message, id } => match unscope(prefix, &channel) {
+            Some(name) => PubSubMessage::Message { channel: Arc::from(name), message, id },
+            None => PubSubMessage::Message { channel, message, id },
+        },
+        PubSubMessage::Subscribe { channel, count } => PubSubMessage::Subscribe { channel: strip(channel), count },
+        PubSubMessage::Unsubscribe { channel, count } => PubSubMessage::Unsubscribe { channel: strip(channel), count },
+        PubSubMessage::PSubscribe { pattern, count } => PubSubMessage::PSubscribe { pattern: strip(pattern), count },
+        PubSubMessage::PUnsubscribe { pattern, count } => PubSubMessage::PUnsubscribe { pattern: strip(pattern), count },
+    }
+}
+
+/// Rewrites the keys (and index names, channels and consumers) `command` touches into the
+/// tenant's keyspace. Commands that act on the whole dataset, or report on every tenant's
+/// channels, are refused.
+pub fn scope_command(command: Command, prefix: &str) -> Result<Command, String> {
+    let p = prefix;
+    Ok(match command {
+        Command::Get { key } => Command::Get { key: scope(p, key) },
+        Command::GetWithMeta { key } => Command::GetWithMeta { key: scope(p, key) },
+        Command::Set { key, value, expiry, condition, keep_ttl, get } => {
+            Command::Set { key: scope(p, key), value, expiry, condition, keep_ttl, get }
+        },
+        Command::SetEx { key, value, ttl } => Command::SetEx { key: scope(p, key), value, ttl },
+        Command::SetNx { key, value } => Command::SetNx { key: scope(p, key), value },
+        Command::DelIfEq { key, value } => Command::DelIfEq { key: scope(p, key), value },
+        Command::Cas { key, expected, value, expiry } => Command::Cas { key: scope(p, key), expected, value, expiry },
+        Command::Del { keys } => Command::Del { keys: scope_all(p, keys) },
+        Command::RestoreKey { key, replace } => Command::RestoreKey { key: scope(p, key), replace },
+        Command::Exists { keys } => Command::Exists { keys: scope_all(p, keys) },
+        Command::Invalidate { keys } => Command::Invalidate { keys: scope_all(p, keys) },
+        Command::Incr { key } => Command::Incr { key: scope(p, key) },
+        Command::Decr { key } => Command::Decr { key: scope(p, key) },
+        Command::IncrBy { key, increment } => Command::IncrBy { key: scope(p, key), increment },
+        Command::DecrBy { key, decrement } => Command::DecrBy { key: scope(p, key), decrement },
+        Command::IncrByFloat { key, increment } => Command::IncrByFloat { key: scope(p, key), increment },
+        Command::RateLimit { key, max, window_secs } => Command::RateLimit { key: scope(p, key), max, window_secs },
+        Command::Append { key, value } => Command::Append { key: scope(p, key), value },
+        Command::Strlen { key } => Command::Strlen { key: scope(p, key) },
+        Command::GetRange { key, start, end } => Command::GetRange { key: scope(p, key), start, end },
+        Command::Lcs { key1, key2, len, idx, min_match_len, with_match_len } => {
+            Command::Lcs { key1: scope(p, key1), key2: scope(p, key2), len, idx, min_match_len, with_match_len }
+        },
+        Command::SetBit { key, offset, on } => Command::SetBit { key: scope(p, key), offset, on },
+        Command::GetBit { key, offset } => Command::GetBit { key: scope(p, key), offset },
+        Command::BitCount { key, range, bits } => Command::BitCount { key: scope(p, key), range, bits },
+        Command::BitOp { op, destkey, keys } => Command::BitOp { op, destkey: scope(p, destkey), keys: scope_all(p, keys) },
+
+        Command::LPush { key, values } => Command::LPush { key: scope(p, key), values },
+        Command::RPush { key, values } => Command::RPush { key: scope(p, key), values },
+        Command::LPop { key, count } => Command::LPop { key: scope(p, key), count },
+        Command::RPop { key, count } => Command::RPop { key: scope(p, key), count },
+        Command::LMove { source, destination, from, to } => Command::LMove { source: scope(p, source), destination: scope(p, destination), from, to },
+        Command::BPop { keys, end, timeout } => Command::BPop { keys: scope_all(p, keys), end, timeout },
+        Command::BLMove { source, destination, from, to, timeout } => {
+            Command::BLMove { source: scope(p, source), destination: scope(p, destination), from, to, timeout }
+        },
+        Command::LLen { key } => Command::LLen { key: scope(p, key) },
+        Command::LRange { key, start, stop } => Command::LRange { key: scope(p, key), start, stop },
+        Command::LIndex { key, index } => Command::LIndex { key: scope(p, key), index },
+        Command::LSet { key, index, value } => Command::LSet { key: scope(p, key), index, value },
+
+        Command::DelayQPush { key, delay_ms, members } => Command::DelayQPush { key: scope(p, key), delay_ms, members },
+        Command::DelayQPop { key, count } => Command::DelayQPop { key: scope(p, key), count },
+        Command::DelayQBPop { key, timeout } => Command::DelayQBPop { key: scope(p, key), timeout },
+        Command::DelayQLen { key } => Command::DelayQLen { key: scope(p, key) },
+
+        Command::BfReserve { key, error_rate, capacity } => Command::BfReserve { key: scope(p, key), error_rate, capacity },
+        Command::BfAdd { key, items, multi } => Command::BfAdd { key: scope(p, key), items, multi },
+        Command::BfExists { key, items, multi } => Command::BfExists { key: scope(p, key), items, multi },
+        Command::BfInfo { key } => Command::BfInfo { key: scope(p, key) },
+
+        Command::TsCreate { key, retention_ms } => Command::TsCreate { key: scope(p, key), retention_ms },
+        Command::TsAdd { key, timestamp, value, retention_ms } => Command::TsAdd { key: scope(p, key), timestamp, value, retention_ms },
+        Command::TsGet { key } => Command::TsGet { key: scope(p, key) },
+        Command::TsRange { key, from, to, aggregation } => Command::TsRange { key: scope(p, key), from, to, aggregation },
+        Command::TsCreateRule { source, dest, aggregation, bucket_ms } => {
+            Command::TsCreateRule { source: scope(p, source), dest: scope(p, dest), aggregation, bucket_ms }
+        },
+
+        Command::JsonSet { key, path, value, condition } => Command::JsonSet { key: scope(p, key), path, value, condition },
+        Command::JsonGet { key, path } => Command::JsonGet { key: scope(p, key), path },
+        Command::JsonDel { key, path } => Command::JsonDel { key: scope(p, key), path },
+        Command::JsonNumIncrBy { key, path, increment } => Command::JsonNumIncrBy { key: scope(p, key), path, increment },
+
+        // Indexes only cover the tenant's keys, and their names are per tenant as well
+        #[cfg(feature = "search")]
+        Command::FtCreate { index, prefixes, fields } => {
+            let prefixes = if prefixes.is_empty() { vec![p.to_string()] } else { scope_all(p, prefixes) };
+            Command::FtCreate { index: scope(p, index), prefixes, fields }
+        },
+        #[cfg(feature = "search")]
+        Command::FtSearch { index, predicates } => Command::FtSearch { index: scope(p, index), predicates },
+        #[cfg(feature = "search")]
+        Command::FtDropIndex { index } => Command::FtDropIndex { index: scope(p, index) },
+        #[cfg(feature = "search")]
+        Command::FtInfo { index } => Command::FtInfo { index: scope(p, index) },
+
+        Command::VectorAdd { key, element, vector } => Command::VectorAdd { key: scope(p, key), element, vector },
+        Command::VectorSearch { key, k, metric, vector } => Command::VectorSearch { key: scope(p, key), k, metric, vector },
+        Command::VectorRem { key, element } => Command::VectorRem { key: scope(p, key), element },
+
+        Command::SAdd { key, members } => Command::SAdd { key: scope(p, key), members },
+        Command::SRem { key, members } => Command::SRem { key: scope(p, key), members },
+        Command::SMembers { key } => Command::SMembers { key: scope(p, key) },
+        Command::SCard { key } => Command::SCard { key: scope(p, key) },
+        Command::SIsMember { key, member } => Command::SIsMember { key: scope(p, key), member },
+        Command::SInter { keys } => Command::SInter { keys: scope_all(p, keys) },
+        Command::SUnion { keys } => Command::SUnion { keys: scope_all(p, keys) },
+        Command::SDiff { keys } => Command::SDiff { keys: scope_all(p, keys) },
+
+        Command::HSet { key, field, value } => Command::HSet { key: scope(p, key), field, value },
+        Command::HGet { key, field } => Command::HGet { key: scope(p, key), field },
+        Command::HDel { key, fields } => Command::HDel { key: scope(p, key), fields },
+        Command::HGetAll { key } => Command::HGetAll { key: scope(p, key) },
+        Command::HKeys { key } => Command::HKeys { key: scope(p, key) },
+        Command::HVals { key } => Command::HVals { key: scope(p, key) },
+        Command::HLen { key } => Command::HLen { key: scope(p, key) },
+        Command::HExists { key, field } => Command::HExists { key: scope(p, key), field },
+        Command::HIncrBy { key, field, increment } => Command::HIncrBy { key: scope(p, key), field, increment },
+        Command::HRandField { key, count, with_values } => Command::HRandField { key: scope(p, key), count, with_values },
+        Command::HExpire { key, ttl, condition, fields } => Command::HExpire { key: scope(p, key), ttl, condition, fields },
+        Command::HTtl { key, fields, millis } => Command::HTtl { key: scope(p, key), fields, millis },
+        Command::HPersist { key, fields } => Command::HPersist { key: scope(p, key), fields },
+
+        Command::Keys { pattern } => Command::Keys { pattern: scope(p, pattern) },
+        Command::Aggregate { reducer, pattern } => Command::Aggregate { reducer, pattern: scope(p, pattern) },
+        Command::TagSet { key, tags } => Command::TagSet { key: scope(p, key), tags },
+        Command::TagGet { key } => Command::TagGet { key: scope(p, key) },
+        Command::TagDel { key, tags } => Command::TagDel { key: scope(p, key), tags },
+        // The executor keeps only the tenant's keys
+        Command::KeysByTag { tag } => Command::KeysByTag { tag },
+        Command::DelPattern { pattern, throttle } => Command::DelPattern { pattern: scope(p, pattern), throttle },
+        // Checked against the job's scoped pattern by the executor
+        command @ (Command::JobList | Command::JobStatus { .. } | Command::JobCancel { .. }) => command,
+        Command::Type { key } => Command::Type { key: scope(p, key) },
+        Command::Expire { key, ttl } => Command::Expire { key: scope(p, key), ttl },
+        Command::ExpireAt { key, unix_ms } => Command::ExpireAt { key: scope(p, key), unix_ms },
+        Command::Ttl { key, millis } => Command::Ttl { key: scope(p, key), millis },
+        Command::TtlMany { keys, millis } => Command::TtlMany { keys: scope_all(p, keys), millis },
+        Command::Pin { keys } => Command::Pin { keys: scope_all(p, keys) },
+        Command::Unpin { keys } => Command::Unpin { keys: scope_all(p, keys) },
+        Command::Persist { key } => Command::Persist { key: scope(p, key) },
+        Command::Rename { key, newkey } => Command::Rename { key: scope(p, key), newkey: scope(p, newkey) },
+        Command::Copy { source, destination, replace } => {
+            Command::Copy { source: scope(p, source), destination: scope(p, destination), replace }
+        },
+        Command::ObjectEncoding { key } => Command::ObjectEncoding { key: scope(p, key) },
+
+        Command::Lock { key, token, ttl } => Command::Lock { key: scope(p, key), token, ttl },
+        Command::Unlock { key, token } => Command::Unlock { key: scope(p, key), token },
+
+        // The executor limits these to the tenant's keys itself
+        command @ (Command::DbSize | Command::RandomKey | Command::Memory | Command::MemoryStats | Command::TtlStats |
+                   Command::HotKeys { .. }) => command,
+
+        Command::Publish { channel, message } => Command::Publish { channel: scope_channel(p, channel)?, message },
+        Command::Subscribe { channels, replay, consumer } => {
+            Command::Subscribe { channels: scope_channels(p, channels)?, replay, consumer: consumer.map(|name| scope(p, name)) }
+        },
+        Command::Unsubscribe { channels } => Command::Unsubscribe { channels: scope_channels(p, channels)? },
+        Command::PSubscribe { patterns } => Command::PSubscribe { patterns: scope_channels(p, patterns)? },
+        Command::PUnsubscribe { patterns } => Command::PUnsubscribe { patterns: scope_channels(p, patterns)? },
+        Command::PubSubNumSub { channels } => Command::PubSubNumSub { channels: scope_channels(p, channels)? },
+        Command::PubSubRetention { channel, policy } => Command::PubSubRetention { channel: scope_channel(p, channel)?, policy },
+        Command::PubSubReliable { channel, ack_timeout } => Command::PubSubReliable { channel: scope_channel(p, channel)?, ack_timeout },
+        Command::PubSubPending { channel: Some(channel) } => Command::PubSubPending { channel: Some(scope_channel(p, channel)?) },
+        // The executor keeps only the tenant's channels, and the server only its own connections
+        command @ (Command::PubSubChannels { .. } | Command::ClientList { .. }) => command,
+
+        // Connection state and server statistics are shared by all tenants
+        command @ (Command::Ack { .. } |
+                   Command::Ping { .. } | Command::Echo { .. } | Command::Auth { .. } | Command::Hello { .. } |
+                   Command::ReadOnly | Command::ReadWrite |
+                   Command::SessionCreate { .. } | Command::SessionAuth { .. } | Command::SessionRevoke { .. } |
+                   Command::Info | Command::StatHistory { .. } | Command::StatSizes { .. } |
+                   Command::SnapshotBegin | Command::SnapshotEnd | Command::ClientId |
+                   Command::Atomic { .. } | Command::Quit) => command,
+
+        Command::FlushAll | Command::UndoFlush | Command::ShowAll | Command::Merge { .. } |
+        Command::VerifyIntegrity | Command::RecoverFromBackup | Command::DebugSetRngSeed { .. } |
+        Command::DebugPersistenceBench { .. } | Command::DebugPanic |
+        Command::DbStatsByPrefix | Command::ExpiredRead { .. } | Command::ExpiredInfo | Command::MigrationStatus => {
+            return Err("NOPERM this command acts on every tenant's keys".to_string());
+        },
+        Command::PubSubNumPat | Command::PubSubPending { channel: None } | Command::PubSubConsumers | Command::PubSubStats { .. } => {
+            return Err("NOPERM this command reports on every tenant's channels".to_string());
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_prefixed() {
+        let command = scope_command(Command::Rename { key: "a".to_string(), newkey: "b".to_string() }, "app1:").unwrap();
+        assert!(matches!(command, Command::Rename { key, newkey } if key == "app1:a" && newkey == "app1:b"));
+
+        let command = scope_command(Command::Del { keys: vec!["x".to_string(), "y".to_string()] }, "app1:").unwrap();
+        assert!(matches!(command, Command::Del { keys } if keys == vec!["app1:x", "app1:y"]));
+
+        assert_eq!(unscope("app1:", "app1:x"), Some("x"));
+        assert_eq!(unscope("app1:", "app2:x"), None);
+    }
+
+    #[test]
+    fn test_dataset_wide_commands_are_refused() {
+        assert!(scope_command(Command::FlushAll, "app1:").is_err());
+        assert!(scope_command(Command::ShowAll, "app1:").is_err());
+        assert!(matches!(scope_command(Command::DbSize, "app1:"), Ok(Command::DbSize)));
+    }
+
+    #[test]
+    fn test_channels_are_prefixed_and_system_channels_refused() {
+        let command = scope_command(Command::PSubscribe { patterns: vec!["news.*".to_string()] }, "app1:").unwrap();
+        assert!(matches!(command, Command::PSubscribe { patterns } if patterns == vec!["app1:news.*"]));
+        let subscribe = Command::Subscribe { channels: vec!["__events__:clients".to_string()], replay: None, consumer: None };
+        assert!(scope_command(subscribe, "app1:").is_err());
+        assert!(scope_command(Command::PubSubStats { count: 10 }, "app1:").is_err());
+
+        let message = PubSubMessage::Message { channel: Arc::from("app1:news"), message: Arc::from("hi"), id: None };
+        assert!(matches!(unscope_message("app1:", message), PubSubMessage::Message { channel, .. } if &*channel == "news"));
+    }
+}
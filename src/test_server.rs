@@ -0,0 +1,208 @@
+// Fixture for integration tests: the full TCP server on an ephemeral port, with its snapshot in
+// a fresh temporary directory. Enable with the `test-server` feature.
+use crate::server::Server;
+use crate::storage::StorageConfig;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+// Keeps the directories of servers started by one process apart
+static NEXT_SERVER: AtomicUsize = AtomicUsize::new(0);
+
+/// A connection that sends one command at a time and reads back its reply.
+#[derive(Debug)]
+pub struct TestClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl TestClient {
+    pub async fn connect(addr: SocketAddr) -> std::io::Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Self { reader: BufReader::new(reader), writer })
+    }
+
+    async fn read_reply(&mut self) -> std::io::Result<String> {
+        // Lines within a reply are separated by \n; only the end of the reply is \r\n
+        let mut reply = Vec::new();
+        while !reply.ends_with(b"\r\n") {
+            if self.reader.read_until(b'\n', &mut reply).await? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        reply.truncate(reply.len() - 2);
+        Ok(String::from_utf8_lossy(&reply).into_owned())
+    }
+
+    /// Sends `line` and returns the reply as the CLI would print it, e.g. `(integer) 1`.
+    pub async fn command(&mut self, line: &str) -> std::io::Result<String> {
+        self.writer.write_all(format!("{}\r\n", line).as_bytes()).await?;
+        self.read_reply().await
+    }
+}
+
+/// The server runs until this is dropped; its temporary directory is removed with it.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub client: TestClient,
+    pub dir: PathBuf,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Boots a default server, without password or memory limit.
+    pub async fn start() -> std::io::Result<Self> {
+        Self::start_with(|server| server).await
+    }
+
+    /// Boots a server after `configure` applies any `with_*` options to it.
+    pub async fn start_with(configure: impl FnOnce(Server) -> Server) -> std::io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "rust_redis_test_server_{}_{}",
+            std::process::id(),
+            NEXT_SERVER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = configure(Server::new(
+            addr.ip().to_string(),
+            addr.port(),
+            None,
+            dir.join("dump.rdb").to_string_lossy().into_owned(),
+            None,
+            "allkeys-lru".to_string(),
+            StorageConfig::default(),
+        ));
+        let task = tokio::spawn(async move {
+            if let Err(e) = server.serve(listener).await {
+                eprintln!("Test server stopped: {}", e);
+            }
+        });
+
+        let client = TestClient::connect(addr).await?;
+        Ok(Self { addr, client, dir, task })
+    }
+
+    /// Opens another connection to the server.
+    pub async fn connect(&self) -> std::io::Result<TestClient> {
+        TestClient::connect(self.addr).await
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_server_serves_commands() {
+        let mut server = TestServer::start().await.unwrap();
+        assert_eq!(server.client.command("SET greeting hello").await.unwrap(), "OK");
+        assert_eq!(server.client.command("RPUSH list a b").await.unwrap(), "(integer) 2");
+
+        let mut other = server.connect().await.unwrap();
+        assert_eq!(other.command("GET greeting").await.unwrap(), "\"hello\"");
+        assert_eq!(other.command("LRANGE list 0 -1").await.unwrap(), "1) \"a\"\n2) \"b\"");
+    }
+
+    #[tokio::test]
+    async fn test_servers_are_isolated() {
+        let mut first = TestServer::start().await.unwrap();
+        let mut second = TestServer::start().await.unwrap();
+        assert_ne!(first.addr, second.addr);
+        first.client.command("SET k 1").await.unwrap();
+        assert_eq!(second.client.command("GET k").await.unwrap(), "(nil)");
+
+        let dir = first.dir.clone();
+        drop(first);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_banner_greets_only_text_clients() {
+        let mut server = TestServer::start_with(|server| server.with_banner(Some("Welcome!".to_string()))).await.unwrap();
+        assert_eq!(server.client.command("PING").await.unwrap(), "Welcome!");
+        assert_eq!(server.client.read_reply().await.unwrap(), "OK");
+        assert_eq!(server.client.command("PING").await.unwrap(), "OK");
+
+        let mut stream = TcpStream::connect(server.addr).await.unwrap();
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let mut received = [0u8; 10];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"+OK\r\n+OK\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_atomic_runs_the_next_commands_as_one_block() {
+        let mut server = TestServer::start().await.unwrap();
+        let reply = server.client.command("ATOMIC 3\r\nSET counter 1\r\nINCR counter\r\nRPUSH l a b").await.unwrap();
+        assert_eq!(reply, "1) OK\n2) (integer) 2\n3) (integer) 2");
+        let reply = server.client.command("ATOMIC 2\r\nLRANGE l 0 -1\r\nLLEN l").await.unwrap();
+        assert_eq!(reply, "1) 1) \"a\"\n   2) \"b\"\n2) (integer) 2");
+
+        // A command that cannot be queued discards the whole block, which is still consumed
+        let reply = server.client.command("ATOMIC 3\r\nRPUSH l c\r\nNOSUCH\r\nSUBSCRIBE c").await.unwrap();
+        assert!(reply.starts_with("(error) EXECABORT"), "{}", reply);
+        assert_eq!(server.client.command("LLEN l").await.unwrap(), "(integer) 2");
+        assert_eq!(server.client.command("ATOMIC 0").await.unwrap(), "ERR ATOMIC count must be between 1 and 1000");
+    }
+
+    #[tokio::test]
+    async fn test_blocked_clients_are_woken_or_dropped_when_they_hang_up() {
+        let mut server = TestServer::start().await.unwrap();
+        let mut waiting = TcpStream::connect(server.addr).await.unwrap();
+        waiting.write_all(b"BLPOP jobs 0\r\n").await.unwrap();
+        {
+            let mut gone = TcpStream::connect(server.addr).await.unwrap();
+            gone.write_all(b"BLPOP jobs 0\r\n").await.unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        // The client that hung up no longer takes elements
+        assert_eq!(server.client.command("RPUSH jobs a").await.unwrap(), "(integer) 1");
+        let reply = b"1) \"jobs\"\n2) \"a\"\r\n";
+        let mut received = vec![0u8; reply.len()];
+        waiting.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, reply);
+        assert_eq!(server.client.command("RPUSH jobs b").await.unwrap(), "(integer) 1");
+        assert_eq!(server.client.command("LLEN jobs").await.unwrap(), "(integer) 1");
+    }
+
+    #[tokio::test]
+    async fn test_resp_requests_get_resp_replies() {
+        let server = TestServer::start().await.unwrap();
+        // Nothing is sent before the first reply, which RESP clients rely on
+        let mut stream = TcpStream::connect(server.addr).await.unwrap();
+
+        for (request, reply) in [
+            (&b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\na b c\r\n"[..], &b"+OK\r\n"[..]),
+            (b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n", b"$5\r\na b c\r\n"),
+            (b"*2\r\n$3\r\nGET\r\n$4\r\nnone\r\n", b"$-1\r\n"),
+            (b"*4\r\n$5\r\nRPUSH\r\n$1\r\nl\r\n$1\r\nx\r\n$1\r\ny\r\n", b":2\r\n"),
+            (b"*4\r\n$6\r\nLRANGE\r\n$1\r\nl\r\n$1\r\n0\r\n$2\r\n-1\r\n", b"*2\r\n$1\r\nx\r\n$1\r\ny\r\n"),
+            (b"*2\r\n$3\r\nGET\r\n$1\r\nl\r\n", b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"),
+            (b"*1\r\n$3\r\nGET\r\n", b"-ERR wrong number of arguments for 'get' command\r\n"),
+            // Inline commands keep the text format
+            (b"GET k\r\n", b"\"a b c\"\r\n"),
+        ] {
+            stream.write_all(request).await.unwrap();
+            let mut received = vec![0u8; reply.len()];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(String::from_utf8_lossy(&received), String::from_utf8_lossy(reply));
+        }
+    }
+}
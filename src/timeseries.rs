@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aggregation {
+    Avg,
+    Min,
+    Max,
+    Sum,
+    Count,
+}
+
+impl Aggregation {
+    pub fn from_string(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "avg" => Some(Aggregation::Avg),
+            "min" => Some(Aggregation::Min),
+            "max" => Some(Aggregation::Max),
+            "sum" => Some(Aggregation::Sum),
+            "count" => Some(Aggregation::Count),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Accumulator {
+    sum: f64,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl Accumulator {
+    fn new(value: f64) -> Self {
+        Self { sum: value, count: 1, min: value, max: value }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.sum += value;
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn finish(&self, aggregation: Aggregation) -> f64 {
+        match aggregation {
+            Aggregation::Avg => self.sum / self.count as f64,
+            Aggregation::Min => self.min,
+            Aggregation::Max => self.max,
+            Aggregation::Sum => self.sum,
+            Aggregation::Count => self.count as f64,
+        }
+    }
+}
+
+// Downsamples every closed bucket of the source series into `dest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactionRule {
+    pub dest: String,
+    pub aggregation: Aggregation,
+    pub bucket_ms: i64,
+    open_bucket: Option<(i64, Accumulator)>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeSeries {
+    samples: VecDeque<(i64, f64)>,
+    pub retention_ms: u64,
+    pub rules: Vec<CompactionRule>,
+}
+
+fn bucket_start(timestamp: i64, bucket_ms: i64) -> i64 {
+    timestamp - timestamp.rem_euclid(bucket_ms)
+}
+
+impl TimeSeries {
+    pub fn new(retention_ms: u64) -> Self {
+        Self { samples: VecDeque::new(), retention_ms, rules: Vec::new() }
+    }
+
+    /// Appends a sample and returns the compacted samples to write into each rule's destination.
+    pub fn add(&mut self, timestamp: i64, value: f64) -> Result<Vec<(String, i64, f64)>, String> {
+        if let Some((last, _)) = self.samples.back() {
+            if timestamp < *last {
+                return Err("ERR TSDB: timestamp must be equal to or higher than the maximum existing timestamp".to_string());
+            }
+        }
+
+        self.samples.push_back((timestamp, value));
+        if self.retention_ms > 0 {
+            let cutoff = timestamp.saturating_sub(self.retention_ms as i64);
+            while self.samples.front().is_some_and(|(ts, _)| *ts < cutoff) {
+                self.samples.pop_front();
+            }
+        }
+
+        let mut compacted = Vec::new();
+        for rule in &mut self.rules {
+            let start = bucket_start(timestamp, rule.bucket_ms);
+            match &mut rule.open_bucket {
+                Some((open_start, accumulator)) if *open_start == start => accumulator.add(value),
+                open_bucket => {
+                    if let Some((closed_start, accumulator)) = open_bucket.take() {
+                        compacted.push((rule.dest.clone(), closed_start, accumulator.finish(rule.aggregation)));
+                    }
+                    *open_bucket = Some((start, Accumulator::new(value)));
+                },
+            }
+        }
+        Ok(compacted)
+    }
+
+    pub fn add_rule(&mut self, dest: String, aggregation: Aggregation, bucket_ms: i64) {
+        self.rules.push(CompactionRule { dest, aggregation, bucket_ms, open_bucket: None });
+    }
+
+    pub fn last(&self) -> Option<(i64, f64)> {
+        self.samples.back().copied()
+    }
+
+    pub fn first_timestamp(&self) -> Option<i64> {
+        self.samples.front().map(|(ts, _)| *ts)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Samples in [from, to], optionally aggregated into buckets keyed by bucket start time.
+    pub fn range(&self, from: i64, to: i64, aggregation: Option<(Aggregation, i64)>) -> Vec<(i64, f64)> {
+        let in_range = self.samples.iter().filter(|(ts, _)| *ts >= from && *ts <= to);
+
+        let (aggregation, bucket_ms) = match aggregation {
+            Some(aggregation) => aggregation,
+            None => return in_range.copied().collect(),
+        };
+
+        let mut buckets: Vec<(i64, Accumulator)> = Vec::new();
+        for &(timestamp, value) in in_range {
+            let start = bucket_start(timestamp, bucket_ms);
+            match buckets.last_mut() {
+                Some((open_start, accumulator)) if *open_start == start => accumulator.add(value),
+                _ => buckets.push((start, Accumulator::new(value))),
+            }
+        }
+        buckets.into_iter()
+            .map(|(start, accumulator)| (start, accumulator.finish(aggregation)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_aggregation() {
+        let mut series = TimeSeries::new(0);
+        for (ts, value) in [(1000, 1.0), (1500, 3.0), (2000, 10.0), (2999, 20.0), (3000, 5.0)] {
+            series.add(ts, value).unwrap();
+        }
+
+        assert_eq!(series.range(0, i64::MAX, Some((Aggregation::Avg, 1000))), vec![(1000, 2.0), (2000, 15.0), (3000, 5.0)]);
+        assert_eq!(series.range(1500, 2999, Some((Aggregation::Max, 1000))), vec![(1000, 3.0), (2000, 20.0)]);
+        assert_eq!(series.range(2000, 2000, None), vec![(2000, 10.0)]);
+        assert!(series.add(10, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_retention_and_compaction() {
+        let mut series = TimeSeries::new(1000);
+        series.add_rule("dest".to_string(), Aggregation::Sum, 1000);
+
+        assert!(series.add(100, 1.0).unwrap().is_empty());
+        assert!(series.add(900, 2.0).unwrap().is_empty());
+        assert_eq!(series.add(1200, 4.0).unwrap(), vec![("dest".to_string(), 0, 3.0)]);
+        series.add(2500, 1.0).unwrap();
+
+        assert_eq!(series.first_timestamp(), Some(2500));
+        assert_eq!(series.len(), 1);
+    }
+}
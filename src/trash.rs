@@ -0,0 +1,105 @@
+// Soft delete: with a trash retention set, DEL moves keys here instead of freeing them, and
+// RESTOREKEY brings one back until the retention runs out. Trashed keys are kept apart from the
+// keyspace, so KEYS, DBSIZE and reads do not see them, and like UNDOFLUSH's copy they live in
+// memory only.
+use crate::data_types::RedisValue;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A deleted key with what it takes to put it back as it was.
+#[derive(Debug, Clone)]
+pub struct TrashedKey {
+    pub value: Arc<RedisValue>,
+    pub expires_at: Option<Instant>,
+    pub field_expires: HashMap<String, Instant>,
+    pub tags: Vec<String>,
+    // The end of the retention, or the key's own expiry if that comes first
+    purge_at: Instant,
+}
+
+impl TrashedKey {
+    pub fn new(value: Arc<RedisValue>, expires_at: Option<Instant>, field_expires: HashMap<String, Instant>, tags: Vec<String>, purge_at: Instant) -> Self {
+        let purge_at = expires_at.map_or(purge_at, |at| at.min(purge_at));
+        Self { value, expires_at, field_expires, tags, purge_at }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Trash {
+    // How long deleted keys are kept; None deletes them for good
+    pub retention: Option<Duration>,
+    keys: HashMap<String, TrashedKey>,
+}
+
+impl Trash {
+    /// Keeps a deleted key, replacing an earlier deletion of the same name.
+    pub fn put(&mut self, key: String, entry: TrashedKey) {
+        self.keys.insert(key, entry);
+    }
+
+    pub fn contains(&self, key: &str, now: Instant) -> bool {
+        self.keys.get(key).is_some_and(|entry| entry.purge_at > now)
+    }
+
+    /// Takes a key out of the trash, unless its retention has run out.
+    pub fn take(&mut self, key: &str, now: Instant) -> Option<TrashedKey> {
+        self.keys.remove(key).filter(|entry| entry.purge_at > now)
+    }
+
+    /// Frees keys whose retention has run out, returning how many.
+    pub fn purge_expired(&mut self, now: Instant) -> usize {
+        let before = self.keys.len();
+        self.keys.retain(|_, entry| entry.purge_at > now);
+        before - self.keys.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(expires_at: Option<Instant>, purge_at: Instant) -> TrashedKey {
+        TrashedKey::new(Arc::new(RedisValue::String("v".into())), expires_at, HashMap::new(), Vec::new(), purge_at)
+    }
+
+    #[test]
+    fn test_keys_are_kept_until_their_retention_ends() {
+        let now = Instant::now();
+        let mut trash = Trash::default();
+        trash.put("a".to_string(), entry(None, now + Duration::from_secs(60)));
+        trash.put("b".to_string(), entry(None, now + Duration::from_secs(1)));
+        assert!(trash.contains("a", now));
+        assert!(!trash.contains("b", now + Duration::from_secs(2)));
+
+        assert_eq!(trash.purge_expired(now + Duration::from_secs(2)), 1);
+        assert_eq!(trash.len(), 1);
+        assert!(trash.take("a", now).is_some());
+        assert!(trash.take("a", now).is_none());
+        assert!(trash.is_empty());
+    }
+
+    #[test]
+    fn test_a_key_expiring_sooner_leaves_the_trash_then() {
+        let now = Instant::now();
+        let mut trash = Trash::default();
+        let expires_at = now + Duration::from_secs(5);
+        trash.put("session".to_string(), entry(Some(expires_at), now + Duration::from_secs(60)));
+        assert_eq!(trash.take("session", now).unwrap().expires_at, Some(expires_at));
+
+        trash.put("session".to_string(), entry(Some(expires_at), now + Duration::from_secs(60)));
+        assert!(trash.take("session", now + Duration::from_secs(6)).is_none());
+    }
+}
@@ -0,0 +1,210 @@
+// Expiry deadlines of volatile keys. Reads go straight to the underlying map; writes also keep
+// the keys ordered by deadline and a running sum of deadlines, so TTLSTATS can count what
+// expires within a window and average the remaining TTL, and the expire cycle can find what
+// is due, without walking every key.
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Bound, Deref};
+use std::time::{Duration, Instant};
+
+/// The windows TTLSTATS and INFO report, each counting keys expiring within it from now.
+pub const TTL_BUCKETS: [(&str, Duration); 4] = [
+    ("1m", Duration::from_secs(60)),
+    ("10m", Duration::from_secs(600)),
+    ("1h", Duration::from_secs(3600)),
+    ("1d", Duration::from_secs(86400)),
+];
+
+#[derive(Debug, Clone)]
+pub struct TtlIndex {
+    deadlines: HashMap<String, Instant>,
+    // The keys due at each deadline
+    by_deadline: BTreeMap<Instant, Vec<String>>,
+    // Deadlines are summed as signed nanoseconds from `origin`, since Instants cannot be added
+    origin: Instant,
+    deadline_sum_ns: i128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TtlStats {
+    pub volatile_keys: usize,
+    /// Keys expiring within each of TTL_BUCKETS, in the same order.
+    pub expiring: [usize; 4],
+    /// Mean remaining TTL of keys that have not expired yet; zero when there are none.
+    pub avg_ttl_ms: u64,
+}
+
+impl Default for TtlIndex {
+    fn default() -> Self {
+        Self { deadlines: HashMap::new(), by_deadline: BTreeMap::new(), origin: Instant::now(), deadline_sum_ns: 0 }
+    }
+}
+
+impl Deref for TtlIndex {
+    type Target = HashMap<String, Instant>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.deadlines
+    }
+}
+
+impl FromIterator<(String, Instant)> for TtlIndex {
+    fn from_iter<I: IntoIterator<Item = (String, Instant)>>(iter: I) -> Self {
+        let mut index = Self::default();
+        for (key, at) in iter {
+            index.insert(key, at);
+        }
+        index
+    }
+}
+
+impl TtlIndex {
+    fn offset_ns(&self, at: Instant) -> i128 {
+        match at.checked_duration_since(self.origin) {
+            Some(after) => after.as_nanos() as i128,
+            None => -(self.origin.duration_since(at).as_nanos() as i128),
+        }
+    }
+
+    fn unlink(&mut self, key: &str, at: Instant) {
+        if let Some(keys) = self.by_deadline.get_mut(&at) {
+            if let Some(position) = keys.iter().position(|due| due == key) {
+                keys.swap_remove(position);
+            }
+            if keys.is_empty() {
+                self.by_deadline.remove(&at);
+            }
+        }
+        self.deadline_sum_ns -= self.offset_ns(at);
+    }
+
+    pub fn insert(&mut self, key: String, at: Instant) -> Option<Instant> {
+        let previous = self.deadlines.insert(key.clone(), at);
+        if let Some(previous) = previous {
+            self.unlink(&key, previous);
+        }
+        self.by_deadline.entry(at).or_default().push(key);
+        self.deadline_sum_ns += self.offset_ns(at);
+        previous
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Instant> {
+        let removed = self.deadlines.remove(key);
+        if let Some(at) = removed {
+            self.unlink(key, at);
+        }
+        removed
+    }
+
+    /// Up to `limit` keys whose deadline passed before `now`, the longest overdue first.
+    pub fn expired(&self, now: Instant, limit: usize) -> Vec<String> {
+        self.by_deadline.range(..now).flat_map(|(_, keys)| keys).take(limit).cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.deadlines.clear();
+        self.by_deadline.clear();
+        self.deadline_sum_ns = 0;
+    }
+
+    /// How many keys expire after `now` but no later than `now + window`.
+    pub fn expiring_within(&self, now: Instant, window: Duration) -> usize {
+        self.by_deadline.range((Bound::Excluded(now), Bound::Included(now + window)))
+            .map(|(_, keys)| keys.len())
+            .sum()
+    }
+
+    pub fn stats(&self, now: Instant) -> TtlStats {
+        // Keys past their deadline are still indexed until the expire cycle reaps them
+        let (expired, expired_sum_ns) = self.by_deadline.range(..=now)
+            .fold((0usize, 0i128), |(expired, sum), (at, keys)| (expired + keys.len(), sum + self.offset_ns(*at) * keys.len() as i128));
+        let live = self.deadlines.len() - expired;
+        let avg_ttl_ms = if live == 0 {
+            0
+        } else {
+            let live_sum_ns = self.deadline_sum_ns - expired_sum_ns;
+            ((live_sum_ns / live as i128 - self.offset_ns(now)) / 1_000_000).max(0) as u64
+        };
+        TtlStats {
+            volatile_keys: live,
+            expiring: TTL_BUCKETS.map(|(_, window)| self.expiring_within(now, window)),
+            avg_ttl_ms,
+        }
+    }
+}
+
+/// Computes the same statistics by walking `deadlines`, for callers that only see some keys.
+pub fn stats_of<'a>(deadlines: impl IntoIterator<Item = &'a Instant>, now: Instant) -> TtlStats {
+    let mut stats = TtlStats { volatile_keys: 0, expiring: [0; 4], avg_ttl_ms: 0 };
+    let mut total_ms: u128 = 0;
+    for at in deadlines {
+        let Some(left) = at.checked_duration_since(now).filter(|left| !left.is_zero()) else { continue };
+        stats.volatile_keys += 1;
+        total_ms += left.as_millis();
+        for (count, (_, window)) in stats.expiring.iter_mut().zip(TTL_BUCKETS) {
+            if left <= window {
+                *count += 1;
+            }
+        }
+    }
+    if stats.volatile_keys > 0 {
+        stats.avg_ttl_ms = (total_ms / stats.volatile_keys as u128) as u64;
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_follow_inserts_and_removes() {
+        let now = Instant::now();
+        let mut index = TtlIndex::default();
+        index.insert("soon".to_string(), now + Duration::from_secs(30));
+        index.insert("later".to_string(), now + Duration::from_secs(1800));
+        index.insert("tomorrow".to_string(), now + Duration::from_secs(7200));
+        assert_eq!(index.stats(now).expiring, [1, 1, 2, 3]);
+
+        // Moving a deadline drops the old one from its bucket
+        index.insert("soon".to_string(), now + Duration::from_secs(300));
+        index.remove("tomorrow");
+        let stats = index.stats(now);
+        assert_eq!(stats.expiring, [0, 1, 2, 2]);
+        assert_eq!(stats.volatile_keys, 2);
+        assert_eq!(stats.avg_ttl_ms, 1_050_000);
+        assert_eq!(stats, stats_of(index.values(), now));
+    }
+
+    #[test]
+    fn test_expired_keys_are_left_out() {
+        let now = Instant::now();
+        let mut index: TtlIndex = [
+            ("gone".to_string(), now),
+            ("left".to_string(), now + Duration::from_secs(10)),
+        ].into_iter().collect();
+        let stats = index.stats(now);
+        assert_eq!(stats.volatile_keys, 1);
+        assert_eq!(stats.avg_ttl_ms, 10_000);
+
+        index.clear();
+        assert_eq!(index.stats(now), TtlStats { volatile_keys: 0, expiring: [0; 4], avg_ttl_ms: 0 });
+    }
+
+    #[test]
+    fn test_due_keys_come_out_oldest_first_up_to_the_limit() {
+        let now = Instant::now();
+        let mut index: TtlIndex = [
+            ("second".to_string(), now - Duration::from_secs(5)),
+            ("first".to_string(), now - Duration::from_secs(10)),
+            ("third".to_string(), now - Duration::from_secs(1)),
+            ("live".to_string(), now + Duration::from_secs(10)),
+        ].into_iter().collect();
+        assert_eq!(index.expired(now, 2), vec!["first", "second"]);
+        assert_eq!(index.expired(now, 10), vec!["first", "second", "third"]);
+
+        // A moved deadline is only found under its new one
+        index.insert("first".to_string(), now + Duration::from_secs(60));
+        index.remove("third");
+        assert_eq!(index.expired(now, 10), vec!["second"]);
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMetric {
+    Cosine,
+    L2,
+}
+
+impl DistanceMetric {
+    pub fn from_string(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "COSINE" => Some(DistanceMetric::Cosine),
+            "L2" => Some(DistanceMetric::L2),
+            _ => None,
+        }
+    }
+}
+
+fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+// Lower is closer for both metrics: cosine distance is 1 - cosine similarity
+fn distance(metric: DistanceMetric, a: &[f32], b: &[f32], b_norm: f32) -> f32 {
+    match metric {
+        DistanceMetric::L2 => a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt(),
+        DistanceMetric::Cosine => {
+            let a_norm = norm(a);
+            if a_norm == 0.0 || b_norm == 0.0 {
+                return 1.0;
+            }
+            let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+            1.0 - dot / (a_norm * b_norm)
+        },
+    }
+}
+
+// Named vectors of one fixed dimension, searched by brute force. Fine for the few-thousand
+// element sets this is meant for; an approximate index would be needed beyond that.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VectorSet {
+    pub dim: usize,
+    vectors: HashMap<String, Vec<f32>>,
+}
+
+impl VectorSet {
+    pub fn new(dim: usize) -> Self {
+        Self { dim, vectors: HashMap::new() }
+    }
+
+    /// Returns true if the element is new, false if its vector was replaced.
+    pub fn add(&mut self, element: String, vector: Vec<f32>) -> Result<bool, String> {
+        if vector.len() != self.dim {
+            return Err(format!("ERR vector dimension mismatch: expected {}, got {}", self.dim, vector.len()));
+        }
+        Ok(self.vectors.insert(element, vector).is_none())
+    }
+
+    pub fn remove(&mut self, element: &str) -> bool {
+        self.vectors.remove(element).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// The `k` closest elements to `query`, closest first (ties broken by element name).
+    pub fn search(&self, query: &[f32], k: usize, metric: DistanceMetric) -> Result<Vec<(String, f32)>, String> {
+        if query.len() != self.dim {
+            return Err(format!("ERR vector dimension mismatch: expected {}, got {}", self.dim, query.len()));
+        }
+
+        let query_norm = norm(query);
+        let mut scored: Vec<(String, f32)> = self.vectors.iter()
+            .map(|(element, vector)| (element.clone(), distance(metric, vector, query, query_norm)))
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(k);
+        Ok(scored)
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.vectors.keys().map(|element| element.len() + self.dim * std::mem::size_of::<f32>()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_orders_by_distance() {
+        let mut set = VectorSet::new(2);
+        set.add("east".to_string(), vec![1.0, 0.0]).unwrap();
+        set.add("north".to_string(), vec![0.0, 1.0]).unwrap();
+        set.add("far-east".to_string(), vec![10.0, 0.0]).unwrap();
+
+        let cosine = set.search(&[1.0, 0.1], 2, DistanceMetric::Cosine).unwrap();
+        assert_eq!(cosine.iter().map(|(e, _)| e.as_str()).collect::<Vec<_>>(), vec!["east", "far-east"]);
+
+        let l2 = set.search(&[1.0, 0.1], 3, DistanceMetric::L2).unwrap();
+        assert_eq!(l2.iter().map(|(e, _)| e.as_str()).collect::<Vec<_>>(), vec!["east", "north", "far-east"]);
+    }
+
+    #[test]
+    fn test_dimension_is_enforced() {
+        let mut set = VectorSet::new(3);
+        assert!(set.add("a".to_string(), vec![1.0, 2.0]).is_err());
+        assert!(set.add("a".to_string(), vec![1.0, 2.0, 3.0]).unwrap());
+        assert!(!set.add("a".to_string(), vec![0.0, 2.0, 3.0]).unwrap());
+        assert!(set.search(&[1.0], 1, DistanceMetric::L2).is_err());
+        assert!(set.remove("a"));
+        assert!(set.is_empty());
+    }
+}
@@ -3,6 +3,15 @@ use std::io::{Write, BufWriter, BufReader, BufRead};
 use std::path::Path;
 use serde::{Serialize, Deserialize};
 use std::time::SystemTime;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WalError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Failed to encode WAL entry: {0}")]
+    Encode(#[from] serde_json::Error),
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum WalEntry {
@@ -18,7 +27,7 @@ pub struct WriteAheadLog {
 }
 
 impl WriteAheadLog {
-    pub fn new(file_path: String) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(file_path: String) -> Result<Self, WalError> {
         let file = OpenOptions::new()
             .create(true)
             .append(true)
@@ -32,7 +41,7 @@ impl WriteAheadLog {
         })
     }
 
-    pub fn log_entry(&mut self, entry: &WalEntry) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn log_entry(&mut self, entry: &WalEntry) -> Result<(), WalError> {
         if let Some(writer) = &mut self.writer {
             let json = serde_json::to_string(entry)?;
             writeln!(writer, "{}", json)?;
@@ -41,14 +50,33 @@ impl WriteAheadLog {
         Ok(())
     }
 
-    pub fn replay(&self) -> Result<Vec<WalEntry>, Box<dyn std::error::Error>> {
+    /// Forces logged entries to disk.
+    pub fn sync(&mut self) -> Result<(), WalError> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    pub fn replay(&self) -> Result<Vec<WalEntry>, WalError> {
+        self.read_entries().map(|(entries, _)| entries)
+    }
+
+    /// Counts the entries that parse and the records that do not.
+    pub fn verify(&self) -> Result<(usize, usize), WalError> {
+        self.read_entries().map(|(entries, corrupt)| (entries.len(), corrupt))
+    }
+
+    fn read_entries(&self) -> Result<(Vec<WalEntry>, usize), WalError> {
         if !Path::new(&self.file_path).exists() {
-            return Ok(Vec::new());
+            return Ok((Vec::new(), 0));
         }
 
         let file = File::open(&self.file_path)?;
         let reader = BufReader::new(file);
         let mut entries = Vec::new();
+        let mut corrupt = 0;
 
         for line in reader.lines() {
             let line = line?;
@@ -60,14 +88,15 @@ impl WriteAheadLog {
                 Ok(entry) => entries.push(entry),
                 Err(e) => {
                     eprintln!("Warning: Failed to parse WAL entry: {} - {}", line, e);
+                    corrupt += 1;
                 }
             }
         }
 
-        Ok(entries)
+        Ok((entries, corrupt))
     }
 
-    pub fn truncate(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn truncate(&mut self) -> Result<(), WalError> {
         self.writer = None;
 
         File::create(&self.file_path)?;
@@ -0,0 +1,91 @@
+// Brings the most frequently used keys back from the cold tier after a restart, hottest first,
+// so latency recovers before clients have touched each key once. Access counts come from the
+// sidecar written next to every snapshot; keys with no recorded count are left cold.
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+// Keys promoted per database lock acquisition, so clients are served between batches
+pub const WARMUP_BATCH: usize = 256;
+
+#[derive(Debug, Default)]
+pub struct Warmup {
+    queue: VecDeque<String>,
+    pub total: usize,
+    pub loaded: usize,
+    started: Option<Instant>,
+    duration_ms: Option<u64>,
+}
+
+impl Warmup {
+    /// Orders `cold_keys` by descending access count, keeping as many as fit in `capacity`.
+    pub fn plan(access_counts: &HashMap<String, u64>, cold_keys: Vec<String>, capacity: usize) -> Self {
+        let mut ranked: Vec<(u64, String)> = cold_keys.into_iter()
+            .filter_map(|key| access_counts.get(&key).map(|count| (*count, key)))
+            .collect();
+        // Ties go alphabetically so the order does not depend on hashing
+        ranked.sort_by(|(a_count, a_key), (b_count, b_key)| b_count.cmp(a_count).then_with(|| a_key.cmp(b_key)));
+        ranked.truncate(capacity);
+        let queue: VecDeque<String> = ranked.into_iter().map(|(_, key)| key).collect();
+        Self { total: queue.len(), queue, ..Default::default() }
+    }
+
+    /// The next keys to promote; empty once the plan is finished.
+    pub fn next_batch(&mut self, size: usize) -> Vec<String> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let batch: Vec<String> = self.queue.drain(..size.min(self.queue.len())).collect();
+        if self.queue.is_empty() && self.duration_ms.is_none() {
+            self.duration_ms = Some(started.elapsed().as_millis() as u64);
+        }
+        batch
+    }
+
+    pub fn status(&self) -> &'static str {
+        match (self.total, self.duration_ms) {
+            (0, _) => "none",
+            (_, Some(_)) => "done",
+            _ => "running",
+        }
+    }
+
+    /// INFO lines for the warm-up section.
+    pub fn render(&self) -> String {
+        format!(
+            "warmup_status:{}\nwarmup_keys_loaded:{}\nwarmup_keys_total:{}\nwarmup_duration_ms:{}",
+            self.status(),
+            self.loaded,
+            self.total,
+            self.duration_ms.unwrap_or(0)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_orders_hottest_first_within_capacity() {
+        let counts: HashMap<String, u64> = [("a", 5), ("b", 50), ("c", 5), ("d", 500)]
+            .into_iter()
+            .map(|(key, count)| (key.to_string(), count))
+            .collect();
+        let cold = ["a", "b", "c", "d", "never-read"].iter().map(|key| key.to_string()).collect();
+        let mut warmup = Warmup::plan(&counts, cold, 3);
+        assert_eq!(warmup.total, 3);
+        assert_eq!(warmup.next_batch(10), vec!["d", "b", "a"]);
+    }
+
+    #[test]
+    fn test_status_follows_batches() {
+        assert_eq!(Warmup::default().status(), "none");
+
+        let counts: HashMap<String, u64> = [("a".to_string(), 1), ("b".to_string(), 2)].into_iter().collect();
+        let mut warmup = Warmup::plan(&counts, vec!["a".to_string(), "b".to_string()], 10);
+        assert_eq!(warmup.status(), "running");
+        assert_eq!(warmup.next_batch(1), vec!["b"]);
+        assert_eq!(warmup.status(), "running");
+        assert_eq!(warmup.next_batch(1), vec!["a"]);
+        assert_eq!(warmup.status(), "done");
+        assert!(warmup.next_batch(1).is_empty());
+    }
+}
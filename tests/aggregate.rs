@@ -0,0 +1,39 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn aggregates_integer_values_of_matching_keys() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    for i in 1..=2500 {
+        expect(&format!("SET views:{} {}", i, i), "OK").await;
+    }
+    expect("SET views:label popular", "OK").await;
+    expect("RPUSH views:list 1", "(integer) 1").await;
+    expect("SET likes:1 1000000", "OK").await;
+
+    // Spans several batches
+    expect("AGGREGATE COUNT views:*", "(integer) 2502").await;
+    expect("AGGREGATE SUM views:*", "(integer) 3126250").await;
+    expect("AGGREGATE MIN views:*", "(integer) 1").await;
+    expect("AGGREGATE max views:*", "(integer) 2500").await;
+    expect("AGGREGATE AVG views:*", "\"1250.5\"").await;
+    expect("AGGREGATE SUM views:?", "(integer) 45").await;
+    expect("AGGREGATE COUNT nothing:*", "(integer) 0").await;
+    expect("AGGREGATE MAX nothing:*", "(nil)").await;
+
+    expect("AGGREGATE MEDIAN views:*", "ERR unknown reducer 'MEDIAN', expected COUNT, SUM, MIN, MAX or AVG").await;
+    expect("AGGREGATE SUM", "ERR wrong number of arguments for 'aggregate' command").await;
+}
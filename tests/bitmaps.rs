@@ -0,0 +1,67 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn bit_commands_work_on_string_bytes() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    // Bit 1 of "a" (0x61) is set; turning on bit 6 makes it "c"
+    expect("SET k a", "OK").await;
+    expect("GETBIT k 1", "(integer) 1").await;
+    expect("SETBIT k 6 1", "(integer) 0").await;
+    expect("GET k", "\"c\"").await;
+    expect("GETBIT k 100", "(integer) 0").await;
+
+    // An integer is changed as its digits: "1" (0x31) becomes "3" (0x33)
+    expect("SET n 1", "OK").await;
+    expect("GETBIT n 2", "(integer) 1").await;
+    expect("SETBIT n 6 1", "(integer) 0").await;
+    expect("GET n", "\"3\"").await;
+
+    // Bytes that are not UTF-8 are kept exactly and shown escaped
+    expect("SETBIT flags 0 1", "(integer) 0").await;
+    expect("SETBIT flags 15 1", "(integer) 0").await;
+    expect("GET flags", "\"\\x80\\x01\"").await;
+    expect("STRLEN flags", "(integer) 2").await;
+    expect("TYPE flags", "string").await;
+    expect("BITCOUNT flags", "(integer) 2").await;
+    expect("BITCOUNT flags 1 1", "(integer) 1").await;
+    expect("BITCOUNT flags 1 14 BIT", "(integer) 0").await;
+
+    expect("SET foobar foobar", "OK").await;
+    expect("BITCOUNT foobar 1 1", "(integer) 6").await;
+    expect("BITCOUNT foobar 5 30 BIT", "(integer) 17").await;
+    expect("BITCOUNT missing", "(integer) 0").await;
+
+    expect("SET a abc", "OK").await;
+    expect("SET b a", "OK").await;
+    expect("BITOP AND dest a b missing", "(integer) 3").await;
+    expect("BITCOUNT dest", "(integer) 0").await;
+    expect("STRLEN dest", "(integer) 3").await;
+    expect("BITOP OR dest a b", "(integer) 3").await;
+    expect("GET dest", "\"abc\"").await;
+    expect("BITOP XOR dest a a", "(integer) 3").await;
+    expect("BITCOUNT dest", "(integer) 0").await;
+    expect("BITOP NOT dest flags", "(integer) 2").await;
+    expect("GET dest", "\"\\x7f\\xfe\"").await;
+    expect("BITOP OR dest missing", "(integer) 0").await;
+    expect("EXISTS dest", "(integer) 0").await;
+
+    expect("SETBIT k 4294967296 1", "ERR bit offset is not an integer or out of range").await;
+    expect("SETBIT k 0 2", "ERR bit is not an integer or out of range").await;
+    expect("BITOP NOT dest a b", "ERR BITOP NOT must be called with a single source key.").await;
+    expect("LPUSH l x", "(integer) 1").await;
+    expect("GETBIT l 0", "(error) WRONGTYPE Operation against a key holding the wrong kind of value").await;
+}
@@ -0,0 +1,95 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+// Runs a command on its own connection, as a blocked client would
+fn spawn(db: &Database, line: &str) -> JoinHandle<String> {
+    let db = Arc::clone(db);
+    let line = line.to_string();
+    tokio::spawn(async move {
+        let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+        run(&db, &mut auth, &line).await
+    })
+}
+
+async fn blocked_clients(db: &Database) -> usize {
+    db.read().await.waiters.blocked_clients()
+}
+
+#[tokio::test]
+async fn blocking_pops_reply_at_once_when_a_list_has_elements() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("RPUSH b x y", "(integer) 2").await;
+    expect("RPUSH c z", "(integer) 1").await;
+    // The first key holding a list wins
+    expect("BLPOP a b c 1", "1) \"b\"\n2) \"x\"").await;
+    expect("BRPOP a c b 1", "1) \"c\"\n2) \"z\"").await;
+    expect("EXISTS c", "(integer) 0").await;
+    expect("BLMOVE b d RIGHT LEFT 1", "\"y\"").await;
+    expect("BRPOPLPUSH d d 1", "\"y\"").await;
+
+    expect("SET s v", "OK").await;
+    expect("BLPOP s 1", "(error) WRONGTYPE Operation against a key holding the wrong kind of value").await;
+    expect("BLMOVE d s LEFT LEFT 1", "(error) WRONGTYPE Operation against a key holding the wrong kind of value").await;
+    expect("BLPOP a -1", "ERR timeout is negative").await;
+    expect("BRPOP a soon", "ERR timeout is not a float or out of range").await;
+    expect("BLPOP a", "ERR wrong number of arguments for 'blpop' command").await;
+    expect("BLMOVE a b UP LEFT 1", "ERR syntax error").await;
+}
+
+#[tokio::test]
+async fn blocked_clients_are_woken_by_pushes() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+
+    let popper = spawn(&db, "BRPOP jobs other 0");
+    let mover = spawn(&db, "BLMOVE queue processing LEFT RIGHT 0");
+    while blocked_clients(&db).await < 2 {
+        tokio::task::yield_now().await;
+    }
+    assert!(run(&db, &mut auth, "INFO").await.contains("# Clients\nblocked_clients:2\n"));
+
+    assert_eq!(run(&db, &mut auth, "LPUSH other job1").await, "(integer) 1");
+    assert_eq!(popper.await.unwrap(), "1) \"other\"\n2) \"job1\"");
+    assert_eq!(run(&db, &mut auth, "RPUSH queue task").await, "(integer) 1");
+    assert_eq!(mover.await.unwrap(), "\"task\"");
+    assert_eq!(run(&db, &mut auth, "LRANGE processing 0 -1").await, "1) \"task\"");
+    assert_eq!(blocked_clients(&db).await, 0);
+
+    // One element goes to one of two waiters; the other stays parked until its timeout
+    let first = spawn(&db, "BLPOP jobs 0.2");
+    let second = spawn(&db, "BLPOP jobs 0.2");
+    while blocked_clients(&db).await < 2 {
+        tokio::task::yield_now().await;
+    }
+    run(&db, &mut auth, "RPUSH jobs only").await;
+    let mut replies = vec![first.await.unwrap(), second.await.unwrap()];
+    replies.sort();
+    assert_eq!(replies, vec!["(nil)", "1) \"jobs\"\n2) \"only\""]);
+}
+
+#[tokio::test]
+async fn blocking_pops_time_out_with_nil() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+
+    let started = Instant::now();
+    assert_eq!(run(&db, &mut auth, "BLPOP empty 0.05").await, "(nil)");
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert_eq!(run(&db, &mut auth, "BLMOVE empty dest LEFT LEFT 0.01").await, "(nil)");
+    assert_eq!(blocked_clients(&db).await, 0);
+}
@@ -0,0 +1,13 @@
+use rust_redis::compat::{check_fixture, load_fixtures};
+use std::path::Path;
+
+#[tokio::test]
+async fn replies_match_the_captured_redis_sessions() {
+    let fixtures = load_fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/compat")).unwrap();
+    assert!(!fixtures.is_empty());
+    let mut mismatches = Vec::new();
+    for fixture in &fixtures {
+        mismatches.extend(check_fixture(fixture).await.iter().map(|mismatch| mismatch.to_string()));
+    }
+    assert!(mismatches.is_empty(), "replies differ from Redis:\n{}", mismatches.join("\n"));
+}
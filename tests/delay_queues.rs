@@ -0,0 +1,24 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn huge_delays_and_timeouts_are_refused() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+
+    assert_eq!(run(&db, &mut auth, "DELAYQ BPOP q 1e30").await, "ERR timeout is not a float or out of range");
+    assert_eq!(run(&db, &mut auth, "DELAYQ BPOP q -1").await, "ERR timeout is not a float or out of range");
+    assert_eq!(run(&db, &mut auth, &format!("DELAYQ PUSH q {} job", u64::MAX)).await, "(error) ERR delay is out of range");
+    assert_eq!(run(&db, &mut auth, "DELAYQ LEN q").await, "1) (integer) 0\n2) (integer) 0");
+    assert_eq!(run(&db, &mut auth, "DELAYQ PUSH q 1000 job").await, "(integer) 1");
+}
@@ -0,0 +1,51 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn delpattern_deletes_matching_keys_in_the_background() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+
+    for i in 0..300 {
+        run(&db, &mut auth, &format!("SET user:{} v", i)).await;
+    }
+    run(&db, &mut auth, "SET account:1 v").await;
+
+    assert_eq!(run(&db, &mut auth, "DELPATTERN user:* RATE 1000000").await, "(integer) 1");
+    let mut status = String::new();
+    for _ in 0..100 {
+        status = run(&db, &mut auth, "JOB STATUS 1").await;
+        if status.contains("status:done") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(status.starts_with("\"id:1\ntype:delpattern\nstatus:done\ntarget:user:*\ndone:300\ntotal:300\n"), "{}", status);
+    assert!(status.ends_with("keys_per_sec_limit:1000000\nbytes_per_sec_limit:unlimited\ndeleted:300\""), "{}", status);
+    assert_eq!(run(&db, &mut auth, "DBSIZE").await, "(integer) 1");
+
+    // A slow job can be cancelled between batches
+    for i in 0..300 {
+        run(&db, &mut auth, &format!("SET user:{} v", i)).await;
+    }
+    assert_eq!(run(&db, &mut auth, "DELPATTERN user:* RATE 1").await, "(integer) 2");
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(run(&db, &mut auth, "JOB CANCEL 2").await, "OK");
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(run(&db, &mut auth, "JOB STATUS 2").await.contains("status:cancelled\ntarget:user:*\ndone:100\ntotal:300\n"));
+    assert_eq!(run(&db, &mut auth, "DBSIZE").await, "(integer) 201");
+
+    assert_eq!(run(&db, &mut auth, "JOB CANCEL 2").await, "(error) ERR no running job 2");
+    assert_eq!(run(&db, &mut auth, "DELPATTERN user:* RATE 0").await, "ERR RATE needs a positive integer");
+}
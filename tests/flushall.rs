@@ -0,0 +1,22 @@
+use rust_redis::data_types::RedisValue;
+use rust_redis::RedisDatabase;
+use std::time::Duration;
+
+fn database_with_keys(undo_window: Option<Duration>) -> RedisDatabase {
+    let mut db = RedisDatabase::new();
+    db.flush_policy.undo_window = undo_window;
+    for key in ["a", "b", "c"] {
+        db.set(key.to_string(), RedisValue::String("v".to_string())).unwrap();
+    }
+    db
+}
+
+#[test]
+fn flushall_counts_each_key_once_toward_saves() {
+    for undo_window in [None, Some(Duration::from_secs(60))] {
+        let mut db = database_with_keys(undo_window);
+        let before = db.dirty;
+        assert_eq!(db.flush_all(), 3);
+        assert_eq!(db.dirty, before + 3, "undo window {:?}", undo_window);
+    }
+}
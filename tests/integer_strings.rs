@@ -0,0 +1,84 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn integers_behave_as_strings() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("INCR n", "(integer) 1").await;
+    expect("INCR n", "(integer) 2").await;
+    expect("GET n", "\"2\"").await;
+    expect("TYPE n", "string").await;
+    expect("STRLEN n", "(integer) 1").await;
+    expect("APPEND n 5", "(integer) 2").await;
+    expect("INCR n", "(integer) 26").await;
+    expect("GETRANGE n 1 -1", "\"6\"").await;
+    expect("LCS n n", "\"26\"").await;
+    expect("SET m old GET", "(nil)").await;
+    expect("DECR d", "(integer) -1").await;
+    expect("SET d x GET", "\"-1\"").await;
+}
+
+#[tokio::test]
+async fn increments_by_amount() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("INCRBY n 10", "(integer) 10").await;
+    expect("DECRBY n 15", "(integer) -5").await;
+    expect("INCRBYFLOAT n 7.5", "\"2.5\"").await;
+    expect("INCRBYFLOAT n 0.5", "\"3\"").await;
+    expect("INCRBY n 1", "(integer) 4").await;
+    expect("SET f 10.5", "OK").await;
+    expect("INCRBYFLOAT f 0.1", "\"10.6\"").await;
+    expect("INCRBYFLOAT f -10.6", "\"0\"").await;
+    expect("INCRBYFLOAT f 5e3", "\"5000\"").await;
+
+    expect("INCRBY f x", "ERR value is not an integer or out of range").await;
+    expect("INCRBYFLOAT f inf", "ERR value is not a valid float").await;
+    expect("SET big 9223372036854775807", "OK").await;
+    expect("INCRBY big 1", "(error) ERR increment or decrement would overflow").await;
+    expect("DECRBY big -9223372036854775808", "(error) ERR increment or decrement would overflow").await;
+    expect("SET s text", "OK").await;
+    expect("INCRBYFLOAT s 1", "(error) ERR value is not a valid float").await;
+    expect("INCRBYFLOAT f 0.5", "\"5000.5\"").await;
+    expect("INCRBY f 1", "(error) ERR value is not an integer or out of range").await;
+}
+
+#[tokio::test]
+async fn numeric_strings_are_stored_as_integers() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("SET n 10", "OK").await;
+    expect("OBJECT ENCODING n", "\"int\"").await;
+    expect("TYPE n", "string").await;
+    expect("GET n", "\"10\"").await;
+    for padded in ["010", "+10", "-0", "1e3"] {
+        expect(&format!("SET p {}", padded), "OK").await;
+        expect("OBJECT ENCODING p", "\"embstr\"").await;
+        expect("GET p", &format!("\"{}\"", padded)).await;
+    }
+
+    expect("APPEND n x", "(integer) 3").await;
+    expect("OBJECT ENCODING n", "\"embstr\"").await;
+    expect("SET n 1", "OK").await;
+    expect("APPEND n 2", "(integer) 2").await;
+    expect("OBJECT ENCODING n", "\"int\"").await;
+    expect("INCRBYFLOAT n 0.5", "\"12.5\"").await;
+    expect("OBJECT ENCODING n", "\"embstr\"").await;
+}
@@ -0,0 +1,66 @@
+use rust_redis::commands::execute_command;
+use rust_redis::database::KeyInvalidator;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::{Arc, Mutex};
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+// Collects what would have been sent to peers
+#[derive(Debug, Default)]
+struct Recorder(Mutex<Vec<String>>);
+
+impl KeyInvalidator for Recorder {
+    fn invalidate(&self, keys: &mut dyn Iterator<Item = &String>) {
+        self.0.lock().unwrap().extend(keys.cloned());
+    }
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+#[tokio::test]
+async fn every_write_is_announced_once_to_peers() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let recorder = Arc::new(Recorder::default());
+    db.write().await.invalidation = Some(recorder.clone());
+
+    for (line, announced) in [
+        ("SET n 1", vec!["n"]),
+        ("INCR n", vec!["n"]),
+        ("INCRBYFLOAT n 1.5", vec!["n"]),
+        ("APPEND s x", vec!["s"]),
+        ("RENAME s t", vec!["s", "t"]),
+        ("COPY t u", vec!["u"]),
+        ("RPUSH l a", vec!["l"]),
+        ("LMOVE l m LEFT LEFT", vec!["l", "m"]),
+    ] {
+        run(&db, &mut auth, line).await;
+        let mut keys = recorder.take();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys, announced, "{}", line);
+    }
+
+    run(&db, &mut auth, "FLUSHALL").await;
+    let mut keys = recorder.take();
+    keys.sort();
+    assert_eq!(keys, ["m", "n", "t", "u"]);
+
+    // Deletes asked for by a peer are not passed back
+    run(&db, &mut auth, "SET k v").await;
+    recorder.take();
+    assert_eq!(run(&db, &mut auth, "INVALIDATE k").await, "(integer) 1");
+    assert!(recorder.take().is_empty());
+    assert!(db.read().await.invalidation.is_some());
+}
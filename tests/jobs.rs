@@ -0,0 +1,94 @@
+use rust_redis::commands::execute_command;
+use rust_redis::persistence_clean::MmapPersistence;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn jobs_are_listed_capped_and_cancelled() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut tenant = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    tenant.key_prefix = Some("app1:".to_string());
+
+    assert_eq!(run(&db, &mut auth, "JOB LIST").await, "(empty array)");
+    for i in 0..10 {
+        run(&db, &mut auth, &format!("SET k:{} v", i)).await;
+        run(&db, &mut tenant, &format!("SET k:{} v", i)).await;
+    }
+
+    // Slow enough that the jobs are still running when listed
+    assert_eq!(run(&db, &mut tenant, "DELPATTERN k:* RATE 1").await, "(integer) 1");
+    for id in 2..=4 {
+        assert_eq!(run(&db, &mut auth, "DELPATTERN k:* RATE 1").await, format!("(integer) {}", id));
+    }
+    assert_eq!(run(&db, &mut auth, "DELPATTERN k:* RATE 1").await, "(error) ERR 4 jobs are already running, see JOB LIST");
+
+    let list = run(&db, &mut auth, "JOB LIST").await;
+    assert!(list.starts_with("1) \"id=1 type=delpattern status=running target=app1:k:* progress="), "{}", list);
+    assert!(list.contains("\n4) \"id=4 type=delpattern status=running target=k:* progress="), "{}", list);
+
+    // A tenant only sees and cancels its own jobs, with its prefix taken off
+    assert!(run(&db, &mut tenant, "JOB LIST").await.starts_with("1) \"id=1 type=delpattern status=running target=k:* progress="));
+    assert_eq!(run(&db, &mut tenant, "JOB LIST").await.lines().count(), 1);
+    assert_eq!(run(&db, &mut tenant, "JOB STATUS 2").await, "(error) ERR no such job 2");
+    assert_eq!(run(&db, &mut tenant, "JOB CANCEL 2").await, "(error) ERR no running job 2");
+    assert_eq!(run(&db, &mut tenant, "JOB CANCEL 1").await, "OK");
+
+    for id in 2..=4 {
+        assert_eq!(run(&db, &mut auth, &format!("JOB CANCEL {}", id)).await, "OK");
+    }
+    assert!(run(&db, &mut auth, "JOB LIST").await.contains("\n4) \"id=4 type=delpattern status=cancelled target=k:*"));
+
+    assert_eq!(run(&db, &mut auth, "JOB STATUS x").await, "ERR job id must be a positive integer");
+    assert_eq!(run(&db, &mut auth, "JOB STATUS").await, "ERR wrong number of arguments for 'job|status' command");
+    assert_eq!(run(&db, &mut auth, "JOB PAUSE 1").await, "ERR unknown JOB subcommand 'PAUSE'");
+}
+
+#[tokio::test]
+async fn merge_runs_in_the_background() {
+    let source = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    for i in 0..250 {
+        run(&source, &mut auth, &format!("SET k:{} v", i)).await;
+    }
+    let dir = std::env::temp_dir().join(format!("rust_redis_jobs_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("source.json").to_string_lossy().to_string();
+    MmapPersistence::new(path.clone()).save_database(&*source.read().await).unwrap();
+
+    let db = create_database();
+    run(&db, &mut auth, "SET k:0 old").await;
+    assert_eq!(run(&db, &mut auth, &format!("MERGE {} SKIP BACKGROUND", path)).await, "(integer) 1");
+    let mut status = String::new();
+    for _ in 0..100 {
+        status = run(&db, &mut auth, "JOB STATUS 1").await;
+        if status.contains("status:done") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(status.contains("type:merge\nstatus:done\n"), "{}", status);
+    assert!(status.contains("done:250\ntotal:250\n"), "{}", status);
+    assert!(status.ends_with("errors:0\nnew_keys:249\noverwritten:0\nskipped:1\""), "{}", status);
+    assert_eq!(run(&db, &mut auth, "GET k:0").await, "\"old\"");
+    assert!(run(&db, &mut auth, "MIGRATION STATUS").await.starts_with("\"status:done\n"));
+
+    // A throttled one stops once cancelled
+    let db = create_database();
+    assert_eq!(run(&db, &mut auth, &format!("MERGE {} KEYSPERSEC 1 BACKGROUND", path)).await, "(integer) 1");
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(run(&db, &mut auth, "JOB CANCEL 1").await, "OK");
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(run(&db, &mut auth, "DBSIZE").await, "(integer) 100");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
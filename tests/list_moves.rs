@@ -0,0 +1,34 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn lmove_checks_both_keys_before_moving() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("RPUSH jobs j1 j2", "(integer) 2").await;
+    expect("SET busy x", "OK").await;
+    expect("LMOVE jobs busy LEFT RIGHT", "(error) WRONGTYPE Operation against a key holding the wrong kind of value").await;
+    expect("LLEN jobs", "(integer) 2").await;
+    expect("LMOVE busy jobs LEFT RIGHT", "(error) WRONGTYPE Operation against a key holding the wrong kind of value").await;
+
+    // Both lists keep their TTLs
+    expect("RPUSH working w0", "(integer) 1").await;
+    expect("EXPIRE jobs 100", "(integer) 1").await;
+    expect("EXPIRE working 200", "(integer) 1").await;
+    expect("LMOVE jobs working LEFT RIGHT", "\"j1\"").await;
+    assert!(db.read().await.expires.contains_key("jobs"));
+    assert!(db.read().await.expires.contains_key("working"));
+    expect("LRANGE working 0 -1", "1) \"w0\"\n2) \"j1\"").await;
+}
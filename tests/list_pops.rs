@@ -0,0 +1,46 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn pops_with_a_count_reply_with_arrays() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("RPUSH l a b c", "(integer) 3").await;
+    expect("EXPIRE l 100", "(integer) 1").await;
+    expect("RPOP l 2", "1) \"c\"\n2) \"b\"").await;
+    // What is left keeps its TTL
+    assert!(db.read().await.expires.contains_key("l"));
+    expect("LPOP l", "\"a\"").await;
+    expect("LPOP l", "(nil)").await;
+    expect("SET s v", "OK").await;
+    expect("LPOP s 2", "(error) WRONGTYPE Operation against a key holding the wrong kind of value").await;
+    expect("RPOP l x", "ERR value is out of range, must be positive").await;
+
+    // A single element popped with a count is still an array on the wire
+    expect("RPUSH one only", "(integer) 1").await;
+    let reply = execute_command(Arc::clone(&db), parse_command("LPOP one 1").unwrap(), &mut auth, None, None).await;
+    assert_eq!(reply.encode(), b"*1\r\n$4\r\nonly\r\n");
+}
+
+#[tokio::test]
+async fn values_shaped_like_reply_text_keep_their_bytes() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let command = rust_redis::commands::Command::RPush { key: "l".into(), values: vec!["a\n2) b".into(), "  indented".into()] };
+    execute_command(Arc::clone(&db), command, &mut auth, None, None).await;
+
+    let reply = execute_command(Arc::clone(&db), parse_command("LRANGE l 0 -1").unwrap(), &mut auth, None, None).await;
+    assert_eq!(reply.encode(), b"*2\r\n$6\r\na\n2) b\r\n$10\r\n  indented\r\n");
+}
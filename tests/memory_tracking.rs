@@ -0,0 +1,22 @@
+use rust_redis::data_types::RedisValue;
+use rust_redis::RedisDatabase;
+
+#[test]
+fn tracking_never_outgrows_the_keyspace() {
+    let mut db = RedisDatabase::new();
+    for i in 0..100 {
+        db.set(format!("key{}", i), RedisValue::String("v".to_string())).unwrap();
+    }
+    db.memory_manager.pin("key0");
+    let tracked = db.memory_manager.tracking_overhead();
+    assert_eq!(db.prune_tracking(), 0);
+
+    // Removed behind the tracking's back, as a path that skips delete() would
+    for i in 0..50 {
+        db.data.remove(&format!("key{}", i));
+    }
+    assert_eq!(db.prune_tracking(), 51);
+    assert_eq!(db.memory_manager.access_times.len(), 50);
+    assert!(!db.memory_manager.is_pinned("key0"));
+    assert!(db.memory_manager.tracking_overhead() < tracked);
+}
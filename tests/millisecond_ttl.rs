@@ -0,0 +1,65 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+fn integer(reply: &str) -> i64 {
+    reply.strip_prefix("(integer) ").and_then(|n| n.parse().ok()).unwrap_or_else(|| panic!("not an integer: {}", reply))
+}
+
+#[tokio::test]
+async fn sub_second_expirations() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+
+    assert_eq!(run(&db, &mut auth, "PSETEX lock 80 owner").await, "OK");
+    let pttl = integer(&run(&db, &mut auth, "PTTL lock").await);
+    assert!((1..=80).contains(&pttl), "{}", pttl);
+    assert_eq!(run(&db, &mut auth, "TTL lock").await, "(integer) 0");
+
+    assert_eq!(run(&db, &mut auth, "SET window 1").await, "OK");
+    assert_eq!(run(&db, &mut auth, "PTTL window").await, "(integer) -1");
+    assert_eq!(run(&db, &mut auth, "PEXPIRE window 80").await, "(integer) 1");
+    assert_eq!(run(&db, &mut auth, "PEXPIRE missing 80").await, "(integer) 0");
+
+    tokio::time::sleep(Duration::from_millis(120)).await;
+    assert_eq!(run(&db, &mut auth, "GET lock").await, "(nil)");
+    assert_eq!(run(&db, &mut auth, "PTTL window").await, "(integer) -2");
+}
+
+#[tokio::test]
+async fn pexpireat_takes_a_unix_time_in_milliseconds() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+    run(&db, &mut auth, "SET k v").await;
+    assert_eq!(run(&db, &mut auth, &format!("PEXPIREAT k {}", now_ms + 60_000)).await, "(integer) 1");
+    let pttl = integer(&run(&db, &mut auth, "PTTL k").await);
+    assert!((59_000..=60_000).contains(&pttl), "{}", pttl);
+
+    // A time already past deletes the key
+    assert_eq!(run(&db, &mut auth, &format!("PEXPIREAT k {}", now_ms - 1)).await, "(integer) 1");
+    assert_eq!(run(&db, &mut auth, "EXISTS k").await, "(integer) 0");
+    assert_eq!(run(&db, &mut auth, "PEXPIREAT k 1").await, "(integer) 0");
+
+    assert_eq!(run(&db, &mut auth, "PSETEX k 0 v").await, "ERR invalid expire time in 'psetex' command");
+    assert_eq!(run(&db, &mut auth, "PEXPIRE k soon").await, "ERR invalid expire time");
+
+    // TTLs that would overflow the clock are refused before the key is touched
+    assert_eq!(run(&db, &mut auth, &format!("SETEX k {} v", u64::MAX)).await, "ERR invalid expire time in 'setex' command");
+    assert_eq!(run(&db, &mut auth, &format!("PSETEX k {} v", u64::MAX)).await, "ERR invalid expire time in 'psetex' command");
+    assert_eq!(run(&db, &mut auth, "EXISTS k").await, "(integer) 0");
+    run(&db, &mut auth, "SET k v").await;
+    assert_eq!(run(&db, &mut auth, &format!("EXPIRE k {}", u64::MAX)).await, "ERR invalid expire time");
+    assert_eq!(run(&db, &mut auth, "TTL k").await, "(integer) -1");
+}
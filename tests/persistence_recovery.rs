@@ -1,42 +1,199 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use std::collections::HashMap;
+use rust_redis::data_types::RedisValue;
+use rust_redis::persistence_clean::{MmapPersistence, PersistenceError};
 use std::fs;
+use std::path::PathBuf;
 use std::process::Command;
-use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize)]
-struct MyDb {
-    data: HashMap<String, String>,
+fn crash_and_recover(crash_point: &str) -> rust_redis::RedisDatabase {
+    let dir = std::env::temp_dir().join(format!("rust_redis_crash_{}_{}", std::process::id(), crash_point));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let db_file: PathBuf = dir.join("db.json");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_rust_redis"))
+        .arg("--dbfilename")
+        .arg(&db_file)
+        .arg("simulate_crash")
+        .arg("--crash-point")
+        .arg(crash_point)
+        .status()
+        .expect("Failed to spawn child process");
+    assert!(!status.success(), "simulate_crash should not exit cleanly");
+
+    let db = MmapPersistence::new(db_file.to_string_lossy().to_string())
+        .load_database()
+        .expect("recovery should always produce a database");
+
+    let _ = fs::remove_dir_all(&dir);
+    db
 }
 
-impl MyDb {
-    fn load(file: &str) -> Self {
-        let content = fs::read_to_string(file).unwrap_or_else(|_| "{}".to_string());
-        let data: HashMap<String, String> = serde_json::from_str(&content).unwrap();
-        Self { data }
+fn assert_first_generation(db: &rust_redis::RedisDatabase) {
+    match db.data.get("key1").map(|value| &**value) {
+        Some(RedisValue::String(v)) => assert_eq!(v, "value1"),
+        other => panic!("key1 missing after recovery: {:?}", other),
     }
+    match db.data.get("key2").map(|value| &**value) {
+        Some(RedisValue::String(v)) => assert_eq!(v, "value2"),
+        other => panic!("key2 missing after recovery: {:?}", other),
+    }
+    assert!(matches!(db.data.get("counter").map(|value| &**value), Some(RedisValue::Integer(42))));
 }
 
-fn persistence_crash_test(c: &mut Criterion) {
-    let db_file = "db.json";
+#[test]
+fn recovers_after_crash_following_backup() {
+    let db = crash_and_recover("after-backup");
+    assert_first_generation(&db);
+    assert!(!db.data.contains_key("key3"));
+}
 
-    c.bench_function("db_persistence_crash", |b| {
-        b.iter(|| {
-            let _ = fs::remove_file(db_file);
-            let status = Command::new(std::env::current_exe().unwrap())
-                .arg("simulate_crash")
-                .status()
-                .expect("Failed to spawn child process");
+#[test]
+fn recovers_after_crash_mid_write() {
+    let db = crash_and_recover("mid-write");
+    assert_first_generation(&db);
+    assert!(!db.data.contains_key("key3"));
+}
+
+#[test]
+fn recovers_after_crash_before_rename() {
+    let db = crash_and_recover("before-rename");
+    assert_first_generation(&db);
+    assert!(!db.data.contains_key("key3"));
+}
 
-            assert!(status.code().is_some());
-            let db = MyDb::load(db_file);
-            match db.data.get("key1") {
-                Some(v) => assert_eq!(v, "value1"),
-                None => panic!("key1 missing after recovery"),
-            }
-        })
-    });
+#[test]
+fn keeps_second_generation_after_crash_following_rename() {
+    let db = crash_and_recover("after-rename");
+    assert_first_generation(&db);
+    assert!(db.data.contains_key("key3"));
 }
 
-criterion_group!(tests, persistence_crash_test);
-criterion_main!(tests);
+fn sanity_check(db_file: &PathBuf, wal: Option<&PathBuf>) -> (bool, String) {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rust_redis"));
+    command.arg("--dbfilename").arg(db_file).arg("sanity_check");
+    if let Some(wal) = wal {
+        command.arg("--wal").arg(wal);
+    }
+    let output = command.output().expect("Failed to spawn child process");
+    (output.status.success(), String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[test]
+fn sanity_check_reports_healthy_snapshot() {
+    let dir = std::env::temp_dir().join(format!("rust_redis_sanity_ok_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let db_file = dir.join("db.json");
+    let wal_file = dir.join("wal.log");
+
+    let mut db = rust_redis::RedisDatabase::new();
+    db.set("a".to_string(), RedisValue::String("1".to_string())).unwrap();
+    db.set("n".to_string(), RedisValue::Integer(2)).unwrap();
+    MmapPersistence::new(db_file.to_string_lossy().to_string()).save_database(&db).unwrap();
+    fs::write(&wal_file, "{\"Delete\":{\"key\":\"a\",\"timestamp\":1}}\n").unwrap();
+
+    let (healthy, report) = sanity_check(&db_file, Some(&wal_file));
+    let _ = fs::remove_dir_all(&dir);
+    assert!(healthy, "{}", report);
+    assert!(report.contains("checksum: verified"));
+    assert!(report.contains("keys: 2"));
+    assert!(report.contains("1 entries, 0 corrupt"));
+}
+
+#[test]
+fn sanity_check_fails_on_corruption() {
+    let dir = std::env::temp_dir().join(format!("rust_redis_sanity_bad_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let db_file = dir.join("db.json");
+    let wal_file = dir.join("wal.log");
+
+    let mut db = rust_redis::RedisDatabase::new();
+    db.set("a".to_string(), RedisValue::String("one".to_string())).unwrap();
+    MmapPersistence::new(db_file.to_string_lossy().to_string()).save_database(&db).unwrap();
+    // Change the value behind the checksum's back
+    let generation_file = dir.join("db.json.1");
+    let tampered = fs::read_to_string(&generation_file).unwrap().replace("\"one\"", "\"two\"");
+    fs::write(&generation_file, tampered).unwrap();
+    fs::write(&wal_file, "not json\n").unwrap();
+
+    let (healthy, report) = sanity_check(&db_file, Some(&wal_file));
+    assert!(!healthy);
+    assert!(report.contains("checksum: MISMATCH"));
+    assert!(report.contains("1 corrupt"));
+
+    let (healthy, _) = sanity_check(&dir.join("missing.json"), None);
+    let _ = fs::remove_dir_all(&dir);
+    assert!(!healthy);
+}
+
+#[test]
+fn falls_back_to_previous_generation_named_in_manifest() {
+    let dir = std::env::temp_dir().join(format!("rust_redis_manifest_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let db_file = dir.join("db.json").to_string_lossy().to_string();
+    let persistence = MmapPersistence::new(db_file.clone());
+
+    let mut db = rust_redis::RedisDatabase::new();
+    db.set("key1".to_string(), RedisValue::String("value1".to_string())).unwrap();
+    persistence.save_database(&db).unwrap();
+    db.set("key2".to_string(), RedisValue::String("value2".to_string())).unwrap();
+    persistence.save_database(&db).unwrap();
+    db.set("key3".to_string(), RedisValue::String("value3".to_string())).unwrap();
+    persistence.save_database(&db).unwrap();
+
+    // Only the current generation and the one before it are kept
+    assert!(!dir.join("db.json.1").exists());
+    assert!(persistence.load_database().unwrap().data.contains_key("key3"));
+
+    fs::write(dir.join("db.json.3"), "{}").unwrap();
+    let recovered = persistence.load_database().unwrap();
+    assert!(recovered.data.contains_key("key2"));
+    assert!(!recovered.data.contains_key("key3"));
+
+    // The rolled back manifest keeps loading the same generation
+    assert!(persistence.verify_integrity().unwrap());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn refuses_to_start_when_snapshot_exceeds_maxmemory() {
+    let dir = std::env::temp_dir().join(format!("rust_redis_preflight_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let db_file = dir.join("db.json");
+
+    let mut db = rust_redis::RedisDatabase::new();
+    db.set("big".to_string(), RedisValue::String("x".repeat(8192))).unwrap();
+    MmapPersistence::new(db_file.to_string_lossy().to_string()).save_database(&db).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rust_redis"))
+        .arg("--dbfilename").arg(&db_file)
+        .args(["--port", "0", "--flush-audit-log", "", "--maxmemory", "4KB", "--maxmemory-preflight", "refuse"])
+        .output()
+        .expect("Failed to spawn child process");
+    let _ = fs::remove_dir_all(&dir);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Refusing to start: the loaded snapshot uses"));
+}
+
+#[test]
+fn persistence_errors_can_be_matched() {
+    let dir = std::env::temp_dir().join(format!("rust_redis_errors_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let db_file = dir.join("db.json");
+    let persistence = MmapPersistence::new(db_file.to_string_lossy().to_string());
+
+    assert!(matches!(persistence.verify_integrity(), Err(PersistenceError::Missing(path)) if path == db_file));
+    let segment = dir.join("wal.1");
+    fs::write(&segment, "").unwrap();
+    assert!(matches!(persistence.add_wal_segment(&segment.to_string_lossy()), Err(PersistenceError::NoSnapshot)));
+
+    persistence.save_database(&rust_redis::RedisDatabase::new()).unwrap();
+    fs::write(dir.join("db.json.1"), "{}").unwrap();
+    let error = persistence.sanity_check().unwrap_err();
+    assert!(matches!(error, PersistenceError::Json(_)));
+    let _ = fs::remove_dir_all(&dir);
+}
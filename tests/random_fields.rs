@@ -0,0 +1,31 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::{parse_command, Reply};
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> Reply {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await,
+        Err(error) => Reply::error(error),
+    }
+}
+
+#[tokio::test]
+async fn huge_counts_are_clamped_or_refused() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    run(&db, &mut auth, "HSET h a 1").await;
+    run(&db, &mut auth, "HSET h b 2").await;
+
+    // Distinct picks stop at the fields there are
+    match run(&db, &mut auth, "HRANDFIELD h 100000000000").await {
+        Reply::Array(fields) => assert_eq!(fields.len(), 2),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+    match run(&db, &mut auth, "HRANDFIELD h -5 WITHVALUES").await {
+        Reply::Array(items) => assert_eq!(items.len(), 10),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+    assert_eq!(run(&db, &mut auth, "HRANDFIELD h -100000000000").await, Reply::error("ERR value is out of range"));
+}
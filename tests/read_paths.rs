@@ -0,0 +1,64 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+// Runs each read and checks it left the dirty counter, which drives saves, where it was
+async fn assert_reads_are_clean(db: &Database, auth: &mut ClientAuth, reads: &[&str]) {
+    for line in reads {
+        let before = db.read().await.dirty;
+        let reply = run(db, auth, line).await;
+        assert!(!reply.starts_with("(error)"), "{}: {}", line, reply);
+        assert_eq!(db.read().await.dirty, before, "{} marked the dataset dirty", line);
+    }
+}
+
+#[tokio::test]
+async fn json_and_timeseries_reads_do_not_dirty_the_dataset() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    run(&db, &mut auth, r#"JSON.SET doc $ '{"a":1}'"#).await;
+    run(&db, &mut auth, "TS.ADD ts 1 2").await;
+
+    assert_reads_are_clean(&db, &mut auth, &["JSON.GET doc $.a", "TS.GET ts", "TS.RANGE ts - +"]).await;
+}
+
+#[tokio::test]
+async fn filter_hash_vector_and_queue_reads_do_not_dirty_the_dataset() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    run(&db, &mut auth, "BF.ADD bf a").await;
+    run(&db, &mut auth, "HSET h f v").await;
+    run(&db, &mut auth, "VECTOR.ADD vs e 1 0").await;
+    run(&db, &mut auth, "DELAYQ PUSH q 0 job").await;
+    run(&db, &mut auth, "SETBIT bits 3 1").await;
+
+    assert_reads_are_clean(&db, &mut auth, &[
+        "BF.EXISTS bf a",
+        "BF.INFO bf",
+        "HTTL h FIELDS 1 f",
+        "HRANDFIELD h",
+        "VECTOR.SEARCH vs 1 1 0",
+        "DELAYQ LEN q",
+        "GETBIT bits 3",
+    ]).await;
+}
+
+#[tokio::test]
+async fn delay_queue_pops_with_nothing_due_do_not_dirty_the_dataset() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    run(&db, &mut auth, "DELAYQ PUSH q 60000 job").await;
+
+    // The blocked pop polls several times before it times out
+    assert_reads_are_clean(&db, &mut auth, &["DELAYQ POP q", "DELAYQ BPOP q 0.3"]).await;
+    assert_eq!(run(&db, &mut auth, "DELAYQ LEN q").await, "1) (integer) 1\n2) (integer) 0");
+}
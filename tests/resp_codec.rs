@@ -0,0 +1,66 @@
+use bytes::BytesMut;
+use rust_redis::resp::Request;
+use rust_redis::{Reply, RespCodec};
+
+#[test]
+fn codec_decodes_a_stream_fed_byte_by_byte() {
+    let mut upstream = RespCodec::new();
+    let mut wire = BytesMut::new();
+    let sent = [
+        Request::Array(vec!["SET".to_string(), "key".to_string(), "two\r\nlines".to_string()]),
+        Request::Inline("GET key".to_string()),
+    ];
+    for request in &sent {
+        upstream.encode_request(request, &mut wire).unwrap();
+    }
+
+    let mut codec = RespCodec::new();
+    let mut received = Vec::new();
+    for byte in wire.iter() {
+        let mut src = BytesMut::from(&[*byte][..]);
+        while let Some(frame) = codec.decode(&mut src).unwrap() {
+            received.push(frame.request.unwrap());
+        }
+        assert!(src.is_empty());
+    }
+    assert_eq!(received, sent);
+
+    let mut reply = BytesMut::new();
+    codec.encode(Reply::Array(vec![Reply::Bulk("a".to_string()), Reply::Integer(2)]), &mut reply).unwrap();
+    assert_eq!(&reply[..], b"*2\r\n$1\r\na\r\n:2\r\n");
+}
+
+#[test]
+fn codec_reports_truncated_frames_at_eof() {
+    let mut codec = RespCodec::new();
+    let frame = codec.decode_eof(&mut BytesMut::from(&b"PING"[..])).unwrap().unwrap();
+    assert_eq!(frame.request, Ok(Request::Inline("PING".to_string())));
+    assert!(codec.decode_eof(&mut BytesMut::new()).unwrap().is_none());
+
+    let error = codec.decode_eof(&mut BytesMut::from(&b"*2\r\n$3\r\nGET\r\n"[..])).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn codec_drives_a_framed_connection() {
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::Framed;
+
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut framed = Framed::new(server, RespCodec::new());
+
+    client.write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\nPING\r\n").await.unwrap();
+    let frame = framed.next().await.unwrap().unwrap();
+    assert_eq!(frame.request, Ok(Request::Array(vec!["GET".to_string(), "key".to_string()])));
+    let frame = framed.next().await.unwrap().unwrap();
+    assert_eq!(frame.request, Ok(Request::Inline("PING".to_string())));
+
+    framed.send(Reply::Simple("PONG".to_string())).await.unwrap();
+    let mut reply = [0; 7];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+
+    drop(client);
+    assert!(framed.next().await.is_none());
+}
@@ -0,0 +1,39 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn set_options_follow_redis() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("SET k v1 XX", "(nil)").await;
+    expect("SET k v1 NX GET", "(nil)").await;
+    expect("SET k v2 NX", "(nil)").await;
+    expect("SET k v2 XX GET PX 100000", "\"v1\"").await;
+    expect("SET k v3 KEEPTTL", "OK").await;
+    assert!(db.read().await.expires.contains_key("k"));
+    expect("SET k v4 GET", "\"v3\"").await;
+    assert!(!db.read().await.expires.contains_key("k"));
+
+    expect("SET k v NX XX", "ERR syntax error").await;
+    expect("SET k v EX 10 KEEPTTL", "ERR syntax error").await;
+    expect("SET k v PX 0", "ERR invalid expire time in set").await;
+    // Refused before anything is stored, rather than overflowing the clock afterwards
+    expect(&format!("SET big v EX {}", u64::MAX), "ERR invalid expire time in set").await;
+    expect(&format!("SET big v NX EX {}", u64::MAX / 1000), "ERR invalid expire time in set").await;
+    expect(&format!("SET big v PX {}", u64::MAX), "ERR invalid expire time in set").await;
+    expect("EXISTS big", "(integer) 0").await;
+    expect("SADD s m", "(integer) 1").await;
+    expect("SET s v GET", "(error) WRONGTYPE Operation against a key holding the wrong kind of value").await;
+}
@@ -0,0 +1,22 @@
+use std::process::Command;
+
+#[test]
+fn invalid_configuration_is_reported_in_full_and_refused() {
+    let dir = std::env::temp_dir().join(format!("rust_redis_config_{}", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_rust_redis"))
+        .arg("--dbfilename").arg(dir.join("dump.rdb"))
+        .args(["--port", "0", "--flush-audit-log", "", "--maxmemory", "512", "--maxmemory-policy", "lru"])
+        .args(["--write-stall-after", "100", "--write-reject-after", "10", "--save", "3600", "1"])
+        .output()
+        .expect("Failed to spawn child process");
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Configuration check: 3 error(s), 1 warning(s)"), "{}", stderr);
+    assert!(stderr.contains("error    --maxmemory: 512B is below the minimum of 1.00KB"), "{}", stderr);
+    assert!(stderr.contains("error    --maxmemory-policy: invalid value 'lru'"), "{}", stderr);
+    assert!(stderr.contains("error    --write-reject-after: 10 must not be below --write-stall-after (100)"), "{}", stderr);
+    assert!(stderr.contains("warning  --save: rule 3600 1 never fires"), "{}", stderr);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Starting Redis-clone server"));
+    assert!(!dir.exists());
+}
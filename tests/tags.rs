@@ -0,0 +1,57 @@
+use rust_redis::commands::execute_command;
+use rust_redis::persistence_clean::MmapPersistence;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn tags_follow_their_keys() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("SET s:1 a", "OK").await;
+    expect("SET s:2 b", "OK").await;
+    expect("TAG SET s:1 session eu", "(integer) 2").await;
+    expect("TAG SET s:2 session session", "(integer) 1").await;
+    expect("TAG SET missing session", "(integer) 0").await;
+    expect("TAG GET s:1", "1) \"eu\"\n2) \"session\"").await;
+    expect("KEYSBYTAG session", "1) \"s:1\"\n2) \"s:2\"").await;
+
+    // Tags survive overwrites, and go with the key on RENAME and COPY
+    expect("SET s:1 c", "OK").await;
+    expect("RENAME s:1 s:3", "OK").await;
+    expect("TAG GET s:1", "(empty array)").await;
+    expect("TAG GET s:3", "1) \"eu\"\n2) \"session\"").await;
+    expect("COPY s:3 s:4", "(integer) 1").await;
+    expect("KEYSBYTAG eu", "1) \"s:3\"\n2) \"s:4\"").await;
+
+    expect("TAG DEL s:4 eu missing", "(integer) 1").await;
+    expect("DEL s:2", "(integer) 1").await;
+    expect("PEXPIRE s:3 1", "(integer) 1").await;
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    expect("KEYSBYTAG session", "1) \"s:4\"").await;
+    expect("KEYSBYTAG eu", "(empty array)").await;
+
+    let dir = std::env::temp_dir().join(format!("rust_redis_tags_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let persistence = MmapPersistence::new(dir.join("db.json").to_string_lossy().to_string());
+    persistence.save_database(&*db.read().await).unwrap();
+    let loaded = persistence.load_database().unwrap();
+    assert_eq!(loaded.tags.keys_with("session"), vec!["s:4".to_string()]);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    expect("FLUSHALL", "OK").await;
+    expect("KEYSBYTAG session", "(empty array)").await;
+    expect("TAG LIST s:4", "ERR unknown TAG subcommand 'LIST'").await;
+    expect("TAG SET s:4", "ERR wrong number of arguments for 'tag|set' command").await;
+}
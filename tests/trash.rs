@@ -0,0 +1,65 @@
+use rust_redis::commands::execute_command;
+use rust_redis::protocol::parse_command;
+use rust_redis::shared::create_database;
+use rust_redis::{AuthConfig, ClientAuth, Database};
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn run(db: &Database, auth: &mut ClientAuth, line: &str) -> String {
+    match parse_command(line) {
+        Ok(command) => execute_command(Arc::clone(db), command, auth, None, None).await.to_text(),
+        Err(error) => error,
+    }
+}
+
+#[tokio::test]
+async fn deleted_keys_can_be_restored_from_the_trash() {
+    let db = create_database();
+    db.write().await.trash.retention = Some(Duration::from_secs(60));
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+    let mut expect = async |line: &str, reply: &str| assert_eq!(run(&db, &mut auth, line).await, reply, "{}", line);
+
+    expect("SET session:1 alice EX 100", "OK").await;
+    expect("TAG SET session:1 eu", "(integer) 1").await;
+    expect("RPUSH queue a b", "(integer) 2").await;
+    expect("DEL session:1 queue missing session:1", "(integer) 2").await;
+    expect("GET session:1", "(nil)").await;
+    expect("DBSIZE", "(integer) 0").await;
+    expect("KEYSBYTAG eu", "(empty array)").await;
+
+    // Back with its value, TTL and tags
+    expect("RESTOREKEY session:1", "OK").await;
+    expect("GET session:1", "\"alice\"").await;
+    assert!(db.read().await.expires.contains_key("session:1"));
+    expect("KEYSBYTAG eu", "1) \"session:1\"").await;
+    expect("RESTOREKEY session:1", "(error) ERR no such key in the trash").await;
+
+    // A key written again since its deletion is only replaced on request
+    expect("SET queue new", "OK").await;
+    expect("RESTOREKEY queue", "(error) BUSYKEY Target key name already exists.").await;
+    expect("RESTOREKEY queue REPLACE", "OK").await;
+    expect("LRANGE queue 0 -1", "1) \"a\"\n2) \"b\"").await;
+
+    expect("RESTOREKEY queue NOW", "ERR syntax error").await;
+    expect("RESTOREKEY", "ERR wrong number of arguments for 'restorekey' command").await;
+}
+
+#[tokio::test]
+async fn the_trash_forgets_keys_after_their_retention() {
+    let db = create_database();
+    let mut auth = ClientAuth::new(Arc::new(AuthConfig::new(None)));
+
+    // Off by default: DEL frees the key at once
+    run(&db, &mut auth, "SET k v").await;
+    assert_eq!(run(&db, &mut auth, "DEL k").await, "(integer) 1");
+    assert_eq!(run(&db, &mut auth, "RESTOREKEY k").await, "(error) ERR the trash is disabled, see --trash-retention-seconds");
+
+    db.write().await.trash.retention = Some(Duration::from_millis(20));
+    run(&db, &mut auth, "SET k v").await;
+    run(&db, &mut auth, "SET other v").await;
+    assert_eq!(run(&db, &mut auth, "DEL k other").await, "(integer) 2");
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    assert_eq!(run(&db, &mut auth, "RESTOREKEY k").await, "(error) ERR no such key in the trash");
+    db.write().await.active_expire_cycle();
+    assert!(db.read().await.trash.is_empty());
+}